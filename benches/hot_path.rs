@@ -0,0 +1,97 @@
+//! Throughput and allocations per order on a warm book.
+//!
+//! Run with `cargo bench --bench hot_path`.
+
+use orderbook::models::{BidOrAsk, MatchedOrder, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const CYCLES: u64 = 1_000_000;
+
+fn order(id: u64, bid_or_ask: BidOrAsk, price: f64) -> Order {
+    Order {
+        id,
+        order_type: OrderType::Limit,
+        trading_pair: "BTC-USD".to_string(),
+        amount: 1.0,
+        price: Some(Price::new(price)),
+        timestamp: 0,
+        bid_or_ask,
+        client_order_id: None,
+        account: None,
+    }
+}
+
+fn main() {
+    let mut book = OrderBook::default();
+    let mut fills: Vec<MatchedOrder> = Vec::with_capacity(16);
+    let mut next_id = 0;
+    for level in 0..10 {
+        for _ in 0..100 {
+            for (bid_or_ask, price) in [
+                (BidOrAsk::Ask, 101.0 + level as f64),
+                (BidOrAsk::Bid, 99.0 - level as f64),
+            ] {
+                next_id += 1;
+                book.submit(&mut order(next_id, bid_or_ask, price), 0, &mut fills)
+                    .unwrap();
+            }
+        }
+    }
+
+    // Each cycle rests an ask behind the best level and then takes the front
+    // of that level with a crossing bid, so the book's shape never changes.
+    let mut maker = order(0, BidOrAsk::Ask, 101.0);
+    let mut taker = order(0, BidOrAsk::Bid, 101.0);
+    let mut run = |book: &mut OrderBook, cycles: u64| {
+        for _ in 0..cycles {
+            for order in [&mut maker, &mut taker] {
+                next_id += 1;
+                order.id = next_id;
+                order.amount = 1.0;
+                fills.clear();
+                book.submit(order, 0, &mut fills).unwrap();
+            }
+        }
+    };
+    run(&mut book, 1_000);
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    run(&mut book, CYCLES);
+    let elapsed = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    let orders = CYCLES * 2;
+    println!(
+        "hot_path: {} orders, {:.1} ns/order, {:.3} allocations/order",
+        orders,
+        elapsed.as_nanos() as f64 / orders as f64,
+        allocations as f64 / orders as f64,
+    );
+}
@@ -0,0 +1,211 @@
+//! Matcher benchmarks over synthetic order flow.
+//!
+//! Run with `cargo bench --bench matching`. Books are built without a
+//! notifier, so these measure matching alone and not market data fan-out.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use orderbook::models::{BidOrAsk, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use std::time::{Duration, Instant};
+
+/// Levels per side in a deep book, one tick apart.
+const DEPTH: u64 = 100;
+/// Resting orders per level in a deep book.
+const ORDERS_PER_LEVEL: u64 = 10;
+const MID: f64 = 10_000.0;
+
+/// A small xorshift generator, so the flow is the same on every run.
+struct Flow(u64);
+
+impl Flow {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn side(&mut self) -> BidOrAsk {
+        if self.next().is_multiple_of(2) {
+            BidOrAsk::Bid
+        } else {
+            BidOrAsk::Ask
+        }
+    }
+}
+
+fn order(id: u64, order_type: OrderType, bid_or_ask: BidOrAsk, amount: f64, price: f64) -> Order {
+    Order {
+        id,
+        order_type,
+        trading_pair: "BTC-USD".to_string(),
+        amount,
+        price: Some(Price::new(price)),
+        timestamp: 0,
+        bid_or_ask,
+        client_order_id: None,
+        account: None,
+    }
+}
+
+/// A passive price `offset` ticks away from the touch on `bid_or_ask`'s side.
+fn passive_price(bid_or_ask: BidOrAsk, offset: u64) -> f64 {
+    match bid_or_ask {
+        BidOrAsk::Bid => MID - 1.0 - offset as f64,
+        BidOrAsk::Ask => MID + 1.0 + offset as f64,
+    }
+}
+
+/// Builds a book `DEPTH` levels deep on each side, with ids from 1 upwards.
+fn deep_book() -> (OrderBook, u64) {
+    let mut book = OrderBook::default();
+    let mut id = 0;
+    for level in 0..DEPTH {
+        for _ in 0..ORDERS_PER_LEVEL {
+            for bid_or_ask in [BidOrAsk::Bid, BidOrAsk::Ask] {
+                id += 1;
+                let price = passive_price(bid_or_ask, level);
+                book.add_order(order(id, OrderType::Limit, bid_or_ask, 1.0, price), 0)
+                    .unwrap();
+            }
+        }
+    }
+    (book, id)
+}
+
+fn bench_add_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add_order");
+
+    // Passive orders anywhere in a deep book. Each one is cancelled again
+    // outside the timed section so the book keeps its shape.
+    group.bench_function("rest_in_deep_book", |b| {
+        let (mut book, mut id) = deep_book();
+        let mut flow = Flow(0x9e37_79b9_7f4a_7c15);
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                id += 1;
+                let bid_or_ask = flow.side();
+                let price = passive_price(bid_or_ask, flow.below(DEPTH));
+                let order = order(id, OrderType::Limit, bid_or_ask, 1.0, price);
+                let started = Instant::now();
+                black_box(book.add_order(order, 0).unwrap());
+                elapsed += started.elapsed();
+                book.cancel_order(id).unwrap();
+            }
+            elapsed
+        })
+    });
+
+    // A limit order that takes the front of the best level, immediately
+    // followed by a passive order that puts the liquidity back.
+    group.bench_function("cross_and_replenish", |b| {
+        let (mut book, mut id) = deep_book();
+        b.iter(|| {
+            id += 1;
+            let taker = order(id, OrderType::Limit, BidOrAsk::Bid, 1.0, MID + 1.0);
+            black_box(book.add_order(taker, 0).unwrap());
+            id += 1;
+            let maker = order(id, OrderType::Limit, BidOrAsk::Ask, 1.0, MID + 1.0);
+            black_box(book.add_order(maker, 0).unwrap());
+        })
+    });
+    group.finish();
+}
+
+fn bench_cancel_heavy(c: &mut Criterion) {
+    // Market-maker style flow: for every new order, nine resting orders
+    // are cancelled and replaced, picked at random from the whole book.
+    c.bench_function("cancel_heavy_flow", |b| {
+        let (mut book, mut id) = deep_book();
+        let mut live: Vec<(u64, BidOrAsk)> = (1..=id)
+            .map(|id| {
+                let side = if id % 2 == 1 {
+                    BidOrAsk::Bid
+                } else {
+                    BidOrAsk::Ask
+                };
+                (id, side)
+            })
+            .collect();
+        let mut flow = Flow(0x2545_f491_4f6c_dd1d);
+        b.iter(|| {
+            for step in 0..10 {
+                let slot = flow.below(live.len() as u64) as usize;
+                let bid_or_ask = if step == 0 { flow.side() } else { live[slot].1 };
+                if step > 0 {
+                    black_box(book.cancel_order(live[slot].0).unwrap());
+                }
+                id += 1;
+                let price = passive_price(bid_or_ask, flow.below(DEPTH));
+                book.add_order(order(id, OrderType::Limit, bid_or_ask, 1.0, price), 0)
+                    .unwrap();
+                if step > 0 {
+                    live[slot] = (id, bid_or_ask);
+                } else {
+                    live.push((id, bid_or_ask));
+                }
+            }
+        })
+    });
+}
+
+fn bench_match_limit_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_limit_order");
+    for levels in [1, 10, 50] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &levels,
+            |b, &levels| {
+                let limit = passive_price(BidOrAsk::Ask, levels - 1);
+                let amount = (levels * ORDERS_PER_LEVEL) as f64;
+                b.iter_batched(
+                    || deep_book().0,
+                    |mut book| {
+                        let taker = order(0, OrderType::Limit, BidOrAsk::Bid, amount, limit);
+                        black_box(book.match_limit_order(taker))
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_match_market_order(c: &mut Criterion) {
+    // Market orders sweeping through an increasing number of levels.
+    let mut group = c.benchmark_group("match_market_order");
+    for levels in [1, 10, 50] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &levels,
+            |b, &levels| {
+                let amount = (levels * ORDERS_PER_LEVEL) as f64;
+                b.iter_batched(
+                    || deep_book().0,
+                    |mut book| {
+                        let mut taker = order(0, OrderType::Market, BidOrAsk::Ask, amount, MID);
+                        taker.price = None;
+                        black_box(book.match_market_order(taker))
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_add_order,
+    bench_cancel_heavy,
+    bench_match_limit_order,
+    bench_match_market_order
+);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+use orderbook::market_data::DepthSnapshot;
+use orderbook::models::{BidOrAsk, DepthLevel, Price};
+use std::collections::BTreeMap;
+
+/// A copy of one symbol's book by price level, kept from `depth` channel
+/// updates on top of a snapshot from the REST API. Each update carries the
+/// best levels of both sides in full, so it replaces every level it
+/// covers; levels deeper than an update reaches are kept from the
+/// snapshot.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LocalBook {
+    bids: BTreeMap<Price, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+    timestamp: u64,
+}
+
+impl LocalBook {
+    /// Builds a book from a snapshot. Only updates numbered above the
+    /// snapshot's `seq` belong on top of it.
+    pub fn from_snapshot(snapshot: &DepthSnapshot) -> Self {
+        let levels = |side: &[DepthLevel]| side.iter().map(|level| (level.price, *level)).collect();
+        LocalBook {
+            bids: levels(&snapshot.bids),
+            asks: levels(&snapshot.asks),
+            timestamp: snapshot.timestamp,
+        }
+    }
+
+    /// Applies a `depth` update from a channel of `levels` levels, best
+    /// first on each side. A side with fewer levels than that is the whole
+    /// side.
+    pub fn apply(
+        &mut self,
+        bids: &[DepthLevel],
+        asks: &[DepthLevel],
+        levels: usize,
+        timestamp: u64,
+    ) {
+        apply_side(&mut self.bids, bids, levels, BidOrAsk::Bid);
+        apply_side(&mut self.asks, asks, levels, BidOrAsk::Ask);
+        self.timestamp = timestamp;
+    }
+
+    /// Bid levels, best first.
+    pub fn bids(&self) -> impl Iterator<Item = &DepthLevel> {
+        self.bids.values().rev()
+    }
+
+    /// Ask levels, best first.
+    pub fn asks(&self) -> impl Iterator<Item = &DepthLevel> {
+        self.asks.values()
+    }
+
+    pub fn best_bid(&self) -> Option<&DepthLevel> {
+        self.bids().next()
+    }
+
+    pub fn best_ask(&self) -> Option<&DepthLevel> {
+        self.asks().next()
+    }
+
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_ask()?.price.to_f64() - self.best_bid()?.price.to_f64())
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_ask()?.price.to_f64() + self.best_bid()?.price.to_f64()) / 2.0)
+    }
+
+    /// When the book last changed, as the server's timestamp.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+fn apply_side(
+    side: &mut BTreeMap<Price, DepthLevel>,
+    update: &[DepthLevel],
+    levels: usize,
+    bid_or_ask: BidOrAsk,
+) {
+    match update.last() {
+        Some(worst) if update.len() >= levels => {
+            // Only the levels the update reaches are replaced.
+            let mut deeper = side.split_off(&worst.price);
+            if bid_or_ask == BidOrAsk::Ask {
+                deeper.remove(&worst.price);
+                *side = deeper;
+            }
+        }
+        _ => side.clear(),
+    }
+    side.extend(update.iter().map(|level| (level.price, *level)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: f64, amount: f64) -> DepthLevel {
+        DepthLevel {
+            price: Price::new(price),
+            amount,
+            orders: 1,
+        }
+    }
+
+    fn prices<'a>(levels: impl Iterator<Item = &'a DepthLevel>) -> Vec<f64> {
+        levels.map(|level| level.price.to_f64()).collect()
+    }
+
+    #[test]
+    fn test_updates_replace_the_levels_they_cover() {
+        let mut book = LocalBook::from_snapshot(&DepthSnapshot {
+            channel: "depth:BTC-USD:2".to_string(),
+            seq: 4,
+            bids: vec![
+                DepthLevel {
+                    orders: 2,
+                    ..level(99.0, 2.0)
+                },
+                level(98.0, 1.0),
+                level(97.0, 1.0),
+            ],
+            asks: vec![level(101.0, 1.0), level(102.0, 1.0), level(103.0, 1.0)],
+            timestamp: 4,
+        });
+        assert_eq!(
+            book.best_bid(),
+            Some(&DepthLevel {
+                orders: 2,
+                ..level(99.0, 2.0)
+            })
+        );
+        assert_eq!(book.spread(), Some(2.0));
+
+        // A two level update: 99 was taken out and 100 is new, the rest of
+        // the snapshot stays below it.
+        book.apply(
+            &[level(100.0, 1.0), level(98.0, 3.0)],
+            &[level(101.0, 1.0), level(102.0, 1.0)],
+            2,
+            5,
+        );
+        assert_eq!(prices(book.bids()), [100.0, 98.0, 97.0]);
+        assert_eq!(book.bids().nth(1).unwrap().amount, 3.0);
+        assert_eq!(prices(book.asks()), [101.0, 102.0, 103.0]);
+        assert_eq!(book.mid(), Some(100.5));
+
+        // Fewer levels than the channel carries means the side ends there.
+        book.apply(&[level(100.0, 1.0)], &[], 2, 6);
+        assert_eq!(prices(book.bids()), [100.0]);
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.timestamp(), 6);
+    }
+}
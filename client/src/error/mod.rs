@@ -0,0 +1,88 @@
+use orderbook::error::ErrorBody;
+use std::fmt;
+use tokio_tungstenite::tungstenite;
+
+/// Everything a client call can fail with.
+#[derive(Debug)]
+pub enum Error {
+    /// The server answered a REST request with an error. `body.code` is
+    /// the server's stable, machine-readable reason, such as `off_tick`.
+    Api {
+        status: u16,
+        body: ErrorBody,
+    },
+    /// The server rejected a WebSocket command, with a `code` as for REST.
+    Rejected {
+        code: String,
+        reason: String,
+    },
+    /// The request could not be sent or its response not read.
+    Http(reqwest::Error),
+    /// Boxed, as tungstenite's errors are large.
+    WebSocket(Box<tungstenite::Error>),
+    Json(serde_json::Error),
+    /// The server sent something this client does not understand.
+    Protocol(String),
+    /// The connection dropped before the command was answered, so it may or
+    /// may not have been applied. Check with the order status endpoints.
+    Disconnected,
+    /// The session has been closed.
+    Closed,
+}
+
+impl Error {
+    /// The server's machine-readable reason, for errors the server reported.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { body, .. } => Some(&body.code),
+            Error::Rejected { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api { status, body } => {
+                write!(f, "{} ({}): {}", body.code, status, body.message)
+            }
+            Error::Rejected { code, reason } => write!(f, "{}: {}", code, reason),
+            Error::Http(err) => write!(f, "HTTP request failed: {}", err),
+            Error::WebSocket(err) => write!(f, "WebSocket failed: {}", err),
+            Error::Json(err) => write!(f, "invalid JSON: {}", err),
+            Error::Protocol(message) => write!(f, "unexpected message: {}", message),
+            Error::Disconnected => write!(f, "disconnected before the command was answered"),
+            Error::Closed => write!(f, "session closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::WebSocket(err) => Some(err.as_ref()),
+            Error::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(err))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
@@ -0,0 +1,22 @@
+//! A typed async client for the orderbook server, built on the server's own
+//! models. `RestClient` wraps the `/api/v1` REST API and signs requests
+//! with an API key; `WsClient` opens WebSocket sessions for order entry and
+//! market data that reconnect and resubscribe by themselves, report
+//! sequence gaps and keep local books from depth updates.
+
+mod book;
+mod error;
+mod rest;
+mod ws;
+
+pub use book::LocalBook;
+pub use error::Error;
+pub use orderbook::api::{
+    BatchItem, CancelAfterResponse, FillFilter, OrderFilter, Page, PageQuery, TradeFilter,
+};
+pub use orderbook::engine::{Operation, Outcome};
+pub use orderbook::market_data::{Channel, DepthSnapshot, Topic};
+pub use orderbook::models;
+pub use orderbook::order_book::CancelFilter;
+pub use rest::RestClient;
+pub use ws::{Event, Session, WsClient, DEFAULT_MAX_BACKOFF, DEFAULT_MIN_BACKOFF};
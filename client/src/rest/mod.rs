@@ -0,0 +1,408 @@
+use crate::error::Error;
+use orderbook::api::{
+    AmendRequest, BatchItem, CancelAfterRequest, CancelAfterResponse, DepthQuery, FillFilter,
+    OrderFilter, Page, PageQuery, SymbolQuery, TradeFilter,
+};
+use orderbook::auth::SignedRequest;
+use orderbook::engine::{CommandLatency, Operation};
+use orderbook::error::ErrorBody;
+use orderbook::market_data::DepthSnapshot;
+use orderbook::models::{
+    Execution, FillRecord, MatchedOrder, Order, OrderRecord, Price, TradeRecord,
+};
+use orderbook::order_book::{BookState, CancelFilter, Uncross};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug)]
+struct Credentials {
+    key: String,
+    secret: String,
+    /// Makes nonces unique within the process; the timestamp before it
+    /// makes them unique across restarts.
+    nonces: AtomicU64,
+}
+
+/// A client for the `/api/v1` REST API. Cheap to clone; clones share the
+/// connection pool and API key.
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    http: reqwest::Client,
+    base: String,
+    credentials: Option<Arc<Credentials>>,
+    client_id: Option<String>,
+    token: Option<String>,
+}
+
+impl RestClient {
+    /// A client for the server at `base_url`, such as `http://localhost:8080`.
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base: base_url.trim_end_matches('/').to_string(),
+            credentials: None,
+            client_id: None,
+            token: None,
+        }
+    }
+
+    /// Signs every request with an API key, as servers with `auth.api_keys`
+    /// configured require.
+    pub fn with_api_key(mut self, key: &str, secret: &str) -> Self {
+        self.credentials = Some(Arc::new(Credentials {
+            key: key.to_string(),
+            secret: secret.to_string(),
+            nonces: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    /// Sends `X-Client-Id`, which names the account on servers that do not
+    /// sign requests. Signed requests belong to the key's account instead.
+    pub fn with_client_id(mut self, account: &str) -> Self {
+        self.client_id = Some(account.to_string());
+        self
+    }
+
+    /// Sends an account token as `Authorization: Bearer`, which unsigned
+    /// requests need to arm the dead man's switch once the server has
+    /// account tokens configured.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Uses `http` for requests, for example to set timeouts or a proxy.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub async fn health(&self) -> Result<String, Error> {
+        let body = self
+            .send(Method::GET, "/healthcheck", &(), None, &[])
+            .await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// The Prometheus metrics, as text.
+    pub async fn metrics(&self) -> Result<String, Error> {
+        let body = self.send(Method::GET, "/metrics", &(), None, &[]).await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// The server's OpenAPI document.
+    pub async fn openapi(&self) -> Result<serde_json::Value, Error> {
+        self.call(Method::GET, "/openapi.json", &(), None).await
+    }
+
+    /// Submits an order. The server assigns its id. With an
+    /// `idempotency_key`, a retry returns the original execution instead of
+    /// placing the order twice.
+    pub async fn place_order(
+        &self,
+        order: &Order,
+        idempotency_key: Option<&str>,
+    ) -> Result<Execution, Error> {
+        let body = serde_json::to_vec(order)?;
+        let headers: Vec<_> = idempotency_key
+            .map(|key| ("Idempotency-Key", key))
+            .into_iter()
+            .collect();
+        let body = self
+            .send(Method::POST, "/api/v1/orders", &(), Some(body), &headers)
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Applies the operations in a single engine turn, with a result per
+    /// operation.
+    pub async fn batch(&self, operations: &[Operation]) -> Result<Vec<BatchItem>, Error> {
+        let body = serde_json::to_vec(operations)?;
+        self.call(Method::POST, "/api/v1/orders/batch", &(), Some(body))
+            .await
+    }
+
+    /// Cancels every order matching `filter`, returning the cancelled orders.
+    pub async fn cancel_orders(&self, filter: &CancelFilter) -> Result<Vec<Order>, Error> {
+        self.call(Method::DELETE, "/api/v1/orders", filter, None)
+            .await
+    }
+
+    /// Arms the dead man's switch: unless called again within `timeout`, the
+    /// account's orders are cancelled. `None` disarms it.
+    pub async fn cancel_after(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<CancelAfterResponse, Error> {
+        let request = CancelAfterRequest {
+            timeout_ms: timeout.map_or(0, |timeout| timeout.as_millis() as u64),
+        };
+        let body = serde_json::to_vec(&request)?;
+        self.call(Method::POST, "/api/v1/orders/cancel-after", &(), Some(body))
+            .await
+    }
+
+    pub async fn order(&self, id: u64) -> Result<OrderRecord, Error> {
+        self.call(Method::GET, &format!("/api/v1/orders/{}", id), &(), None)
+            .await
+    }
+
+    pub async fn cancel_order(&self, id: u64) -> Result<Order, Error> {
+        self.call(Method::DELETE, &format!("/api/v1/orders/{}", id), &(), None)
+            .await
+    }
+
+    pub async fn amend_order(
+        &self,
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+    ) -> Result<Execution, Error> {
+        let body = serde_json::to_vec(&AmendRequest { amount, price })?;
+        self.call(
+            Method::PATCH,
+            &format!("/api/v1/orders/{}", id),
+            &(),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn orders(
+        &self,
+        filter: &OrderFilter,
+        page: &PageQuery,
+    ) -> Result<Page<OrderRecord>, Error> {
+        self.call(Method::GET, "/api/v1/orders", &Paged { filter, page }, None)
+            .await
+    }
+
+    pub async fn fills(
+        &self,
+        filter: &FillFilter,
+        page: &PageQuery,
+    ) -> Result<Page<FillRecord>, Error> {
+        self.call(Method::GET, "/api/v1/fills", &Paged { filter, page }, None)
+            .await
+    }
+
+    pub async fn trades(
+        &self,
+        filter: &TradeFilter,
+        page: &PageQuery,
+    ) -> Result<Page<TradeRecord>, Error> {
+        self.call(Method::GET, "/api/v1/trades", &Paged { filter, page }, None)
+            .await
+    }
+
+    /// A page of resting bids, best price first.
+    pub async fn bids(&self, symbol: Option<&str>, page: &PageQuery) -> Result<Page<Order>, Error> {
+        self.call(
+            Method::GET,
+            "/api/v1/bids",
+            &Paged {
+                filter: &symbol_query(symbol),
+                page,
+            },
+            None,
+        )
+        .await
+    }
+
+    /// A page of resting asks, best price first.
+    pub async fn asks(&self, symbol: Option<&str>, page: &PageQuery) -> Result<Page<Order>, Error> {
+        self.call(
+            Method::GET,
+            "/api/v1/asks",
+            &Paged {
+                filter: &symbol_query(symbol),
+                page,
+            },
+            None,
+        )
+        .await
+    }
+
+    /// The levels of `symbol`'s book, up to 50 a side, numbered by its
+    /// `depth` channel of `levels` levels.
+    pub async fn depth(&self, symbol: &str, levels: usize) -> Result<DepthSnapshot, Error> {
+        let query = DepthQuery {
+            symbol: symbol.to_string(),
+            levels: Some(levels),
+        };
+        self.call(Method::GET, "/api/v1/depth", &query, None).await
+    }
+
+    pub async fn status(&self, symbol: Option<&str>) -> Result<BookState, Error> {
+        self.call(Method::GET, "/api/v1/status", &symbol_query(symbol), None)
+            .await
+    }
+
+    /// The indicative uncross during a call auction, otherwise `None`.
+    pub async fn auction(&self, symbol: Option<&str>) -> Result<Option<Uncross>, Error> {
+        self.call(Method::GET, "/api/v1/auction", &symbol_query(symbol), None)
+            .await
+    }
+
+    pub async fn halt(&self, symbol: Option<&str>) -> Result<BookState, Error> {
+        self.call(
+            Method::POST,
+            "/api/v1/admin/halt",
+            &symbol_query(symbol),
+            None,
+        )
+        .await
+    }
+
+    pub async fn resume(&self, symbol: Option<&str>) -> Result<BookState, Error> {
+        self.call(
+            Method::POST,
+            "/api/v1/admin/resume",
+            &symbol_query(symbol),
+            None,
+        )
+        .await
+    }
+
+    /// Starts a call period.
+    pub async fn pre_open(&self, symbol: Option<&str>) -> Result<BookState, Error> {
+        self.call(
+            Method::POST,
+            "/api/v1/admin/pre-open",
+            &symbol_query(symbol),
+            None,
+        )
+        .await
+    }
+
+    /// Ends a call period, returning the trades the uncross produced.
+    pub async fn uncross(&self, symbol: Option<&str>) -> Result<Vec<MatchedOrder>, Error> {
+        self.call(
+            Method::POST,
+            "/api/v1/admin/uncross",
+            &symbol_query(symbol),
+            None,
+        )
+        .await
+    }
+
+    pub async fn latencies(&self) -> Result<Vec<CommandLatency>, Error> {
+        self.call(Method::GET, "/api/v1/admin/latency", &(), None)
+            .await
+    }
+
+    pub async fn reset_latencies(&self) -> Result<(), Error> {
+        self.send(Method::DELETE, "/api/v1/admin/latency", &(), None, &[])
+            .await?;
+        Ok(())
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &impl Serialize,
+        body: Option<Vec<u8>>,
+    ) -> Result<T, Error> {
+        let body = self.send(method, path, query, body, &[]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Sends a request, signed if the client has an API key, and returns the
+    /// body of a successful response.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &impl Serialize,
+        body: Option<Vec<u8>>,
+        headers: &[(&str, &str)],
+    ) -> Result<Vec<u8>, Error> {
+        let query = serde_urlencoded::to_string(query)
+            .map_err(|err| Error::Protocol(format!("query does not encode: {}", err)))?;
+        let path = match query.as_str() {
+            "" => path.to_string(),
+            query => format!("{}?{}", path, query),
+        };
+        let mut request = self
+            .http
+            .request(method.clone(), format!("{}{}", self.base, path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(account) = &self.client_id {
+            request = request.header("X-Client-Id", account);
+        }
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let body = body.unwrap_or_default();
+        if let Some(credentials) = &self.credentials {
+            let timestamp = now();
+            let nonce = format!(
+                "{}-{}",
+                timestamp,
+                credentials.nonces.fetch_add(1, Ordering::Relaxed)
+            );
+            let signature = SignedRequest {
+                key: &credentials.key,
+                timestamp,
+                nonce: &nonce,
+                method: method.as_str(),
+                path: &path,
+                body: &body,
+            }
+            .sign(&credentials.secret);
+            request = request
+                .header("X-Api-Key", &credentials.key)
+                .header("X-Api-Timestamp", timestamp.to_string())
+                .header("X-Api-Nonce", nonce)
+                .header("X-Api-Signature", signature);
+        }
+        if !body.is_empty() {
+            request = request.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?.to_vec();
+        if status.is_success() {
+            return Ok(body);
+        }
+        let body = serde_json::from_slice(&body).unwrap_or_else(|_| ErrorBody {
+            code: format!("http_{}", status.as_u16()),
+            message: String::from_utf8_lossy(&body).into_owned(),
+        });
+        Err(Error::Api {
+            status: status.as_u16(),
+            body,
+        })
+    }
+}
+
+/// A list endpoint's filters and paging, in one query string.
+#[derive(Serialize)]
+struct Paged<'a, F> {
+    #[serde(flatten)]
+    filter: &'a F,
+    #[serde(flatten)]
+    page: &'a PageQuery,
+}
+
+fn symbol_query(symbol: Option<&str>) -> SymbolQuery {
+    SymbolQuery {
+        symbol: symbol.map(str::to_string),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
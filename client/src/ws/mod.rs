@@ -0,0 +1,727 @@
+use crate::book::LocalBook;
+use crate::error::Error;
+use crate::rest::RestClient;
+use futures_util::{SinkExt, StreamExt};
+use orderbook::engine::Outcome;
+use orderbook::market_data::{Candle, Channel, DepthSnapshot, Ticker, Topic};
+use orderbook::models::{DepthLevel, Execution, ExecutionReport, MarketEvent, Order, Price};
+use orderbook::websocket::{ClientCommand, Request, Response, CLIENT_TIMEOUT};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// The first wait before reconnecting; it doubles on each failed attempt.
+pub const DEFAULT_MIN_BACKOFF: Duration = Duration::from_millis(250);
+/// The longest wait between reconnect attempts.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type Reply = oneshot::Sender<Result<Option<Outcome>, Error>>;
+type Books = Arc<Mutex<HashMap<String, LocalBook>>>;
+type SnapshotTx = mpsc::UnboundedSender<(String, Result<DepthSnapshot, Error>)>;
+type SnapshotRx = mpsc::UnboundedReceiver<(String, Result<DepthSnapshot, Error>)>;
+
+/// What a session delivers besides the replies to its own commands.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The connection dropped. Commands in flight failed with
+    /// `Error::Disconnected`, and the session is reconnecting.
+    Disconnected {
+        reason: String,
+    },
+    /// Connected again, with the login and every subscription renewed.
+    /// Local books are resynced.
+    Reconnected,
+    /// A message on a `trades`, `depth`, `status` or `auction` channel.
+    /// Depth updates are already applied to the local book.
+    Market {
+        channel: String,
+        event: MarketEvent,
+    },
+    Ticker {
+        channel: String,
+        ticker: Ticker,
+    },
+    Candle {
+        channel: String,
+        candle: Candle,
+    },
+    /// An execution report from `orders:*`.
+    Order(ExecutionReport),
+    /// A fill from `fills:*`.
+    Fill(ExecutionReport),
+    /// Messages on `channel` were missed: `received` came when `expected`
+    /// was due. A depth channel's local book is resynced.
+    Gap {
+        channel: String,
+        expected: u64,
+        received: u64,
+    },
+    /// A local book was reloaded from a REST snapshot.
+    Resynced {
+        symbol: String,
+    },
+}
+
+/// Connects WebSocket sessions to `/ws/`, for order entry and market data.
+#[derive(Debug, Clone)]
+pub struct WsClient {
+    url: String,
+    client_id: Option<String>,
+    token: Option<String>,
+    cancel_on_disconnect: bool,
+    rest: Option<RestClient>,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl WsClient {
+    /// A client for the session endpoint at `url`, such as
+    /// `ws://localhost:8080/ws/`.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client_id: None,
+            token: None,
+            cancel_on_disconnect: false,
+            rest: None,
+            min_backoff: DEFAULT_MIN_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    /// Authenticates the session with an account token when it connects.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Sends `X-Client-Id`, which names the session's account on servers
+    /// without API keys.
+    pub fn with_client_id(mut self, account: &str) -> Self {
+        self.client_id = Some(account.to_string());
+        self
+    }
+
+    /// Has the server cancel the account's orders whenever the connection
+    /// drops, including the drops the session then reconnects from.
+    pub fn with_cancel_on_disconnect(mut self) -> Self {
+        self.cancel_on_disconnect = true;
+        self
+    }
+
+    /// Loads local books from REST snapshots when depth is first received,
+    /// after a sequence gap and after reconnecting. Without it, local books
+    /// only hold the levels depth updates carry.
+    pub fn with_rest(mut self, rest: RestClient) -> Self {
+        self.rest = Some(rest);
+        self
+    }
+
+    pub fn with_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    /// Opens a session. Fails if the first connection does; afterwards the
+    /// session reconnects by itself until it is dropped.
+    pub async fn connect(self) -> Result<Session, Error> {
+        let socket = self.open(self.token.as_deref()).await?;
+        let (commands, tasks) = mpsc::unbounded_channel();
+        let (events, received) = mpsc::unbounded_channel();
+        let books = Books::default();
+        let driver = Driver {
+            token: self.token.clone(),
+            client: self,
+            topics: Vec::new(),
+            pending: HashMap::new(),
+            next_id: 1,
+            sequences: HashMap::new(),
+            books: books.clone(),
+            resyncing: HashMap::new(),
+            snapshot_seqs: HashMap::new(),
+            events,
+        };
+        tokio::spawn(driver.run(socket, tasks));
+        Ok(Session {
+            commands,
+            events: received,
+            books,
+        })
+    }
+
+    async fn open(&self, token: Option<&str>) -> Result<Socket, Error> {
+        let url = match self.cancel_on_disconnect {
+            true => format!("{}?cancel_on_disconnect=true", self.url),
+            false => self.url.clone(),
+        };
+        let mut request = url.into_client_request()?;
+        let headers = [
+            ("X-Client-Id", self.client_id.clone()),
+            (
+                "Authorization",
+                token.map(|token| format!("Bearer {}", token)),
+            ),
+        ];
+        for (name, value) in headers {
+            if let Some(value) = value {
+                let value = HeaderValue::from_str(&value)
+                    .map_err(|err| Error::Protocol(format!("invalid {} header: {}", name, err)))?;
+                request.headers_mut().insert(name, value);
+            }
+        }
+        Ok(tokio_tungstenite::connect_async(request).await?.0)
+    }
+}
+
+struct Task {
+    command: ClientCommand,
+    reply: Reply,
+}
+
+/// An open session. Commands are sent in the order they are called and
+/// each waits for the server's reply; events arrive on `next_event`.
+/// Dropping the session closes the connection.
+#[derive(Debug)]
+pub struct Session {
+    commands: mpsc::UnboundedSender<Task>,
+    events: mpsc::UnboundedReceiver<Event>,
+    books: Books,
+}
+
+impl Session {
+    /// The next event, or `None` once the session has stopped.
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    pub async fn place(&self, order: Order) -> Result<Execution, Error> {
+        match self.request(ClientCommand::Place { order }).await? {
+            Some(Outcome::Execution(execution)) => Ok(execution),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn cancel(&self, order_id: u64) -> Result<Order, Error> {
+        match self.request(ClientCommand::Cancel { order_id }).await? {
+            Some(Outcome::Cancelled(order)) => Ok(order),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn amend(
+        &self,
+        order_id: u64,
+        amount: f64,
+        price: Option<Price>,
+    ) -> Result<Execution, Error> {
+        let command = ClientCommand::Amend {
+            order_id,
+            amount,
+            price,
+        };
+        match self.request(command).await? {
+            Some(Outcome::Execution(execution)) => Ok(execution),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Authenticates the session. The token is sent again on reconnects.
+    pub async fn login(&self, token: &str) -> Result<(), Error> {
+        let token = token.to_string();
+        self.request(ClientCommand::Login { token }).await?;
+        Ok(())
+    }
+
+    /// Subscribes to `topics`, which are renewed on reconnects. Depth
+    /// topics also keep a local book per symbol.
+    pub async fn subscribe(&self, topics: impl IntoIterator<Item = Topic>) -> Result<(), Error> {
+        let topics = topics.into_iter().collect();
+        self.request(ClientCommand::Subscribe { topics }).await?;
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, topics: impl IntoIterator<Item = Topic>) -> Result<(), Error> {
+        let topics = topics.into_iter().collect();
+        self.request(ClientCommand::Unsubscribe { topics }).await?;
+        Ok(())
+    }
+
+    /// The round trip time to the server.
+    pub async fn ping(&self) -> Result<Duration, Error> {
+        let started = Instant::now();
+        self.request(ClientCommand::Ping).await?;
+        Ok(started.elapsed())
+    }
+
+    /// A copy of the local book of `symbol`, if a depth subscription covers
+    /// it and an update has arrived.
+    pub fn book(&self, symbol: &str) -> Option<LocalBook> {
+        self.books.lock().unwrap().get(symbol).cloned()
+    }
+
+    async fn request(&self, command: ClientCommand) -> Result<Option<Outcome>, Error> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(Task { command, reply })
+            .map_err(|_| Error::Closed)?;
+        result.await.map_err(|_| Error::Closed)?
+    }
+}
+
+fn unexpected(outcome: Option<Outcome>) -> Error {
+    Error::Protocol(format!("unexpected reply {:?}", outcome))
+}
+
+/// A message on a channel.
+#[derive(Deserialize)]
+struct Envelope {
+    channel: String,
+    seq: u64,
+    data: serde_json::Value,
+}
+
+struct Pending {
+    command: ClientCommand,
+    /// `None` for the commands the session sends by itself on reconnects.
+    reply: Option<Reply>,
+}
+
+/// Owns the connection: sends commands, matches replies to them, tracks
+/// sequence numbers and local books, and reconnects.
+struct Driver {
+    client: WsClient,
+    token: Option<String>,
+    /// Everything subscribed to, renewed on reconnects.
+    topics: Vec<Topic>,
+    pending: HashMap<u64, Pending>,
+    next_id: u64,
+    /// The last sequence number seen on each concrete channel.
+    sequences: HashMap<String, u64>,
+    books: Books,
+    /// Symbols with a snapshot on its way, and the depth updates held back
+    /// until it arrives.
+    resyncing: HashMap<String, Vec<DepthUpdate>>,
+    /// The sequence number each depth channel's book was resynced at.
+    /// Updates numbered up to it are already in the book.
+    snapshot_seqs: HashMap<String, u64>,
+    events: mpsc::UnboundedSender<Event>,
+}
+
+/// One message of a `depth` channel.
+struct DepthUpdate {
+    channel: String,
+    seq: u64,
+    levels: usize,
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+    timestamp: u64,
+}
+
+impl Driver {
+    async fn run(mut self, mut socket: Socket, mut tasks: mpsc::UnboundedReceiver<Task>) {
+        let (snapshot_tx, mut snapshots) = mpsc::unbounded_channel();
+        loop {
+            let Some(reason) = self
+                .serve(&mut socket, &mut tasks, &snapshot_tx, &mut snapshots)
+                .await
+            else {
+                let _ = socket.close(None).await;
+                return;
+            };
+            tracing::warn!(reason = reason.as_str(), "websocket disconnected");
+            for (_, pending) in self.pending.drain() {
+                if let Some(reply) = pending.reply {
+                    let _ = reply.send(Err(Error::Disconnected));
+                }
+            }
+            self.emit(Event::Disconnected { reason });
+            let Some(reconnected) = self.reconnect(&mut tasks).await else {
+                return;
+            };
+            socket = reconnected;
+            self.sequences.clear();
+            self.snapshot_seqs.clear();
+            if !self.topics.is_empty() {
+                let topics = self.topics.clone();
+                // A failure shows up as a disconnect when serving resumes.
+                let _ = self
+                    .send(&mut socket, ClientCommand::Subscribe { topics }, None)
+                    .await;
+            }
+            let symbols: Vec<String> = self.books.lock().unwrap().keys().cloned().collect();
+            for symbol in symbols {
+                if let Some(levels) = self.depth_levels(&symbol) {
+                    self.resync(&symbol, levels, &snapshot_tx);
+                }
+            }
+            self.emit(Event::Reconnected);
+        }
+    }
+
+    /// Serves the connection until it drops, returning why, or until the
+    /// session is dropped, returning `None`.
+    async fn serve(
+        &mut self,
+        socket: &mut Socket,
+        tasks: &mut mpsc::UnboundedReceiver<Task>,
+        snapshot_tx: &SnapshotTx,
+        snapshots: &mut SnapshotRx,
+    ) -> Option<String> {
+        // The server pings every few seconds, so silence means it is gone.
+        let mut deadline = Instant::now() + CLIENT_TIMEOUT;
+        loop {
+            tokio::select! {
+                task = tasks.recv() => {
+                    let task = task?;
+                    if let Err(err) = self.send(socket, task.command, Some(task.reply)).await {
+                        return Some(err.to_string());
+                    }
+                }
+                message = socket.next() => {
+                    deadline = Instant::now() + CLIENT_TIMEOUT;
+                    match message {
+                        Some(Ok(Message::Text(text))) => self.receive(&text, snapshot_tx),
+                        Some(Ok(Message::Close(frame))) => {
+                            return Some(frame.map_or_else(
+                                || "closed by the server".to_string(),
+                                |frame| frame.reason.to_string(),
+                            ));
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Some(err.to_string()),
+                        None => return Some("connection closed".to_string()),
+                    }
+                }
+                Some((symbol, snapshot)) = snapshots.recv() => {
+                    self.resynced(symbol, snapshot, snapshot_tx);
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    return Some("server stopped responding".to_string());
+                }
+            }
+        }
+    }
+
+    /// Waits out the backoff and connects again, failing commands that
+    /// arrive in the meantime. `None` if the session is dropped first.
+    async fn reconnect(&mut self, tasks: &mut mpsc::UnboundedReceiver<Task>) -> Option<Socket> {
+        let mut backoff = self.client.min_backoff;
+        loop {
+            let retry = tokio::time::sleep(backoff);
+            tokio::pin!(retry);
+            loop {
+                tokio::select! {
+                    task = tasks.recv() => {
+                        let _ = task?.reply.send(Err(Error::Disconnected));
+                    }
+                    _ = &mut retry => break,
+                }
+            }
+            match self.client.open(self.token.as_deref()).await {
+                Ok(socket) => return Some(socket),
+                Err(err) => tracing::warn!(error = %err, "websocket reconnect failed"),
+            }
+            backoff = (backoff * 2).min(self.client.max_backoff);
+        }
+    }
+
+    async fn send(
+        &mut self,
+        socket: &mut Socket,
+        command: ClientCommand,
+        reply: Option<Reply>,
+    ) -> Result<(), Error> {
+        match &command {
+            ClientCommand::Subscribe { topics } => {
+                for topic in topics {
+                    if !self.topics.contains(topic) {
+                        self.topics.push(topic.clone());
+                    }
+                }
+            }
+            ClientCommand::Unsubscribe { topics } => {
+                self.topics.retain(|topic| !topics.contains(topic));
+                self.drop_books(topics);
+            }
+            _ => {}
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let text = serde_json::to_string(&Request {
+            id,
+            command: command.clone(),
+        })?;
+        self.pending.insert(id, Pending { command, reply });
+        socket.send(Message::Text(text)).await?;
+        Ok(())
+    }
+
+    fn receive(&mut self, text: &str, snapshot_tx: &SnapshotTx) {
+        let value: serde_json::Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::warn!(error = %err, "unreadable websocket message");
+                return;
+            }
+        };
+        let result = if value.get("channel").is_some() {
+            serde_json::from_value(value)
+                .map_err(Error::from)
+                .and_then(|envelope| self.market(envelope, snapshot_tx))
+        } else {
+            serde_json::from_value(value)
+                .map(|response| self.reply(response))
+                .map_err(Error::from)
+        };
+        if let Err(err) = result {
+            tracing::warn!(error = %err, "unexpected websocket message");
+        }
+    }
+
+    fn reply(&mut self, response: Response) {
+        let (id, result) = match response {
+            Response::Ack { id, result } => (id, Ok(result)),
+            Response::Pong { id } => (id, Ok(None)),
+            Response::Reject {
+                id: Some(id),
+                code,
+                reason,
+            } => (id, Err(Error::Rejected { code, reason })),
+            Response::Reject {
+                id: None,
+                code,
+                reason,
+            } => {
+                tracing::warn!(code, reason, "websocket message rejected");
+                return;
+            }
+        };
+        let Some(pending) = self.pending.remove(&id) else {
+            return;
+        };
+        match (&pending.command, &result) {
+            (ClientCommand::Login { token }, Ok(_)) => self.token = Some(token.clone()),
+            (ClientCommand::Subscribe { topics }, Err(_)) => {
+                self.topics.retain(|topic| !topics.contains(topic));
+            }
+            _ => {}
+        }
+        if let Some(reply) = pending.reply {
+            let _ = reply.send(result);
+        }
+    }
+
+    fn market(&mut self, envelope: Envelope, snapshot_tx: &SnapshotTx) -> Result<(), Error> {
+        let Envelope { channel, seq, data } = envelope;
+        let mut gap = false;
+        if let Some(last) = self.sequences.insert(channel.clone(), seq) {
+            if seq != last + 1 {
+                gap = true;
+                self.emit(Event::Gap {
+                    channel: channel.clone(),
+                    expected: last + 1,
+                    received: seq,
+                });
+            }
+        }
+        let topic: Topic = channel
+            .parse()
+            .map_err(|err| Error::Protocol(format!("{}", err)))?;
+        let event = match topic.channel {
+            Channel::Depth { levels } => {
+                let event: MarketEvent = serde_json::from_value(data)?;
+                if let MarketEvent::Depth {
+                    symbol,
+                    bids,
+                    asks,
+                    timestamp,
+                } = &event
+                {
+                    let update = DepthUpdate {
+                        channel: channel.clone(),
+                        seq,
+                        levels,
+                        bids: bids.clone(),
+                        asks: asks.clone(),
+                        timestamp: *timestamp,
+                    };
+                    self.update_book(symbol, update, gap, snapshot_tx);
+                }
+                Event::Market { channel, event }
+            }
+            Channel::Trades | Channel::Status | Channel::Auction => Event::Market {
+                channel,
+                event: serde_json::from_value(data)?,
+            },
+            Channel::Ticker => Event::Ticker {
+                channel,
+                ticker: serde_json::from_value(data)?,
+            },
+            Channel::Candles { .. } => Event::Candle {
+                channel,
+                candle: serde_json::from_value(data)?,
+            },
+            Channel::Orders => Event::Order(serde_json::from_value(data)?),
+            Channel::Fills => Event::Fill(serde_json::from_value(data)?),
+        };
+        self.emit(event);
+        Ok(())
+    }
+
+    /// Applies a depth update to `symbol`'s local book, or holds it back
+    /// while a snapshot is on its way. The first update for a book, and one
+    /// after a gap, fetch a snapshot.
+    fn update_book(
+        &mut self,
+        symbol: &str,
+        update: DepthUpdate,
+        gap: bool,
+        snapshot_tx: &SnapshotTx,
+    ) {
+        if let Some(held) = self.resyncing.get_mut(symbol) {
+            held.push(update);
+            return;
+        }
+        let levels = update.levels;
+        let first = {
+            let mut books = self.books.lock().unwrap();
+            let first = !books.contains_key(symbol);
+            let book = books.entry(symbol.to_string()).or_default();
+            if self
+                .snapshot_seqs
+                .get(&update.channel)
+                .is_none_or(|seq| update.seq > *seq)
+            {
+                book.apply(&update.bids, &update.asks, levels, update.timestamp);
+            }
+            first
+        };
+        if first || gap {
+            self.resync(symbol, levels, snapshot_tx);
+        }
+    }
+
+    /// Fetches a snapshot of `symbol`'s book, numbered by its depth channel
+    /// of `levels` levels, in the background, unless one is already on its
+    /// way or there is no REST client to fetch it with.
+    fn resync(&mut self, symbol: &str, levels: usize, snapshot_tx: &SnapshotTx) {
+        let Some(rest) = self.client.rest.clone() else {
+            return;
+        };
+        if self.resyncing.contains_key(symbol) {
+            return;
+        }
+        self.resyncing.insert(symbol.to_string(), Vec::new());
+        let symbol = symbol.to_string();
+        let snapshot_tx = snapshot_tx.clone();
+        tokio::spawn(async move {
+            let snapshot = rest.depth(&symbol, levels).await;
+            let _ = snapshot_tx.send((symbol, snapshot));
+        });
+    }
+
+    /// Replaces `symbol`'s book with `snapshot` and applies the updates
+    /// held back meanwhile that it does not already hold. Updates missing
+    /// between the snapshot and those fetch another one.
+    fn resynced(
+        &mut self,
+        symbol: String,
+        snapshot: Result<DepthSnapshot, Error>,
+        snapshot_tx: &SnapshotTx,
+    ) {
+        let held = self.resyncing.remove(&symbol).unwrap_or_default();
+        let snapshot = match snapshot {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                tracing::warn!(symbol = symbol.as_str(), error = %err, "book resync failed");
+                None
+            }
+        };
+        let mut books = self.books.lock().unwrap();
+        // Unsubscribed while the snapshot was on its way.
+        let Some(book) = books.get_mut(&symbol) else {
+            return;
+        };
+        let mut gap = false;
+        if let Some(snapshot) = &snapshot {
+            *book = LocalBook::from_snapshot(snapshot);
+            self.snapshot_seqs
+                .insert(snapshot.channel.clone(), snapshot.seq);
+        }
+        for update in held {
+            let seq = self.snapshot_seqs.get(&update.channel).copied();
+            if seq.is_some_and(|seq| update.seq <= seq) {
+                continue;
+            }
+            if snapshot.as_ref().is_some_and(|snapshot| {
+                snapshot.channel == update.channel && seq.is_some_and(|seq| update.seq != seq + 1)
+            }) {
+                gap = true;
+            }
+            if seq.is_some() {
+                self.snapshot_seqs
+                    .insert(update.channel.clone(), update.seq);
+            }
+            book.apply(&update.bids, &update.asks, update.levels, update.timestamp);
+        }
+        drop(books);
+        if snapshot.is_some() {
+            self.emit(Event::Resynced {
+                symbol: symbol.clone(),
+            });
+        }
+        if gap {
+            if let Some(levels) = self.depth_levels(&symbol) {
+                self.resync(&symbol, levels, snapshot_tx);
+            }
+        }
+    }
+
+    /// The levels of a depth subscription covering `symbol`.
+    fn depth_levels(&self, symbol: &str) -> Option<usize> {
+        self.topics.iter().find_map(|topic| match topic.channel {
+            Channel::Depth { levels }
+                if topic
+                    .symbol
+                    .as_deref()
+                    .is_none_or(|covers| covers == symbol) =>
+            {
+                Some(levels)
+            }
+            _ => None,
+        })
+    }
+
+    /// Forgets the local books no remaining depth subscription covers.
+    fn drop_books(&mut self, unsubscribed: &[Topic]) {
+        let depth = |topic: &Topic| matches!(topic.channel, Channel::Depth { .. });
+        if !unsubscribed.iter().any(depth) {
+            return;
+        }
+        let covered: Vec<Option<String>> = self
+            .topics
+            .iter()
+            .filter(|topic| depth(topic))
+            .map(|topic| topic.symbol.clone())
+            .collect();
+        self.books.lock().unwrap().retain(|symbol, _| {
+            covered
+                .iter()
+                .any(|covers| covers.as_ref().is_none_or(|covers| covers == symbol))
+        });
+    }
+
+    fn emit(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+}
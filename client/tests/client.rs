@@ -0,0 +1,224 @@
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+use actix_web::{middleware, web, App, HttpServer};
+use orderbook::api;
+use orderbook::auth::{Accounts, ApiKey, ApiKeys, Permission};
+use orderbook::engine::{Engine, EngineHandle};
+use orderbook::market_data::Hub;
+use orderbook::models::{BidOrAsk, MarketEvent, Order, OrderStatus, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use orderbook_client::{
+    Error, Event, LocalBook, Operation, Outcome, PageQuery, RestClient, Session, WsClient,
+};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn app_engine() -> (EngineHandle, Addr<Hub>) {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    (Engine::new(OrderBook::new(tx)).spawn(16), Hub::start(rx))
+}
+
+/// Starts a real server on `addr`, signing requests with `keys` if any.
+fn serve(
+    engine: EngineHandle,
+    hub: Addr<Hub>,
+    keys: Vec<ApiKey>,
+    addr: &str,
+) -> (SocketAddr, ServerHandle) {
+    let keys = web::Data::new(ApiKeys::new(&keys, 5_000));
+    let accounts = web::Data::new(Accounts::new(&[]));
+    let server = HttpServer::new(move || {
+        let hub = hub.clone();
+        App::new()
+            .wrap(middleware::from_fn(api::authenticate))
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(keys.clone())
+            .app_data(accounts.clone())
+            .configure(move |cfg| api::config(cfg, hub))
+    })
+    .workers(1)
+    .bind(addr)
+    .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    (addr, handle)
+}
+
+fn order(price: f64, side: BidOrAsk) -> Order {
+    Order::new(
+        0,
+        OrderType::Limit,
+        "BTC-USD".to_string(),
+        1.0,
+        Some(Price::new(price)),
+        0,
+        side,
+    )
+}
+
+fn bid_prices(book: &LocalBook) -> Vec<f64> {
+    book.bids().map(|level| level.price.to_f64()).collect()
+}
+
+/// Waits for the first event `matches` accepts.
+async fn wait_for(session: &mut Session, matches: impl Fn(&Event) -> bool) -> Event {
+    let wait = async {
+        while let Some(event) = session.next_event().await {
+            if matches(&event) {
+                return event;
+            }
+        }
+        panic!("session stopped");
+    };
+    actix_web::rt::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("no matching event arrived")
+}
+
+#[actix_web::test]
+async fn test_rest_client_signs_and_pages() {
+    let (engine, hub) = app_engine();
+    let trader = ApiKey::new(
+        "trader",
+        "desk-7",
+        "redart",
+        &[Permission::Read, Permission::Trade],
+    );
+    let (addr, _handle) = serve(engine, hub, vec![trader], "127.0.0.1:0");
+    let base = format!("http://{}", addr);
+    let client = RestClient::new(&base).with_api_key("trader", "redart");
+
+    assert_eq!(client.health().await.unwrap(), "Server is up and running!");
+    let unsigned = RestClient::new(&base).with_client_id("desk-7");
+    match unsigned
+        .place_order(&order(100.0, BidOrAsk::Bid), None)
+        .await
+    {
+        Err(Error::Api { status: 401, .. }) => {}
+        other => panic!("unsigned order was not refused: {:?}", other),
+    }
+
+    let mut ids = Vec::new();
+    for price in [100.0, 99.0, 98.0] {
+        let execution = client
+            .place_order(&order(price, BidOrAsk::Bid), None)
+            .await
+            .unwrap();
+        ids.push(execution.order.id);
+    }
+    client
+        .place_order(&order(101.0, BidOrAsk::Ask), Some("ask-1"))
+        .await
+        .unwrap();
+    let record = client.order(ids[0]).await.unwrap();
+    assert_eq!(record.order.account.as_deref(), Some("desk-7"));
+    assert_eq!(record.status, OrderStatus::New);
+
+    let page = PageQuery {
+        limit: Some(2),
+        cursor: None,
+    };
+    let first = client.bids(Some("BTC-USD"), &page).await.unwrap();
+    assert_eq!(first.data.len(), 2);
+    let rest = PageQuery {
+        cursor: first.next_cursor.clone(),
+        ..page
+    };
+    let second = client.bids(Some("BTC-USD"), &rest).await.unwrap();
+    assert_eq!(second.data[0].id, ids[2]);
+    assert_eq!(second.next_cursor, None);
+
+    let items = client
+        .batch(&[
+            Operation::Cancel { id: ids[0] },
+            Operation::Cancel { id: 999 },
+        ])
+        .await
+        .unwrap();
+    assert_eq!(items[0].status, 200);
+    assert!(matches!(&items[0].outcome, Some(Outcome::Cancelled(order)) if order.id == ids[0]));
+    assert_eq!(items[1].status, 404);
+    assert_eq!(items[1].error.as_ref().unwrap().code, "order_not_found");
+    let err = client.cancel_order(999).await.unwrap_err();
+    assert_eq!(err.code(), Some("order_not_found"));
+
+    // The cancel reaches the hub's depth a moment after the reply.
+    let mut book = LocalBook::default();
+    for _ in 0..50 {
+        let snapshot = client.depth("BTC-USD", 10).await.unwrap();
+        assert_eq!(snapshot.channel, "depth:BTC-USD:10");
+        book = LocalBook::from_snapshot(&snapshot);
+        if bid_prices(&book) == [99.0, 98.0] {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(bid_prices(&book), [99.0, 98.0]);
+    assert_eq!(book.spread(), Some(2.0));
+    let err = client.depth("DOGE-USD", 10).await.unwrap_err();
+    assert_eq!(err.code(), Some("unknown_symbol"));
+}
+
+#[actix_web::test]
+async fn test_ws_session_keeps_a_book_across_reconnects() {
+    let (engine, hub) = app_engine();
+    let (addr, handle) = serve(engine.clone(), hub.clone(), Vec::new(), "127.0.0.1:0");
+    let rest = RestClient::new(&format!("http://{}", addr)).with_client_id("desk-7");
+    for price in [100.0, 99.0, 98.0] {
+        rest.place_order(&order(price, BidOrAsk::Bid), None)
+            .await
+            .unwrap();
+    }
+    let mut session = WsClient::new(&format!("ws://{}/ws/", addr))
+        .with_client_id("desk-7")
+        .with_rest(rest)
+        .with_backoff(Duration::from_millis(50), Duration::from_millis(200))
+        .connect()
+        .await
+        .unwrap();
+
+    session
+        .subscribe(["depth:BTC-USD:1".parse().unwrap()])
+        .await
+        .unwrap();
+    let placed = session.place(order(101.0, BidOrAsk::Ask)).await.unwrap();
+    assert_eq!(placed.order.account.as_deref(), Some("desk-7"));
+    // The first update only carries the best level; the snapshot fills in
+    // the rest.
+    wait_for(&mut session, |event| {
+        matches!(event, Event::Resynced { .. })
+    })
+    .await;
+    let book = session.book("BTC-USD").unwrap();
+    assert_eq!(bid_prices(&book), [100.0, 99.0, 98.0]);
+    assert_eq!(book.best_ask().unwrap().price, Price::new(101.0));
+
+    handle.stop(false).await;
+    wait_for(&mut session, |event| {
+        matches!(event, Event::Disconnected { .. })
+    })
+    .await;
+    match session.place(order(100.5, BidOrAsk::Bid)).await {
+        Err(Error::Disconnected) => {}
+        other => panic!("placed while disconnected: {:?}", other),
+    }
+
+    let (_, _handle) = serve(engine, hub, Vec::new(), &addr.to_string());
+    wait_for(&mut session, |event| matches!(event, Event::Reconnected)).await;
+    session.place(order(100.5, BidOrAsk::Bid)).await.unwrap();
+    // The depth subscription was renewed, so the new best bid arrives.
+    wait_for(&mut session, |event| match event {
+        Event::Market {
+            event: MarketEvent::Depth { bids, .. },
+            ..
+        } => bids[0].price == Price::new(100.5),
+        _ => false,
+    })
+    .await;
+    let book = session.book("BTC-USD").unwrap();
+    assert_eq!(bid_prices(&book), [100.5, 100.0, 99.0, 98.0]);
+    assert!(session.ping().await.unwrap() < Duration::from_secs(1));
+}
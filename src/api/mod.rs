@@ -1,64 +1,1487 @@
-use crate::models::{MatchedOrder, Order};
-use crate::order_book::OrderBook;
+use crate::auth::{Accounts, ApiKeys, AuthError, Identity, Permission, SignedRequest};
+use crate::config::Features;
+use crate::engine::{
+    BookQuery, CommandLatency, EngineError, EngineHandle, FillQuery, Operation, OrderQuery,
+    Outcome, TradeQuery, DEFAULT_PAGE_LIMIT,
+};
+use crate::error::{Error, ErrorBody};
+use crate::market_data::{DepthSnapshot, GetSnapshot, Hub, DEFAULT_DEPTH_LEVELS};
+use crate::metrics::Metrics;
+use crate::models::{
+    BidOrAsk, Execution, FillRecord, MatchedOrder, Order, OrderRecord, OrderStatus, Price,
+    TradeRecord,
+};
+use crate::order_book::{BookError, BookState, CancelFilter, Uncross, PUBLISHED_DEPTH};
+use crate::persistence::Store;
+use crate::rate_limit::RateLimits;
 use crate::websocket::MyWebSocket;
-use actix_web::{web, HttpRequest, HttpResponse};
+use actix::Addr;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::{DefaultHeaders, Next};
+use actix_web::ResponseError;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
-use std::sync::mpsc::Receiver;
-use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
-
-pub fn config(cfg: &mut web::ServiceConfig, rx: Arc<Mutex<Receiver<MatchedOrder>>>) {
-    cfg.service(web::resource("/ws/").route(web::get().to(
-        move |r: HttpRequest, stream: web::Payload| {
-            let rx_clone = rx.clone(); // Clone the Arc for each request
-            async move { ws::start(MyWebSocket::new(rx_clone), &r, stream) }
-        },
-    )));
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+
+mod openapi;
+
+pub use openapi::ApiDoc;
+
+pub fn config(cfg: &mut web::ServiceConfig, hub: Addr<Hub>) {
+    configure(cfg, hub, Features::default());
+}
+
+/// Registers the routes, leaving out the parts switched off in `features`.
+/// The REST API lives under `/api/v1`; health, metrics, the API
+/// documentation and `/ws/` stay at the root. Every error, including
+/// malformed requests and unknown routes, is answered with a JSON
+/// `ErrorBody`.
+pub fn configure(cfg: &mut web::ServiceConfig, hub: Addr<Hub>, features: Features) {
+    cfg.app_data(
+        web::JsonConfig::default()
+            .error_handler(|err, _| Error::validation("invalid_json", err).into()),
+    );
+    cfg.app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| Error::validation("invalid_query", err).into()),
+    );
+    cfg.app_data(
+        web::PathConfig::default()
+            .error_handler(|err, _| Error::validation("invalid_path", err).into()),
+    );
+    cfg.app_data(web::Data::new(hub.clone()));
+    cfg.default_service(web::to(|| async {
+        Err::<HttpResponse, _>(Error::NotFound {
+            code: "route_not_found",
+            message: "no such route".to_string(),
+        })
+    }));
+    if features.websocket {
+        cfg.service(web::resource("/ws/").route(web::get().to(
+            move |r: HttpRequest,
+                  stream: web::Payload,
+                  query: web::Query<SessionQuery>,
+                  metrics: Option<web::Data<Metrics>>,
+                  engine: Option<web::Data<EngineHandle>>,
+                  accounts: Option<web::Data<Accounts>>,
+                  api_keys: Option<web::Data<ApiKeys>>,
+                  rate_limits: Option<web::Data<RateLimits>>| {
+                let hub = hub.clone();
+                let services = SessionServices {
+                    metrics,
+                    engine,
+                    accounts,
+                    api_keys,
+                    rate_limits,
+                };
+                async move { start_session(r, stream, hub, query.into_inner(), services) }
+            },
+        )));
+    }
     cfg.service(web::resource("/healthcheck").route(web::get().to(health_check)));
+    cfg.service(web::resource("/openapi.json").route(web::get().to(openapi::openapi_json)));
+    cfg.service(web::resource("/docs").route(web::get().to(openapi::swagger_ui)));
+    if features.metrics {
+        cfg.service(web::resource("/metrics").route(web::get().to(get_metrics)));
+    }
+    cfg.service(web::scope("/api/v1").configure(|cfg| v1_routes(cfg, features)));
+    legacy_routes(cfg, features);
+}
+
+fn v1_routes(cfg: &mut web::ServiceConfig, features: Features) {
+    cfg.service(
+        web::resource("/orders")
+            .route(web::post().to(create_order))
+            .route(web::get().to(list_orders))
+            .route(web::delete().to(cancel_orders)),
+    );
+    // Registered before `/orders/{id}`, which would otherwise claim the paths.
+    cfg.service(web::resource("/orders/batch").route(web::post().to(batch_orders)));
+    cfg.service(web::resource("/orders/cancel-after").route(web::post().to(cancel_after)));
+    cfg.service(
+        web::resource("/orders/{id}")
+            .route(web::get().to(get_order))
+            .route(web::delete().to(cancel_order))
+            .route(web::patch().to(amend_order)),
+    );
+    cfg.service(web::resource("/fills").route(web::get().to(list_fills)));
+    cfg.service(web::resource("/trades").route(web::get().to(list_trades)));
+    cfg.service(web::resource("/asks").route(web::get().to(list_asks)));
+    cfg.service(web::resource("/bids").route(web::get().to(list_bids)));
+    cfg.service(web::resource("/depth").route(web::get().to(get_depth)));
+    cfg.service(web::resource("/status").route(web::get().to(get_status)));
+    if features.admin {
+        cfg.service(web::resource("/admin/halt").route(web::post().to(halt_trading)));
+        cfg.service(web::resource("/admin/resume").route(web::post().to(resume_trading)));
+        cfg.service(web::resource("/admin/pre-open").route(web::post().to(open_call)));
+        cfg.service(web::resource("/admin/uncross").route(web::post().to(uncross)));
+        cfg.service(
+            web::resource("/admin/latency")
+                .route(web::get().to(get_latencies))
+                .route(web::delete().to(reset_latencies)),
+        );
+    }
+    cfg.service(web::resource("/auction").route(web::get().to(get_indicative)));
+}
+
+/// The unversioned routes from before `/api/v1`, kept for existing clients.
+/// Their responses carry `Deprecation: true`, their lists are not paged
+/// with cursors, and they are left out of the OpenAPI document.
+fn legacy_routes(cfg: &mut web::ServiceConfig, features: Features) {
+    let deprecated = || DefaultHeaders::new().add(("Deprecation", "true"));
     cfg.service(
         web::resource("/orders")
+            .wrap(deprecated())
             .route(web::post().to(create_order))
-            .route(web::get().to(get_orders)),
+            .route(web::get().to(get_orders))
+            .route(web::delete().to(cancel_orders)),
+    );
+    cfg.service(
+        web::resource("/orders/batch")
+            .wrap(deprecated())
+            .route(web::post().to(batch_orders)),
+    );
+    cfg.service(
+        web::resource("/orders/cancel-after")
+            .wrap(deprecated())
+            .route(web::post().to(cancel_after)),
+    );
+    cfg.service(
+        web::resource("/orders/{id}")
+            .wrap(deprecated())
+            .route(web::get().to(get_order))
+            .route(web::delete().to(cancel_order))
+            .route(web::patch().to(amend_order)),
+    );
+    cfg.service(
+        web::resource("/fills")
+            .wrap(deprecated())
+            .route(web::get().to(get_fills)),
+    );
+    cfg.service(
+        web::resource("/asks")
+            .wrap(deprecated())
+            .route(web::get().to(get_all_asks)),
+    );
+    cfg.service(
+        web::resource("/bids")
+            .wrap(deprecated())
+            .route(web::get().to(get_all_bids)),
+    );
+    cfg.service(
+        web::resource("/status")
+            .wrap(deprecated())
+            .route(web::get().to(get_status)),
+    );
+    if features.admin {
+        cfg.service(
+            web::resource("/admin/halt")
+                .wrap(deprecated())
+                .route(web::post().to(halt_trading)),
+        );
+        cfg.service(
+            web::resource("/admin/resume")
+                .wrap(deprecated())
+                .route(web::post().to(resume_trading)),
+        );
+        cfg.service(
+            web::resource("/admin/pre-open")
+                .wrap(deprecated())
+                .route(web::post().to(open_call)),
+        );
+        cfg.service(
+            web::resource("/admin/uncross")
+                .wrap(deprecated())
+                .route(web::post().to(uncross)),
+        );
+        cfg.service(
+            web::resource("/admin/latency")
+                .wrap(deprecated())
+                .route(web::get().to(get_latencies))
+                .route(web::delete().to(reset_latencies)),
+        );
+    }
+    cfg.service(
+        web::resource("/auction")
+            .wrap(deprecated())
+            .route(web::get().to(get_indicative)),
+    );
+}
+
+fn json_response<T: Serialize>(result: Result<T, EngineError>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    #[serde(default)]
+    cancel_on_disconnect: bool,
+}
+
+/// What a WebSocket session uses from the app, where configured.
+struct SessionServices {
+    metrics: Option<web::Data<Metrics>>,
+    engine: Option<web::Data<EngineHandle>>,
+    accounts: Option<web::Data<Accounts>>,
+    api_keys: Option<web::Data<ApiKeys>>,
+    rate_limits: Option<web::Data<RateLimits>>,
+}
+
+/// Opens a market data and order entry session. With `?cancel_on_disconnect=true` every
+/// order of the caller's account is cancelled when the session ends, which
+/// needs the account to be known. An `Authorization: Bearer` token
+/// authenticates the session up front; it must match `X-Client-Id` if both
+/// are sent. Once API keys are configured, `X-Client-Id` alone no longer
+/// names the session's account, and once account tokens are, it is not
+/// enough for cancel on disconnect. With either, only authenticated
+/// sessions place, cancel and amend orders, and only their own.
+fn start_session(
+    req: HttpRequest,
+    stream: web::Payload,
+    hub: Addr<Hub>,
+    query: SessionQuery,
+    services: SessionServices,
+) -> Result<HttpResponse, actix_web::Error> {
+    let accounts = services.accounts.map(|accounts| accounts.into_inner());
+    let signed = services.api_keys.is_some_and(|keys| !keys.is_empty());
+    let tokens = accounts
+        .as_ref()
+        .is_some_and(|accounts| !accounts.is_empty());
+    let mut proven = false;
+    let mut account = Some(client_id(&req)).filter(|account| !account.is_empty() && !signed);
+    let engine = services.engine.map(|engine| engine.get_ref().clone());
+    let mut session = MyWebSocket::new(hub)
+        .with_metrics(services.metrics.map(|metrics| metrics.get_ref().clone()))
+        .with_engine(engine.clone())
+        .with_accounts(accounts.clone())
+        .with_rate_limits(
+            services.rate_limits.map(|limits| limits.into_inner()),
+            req.peer_addr().map(|peer| peer.ip()),
+        );
+    match bearer_token(&req) {
+        Some(token) => {
+            let authenticated = accounts
+                .as_ref()
+                .and_then(|accounts| accounts.authenticate(token));
+            match authenticated {
+                Some(owner) if account.as_deref().is_none_or(|account| account == owner) => {
+                    account = Some(owner.to_string());
+                    proven = true;
+                    session = session.with_authenticated_account(owner.to_string());
+                }
+                _ => {
+                    return Err(Error::Unauthorized {
+                        code: "invalid_token",
+                        message: "invalid token".to_string(),
+                    }
+                    .into())
+                }
+            }
+        }
+        None => session = session.with_account(account.clone()),
+    }
+    if signed || tokens {
+        session = session.require_authentication();
+    }
+    if query.cancel_on_disconnect {
+        // Otherwise anyone could name someone else's account and have its
+        // orders cancelled when they hang up.
+        if tokens && !proven {
+            return Err(Error::validation(
+                "account_required",
+                "cancel_on_disconnect needs an Authorization: Bearer token",
+            )
+            .into());
+        }
+        match (engine, &account) {
+            (Some(_), Some(_)) => session = session.cancel_on_disconnect(),
+            (_, None) => {
+                return Err(Error::validation(
+                    "account_required",
+                    "cancel_on_disconnect needs an X-Client-Id header",
+                )
+                .into())
+            }
+            (None, Some(_)) => return Err(Error::from(EngineError::Unavailable).into()),
+        }
+    }
+    tracing::info!(
+        account = account.as_deref(),
+        cancel_on_disconnect = query.cancel_on_disconnect,
+        "websocket session opened"
     );
-    cfg.service(web::resource("/asks").route(web::get().to(get_all_asks)));
-    cfg.service(web::resource("/bids").route(web::get().to(get_all_bids)));
+    ws::start(session, &req, stream)
 }
 
+#[utoipa::path(
+    get,
+    path = "/healthcheck",
+    tag = "system",
+    responses(
+        (status = 200, description = "The server is up", body = String, content_type = "text/plain")
+    ),
+    security(())
+)]
 async fn health_check() -> HttpResponse {
     HttpResponse::Ok().body("Server is up and running!")
 }
 
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain")
+    )
+)]
+async fn get_metrics(engine: web::Data<EngineHandle>, metrics: web::Data<Metrics>) -> HttpResponse {
+    metrics.engine_queue_depth.set(engine.queue_depth() as i64);
+    // Everything else is still worth scraping if the engine is down.
+    if let Ok(books) = engine.depth().await {
+        for (symbol, depth) in &books {
+            metrics.set_depth(symbol, depth);
+        }
+    }
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render())
+}
+
+/// Identifies the caller: the account of its API key when the request was
+/// signed, otherwise the optional `X-Client-Id` header. Orders are filed
+/// under it as their account.
+fn client_id(req: &HttpRequest) -> String {
+    match req.extensions().get::<Identity>() {
+        Some(identity) => identity.account.clone(),
+        None => header(req.headers(), "X-Client-Id")
+            .unwrap_or("")
+            .to_string(),
+    }
+}
+
+/// The account a signed request is confined to: its own, unless its key
+/// has the admin permission. `None` for unsigned requests and admins.
+fn own_account(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<Identity>()
+        .filter(|identity| !identity.can(Permission::Admin))
+        .map(|identity| identity.account.clone())
+}
+
+fn header<'a>(headers: &'a actix_web::http::header::HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// What a route needs of an API key: `/admin/*` needs admin, other reads
+/// read and everything else trade, with or without the `/api/v1` prefix.
+/// `None` for the routes that are always open, such as the API
+/// documentation and `/metrics`, which Prometheus scrapes unsigned, and for
+/// `/ws/`, where sessions authenticate with tokens.
+fn required_permission(method: &Method, path: &str) -> Option<Permission> {
+    if let Some(route) = path
+        .strip_prefix("/api/v1")
+        .filter(|route| route.starts_with('/'))
+    {
+        return match route {
+            _ if route.starts_with("/admin/") => Some(Permission::Admin),
+            _ if method == Method::GET || method == Method::HEAD => Some(Permission::Read),
+            _ => Some(Permission::Trade),
+        };
+    }
+    match path {
+        "/healthcheck" | "/metrics" | "/openapi.json" | "/docs" | "/ws/" => None,
+        _ if path.starts_with("/admin/") => Some(Permission::Admin),
+        _ if method == Method::GET || method == Method::HEAD => Some(Permission::Read),
+        _ => Some(Permission::Trade),
+    }
+}
+
+/// Middleware checking that requests are signed with an API key that has
+/// the route's permission, and recording the caller's `Identity` in the
+/// request's extensions. Does nothing unless `ApiKeys` holding at least one
+/// key are registered as app data.
+pub async fn authenticate(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let keys = req
+        .app_data::<web::Data<ApiKeys>>()
+        .filter(|keys| !keys.is_empty())
+        .cloned();
+    let (Some(keys), Some(permission)) = (keys, required_permission(req.method(), req.path()))
+    else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    // The body is signed too, so it is read here and handed back for the
+    // route's own extractors.
+    let body = req.extract::<web::Bytes>().await?;
+    let verified = verify_signature(&req, &keys, &body).and_then(|identity| {
+        if identity.can(permission) {
+            Ok(identity)
+        } else {
+            Err(AuthError::Forbidden(permission))
+        }
+    });
+    req.set_payload(Payload::from(body));
+    match verified {
+        Ok(identity) => {
+            req.extensions_mut().insert(identity);
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        Err(err) => {
+            tracing::info!(error = %err, path = req.path(), "request refused");
+            Ok(req.error_response(Error::from(err)).map_into_right_body())
+        }
+    }
+}
+
+fn verify_signature(
+    req: &ServiceRequest,
+    keys: &ApiKeys,
+    body: &[u8],
+) -> Result<Identity, AuthError> {
+    let headers = req.headers();
+    let signed = (|| {
+        let request = SignedRequest {
+            key: header(headers, "X-Api-Key")?,
+            timestamp: header(headers, "X-Api-Timestamp")?.parse().ok()?,
+            nonce: header(headers, "X-Api-Nonce")?,
+            method: req.method().as_str(),
+            path: req.uri().path_and_query()?.as_str(),
+            body,
+        };
+        Some((request, header(headers, "X-Api-Signature")?))
+    })();
+    let (request, signature) = signed.ok_or(AuthError::MissingSignature)?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    keys.verify(&request, signature, now)
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    header(
+        req.headers(),
+        actix_web::http::header::AUTHORIZATION.as_str(),
+    )
+    .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// The optional `Idempotency-Key` header, which lets a client safely retry
+/// an order submission.
+fn idempotency_key(req: &HttpRequest) -> Option<String> {
+    header(req.headers(), "Idempotency-Key").map(str::to_string)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/orders",
+    tag = "orders",
+    request_body = Order,
+    params(
+        ("X-Client-Id" = Option<String>, Header, description = "The caller's account when requests are not signed"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes retries return the original execution")
+    ),
+    responses(
+        (status = 200, description = "The order as it stands after matching, and its fills", body = Execution),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 409, description = "Client order id or idempotency key already used", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody),
+        (status = 503, description = "Trading halted or engine unavailable", body = ErrorBody)
+    )
+)]
 async fn create_order(
+    req: HttpRequest,
     order: web::Json<Order>,
-    order_book: web::Data<Arc<Mutex<OrderBook>>>,
-) -> HttpResponse {
-    let order = order.into_inner();
-    let mut order_book = order_book.lock().unwrap(); // Lock the shared OrderBook
-    let order_book = order_book.add_order(
-        order,
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let mut order = order.into_inner();
+    order.id = engine.next_order_id();
+    let client_id = client_id(&req);
+    order.account = Some(client_id.clone()).filter(|account| !account.is_empty());
+    let idempotency_key = idempotency_key(&req);
+    // Everything logged for this order, on this task or on the engine
+    // thread, is recorded inside this span.
+    let span = tracing::info_span!(
+        "order",
+        order_id = order.id,
+        client_id = client_id.as_str(),
+        client_order_id = order.client_order_id.as_deref(),
+        symbol = %order.trading_pair,
+    );
+    async move {
+        tracing::info!(
+            side = ?order.bid_or_ask,
+            order_type = ?order.order_type,
+            amount = order.amount,
+            "order received"
+        );
+        match engine.add_order(order, idempotency_key).await {
+            Ok(execution) => {
+                tracing::info!(
+                    fills = execution.fills.len(),
+                    remaining = execution.order.amount,
+                    "order executed"
+                );
+                Ok(HttpResponse::Ok().json(execution))
+            }
+            Err(err) => {
+                tracing::info!(error = %err, "order failed");
+                Err(err.into())
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// The most operations a single batch may carry.
+const MAX_BATCH_OPERATIONS: usize = 500;
+
+/// The result of one batch operation, with the status code it would have
+/// had as a request of its own.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BatchItem {
+    pub status: u16,
+    #[serde(flatten)]
+    pub outcome: Option<Outcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// Applies up to 500 operations in one go. Cancels and amends of other
+/// accounts' orders are not found for API keys without the admin
+/// permission.
+#[utoipa::path(
+    post,
+    path = "/api/v1/orders/batch",
+    tag = "orders",
+    request_body = Vec<Operation>,
+    params(
+        ("X-Client-Id" = Option<String>, Header, description = "The caller's account when requests are not signed")
+    ),
+    responses(
+        (status = 200, description = "One result per operation, in order", body = Vec<BatchItem>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody),
+        (status = 503, description = "Trading halted or engine unavailable", body = ErrorBody)
+    )
+)]
+async fn batch_orders(
+    req: HttpRequest,
+    operations: web::Json<Vec<Operation>>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let mut operations = operations.into_inner();
+    if operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(Error::validation(
+            "batch_too_large",
+            format!(
+                "a batch may hold at most {} operations",
+                MAX_BATCH_OPERATIONS
+            ),
+        ));
+    }
+    let client_id = client_id(&req);
+    for operation in &mut operations {
+        if let Operation::New { order } = operation {
+            order.id = engine.next_order_id();
+            order.account = Some(client_id.clone()).filter(|account| !account.is_empty());
+        }
+    }
+    let span = tracing::info_span!(
+        "batch",
+        client_id = client_id.as_str(),
+        operations = operations.len()
     );
-    println!("{:?}", order_book);
-    HttpResponse::Ok().body(format!("{:?}", order_book))
+    let results = engine
+        .batch(operations, own_account(&req))
+        .instrument(span)
+        .await?;
+    let items: Vec<BatchItem> = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(outcome) => BatchItem {
+                status: StatusCode::OK.as_u16(),
+                outcome: Some(outcome),
+                error: None,
+            },
+            Err(err) => {
+                let err = Error::from(err);
+                BatchItem {
+                    status: err.status_code().as_u16(),
+                    outcome: None,
+                    error: Some(err.body()),
+                }
+            }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(items))
+}
+
+/// Cancels every order matching the `symbol`, `side` and `account` query
+/// parameters; any of them left out matches all orders. API keys without
+/// the admin permission only ever cancel their own account's orders.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/orders",
+    tag = "orders",
+    params(
+        CancelFilter
+    ),
+    responses(
+        (status = 200, description = "The cancelled orders", body = Vec<Order>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn cancel_orders(
+    req: HttpRequest,
+    filter: web::Query<CancelFilter>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let mut filter = filter.into_inner();
+    if let Some(account) = own_account(&req) {
+        filter.account = Some(account);
+    }
+    let span = tracing::info_span!(
+        "mass_cancel",
+        client_id = client_id(&req).as_str(),
+        symbol = filter.symbol.as_deref(),
+        side = ?filter.side,
+        account = filter.account.as_deref(),
+    );
+    json_response(engine.cancel_all(filter).instrument(span).await)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CancelAfterRequest {
+    /// Zero disarms the switch.
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CancelAfterResponse {
+    pub account: String,
+    /// When the account's orders will be cancelled, in Unix milliseconds.
+    pub cancel_at: Option<u64>,
+}
+
+/// A dead man's switch for REST clients: unless it is called again within
+/// `timeout_ms`, every order of the caller's account is cancelled. Once
+/// account tokens are configured, unsigned callers prove their account with
+/// an `Authorization: Bearer` token, as for cancel on disconnect.
+#[utoipa::path(
+    post,
+    path = "/api/v1/orders/cancel-after",
+    tag = "orders",
+    request_body = CancelAfterRequest,
+    params(
+        ("X-Client-Id" = Option<String>, Header, description = "The caller's account when requests are not signed"),
+        ("Authorization" = Option<String>, Header, description = "`Bearer` and the account's token, needed for unsigned requests once account tokens are configured")
+    ),
+    responses(
+        (status = 200, description = "When the account's orders will be cancelled", body = CancelAfterResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody),
+        (status = 503, description = "Trading halted or engine unavailable", body = ErrorBody)
+    )
+)]
+async fn cancel_after(
+    req: HttpRequest,
+    request: web::Json<CancelAfterRequest>,
+    engine: web::Data<EngineHandle>,
+    accounts: Option<web::Data<Accounts>>,
+) -> Result<HttpResponse, Error> {
+    let mut account = client_id(&req);
+    let signed = req.extensions().get::<Identity>().is_some();
+    let tokens = accounts
+        .as_ref()
+        .is_some_and(|accounts| !accounts.is_empty());
+    // Otherwise anyone could name someone else's account and arm a switch
+    // that cancels its orders.
+    if tokens && !signed {
+        let Some(token) = bearer_token(&req) else {
+            return Err(Error::validation(
+                "account_required",
+                "cancel-after needs an Authorization: Bearer token",
+            ));
+        };
+        match accounts
+            .as_ref()
+            .and_then(|accounts| accounts.authenticate(token))
+        {
+            Some(owner) if account.is_empty() || account == owner => account = owner.to_string(),
+            _ => {
+                return Err(Error::Unauthorized {
+                    code: "invalid_token",
+                    message: "invalid token".to_string(),
+                })
+            }
+        }
+    }
+    if account.is_empty() {
+        return Err(Error::validation(
+            "account_required",
+            "cancel-after needs an X-Client-Id header",
+        ));
+    }
+    let timeout = Some(Duration::from_millis(request.timeout_ms)).filter(|t| !t.is_zero());
+    let cancel_at = engine.cancel_after(account.clone(), timeout).await?;
+    Ok(HttpResponse::Ok().json(CancelAfterResponse { account, cancel_at }))
+}
+
+/// Every resting ask at once. Superseded by `/api/v1/asks`.
+async fn get_all_asks(engine: web::Data<EngineHandle>) -> Result<HttpResponse, Error> {
+    json_response(engine.get_all_asks().await)
+}
+
+/// Every resting bid at once. Superseded by `/api/v1/bids`.
+async fn get_all_bids(engine: web::Data<EngineHandle>) -> Result<HttpResponse, Error> {
+    json_response(engine.get_all_bids().await)
+}
+
+/// Most results a history query returns at once.
+const MAX_PAGE_LIMIT: usize = 1000;
+
+fn check_page_limit(limit: usize) -> Result<(), Error> {
+    if limit == 0 || limit > MAX_PAGE_LIMIT {
+        return Err(Error::validation(
+            "invalid_query",
+            format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+        ));
+    }
+    Ok(())
+}
+
+/// Paging for the `/api/v1` list endpoints. Results come in a fixed order
+/// per endpoint, so a cursor stays valid while new results arrive.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Results per page: 100 unless given, at most 1000.
+    pub limit: Option<usize>,
+    /// The `next_cursor` of the previous page. Cursors are opaque.
+    pub cursor: Option<String>,
+}
+
+impl PageQuery {
+    fn limit(&self) -> Result<usize, Error> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        check_page_limit(limit)?;
+        Ok(limit)
+    }
+
+    fn cursor<T>(&self, parse: impl FnOnce(&str) -> Option<T>) -> Result<Option<T>, Error> {
+        self.cursor
+            .as_deref()
+            .map(|cursor| {
+                parse(cursor).ok_or_else(|| Error::validation("invalid_query", "invalid cursor"))
+            })
+            .transpose()
+    }
+}
+
+/// One page of a list. `next_cursor` is absent on the last page.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` results, fetched to tell
+    /// whether there is another page.
+    fn new(mut data: Vec<T>, limit: usize, cursor: impl Fn(&T) -> String) -> Self {
+        let next_cursor = if data.len() > limit {
+            data.truncate(limit);
+            data.last().map(cursor)
+        } else {
+            None
+        };
+        Page { data, next_cursor }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OrderFilter {
+    /// Working orders unless given.
+    pub status: Option<OrderStatus>,
+    pub symbol: Option<String>,
+    pub side: Option<BidOrAsk>,
+    pub account: Option<String>,
+}
+
+/// Lists working orders, or with `?status=` orders in that status, by
+/// ascending id. API keys without the admin permission only see their own
+/// account's orders.
+#[utoipa::path(
+    get,
+    path = "/api/v1/orders",
+    tag = "orders",
+    params(
+        OrderFilter,
+        PageQuery
+    ),
+    responses(
+        (status = 200, description = "A page of matching orders by ascending id", body = Page<OrderRecord>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn list_orders(
+    req: HttpRequest,
+    filter: web::Query<OrderFilter>,
+    page: web::Query<PageQuery>,
+    engine: web::Data<EngineHandle>,
+    store: Option<web::Data<Store>>,
+) -> Result<HttpResponse, Error> {
+    let filter = filter.into_inner();
+    let limit = page.limit()?;
+    let query = OrderQuery {
+        status: filter.status,
+        symbol: filter.symbol,
+        side: filter.side,
+        account: own_account(&req).or(filter.account),
+        after: page.cursor(|cursor| cursor.parse().ok())?,
+        unwritten: false,
+        offset: 0,
+        limit: limit + 1,
+    };
+    let orders = query_orders(&engine, store, query).await?;
+    Ok(HttpResponse::Ok().json(Page::new(orders, limit, |record| {
+        record.order.id.to_string()
+    })))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FillFilter {
+    pub symbol: Option<String>,
+    pub side: Option<BidOrAsk>,
+    pub account: Option<String>,
+    pub order_id: Option<u64>,
+}
+
+/// Lists fills, oldest first. API keys without the admin permission only
+/// see their own account's fills.
+#[utoipa::path(
+    get,
+    path = "/api/v1/fills",
+    tag = "orders",
+    params(
+        FillFilter,
+        PageQuery
+    ),
+    responses(
+        (status = 200, description = "A page of matching fills, oldest first", body = Page<FillRecord>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn list_fills(
+    req: HttpRequest,
+    filter: web::Query<FillFilter>,
+    page: web::Query<PageQuery>,
+    engine: web::Data<EngineHandle>,
+    store: Option<web::Data<Store>>,
+) -> Result<HttpResponse, Error> {
+    let filter = filter.into_inner();
+    let limit = page.limit()?;
+    let query = FillQuery {
+        account: own_account(&req).or(filter.account),
+        symbol: filter.symbol,
+        side: filter.side,
+        order_id: filter.order_id,
+        after: page.cursor(|cursor| {
+            let (trade_id, order_id) = cursor.split_once('-')?;
+            Some((trade_id.parse().ok()?, order_id.parse().ok()?))
+        })?,
+        unwritten: false,
+        offset: 0,
+        limit: limit + 1,
+    };
+    let fills = query_fills(&engine, store, query).await?;
+    Ok(HttpResponse::Ok().json(Page::new(fills, limit, |record| {
+        format!("{}-{}", record.fill.trade_id, record.order_id)
+    })))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TradeFilter {
+    pub symbol: Option<String>,
+    /// The side that took liquidity. Auction trades have none.
+    pub side: Option<BidOrAsk>,
+}
+
+/// The public trade tape, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/trades",
+    tag = "market",
+    params(
+        TradeFilter,
+        PageQuery
+    ),
+    responses(
+        (status = 200, description = "A page of trades, oldest first", body = Page<TradeRecord>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn list_trades(
+    filter: web::Query<TradeFilter>,
+    page: web::Query<PageQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let filter = filter.into_inner();
+    let limit = page.limit()?;
+    let query = TradeQuery {
+        symbol: filter.symbol,
+        side: filter.side,
+        after: page.cursor(|cursor| cursor.parse().ok())?,
+        limit: limit + 1,
+    };
+    let trades = engine.query_trades(query).await?;
+    Ok(HttpResponse::Ok().json(Page::new(trades, limit, |trade| trade.trade_id.to_string())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/asks",
+    tag = "market",
+    params(
+        SymbolQuery,
+        PageQuery
+    ),
+    responses(
+        (status = 200, description = "A page of resting asks, best price first", body = Page<Order>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn list_asks(
+    query: web::Query<SymbolQuery>,
+    page: web::Query<PageQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    book_page(BidOrAsk::Ask, query.into_inner(), &page, &engine).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/bids",
+    tag = "market",
+    params(
+        SymbolQuery,
+        PageQuery
+    ),
+    responses(
+        (status = 200, description = "A page of resting bids, best price first", body = Page<Order>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn list_bids(
+    query: web::Query<SymbolQuery>,
+    page: web::Query<PageQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    book_page(BidOrAsk::Bid, query.into_inner(), &page, &engine).await
+}
+
+/// Picks the book for `GET /api/v1/depth`, and the `depth` channel whose
+/// sequence number comes with it.
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DepthQuery {
+    pub symbol: String,
+    /// The levels of the `depth` channel: 10 unless given, at most 50.
+    pub levels: Option<usize>,
+}
+
+/// Up to 50 levels of each side of a book, taken in one piece with the
+/// sequence number the `depth` WebSocket channel of `levels` levels had
+/// reached, for building a local book that channel keeps up to date.
+#[utoipa::path(
+    get,
+    path = "/api/v1/depth",
+    tag = "market",
+    params(
+        DepthQuery
+    ),
+    responses(
+        (status = 200, description = "The levels of each side, best first", body = DepthSnapshot),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn get_depth(
+    query: web::Query<DepthQuery>,
+    engine: web::Data<EngineHandle>,
+    hub: web::Data<Addr<Hub>>,
+) -> Result<HttpResponse, Error> {
+    let DepthQuery { symbol, levels } = query.into_inner();
+    let levels = levels.unwrap_or(DEFAULT_DEPTH_LEVELS);
+    if !(1..=PUBLISHED_DEPTH).contains(&levels) {
+        return Err(Error::validation(
+            "invalid_query",
+            format!("levels must be 1 to {}", PUBLISHED_DEPTH),
+        ));
+    }
+    // Only books the engine knows have a depth to give.
+    engine.state(Some(symbol.clone())).await?;
+    let snapshot = hub
+        .send(GetSnapshot { symbol, levels })
+        .await
+        .map_err(|_| Error::Internal {
+            code: "unavailable",
+            message: "market data is unavailable right now".to_string(),
+        })?;
+    Ok(HttpResponse::Ok().json(snapshot))
+}
+
+/// Pages through one side of a book in priority order. The cursor holds
+/// the last order's price, as units and scalar, and id.
+async fn book_page(
+    side: BidOrAsk,
+    query: SymbolQuery,
+    page: &PageQuery,
+    engine: &EngineHandle,
+) -> Result<HttpResponse, Error> {
+    let limit = page.limit()?;
+    let after = page.cursor(|cursor| {
+        let mut parts = cursor.splitn(3, '-').map(|part| part.parse::<u64>().ok());
+        let (units, scalar, id) = (parts.next()??, parts.next()??, parts.next()??);
+        (scalar > 0).then(|| (Price::from_units(units, scalar), id))
+    })?;
+    let orders = engine
+        .book_page(BookQuery {
+            symbol: query.symbol,
+            side,
+            after,
+            limit: limit + 1,
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(Page::new(orders, limit, |order| {
+        let price = order.price.expect("resting orders have a price");
+        format!("{}-{}-{}", price.units(), price.scalar(), order.id)
+    })))
+}
+
+/// Lists working orders, or with `?status=` orders in that status, filtered
+/// by `symbol` and `account` and paged with `offset` and `limit`. API keys
+/// without the admin permission only see their own account's orders.
+/// Superseded by `/api/v1/orders`.
+async fn get_orders(
+    req: HttpRequest,
+    query: web::Query<OrderQuery>,
+    engine: web::Data<EngineHandle>,
+    store: Option<web::Data<Store>>,
+) -> Result<HttpResponse, Error> {
+    let mut query = query.into_inner();
+    check_page_limit(query.limit)?;
+    if let Some(account) = own_account(&req) {
+        query.account = Some(account);
+    }
+    let orders = query_orders(&engine, store, query).await?;
+    Ok(HttpResponse::Ok().json(orders))
+}
+
+/// An order's status, filled quantity and average price. Orders of other
+/// accounts are not found for API keys without the admin permission.
+#[utoipa::path(
+    get,
+    path = "/api/v1/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = u64, Path, description = "Order id")
+    ),
+    responses(
+        (status = 200, description = "The order's status and fills so far", body = OrderRecord),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 404, description = "No such order", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn get_order(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    engine: web::Data<EngineHandle>,
+    store: Option<web::Data<Store>>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    // Final orders the engine no longer keeps are looked up in the history
+    // database.
+    let record = match (engine.get_order(id).await, store) {
+        (Err(err @ EngineError::Book(BookError::OrderNotFound(_))), Some(store)) => {
+            store.get_order(id).await?.ok_or(err)?
+        }
+        (record, _) => record?,
+    };
+    if let Some(account) = own_account(&req) {
+        if record.order.account.as_ref() != Some(&account) {
+            return Err(EngineError::Book(BookError::OrderNotFound(id)).into());
+        }
+    }
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Lists fills, oldest first, filtered by `account`, `symbol` and
+/// `order_id` and paged with `offset` and `limit`. API keys without the
+/// admin permission only see their own account's fills. Superseded by
+/// `/api/v1/fills`.
+async fn get_fills(
+    req: HttpRequest,
+    query: web::Query<FillQuery>,
+    engine: web::Data<EngineHandle>,
+    store: Option<web::Data<Store>>,
+) -> Result<HttpResponse, Error> {
+    let mut query = query.into_inner();
+    check_page_limit(query.limit)?;
+    if let Some(account) = own_account(&req) {
+        query.account = Some(account);
+    }
+    let fills = query_fills(&engine, store, query).await?;
+    Ok(HttpResponse::Ok().json(fills))
+}
+
+/// Orders in a final status come from the history database when there is
+/// one, since the engine only keeps the latest, together with those the
+/// engine has yet to have written; working orders always come from the
+/// engine.
+async fn query_orders(
+    engine: &EngineHandle,
+    store: Option<web::Data<Store>>,
+    query: OrderQuery,
+) -> Result<Vec<OrderRecord>, Error> {
+    let Some(store) = store.filter(|_| query.status.is_some_and(|status| status.is_final())) else {
+        return Ok(engine.query_orders(query).await?);
+    };
+    let (offset, limit) = (query.offset, query.limit);
+    let query = OrderQuery {
+        offset: 0,
+        limit: offset.saturating_add(limit),
+        ..query
+    };
+    // The engine goes first, so a record written in between turns up in
+    // both answers rather than in neither.
+    let unwritten = engine
+        .query_orders(OrderQuery {
+            unwritten: true,
+            ..query.clone()
+        })
+        .await?;
+    let stored = store.query_orders(&query).await?;
+    Ok(merge(
+        stored,
+        unwritten,
+        |record| record.order.id,
+        offset,
+        limit,
+    ))
+}
+
+/// Fills come from the history database when there is one, together with
+/// those the engine has yet to have written.
+async fn query_fills(
+    engine: &EngineHandle,
+    store: Option<web::Data<Store>>,
+    query: FillQuery,
+) -> Result<Vec<FillRecord>, Error> {
+    let Some(store) = store else {
+        return Ok(engine.query_fills(query).await?);
+    };
+    let (offset, limit) = (query.offset, query.limit);
+    let query = FillQuery {
+        offset: 0,
+        limit: offset.saturating_add(limit),
+        ..query
+    };
+    let unwritten = engine
+        .query_fills(FillQuery {
+            unwritten: true,
+            ..query.clone()
+        })
+        .await?;
+    let stored = store.query_fills(&query).await?;
+    Ok(merge(
+        stored,
+        unwritten,
+        |record| (record.fill.trade_id, record.order_id),
+        offset,
+        limit,
+    ))
+}
+
+/// Pages through `stored` and `unwritten` records together, in key order.
+/// Where both hold a record, the engine's unwritten one is the newer.
+fn merge<T, K: Ord>(
+    stored: Vec<T>,
+    unwritten: Vec<T>,
+    key: impl Fn(&T) -> K,
+    offset: usize,
+    limit: usize,
+) -> Vec<T> {
+    let mut merged = BTreeMap::new();
+    for record in stored.into_iter().chain(unwritten) {
+        merged.insert(key(&record), record);
+    }
+    merged.into_values().skip(offset).take(limit).collect()
+}
+
+/// Cancels an order. Orders of other accounts are not found for API keys
+/// without the admin permission.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = u64, Path, description = "Order id")
+    ),
+    responses(
+        (status = 200, description = "The cancelled order", body = Order),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 404, description = "No such order", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody),
+        (status = 503, description = "Trading halted or engine unavailable", body = ErrorBody)
+    )
+)]
+async fn cancel_order(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let span = tracing::info_span!("order", order_id = id, client_id = client_id(&req).as_str());
+    json_response(
+        engine
+            .cancel_order(id, own_account(&req))
+            .instrument(span)
+            .await,
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AmendRequest {
+    pub amount: f64,
+    pub price: Option<Price>,
+}
+
+/// Changes an order's amount and price. Orders of other accounts are not
+/// found for API keys without the admin permission.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/orders/{id}",
+    tag = "orders",
+    request_body = AmendRequest,
+    params(
+        ("id" = u64, Path, description = "Order id")
+    ),
+    responses(
+        (status = 200, description = "The amended order and any fills", body = Execution),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 404, description = "No such order", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody),
+        (status = 503, description = "Trading halted or engine unavailable", body = ErrorBody)
+    )
+)]
+async fn amend_order(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    amend: web::Json<AmendRequest>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let amend = amend.into_inner();
+    let span = tracing::info_span!("order", order_id = id, client_id = client_id(&req).as_str());
+    json_response(
+        engine
+            .amend_order(id, amend.amount, amend.price, own_account(&req))
+            .instrument(span)
+            .await,
+    )
+}
+
+/// Picks the book for per-book endpoints. Optional while the server runs a
+/// single book.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SymbolQuery {
+    pub symbol: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/status",
+    tag = "market",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The book's trading status and phase", body = BookState),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn get_status(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.state(query.into_inner().symbol).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/halt",
+    tag = "admin",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The book's new state", body = BookState),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn halt_trading(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.halt(query.into_inner().symbol).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/resume",
+    tag = "admin",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The book's new state", body = BookState),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn resume_trading(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.resume(query.into_inner().symbol).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/pre-open",
+    tag = "admin",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The book's new state", body = BookState),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn open_call(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.open_call(query.into_inner().symbol).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/uncross",
+    tag = "admin",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The trades the uncross produced", body = Vec<MatchedOrder>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn uncross(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.uncross(query.into_inner().symbol).await)
 }
 
-async fn get_all_asks(order_book: web::Data<Arc<Mutex<OrderBook>>>) -> HttpResponse {
-    let order_book = order_book.lock().unwrap();
-    let order_book = order_book.get_all_asks();
-    HttpResponse::Ok().json(order_book)
+#[utoipa::path(
+    get,
+    path = "/api/v1/auction",
+    tag = "market",
+    params(
+        SymbolQuery
+    ),
+    responses(
+        (status = 200, description = "The indicative uncross during a call auction, otherwise null", body = Option<Uncross>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn get_indicative(
+    query: web::Query<SymbolQuery>,
+    engine: web::Data<EngineHandle>,
+) -> Result<HttpResponse, Error> {
+    json_response(engine.indicative(query.into_inner().symbol).await)
 }
 
-async fn get_all_bids(order_book: web::Data<Arc<Mutex<OrderBook>>>) -> HttpResponse {
-    let order_book = order_book.lock().unwrap();
-    let order_book = order_book.get_all_bids();
-    HttpResponse::Ok().json(order_book)
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/latency",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Latency per command kind", body = Vec<CommandLatency>),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn get_latencies(engine: web::Data<EngineHandle>) -> Result<HttpResponse, Error> {
+    json_response(engine.latencies().await)
 }
 
-async fn get_orders(order_book: web::Data<Arc<Mutex<OrderBook>>>) -> HttpResponse {
-    let order_book = order_book.lock().unwrap();
-    let order_book = order_book.get_orders();
-    HttpResponse::Ok().json(order_book)
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/latency",
+    tag = "admin",
+    responses(
+        (status = 204, description = "The histograms were cleared"),
+        (status = 401, description = "Missing or invalid signature", body = ErrorBody),
+        (status = 429, description = "Rate limited or throttled", body = ErrorBody)
+    )
+)]
+async fn reset_latencies(engine: web::Data<EngineHandle>) -> Result<HttpResponse, Error> {
+    engine.reset_latencies().await?;
+    Ok(HttpResponse::NoContent().finish())
 }
@@ -0,0 +1,124 @@
+use actix_web::HttpResponse;
+use std::sync::OnceLock;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The OpenAPI 3 document for the REST routes, built from the handlers'
+/// `#[utoipa::path]` attributes and the schemas of the models they take and
+/// return. `/ws/` is left out: it is a WebSocket, described in the README.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "orderbook",
+        description = "A limit order book and matching engine. The REST API is versioned under \
+            `/api/v1`; its lists are paged with `limit` and the `next_cursor` of the previous \
+            page. Once API keys are configured, requests must be signed: see the `api_key` \
+            security scheme."
+    ),
+    paths(
+        super::health_check,
+        openapi_json,
+        swagger_ui,
+        super::get_metrics,
+        super::create_order,
+        super::list_orders,
+        super::cancel_orders,
+        super::batch_orders,
+        super::cancel_after,
+        super::get_order,
+        super::cancel_order,
+        super::amend_order,
+        super::list_fills,
+        super::list_trades,
+        super::list_asks,
+        super::list_bids,
+        super::get_depth,
+        super::get_status,
+        super::get_indicative,
+        super::halt_trading,
+        super::resume_trading,
+        super::open_call,
+        super::uncross,
+        super::get_latencies,
+        super::reset_latencies,
+    ),
+    modifiers(&SignedRequests),
+    security(("api_key" = [])),
+    tags(
+        (name = "orders", description = "Order entry, status and history"),
+        (name = "market", description = "Resting orders and book state"),
+        (name = "admin", description = "Trading controls and engine statistics"),
+        (name = "system", description = "Health, metrics and this document"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Describes how requests are signed, which OpenAPI has no scheme for.
+struct SignedRequests;
+
+impl Modify for SignedRequests {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Api-Key",
+                "Alongside the key, send X-Api-Timestamp (Unix milliseconds), X-Api-Nonce and \
+                 X-Api-Signature: the hex HMAC-SHA256 of the timestamp, nonce, method and path \
+                 with query, each followed by a newline, then the body, keyed by the SHA-256 of \
+                 the secret. Only needed once API keys are configured.",
+            ))),
+        );
+    }
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>orderbook API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// The OpenAPI document, rendered once.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "system",
+    responses((status = 200, description = "This document", content_type = "application/json")),
+    security(())
+)]
+pub(super) async fn openapi_json() -> HttpResponse {
+    static SPEC: OnceLock<String> = OnceLock::new();
+    let spec = SPEC.get_or_init(|| {
+        ApiDoc::openapi()
+            .to_json()
+            .expect("the OpenAPI document serializes")
+    });
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(spec.as_str())
+}
+
+/// Swagger UI for the OpenAPI document. The UI itself is loaded from a CDN.
+#[utoipa::path(
+    get,
+    path = "/docs",
+    tag = "system",
+    responses((status = 200, description = "An HTML page", content_type = "text/html")),
+    security(())
+)]
+pub(super) async fn swagger_ui() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI)
+}
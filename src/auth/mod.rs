@@ -0,0 +1,369 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// How far a signed request's timestamp may be from the server's clock, in
+/// milliseconds, unless configured otherwise.
+pub const DEFAULT_RECV_WINDOW_MS: u64 = 5_000;
+
+/// A bearer token that identifies an account. Only the token's SHA-256 is
+/// configured, so a leaked config file does not leak the tokens.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountToken {
+    pub account: String,
+    /// Hex-encoded SHA-256 of the token.
+    pub token_sha256: String,
+}
+
+impl AccountToken {
+    pub fn new(account: &str, token: &str) -> Self {
+        Self {
+            account: account.to_string(),
+            token_sha256: hex::encode(digest(token)),
+        }
+    }
+}
+
+/// Looks accounts up by token.
+#[derive(Debug, Clone, Default)]
+pub struct Accounts {
+    by_digest: HashMap<[u8; 32], String>,
+}
+
+impl Accounts {
+    /// Tokens whose digest is not 64 hex characters are skipped; config
+    /// validation reports them.
+    pub fn new(tokens: &[AccountToken]) -> Self {
+        let by_digest = tokens
+            .iter()
+            .filter_map(|token| {
+                let mut digest = [0; 32];
+                hex::decode_to_slice(&token.token_sha256, &mut digest).ok()?;
+                Some((digest, token.account.clone()))
+            })
+            .collect();
+        Self { by_digest }
+    }
+
+    /// The account `token` belongs to, if any.
+    pub fn authenticate(&self, token: &str) -> Option<&str> {
+        self.by_digest.get(&digest(token)).map(String::as_str)
+    }
+
+    /// Whether any tokens are configured.
+    pub fn is_empty(&self) -> bool {
+        self.by_digest.is_empty()
+    }
+}
+
+/// What an API key may do. Keys hold any combination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Query books, orders and status.
+    Read,
+    /// Place, amend and cancel the account's own orders.
+    Trade,
+    /// Halts, auctions, latency stats and cancelling other accounts' orders.
+    Admin,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Permission::Read => "read",
+            Permission::Trade => "trade",
+            Permission::Admin => "admin",
+        })
+    }
+}
+
+/// An API key for signed REST requests. Requests are signed with
+/// HMAC-SHA256 keyed by the SHA-256 of the secret, so only that digest is
+/// configured and the secret itself never leaves the client. The digest
+/// can still sign requests, so the config file must stay private.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub key: String,
+    pub account: String,
+    /// Hex-encoded SHA-256 of the secret.
+    pub secret_sha256: String,
+    pub permissions: Vec<Permission>,
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("key", &self.key)
+            .field("account", &self.account)
+            .field("permissions", &self.permissions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ApiKey {
+    pub fn new(key: &str, account: &str, secret: &str, permissions: &[Permission]) -> Self {
+        Self {
+            key: key.to_string(),
+            account: account.to_string(),
+            secret_sha256: hex::encode(digest(secret)),
+            permissions: permissions.to_vec(),
+        }
+    }
+}
+
+/// The caller of an authenticated request. The API key middleware stores it
+/// in the request's extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub key: String,
+    pub account: String,
+    pub permissions: Vec<Permission>,
+}
+
+impl Identity {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+/// The parts of a request that are signed.
+#[derive(Debug, Clone, Copy)]
+pub struct SignedRequest<'a> {
+    pub key: &'a str,
+    /// Unix milliseconds.
+    pub timestamp: u64,
+    /// Any string the key has not used within the receive window.
+    pub nonce: &'a str,
+    pub method: &'a str,
+    /// The path including the query string, if any.
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    /// The hex-encoded signature of this request: HMAC-SHA256 keyed by the
+    /// SHA-256 of the secret, over the timestamp, nonce, method and path,
+    /// each followed by a newline, and then the body.
+    pub fn sign(&self, secret: &str) -> String {
+        hex::encode(self.mac(&digest(secret)).finalize().into_bytes())
+    }
+
+    fn mac(&self, key: &[u8; 32]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+        for part in [
+            self.timestamp.to_string().as_bytes(),
+            self.nonce.as_bytes(),
+            self.method.as_bytes(),
+            self.path.as_bytes(),
+        ] {
+            mac.update(part);
+            mac.update(b"\n");
+        }
+        mac.update(self.body);
+        mac
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// A signing header is absent or malformed.
+    MissingSignature,
+    UnknownKey,
+    BadSignature,
+    /// The timestamp is outside the receive window.
+    Expired,
+    ReplayedNonce,
+    Forbidden(Permission),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingSignature => write!(
+                f,
+                "request must be signed with X-Api-Key, X-Api-Timestamp, X-Api-Nonce and X-Api-Signature"
+            ),
+            AuthError::UnknownKey => write!(f, "unknown API key"),
+            AuthError::BadSignature => write!(f, "invalid signature"),
+            AuthError::Expired => write!(f, "timestamp is outside the receive window"),
+            AuthError::ReplayedNonce => write!(f, "nonce was already used"),
+            AuthError::Forbidden(permission) => {
+                write!(f, "API key lacks the {} permission", permission)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+struct Key {
+    account: String,
+    digest: [u8; 32],
+    permissions: Vec<Permission>,
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("account", &self.account)
+            .field("permissions", &self.permissions)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks signed requests against the configured API keys, remembering
+/// each key's nonces for as long as a request carrying them could still be
+/// accepted.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, Key>,
+    recv_window_ms: u64,
+    /// Nonces seen per key, with the timestamps they were signed at.
+    nonces: Mutex<HashMap<String, HashMap<String, u64>>>,
+}
+
+impl ApiKeys {
+    /// Keys whose digest is not 64 hex characters are skipped; config
+    /// validation reports them.
+    pub fn new(keys: &[ApiKey], recv_window_ms: u64) -> Self {
+        let keys = keys
+            .iter()
+            .filter_map(|key| {
+                let mut digest = [0; 32];
+                hex::decode_to_slice(&key.secret_sha256, &mut digest).ok()?;
+                let entry = Key {
+                    account: key.account.clone(),
+                    digest,
+                    permissions: key.permissions.clone(),
+                };
+                Some((key.key.clone(), entry))
+            })
+            .collect();
+        Self {
+            keys,
+            recv_window_ms,
+            nonces: Mutex::default(),
+        }
+    }
+
+    /// Whether any keys are configured. Without any, requests are not
+    /// checked at all.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks the signature, then that the request is fresh and not a
+    /// replay. `now` is in Unix milliseconds.
+    pub fn verify(
+        &self,
+        request: &SignedRequest,
+        signature: &str,
+        now: u64,
+    ) -> Result<Identity, AuthError> {
+        let key = self.keys.get(request.key).ok_or(AuthError::UnknownKey)?;
+        let signature = hex::decode(signature).map_err(|_| AuthError::BadSignature)?;
+        request
+            .mac(&key.digest)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::BadSignature)?;
+        if request.timestamp.abs_diff(now) > self.recv_window_ms {
+            return Err(AuthError::Expired);
+        }
+
+        let mut nonces = self.nonces.lock().expect("nonce lock poisoned");
+        let seen = nonces.entry(request.key.to_string()).or_default();
+        seen.retain(|_, timestamp| timestamp.abs_diff(now) <= self.recv_window_ms);
+        if seen
+            .insert(request.nonce.to_string(), request.timestamp)
+            .is_some()
+        {
+            return Err(AuthError::ReplayedNonce);
+        }
+        Ok(Identity {
+            key: request.key.to_string(),
+            account: key.account.clone(),
+            permissions: key.permissions.clone(),
+        })
+    }
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Whether `digest` looks like a hex-encoded SHA-256.
+pub fn is_sha256_hex(digest: &str) -> bool {
+    digest.len() == 64 && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_matched_by_digest() {
+        let token = AccountToken::new("desk-7", "s3cret");
+        assert!(is_sha256_hex(&token.token_sha256));
+        assert_ne!(token.token_sha256, "s3cret");
+
+        let accounts = Accounts::new(&[token]);
+        assert_eq!(accounts.authenticate("s3cret"), Some("desk-7"));
+        assert_eq!(accounts.authenticate("S3cret"), None);
+    }
+
+    #[test]
+    fn test_signed_requests_are_fresh_and_used_once() {
+        let key = ApiKey::new("key-1", "desk-7", "s3cret", &[Permission::Read]);
+        assert!(is_sha256_hex(&key.secret_sha256));
+        let keys = ApiKeys::new(&[key], 1_000);
+        let request = SignedRequest {
+            key: "key-1",
+            timestamp: 10_000,
+            nonce: "n-1",
+            method: "POST",
+            path: "/orders",
+            body: b"{}",
+        };
+        let signature = request.sign("s3cret");
+
+        let identity = keys.verify(&request, &signature, 10_500).unwrap();
+        assert_eq!(identity.account, "desk-7");
+        assert!(identity.can(Permission::Read) && !identity.can(Permission::Trade));
+        assert_eq!(
+            keys.verify(&request, &signature, 10_500),
+            Err(AuthError::ReplayedNonce)
+        );
+
+        let tampered = SignedRequest {
+            body: b"{\"amount\":9}",
+            nonce: "n-2",
+            ..request
+        };
+        assert_eq!(
+            keys.verify(&tampered, &signature, 10_500),
+            Err(AuthError::BadSignature)
+        );
+        let late = SignedRequest {
+            nonce: "n-3",
+            ..request
+        };
+        assert_eq!(
+            keys.verify(&late, &late.sign("s3cret"), 11_001),
+            Err(AuthError::Expired)
+        );
+        let unknown = SignedRequest {
+            key: "key-2",
+            ..request
+        };
+        assert_eq!(
+            keys.verify(&unknown, &signature, 10_500),
+            Err(AuthError::UnknownKey)
+        );
+    }
+}
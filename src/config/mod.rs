@@ -0,0 +1,592 @@
+use crate::auth::{self, AccountToken, ApiKey};
+use crate::engine::OrderToTradeLimit;
+use crate::engine::{DEFAULT_HISTORY_LIMIT, DEFAULT_QUEUE_CAPACITY};
+use crate::instrument::Instrument;
+use crate::order_book::MatchingAlgorithm;
+use crate::rate_limit::Limit;
+use crate::telemetry::LogFormat;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Names the config file to load. Without it `orderbook.toml` in the
+/// working directory is used if present, and the defaults otherwise.
+pub const CONFIG_PATH_VAR: &str = "ORDERBOOK_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "orderbook.toml";
+/// Prefix of the environment variables that override the config file.
+const ENV_PREFIX: &str = "ORDERBOOK_";
+
+/// Server configuration, read from TOML and then overridden from the
+/// environment. See `orderbook.example.toml` for every setting.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub logging: LoggingConfig,
+    pub persistence: PersistenceConfig,
+    pub features: Features,
+    pub auth: AuthConfig,
+    pub rate_limits: RateLimitConfig,
+    /// Off unless configured.
+    pub order_to_trade: Option<OrderToTradeLimit>,
+    /// Off unless configured.
+    pub fix: Option<FixConfig>,
+    /// One book is run per instrument. With none configured every trading
+    /// pair gets a book without checks on its first order.
+    pub instruments: Vec<Instrument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// HTTP worker threads; defaults to one per core.
+    pub workers: Option<usize>,
+    pub cors_origins: Vec<String>,
+    pub queue_capacity: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8080".to_string(),
+            workers: None,
+            cors_origins: vec!["http://localhost:3000".to_string()],
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// PostgreSQL connection string, e.g. `postgres://user@host/orderbook`,
+    /// for the order and fill history.
+    pub dsn: Option<String>,
+    /// How many final orders, fills and trades, each, are kept in memory
+    /// for the order, fill and trade lists.
+    pub history_limit: usize,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            dsn: None,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Tokens that authenticate WebSocket sessions as an account.
+    pub accounts: Vec<AccountToken>,
+    /// Keys for signed REST requests. With none, REST is open to anyone.
+    pub api_keys: Vec<ApiKey>,
+    /// How far a signed request's timestamp may be from the server's clock.
+    pub recv_window_ms: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            accounts: Vec::new(),
+            api_keys: Vec::new(),
+            recv_window_ms: auth::DEFAULT_RECV_WINDOW_MS,
+        }
+    }
+}
+
+/// Token buckets for REST requests and WebSocket commands.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Per API key, and per account for WebSocket sessions.
+    pub per_key: Limit,
+    /// Per client address.
+    pub per_ip: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_key: Limit {
+                burst: 50,
+                per_second: 20.0,
+            },
+            per_ip: Limit {
+                burst: 100,
+                per_second: 50.0,
+            },
+        }
+    }
+}
+
+/// The FIX order entry gateway.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixConfig {
+    pub bind: String,
+    /// The gateway's CompID, which clients send as TargetCompID.
+    pub comp_id: String,
+}
+
+impl Default for FixConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:9878".to_string(),
+            comp_id: "ORDERBOOK".to_string(),
+        }
+    }
+}
+
+/// Parts of the API that can be switched off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// The `/ws/` market data stream.
+    pub websocket: bool,
+    /// The Prometheus `/metrics` endpoint and request latency tracking.
+    pub metrics: bool,
+    /// The `/admin/*` endpoints.
+    pub admin: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            websocket: true,
+            metrics: true,
+            admin: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Env {
+        var: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// Every problem found while validating, so they can be fixed in one go.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "cannot read config file {}: {}", path.display(), error)
+            }
+            ConfigError::Parse { path, error } => {
+                write!(f, "invalid config file {}: {}", path.display(), error)
+            }
+            ConfigError::Env {
+                var,
+                value,
+                expected,
+            } => write!(f, "{}={:?} is not {}", var, value, expected),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads the config file named by `ORDERBOOK_CONFIG` (or `orderbook.toml`
+    /// if it exists), applies `ORDERBOOK_*` environment overrides and
+    /// validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = std::env::var_os(CONFIG_PATH_VAR).map(PathBuf::from);
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env(std::env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        toml::from_str(&text).map_err(|error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Overrides settings from `ORDERBOOK_*` variables. Instruments can only
+    /// be set in the file.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), ConfigError> {
+        for (var, value) in vars {
+            let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match key {
+                "BIND" => self.server.bind = value,
+                "WORKERS" => self.server.workers = Some(parse(&var, &value, "a number")?),
+                "CORS_ORIGINS" => {
+                    self.server.cors_origins = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|origin| !origin.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "QUEUE_CAPACITY" => self.server.queue_capacity = parse(&var, &value, "a number")?,
+                "LOG_FORMAT" => self.logging.format = parse(&var, &value, "\"json\" or \"text\"")?,
+                "DATABASE_URL" => self.persistence.dsn = Some(value),
+                "HISTORY_LIMIT" => {
+                    self.persistence.history_limit = parse(&var, &value, "a number")?
+                }
+                "AUTH_RECV_WINDOW_MS" => {
+                    self.auth.recv_window_ms = parse(&var, &value, "a number")?
+                }
+                "FEATURES_WEBSOCKET" => {
+                    self.features.websocket = parse(&var, &value, "true or false")?
+                }
+                "FEATURES_METRICS" => self.features.metrics = parse(&var, &value, "true or false")?,
+                "FEATURES_ADMIN" => self.features.admin = parse(&var, &value, "true or false")?,
+                "FIX_BIND" => self.fix.get_or_insert_with(FixConfig::default).bind = value,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.bind.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "server.bind {:?} is not an address like 127.0.0.1:8080",
+                self.server.bind
+            ));
+        }
+        if self.server.workers == Some(0) {
+            problems.push("server.workers must be at least 1".to_string());
+        }
+        if self.server.queue_capacity == 0 {
+            problems.push("server.queue_capacity must be at least 1".to_string());
+        }
+        for origin in &self.server.cors_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/'));
+            if !valid {
+                problems.push(format!(
+                    "server.cors_origins entry {:?} must be \"*\" or a scheme and host such as http://localhost:3000",
+                    origin
+                ));
+            }
+        }
+        if let Some(dsn) = &self.persistence.dsn {
+            if !(dsn.starts_with("postgres://") || dsn.starts_with("postgresql://")) {
+                problems.push("persistence.dsn must be a postgres:// URL".to_string());
+            }
+        }
+        if self.persistence.history_limit == 0 {
+            problems.push("persistence.history_limit must be at least 1".to_string());
+        }
+
+        for token in &self.auth.accounts {
+            if token.account.is_empty() {
+                problems.push("auth.accounts entry has an empty account".to_string());
+            }
+            if !auth::is_sha256_hex(&token.token_sha256) {
+                problems.push(format!(
+                    "auth.accounts entry for {:?}: token_sha256 must be 64 hex characters",
+                    token.account
+                ));
+            }
+        }
+        let mut keys = HashSet::new();
+        for key in &self.auth.api_keys {
+            if key.key.is_empty() || key.account.is_empty() {
+                problems.push("auth.api_keys entry needs a key and an account".to_string());
+            } else if !keys.insert(&key.key) {
+                problems.push(format!(
+                    "auth.api_keys key {:?} is configured twice",
+                    key.key
+                ));
+            }
+            if !auth::is_sha256_hex(&key.secret_sha256) {
+                problems.push(format!(
+                    "auth.api_keys entry {:?}: secret_sha256 must be 64 hex characters",
+                    key.key
+                ));
+            }
+            if key.permissions.is_empty() {
+                problems.push(format!(
+                    "auth.api_keys entry {:?} has no permissions",
+                    key.key
+                ));
+            }
+        }
+        if self.auth.recv_window_ms == 0 {
+            problems.push("auth.recv_window_ms must be at least 1".to_string());
+        }
+        for (name, limit) in [
+            ("per_key", self.rate_limits.per_key),
+            ("per_ip", self.rate_limits.per_ip),
+        ] {
+            if !(limit.per_second.is_finite() && limit.per_second >= 0.0)
+                || (limit.burst > 0 && limit.per_second == 0.0)
+            {
+                problems.push(format!(
+                    "rate_limits.{}.per_second must be positive, or burst 0 to turn the limit off",
+                    name
+                ));
+            }
+        }
+        if let Some(limit) = &self.order_to_trade {
+            if !(limit.max_ratio.is_finite() && limit.max_ratio >= 1.0) {
+                problems.push("order_to_trade.max_ratio must be at least 1".to_string());
+            }
+            if limit.window_secs == 0 {
+                problems.push("order_to_trade.window_secs must be at least 1".to_string());
+            }
+        }
+        if let Some(fix) = &self.fix {
+            if fix.bind.parse::<SocketAddr>().is_err() {
+                problems.push(format!(
+                    "fix.bind {:?} is not an address like 127.0.0.1:9878",
+                    fix.bind
+                ));
+            }
+            if fix.comp_id.is_empty() {
+                problems.push("fix.comp_id must not be empty".to_string());
+            }
+        }
+
+        let mut symbols = HashSet::new();
+        for instrument in &self.instruments {
+            let symbol = &instrument.symbol;
+            if symbol.is_empty() {
+                problems.push("instrument symbol must not be empty".to_string());
+            } else if !symbols.insert(symbol) {
+                problems.push(format!("instrument {} is configured twice", symbol));
+            }
+            problems.extend(
+                instrument_problems(instrument)
+                    .into_iter()
+                    .map(|problem| format!("instrument {}: {}", symbol, problem)),
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn instrument_problems(instrument: &Instrument) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !positive(instrument.tick_size) {
+        problems.push("tick_size must be positive".to_string());
+    }
+    if !positive(instrument.lot_size) {
+        problems.push("lot_size must be positive".to_string());
+    }
+    if !(0.0..).contains(&instrument.min_notional) {
+        problems.push("min_notional must not be negative".to_string());
+    }
+    if instrument.price_precision > 9 {
+        problems.push("price_precision must be at most 9".to_string());
+    } else if instrument.tick_size > 0.0 {
+        let units = instrument.tick_size * instrument.scalar() as f64;
+        if (units - units.round()).abs() > 1e-6 || units.round() < 1.0 {
+            problems.push(format!(
+                "tick_size {} cannot be expressed with price_precision {}",
+                instrument.tick_size, instrument.price_precision
+            ));
+        }
+    }
+    if let Some(band) = &instrument.price_band {
+        if !positive(band.max_deviation_pct) {
+            problems.push("price_band.max_deviation_pct must be positive".to_string());
+        }
+        if band.window_secs == 0 {
+            problems.push("price_band.window_secs must be at least 1".to_string());
+        }
+    }
+    match instrument.matching {
+        MatchingAlgorithm::Fifo => {}
+        MatchingAlgorithm::ProRata { min_allocation } => {
+            if !(0.0..).contains(&min_allocation) {
+                problems.push("matching.min_allocation must not be negative".to_string());
+            }
+        }
+        MatchingAlgorithm::Hybrid {
+            top_order_pct,
+            min_allocation,
+        } => {
+            if !(0.0..=100.0).contains(&top_order_pct) {
+                problems.push("matching.top_order_pct must be between 0 and 100".to_string());
+            }
+            if !(0.0..).contains(&min_allocation) {
+                problems.push("matching.min_allocation must not be negative".to_string());
+            }
+        }
+    }
+    problems
+}
+
+/// False for NaN as well as for zero and below.
+fn positive(value: f64) -> bool {
+    value > 0.0
+}
+
+fn parse<T: FromStr>(var: &str, value: &str, expected: &'static str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError::Env {
+        var: var.to_string(),
+        value: value.to_string(),
+        expected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        [server]
+        bind = "0.0.0.0:9000"
+        workers = 4
+        cors_origins = ["https://exchange.example"]
+
+        [features]
+        admin = false
+
+        [[instruments]]
+        symbol = "BTC-USD"
+        base_asset = "BTC"
+        quote_asset = "USD"
+        tick_size = 0.5
+        lot_size = 0.001
+        min_notional = 10.0
+        price_precision = 2
+        matching = { algorithm = "pro_rata", min_allocation = 0.01 }
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_file_and_applies_overrides() {
+        let mut config: Config = toml::from_str(EXAMPLE).unwrap();
+        assert_eq!(config.server.workers, Some(4));
+        assert!(!config.features.admin);
+        assert!(config.features.metrics);
+        assert_eq!(
+            config.instruments[0].matching,
+            MatchingAlgorithm::ProRata {
+                min_allocation: 0.01
+            }
+        );
+
+        config
+            .apply_env(env(&[
+                ("ORDERBOOK_BIND", "127.0.0.1:7000"),
+                ("ORDERBOOK_CORS_ORIGINS", "http://a.test, http://b.test"),
+                ("ORDERBOOK_FEATURES_ADMIN", "true"),
+                ("ORDERBOOK_FIX_BIND", "0.0.0.0:9878"),
+                ("PATH", "/usr/bin"),
+            ]))
+            .unwrap();
+        assert_eq!(config.server.bind, "127.0.0.1:7000");
+        assert_eq!(
+            config.server.cors_origins,
+            vec!["http://a.test", "http://b.test"]
+        );
+        assert!(config.features.admin);
+        assert_eq!(config.fix.as_ref().unwrap().bind, "0.0.0.0:9878");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_bad_override_names_the_variable() {
+        let err = Config::default()
+            .apply_env(env(&[("ORDERBOOK_WORKERS", "lots")]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ORDERBOOK_WORKERS=\"lots\" is not a number"
+        );
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let mut config: Config = toml::from_str(EXAMPLE).unwrap();
+        config.server.bind = "localhost".to_string();
+        config.instruments.push(config.instruments[0].clone());
+        config.instruments[1].tick_size = 0.001;
+
+        let ConfigError::Invalid(problems) = config.validate().unwrap_err() else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(
+            problems,
+            vec![
+                "server.bind \"localhost\" is not an address like 127.0.0.1:8080",
+                "instrument BTC-USD is configured twice",
+                "instrument BTC-USD: tick_size 0.001 cannot be expressed with price_precision 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_example_file_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("orderbook.example.toml");
+        let config = Config::from_file(&path).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.server, ServerConfig::default());
+        assert_eq!(config.instruments.len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = toml::from_str::<Config>("[server]\nport = 80\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `port`"));
+    }
+}
@@ -0,0 +1,163 @@
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// The slowest command a histogram can record, one minute. Anything slower
+/// is clamped to this value.
+const MAX_TRACKABLE_NANOS: u64 = 60_000_000_000;
+/// Keeps three significant figures at every magnitude.
+const SIGNIFICANT_FIGURES: u8 = 3;
+
+/// The kinds of command whose latency is tracked separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    AddOrder,
+    CancelOrder,
+    AmendOrder,
+    Batch,
+    MassCancel,
+    CancelAfter,
+    GetOrders,
+    GetOrder,
+    QueryOrders,
+    QueryFills,
+    QueryTrades,
+    GetBookPage,
+    GetBids,
+    GetAsks,
+    GetState,
+    GetIndicative,
+    GetDepth,
+    GetLatencies,
+    ResetLatencies,
+    Halt,
+    Resume,
+    OpenCall,
+    Uncross,
+    Tick,
+}
+
+impl CommandKind {
+    pub const ALL: [CommandKind; 24] = [
+        CommandKind::AddOrder,
+        CommandKind::CancelOrder,
+        CommandKind::AmendOrder,
+        CommandKind::Batch,
+        CommandKind::MassCancel,
+        CommandKind::CancelAfter,
+        CommandKind::GetOrders,
+        CommandKind::GetOrder,
+        CommandKind::QueryOrders,
+        CommandKind::QueryFills,
+        CommandKind::QueryTrades,
+        CommandKind::GetBookPage,
+        CommandKind::GetBids,
+        CommandKind::GetAsks,
+        CommandKind::GetState,
+        CommandKind::GetIndicative,
+        CommandKind::GetDepth,
+        CommandKind::GetLatencies,
+        CommandKind::ResetLatencies,
+        CommandKind::Halt,
+        CommandKind::Resume,
+        CommandKind::OpenCall,
+        CommandKind::Uncross,
+        CommandKind::Tick,
+    ];
+}
+
+/// A summary of one command kind's latency distribution, in nanoseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CommandLatency {
+    pub command: CommandKind,
+    pub count: u64,
+    pub min_ns: u64,
+    pub mean_ns: f64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+    pub max_ns: u64,
+}
+
+/// One HDR histogram per command kind, measuring how long the engine spent
+/// applying each command to the book. Recording never allocates.
+#[derive(Debug)]
+pub struct Latencies {
+    histograms: Vec<Histogram<u64>>,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        let histograms = CommandKind::ALL
+            .iter()
+            .map(|_| {
+                Histogram::new_with_bounds(1, MAX_TRACKABLE_NANOS, SIGNIFICANT_FIGURES)
+                    .expect("valid histogram bounds")
+            })
+            .collect();
+        Self { histograms }
+    }
+}
+
+impl Latencies {
+    pub fn record(&mut self, kind: CommandKind, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.histograms[kind as usize].saturating_record(nanos.max(1));
+    }
+
+    /// Summaries for every command kind that has been seen at least once.
+    pub fn report(&self) -> Vec<CommandLatency> {
+        CommandKind::ALL
+            .iter()
+            .zip(&self.histograms)
+            .filter(|(_, histogram)| !histogram.is_empty())
+            .map(|(&command, histogram)| CommandLatency {
+                command,
+                count: histogram.len(),
+                min_ns: histogram.min(),
+                mean_ns: histogram.mean(),
+                p50_ns: histogram.value_at_quantile(0.5),
+                p90_ns: histogram.value_at_quantile(0.9),
+                p99_ns: histogram.value_at_quantile(0.99),
+                p999_ns: histogram.value_at_quantile(0.999),
+                max_ns: histogram.max(),
+            })
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        for histogram in &mut self.histograms {
+            histogram.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_skips_unused_commands() {
+        let mut latencies = Latencies::default();
+        for micros in 1..=100 {
+            latencies.record(CommandKind::AddOrder, Duration::from_micros(micros));
+        }
+        latencies.record(CommandKind::Tick, Duration::ZERO);
+
+        let report = latencies.report();
+        assert_eq!(report.len(), 2);
+        let add_order = &report[0];
+        assert_eq!(add_order.command, CommandKind::AddOrder);
+        assert_eq!(add_order.count, 100);
+        // Three significant figures: within 0.1% of the exact percentile.
+        assert!(add_order.p50_ns.abs_diff(50_000) <= 50);
+        assert!(add_order.p99_ns.abs_diff(99_000) <= 99);
+        assert_eq!(report[1].min_ns, 1);
+
+        latencies.reset();
+        assert!(latencies.report().is_empty());
+    }
+}
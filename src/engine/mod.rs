@@ -0,0 +1,1241 @@
+use crate::metrics::Metrics;
+use crate::models::{
+    BidOrAsk, Execution, ExecutionReport, Fill, FillRecord, HaltReason, Liquidity, MarketEvent,
+    MatchedOrder, Order, OrderRecord, Price, TradeRecord,
+};
+use crate::order_book::{BookDepth, BookError, BookState, CancelFilter, OrderBook, Uncross};
+use crate::persistence::Writer;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, oneshot};
+use tracing::Span;
+use utoipa::ToSchema;
+
+mod latency;
+mod orders;
+mod ratio;
+mod submissions;
+
+pub use latency::{CommandKind, CommandLatency, Latencies};
+use orders::OrderTracker;
+pub use orders::{FillQuery, OrderQuery, TradeQuery, DEFAULT_HISTORY_LIMIT, DEFAULT_PAGE_LIMIT};
+use ratio::{OrderToTrade, Verdict};
+pub use ratio::{OrderToTradeLimit, RatioAction};
+use submissions::Submissions;
+
+/// How many commands may wait for the engine before callers are held back.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// How many books a catch-all book may open for pairs without one of their
+/// own. Orders for further pairs are refused as unknown symbols.
+pub const MAX_OPENED_BOOKS: usize = 64;
+
+type Reply<T> = oneshot::Sender<T>;
+
+/// Everything the engine thread can be asked to do. Commands are applied to
+/// the books one at a time, in the order they were queued. Commands aimed
+/// at a single book name it by symbol; the symbol may be left out when the
+/// engine runs only one book.
+pub enum Command {
+    AddOrder {
+        order: Order,
+        idempotency_key: Option<String>,
+        reply: Reply<Result<Execution, EngineError>>,
+    },
+    /// Cancels an order. With an `account`, only one of that account's
+    /// orders; others are not found.
+    CancelOrder {
+        id: u64,
+        account: Option<String>,
+        reply: Reply<Result<Order, EngineError>>,
+    },
+    /// Amends an order, confined to `account`'s orders like `CancelOrder`.
+    AmendOrder {
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+        account: Option<String>,
+        reply: Reply<Result<Execution, EngineError>>,
+    },
+    /// Applies every operation in one engine turn, so no other flow can
+    /// interleave with them. Cancels and amends are confined to `account`'s
+    /// orders like `CancelOrder`.
+    Batch {
+        operations: Vec<Operation>,
+        account: Option<String>,
+        reply: Reply<Vec<Result<Outcome, EngineError>>>,
+    },
+    MassCancel {
+        filter: CancelFilter,
+        reply: Reply<Vec<Order>>,
+    },
+    /// Arms, re-arms or, with no timeout, disarms an account's dead man's
+    /// switch. Replies with when it will fire, in Unix milliseconds.
+    CancelAfter {
+        account: String,
+        timeout: Option<Duration>,
+        reply: Reply<Option<u64>>,
+    },
+    GetOrders(Reply<Vec<Order>>),
+    /// A working order, or a final one still in the history.
+    GetOrder {
+        id: u64,
+        reply: Reply<Result<OrderRecord, EngineError>>,
+    },
+    QueryOrders {
+        query: OrderQuery,
+        reply: Reply<Vec<OrderRecord>>,
+    },
+    QueryFills {
+        query: FillQuery,
+        reply: Reply<Vec<FillRecord>>,
+    },
+    QueryTrades {
+        query: TradeQuery,
+        reply: Reply<Vec<TradeRecord>>,
+    },
+    GetBookPage {
+        query: BookQuery,
+        reply: Reply<Result<Vec<Order>, EngineError>>,
+    },
+    GetBids(Reply<Vec<Order>>),
+    GetAsks(Reply<Vec<Order>>),
+    GetState {
+        symbol: Option<String>,
+        reply: Reply<Result<BookState, EngineError>>,
+    },
+    GetIndicative {
+        symbol: Option<String>,
+        reply: Reply<Result<Option<Uncross>, EngineError>>,
+    },
+    GetDepth(Reply<Vec<(String, BookDepth)>>),
+    GetLatencies(Reply<Vec<CommandLatency>>),
+    ResetLatencies(Reply<()>),
+    Halt {
+        symbol: Option<String>,
+        reply: Reply<Result<BookState, EngineError>>,
+    },
+    Resume {
+        symbol: Option<String>,
+        reply: Reply<Result<BookState, EngineError>>,
+    },
+    OpenCall {
+        symbol: Option<String>,
+        reply: Reply<Result<BookState, EngineError>>,
+    },
+    Uncross {
+        symbol: Option<String>,
+        reply: Reply<Result<Vec<MatchedOrder>, EngineError>>,
+    },
+    Tick,
+}
+
+impl Command {
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Command::AddOrder { .. } => CommandKind::AddOrder,
+            Command::CancelOrder { .. } => CommandKind::CancelOrder,
+            Command::AmendOrder { .. } => CommandKind::AmendOrder,
+            Command::Batch { .. } => CommandKind::Batch,
+            Command::MassCancel { .. } => CommandKind::MassCancel,
+            Command::CancelAfter { .. } => CommandKind::CancelAfter,
+            Command::GetOrders(_) => CommandKind::GetOrders,
+            Command::GetOrder { .. } => CommandKind::GetOrder,
+            Command::QueryOrders { .. } => CommandKind::QueryOrders,
+            Command::QueryFills { .. } => CommandKind::QueryFills,
+            Command::QueryTrades { .. } => CommandKind::QueryTrades,
+            Command::GetBookPage { .. } => CommandKind::GetBookPage,
+            Command::GetBids(_) => CommandKind::GetBids,
+            Command::GetAsks(_) => CommandKind::GetAsks,
+            Command::GetState { .. } => CommandKind::GetState,
+            Command::GetIndicative { .. } => CommandKind::GetIndicative,
+            Command::GetDepth(_) => CommandKind::GetDepth,
+            Command::GetLatencies(_) => CommandKind::GetLatencies,
+            Command::ResetLatencies(_) => CommandKind::ResetLatencies,
+            Command::Halt { .. } => CommandKind::Halt,
+            Command::Resume { .. } => CommandKind::Resume,
+            Command::OpenCall { .. } => CommandKind::OpenCall,
+            Command::Uncross { .. } => CommandKind::Uncross,
+            Command::Tick => CommandKind::Tick,
+        }
+    }
+}
+
+/// Selects a page of the orders resting on one side of a book, best price
+/// first. The symbol may be left out when the engine runs only one book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookQuery {
+    pub symbol: Option<String>,
+    pub side: BidOrAsk,
+    /// The price and id of the last order of the previous page.
+    pub after: Option<(Price, u64)>,
+    pub limit: usize,
+}
+
+/// One step of a batch.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    New {
+        order: Order,
+    },
+    Cancel {
+        id: u64,
+    },
+    Amend {
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+    },
+}
+
+/// What a successful batch operation produced: an execution for new and
+/// amended orders, the removed order for cancels.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Execution(Execution),
+    Cancelled(Order),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    Book(BookError),
+    /// No book trades this symbol.
+    UnknownSymbol(String),
+    /// The engine runs several books and the command did not say which.
+    SymbolRequired,
+    /// The client order id or idempotency key was already used for the
+    /// given order, which differs from this one.
+    DuplicateOrder(u64),
+    /// The account is over its order-to-trade ratio limit.
+    Throttled(String),
+    /// The engine thread has stopped.
+    Unavailable,
+    /// The engine failed while applying the command and dropped it.
+    Internal,
+    /// The book for this symbol was taken out of service after the engine
+    /// failed while changing it.
+    OutOfService(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Book(err) => err.fmt(f),
+            EngineError::UnknownSymbol(symbol) => write!(f, "unknown symbol {:?}", symbol),
+            EngineError::SymbolRequired => {
+                write!(f, "a symbol is required when trading several instruments")
+            }
+            EngineError::DuplicateOrder(id) => write!(
+                f,
+                "client order id or idempotency key already used by order {}",
+                id
+            ),
+            EngineError::Throttled(account) => write!(
+                f,
+                "account {:?} is over its order-to-trade ratio limit",
+                account
+            ),
+            EngineError::Unavailable => write!(f, "matching engine unavailable"),
+            EngineError::Internal => write!(f, "the matching engine failed to apply the request"),
+            EngineError::OutOfService(symbol) => {
+                write!(
+                    f,
+                    "the {:?} book is out of service after an internal error",
+                    symbol
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<BookError> for EngineError {
+    fn from(err: BookError) -> Self {
+        EngineError::Book(err)
+    }
+}
+
+/// A queued command and the tracing span it was sent from, so that what
+/// the engine logs while applying it is attributed to the original request.
+type Envelope = (Command, Span);
+
+/// A cheap, cloneable handle for submitting commands to the engine thread.
+#[derive(Clone)]
+pub struct EngineHandle {
+    tx: mpsc::Sender<Envelope>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EngineHandle {
+    /// Hands out the id for a new order. Ids are unique across every book
+    /// and handle of this engine, so they are known, and can be logged,
+    /// before the order reaches the engine thread.
+    pub fn next_order_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Submits an order whose id came from `next_order_id`. If an order with
+    /// the same client order id or `idempotency_key` was already accepted
+    /// for the account, its original result is returned instead.
+    pub async fn add_order(
+        &self,
+        order: Order,
+        idempotency_key: Option<String>,
+    ) -> Result<Execution, EngineError> {
+        self.request(|reply| Command::AddOrder {
+            order,
+            idempotency_key,
+            reply,
+        })
+        .await?
+    }
+
+    /// Cancels order `id`. With an `account`, orders of other accounts are
+    /// not found.
+    pub async fn cancel_order(
+        &self,
+        id: u64,
+        account: Option<String>,
+    ) -> Result<Order, EngineError> {
+        self.request(|reply| Command::CancelOrder { id, account, reply })
+            .await?
+    }
+
+    /// Amends order `id`. With an `account`, orders of other accounts are
+    /// not found.
+    pub async fn amend_order(
+        &self,
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+        account: Option<String>,
+    ) -> Result<Execution, EngineError> {
+        self.request(|reply| Command::AmendOrder {
+            id,
+            amount,
+            price,
+            account,
+            reply,
+        })
+        .await?
+    }
+
+    /// Applies `operations` in order, atomically with respect to all other
+    /// commands, and returns one result per operation. New orders' ids must
+    /// come from `next_order_id`. With an `account`, cancels and amends of
+    /// other accounts' orders are not found.
+    pub async fn batch(
+        &self,
+        operations: Vec<Operation>,
+        account: Option<String>,
+    ) -> Result<Vec<Result<Outcome, EngineError>>, EngineError> {
+        self.request(|reply| Command::Batch {
+            operations,
+            account,
+            reply,
+        })
+        .await
+    }
+
+    /// Cancels every order `filter` selects, across all books.
+    pub async fn cancel_all(&self, filter: CancelFilter) -> Result<Vec<Order>, EngineError> {
+        self.request(|reply| Command::MassCancel { filter, reply })
+            .await
+    }
+
+    /// Cancels all of `account`'s orders unless this is called again within
+    /// `timeout`. `None` disarms the switch. Returns when it will fire, in
+    /// Unix milliseconds. Deadlines are checked on every `tick`.
+    pub async fn cancel_after(
+        &self,
+        account: String,
+        timeout: Option<Duration>,
+    ) -> Result<Option<u64>, EngineError> {
+        self.request(|reply| Command::CancelAfter {
+            account,
+            timeout,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn get_orders(&self) -> Result<Vec<Order>, EngineError> {
+        self.request(Command::GetOrders).await
+    }
+
+    /// An order's status, filled quantity and average price, while it is
+    /// working and for as long as it stays in the history once final.
+    pub async fn get_order(&self, id: u64) -> Result<OrderRecord, EngineError> {
+        self.request(|reply| Command::GetOrder { id, reply })
+            .await?
+    }
+
+    pub async fn query_orders(&self, query: OrderQuery) -> Result<Vec<OrderRecord>, EngineError> {
+        self.request(|reply| Command::QueryOrders { query, reply })
+            .await
+    }
+
+    pub async fn query_fills(&self, query: FillQuery) -> Result<Vec<FillRecord>, EngineError> {
+        self.request(|reply| Command::QueryFills { query, reply })
+            .await
+    }
+
+    /// The public trade tape, oldest first, for as long as the history
+    /// limit keeps it.
+    pub async fn query_trades(&self, query: TradeQuery) -> Result<Vec<TradeRecord>, EngineError> {
+        self.request(|reply| Command::QueryTrades { query, reply })
+            .await
+    }
+
+    pub async fn book_page(&self, query: BookQuery) -> Result<Vec<Order>, EngineError> {
+        self.request(|reply| Command::GetBookPage { query, reply })
+            .await?
+    }
+
+    pub async fn get_all_bids(&self) -> Result<Vec<Order>, EngineError> {
+        self.request(Command::GetBids).await
+    }
+
+    pub async fn get_all_asks(&self) -> Result<Vec<Order>, EngineError> {
+        self.request(Command::GetAsks).await
+    }
+
+    pub async fn state(&self, symbol: Option<String>) -> Result<BookState, EngineError> {
+        self.request(|reply| Command::GetState { symbol, reply })
+            .await?
+    }
+
+    pub async fn indicative(&self, symbol: Option<String>) -> Result<Option<Uncross>, EngineError> {
+        self.request(|reply| Command::GetIndicative { symbol, reply })
+            .await?
+    }
+
+    /// Resting interest in every book, by symbol.
+    pub async fn depth(&self) -> Result<Vec<(String, BookDepth)>, EngineError> {
+        self.request(Command::GetDepth).await
+    }
+
+    /// How many commands are waiting for the engine thread.
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// How long the engine has taken to apply each kind of command.
+    pub async fn latencies(&self) -> Result<Vec<CommandLatency>, EngineError> {
+        self.request(Command::GetLatencies).await
+    }
+
+    pub async fn reset_latencies(&self) -> Result<(), EngineError> {
+        self.request(Command::ResetLatencies).await
+    }
+
+    pub async fn halt(&self, symbol: Option<String>) -> Result<BookState, EngineError> {
+        self.request(|reply| Command::Halt { symbol, reply })
+            .await?
+    }
+
+    pub async fn resume(&self, symbol: Option<String>) -> Result<BookState, EngineError> {
+        self.request(|reply| Command::Resume { symbol, reply })
+            .await?
+    }
+
+    pub async fn open_call(&self, symbol: Option<String>) -> Result<BookState, EngineError> {
+        self.request(|reply| Command::OpenCall { symbol, reply })
+            .await?
+    }
+
+    pub async fn uncross(&self, symbol: Option<String>) -> Result<Vec<MatchedOrder>, EngineError> {
+        self.request(|reply| Command::Uncross { symbol, reply })
+            .await?
+    }
+
+    /// Advances the books' clocks so timed halts and auctions fire even when
+    /// no orders are arriving.
+    pub async fn tick(&self) -> Result<(), EngineError> {
+        self.tx
+            .send((Command::Tick, Span::current()))
+            .await
+            .map_err(|_| EngineError::Unavailable)
+    }
+
+    /// Queues a command, waiting for room if the queue is full, and waits
+    /// for the engine's reply.
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(Reply<T>) -> Command,
+    ) -> Result<T, EngineError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send((command(reply), Span::current()))
+            .await
+            .map_err(|_| EngineError::Unavailable)?;
+        // The engine only drops a reply when handling the command panicked.
+        rx.await.map_err(|_| EngineError::Internal)
+    }
+}
+
+/// Owns the order books and applies commands to them on a dedicated thread.
+pub struct Engine {
+    books: Vec<OrderBook>,
+    /// Indices into `books` by symbol.
+    book_index: HashMap<String, usize>,
+    catch_all: Option<usize>,
+    /// How many books the catch-all book has opened.
+    opened_books: usize,
+    latencies: Latencies,
+    metrics: Metrics,
+    submissions: Submissions,
+    /// Dead man's switch deadlines by account, in Unix milliseconds.
+    cancel_deadlines: HashMap<String, u64>,
+    orders: OrderTracker,
+    /// Where execution reports go. Without one they are not built at all.
+    notifier: Option<Sender<MarketEvent>>,
+    /// The id `spawn` starts handing out new orders from.
+    first_order_id: u64,
+    last_trade_id: u64,
+    order_to_trade: Option<OrderToTrade>,
+    /// Books the current command is changing, by index.
+    touching: Vec<usize>,
+    /// Books taken out of service because a command panicked while changing
+    /// them, which may have left them inconsistent.
+    out_of_service: HashSet<usize>,
+}
+
+impl Engine {
+    pub fn new(book: OrderBook) -> Self {
+        Self::with_books(vec![book])
+    }
+
+    /// Runs one book per instrument. Orders are routed to the book whose
+    /// symbol matches their trading pair; a book without an instrument
+    /// opens a book of its own for any other pair.
+    pub fn with_books(books: Vec<OrderBook>) -> Self {
+        assert!(!books.is_empty(), "the engine needs at least one book");
+        let books: Vec<_> = books.into_iter().map(OrderBook::with_journal).collect();
+        let mut book_index = HashMap::new();
+        for (index, book) in books.iter().enumerate() {
+            if !book.is_catch_all() {
+                book_index.entry(book.symbol().to_string()).or_insert(index);
+            }
+        }
+        Self {
+            catch_all: books.iter().position(OrderBook::is_catch_all),
+            books,
+            book_index,
+            opened_books: 0,
+            latencies: Latencies::default(),
+            metrics: Metrics::new(),
+            submissions: Submissions::default(),
+            cancel_deadlines: HashMap::new(),
+            orders: OrderTracker::default(),
+            notifier: None,
+            first_order_id: 1,
+            last_trade_id: 0,
+            order_to_trade: None,
+            touching: Vec::new(),
+            out_of_service: HashSet::new(),
+        }
+    }
+
+    /// Keeps up to `limit` final orders, and as many fills, for history
+    /// queries rather than `DEFAULT_HISTORY_LIMIT`.
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.orders.set_history_limit(limit);
+        self
+    }
+
+    /// Sends every change to an order, and every fill, to `writer` for the
+    /// history database. Order and trade ids carry on after the highest ones
+    /// it has stored.
+    pub fn with_persistence(mut self, writer: Writer) -> Self {
+        self.first_order_id = writer.last_order_id + 1;
+        self.last_trade_id = writer.last_trade_id;
+        self.orders.set_writer(writer);
+        self
+    }
+
+    /// Watches each account's ratio of order messages to trades, and flags
+    /// or throttles accounts that go over `limit`.
+    pub fn with_order_to_trade(mut self, limit: OrderToTradeLimit) -> Self {
+        self.order_to_trade = Some(OrderToTrade::new(limit));
+        self
+    }
+
+    /// Publishes an execution report on `notifier` for every change to an
+    /// order that has an account.
+    pub fn with_notifier(mut self, notifier: Sender<MarketEvent>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Reports order and trade counts to `metrics` rather than to a private
+    /// registry.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Starts the engine thread with a command queue of `capacity` entries.
+    /// The thread exits once every handle has been dropped.
+    pub fn spawn(self, capacity: usize) -> EngineHandle {
+        let (tx, mut rx) = mpsc::channel::<Envelope>(capacity);
+        let next_id = Arc::new(AtomicU64::new(self.first_order_id));
+        let mut engine = self;
+        thread::Builder::new()
+            .name("engine".to_string())
+            .spawn(move || {
+                while let Some((command, span)) = rx.blocking_recv() {
+                    let _entered = span.enter();
+                    let kind = command.kind();
+                    // A panic while handling one command drops that command's
+                    // reply and takes the books it was changing out of
+                    // service, but must not take the other books down.
+                    if panic::catch_unwind(AssertUnwindSafe(|| engine.handle(command))).is_err() {
+                        tracing::error!(command = ?kind, "engine command panicked");
+                        engine.take_out_of_service();
+                        if kind == CommandKind::AddOrder {
+                            engine
+                                .metrics
+                                .orders_rejected
+                                .with_label_values(&["internal_error"])
+                                .inc();
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn engine thread");
+        EngineHandle { tx, next_id }
+    }
+
+    /// Applies `command` to the books and records how long it took.
+    pub fn handle(&mut self, command: Command) {
+        let kind = command.kind();
+        let started = Instant::now();
+        self.touching.clear();
+        self.apply(command);
+        self.latencies.record(kind, started.elapsed());
+        // Fills the command handlers did not pick up themselves, such as
+        // those of orders replayed after a halt.
+        self.report_fills();
+        for position in 0..self.touching.len() {
+            let index = self.touching[position];
+            self.books[index].publish_depth();
+        }
+    }
+
+    /// Takes the books the failed command was changing out of service.
+    fn take_out_of_service(&mut self) {
+        for index in std::mem::take(&mut self.touching) {
+            if self.out_of_service.insert(index) {
+                tracing::error!(
+                    symbol = self.books[index].symbol(),
+                    "book taken out of service"
+                );
+            }
+        }
+    }
+
+    /// Marks the book at `index` as the only one being changed, unless it
+    /// is out of service. Used by the passes over every book, which change
+    /// one at a time.
+    fn touch_only(&mut self, index: usize) -> bool {
+        self.touching.clear();
+        if self.out_of_service.contains(&index) {
+            return false;
+        }
+        self.touching.push(index);
+        true
+    }
+
+    /// Marks the book at `index` as being changed, if it is in service.
+    fn touch(&mut self, index: usize) -> Result<&mut OrderBook, EngineError> {
+        if self.out_of_service.contains(&index) {
+            return Err(EngineError::OutOfService(
+                self.books[index].symbol().to_string(),
+            ));
+        }
+        if !self.touching.contains(&index) {
+            self.touching.push(index);
+        }
+        Ok(&mut self.books[index])
+    }
+
+    fn report(&self, report: Option<ExecutionReport>) {
+        if let (Some(notifier), Some(report)) = (self.notifier.as_ref(), report) {
+            let _ = notifier.send(MarketEvent::Execution(report));
+        }
+    }
+
+    /// Turns the journaled trades of the books the command changed into
+    /// fill reports for both sides, and counts them in the metrics under the
+    /// pair that traded.
+    fn report_fills(&mut self) {
+        for position in 0..self.touching.len() {
+            let index = self.touching[position];
+            let (fees, fee_asset) = match self.books[index].instrument() {
+                Some(instrument) => (instrument.fees, Some(instrument.quote_asset.clone())),
+                None => (Default::default(), None),
+            };
+            let entries: Vec<_> = self.books[index].drain_journal().collect();
+            for entry in entries {
+                self.last_trade_id += 1;
+                let trade = entry.trade;
+                let symbol = self
+                    .orders
+                    .symbol(trade.id)
+                    .unwrap_or(self.books[index].symbol())
+                    .to_string();
+                self.metrics.record_trades(&symbol, 1, trade.amount);
+                self.orders.trade(TradeRecord {
+                    trade_id: self.last_trade_id,
+                    symbol,
+                    price: trade.price,
+                    amount: trade.amount,
+                    aggressor: (!entry.auction).then_some(trade.bid_or_ask),
+                    timestamp: entry.timestamp,
+                });
+                let sides = if entry.auction {
+                    [Liquidity::Auction, Liquidity::Auction]
+                } else {
+                    [Liquidity::Taker, Liquidity::Maker]
+                };
+                for (id, liquidity) in [trade.id, trade.matched_with_id].into_iter().zip(sides) {
+                    let bps = match liquidity {
+                        Liquidity::Taker => fees.taker_bps,
+                        Liquidity::Maker | Liquidity::Auction => fees.maker_bps,
+                    };
+                    let fill = Fill {
+                        trade_id: self.last_trade_id,
+                        price: trade.price,
+                        amount: trade.amount,
+                        liquidity,
+                        fee: trade.price.to_f64() * trade.amount * bps / 10_000.0,
+                        fee_asset: fee_asset.clone(),
+                    };
+                    let report = self.orders.fill(id, fill, entry.timestamp);
+                    if let (Some(monitor), Some(report)) = (&mut self.order_to_trade, &report) {
+                        monitor.traded(&report.account, entry.timestamp);
+                    }
+                    self.report(report);
+                }
+            }
+        }
+    }
+
+    /// Reports an order the books dropped without filling or cancelling it,
+    /// such as the rest of a market order that ran out of liquidity.
+    fn expire_if_gone(&mut self, id: u64) {
+        let gone = self
+            .index_of_order(id)
+            .is_none_or(|index| self.out_of_service.contains(&index));
+        if self.orders.is_working(id) && gone {
+            let report = self.orders.expired(id, now());
+            self.report(report);
+        }
+    }
+
+    /// Counts an order message from `account` against its order-to-trade
+    /// ratio. Fails if the account is throttled and the message is one that
+    /// `throttles`; cancels never are.
+    fn count_message(&mut self, account: Option<&str>, throttles: bool) -> Result<(), EngineError> {
+        let (Some(monitor), Some(account)) = (&mut self.order_to_trade, account) else {
+            return Ok(());
+        };
+        let action = match monitor.message(account, now()) {
+            Verdict::Within => return Ok(()),
+            Verdict::Breached(action) => {
+                tracing::warn!(account, ?action, "order-to-trade ratio limit exceeded");
+                let label = match action {
+                    RatioAction::Flag => "flag",
+                    RatioAction::Throttle => "throttle",
+                };
+                self.metrics
+                    .order_to_trade_breaches
+                    .with_label_values(&[label])
+                    .inc();
+                action
+            }
+            Verdict::StillBreached(action) => action,
+        };
+        match action {
+            RatioAction::Throttle if throttles => Err(EngineError::Throttled(account.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// The book that trades `symbol`. A pair without a book of its own gets
+    /// one from the catch-all book, if there is one.
+    fn book_for_pair(&mut self, symbol: &str) -> Result<&mut OrderBook, EngineError> {
+        if symbol.is_empty() {
+            return Err(BookError::InvalidOrder("trading_pair is required").into());
+        }
+        if let Some(&index) = self.book_index.get(symbol) {
+            return self.touch(index);
+        }
+        let unknown = || EngineError::UnknownSymbol(symbol.to_string());
+        let catch_all = self.catch_all.ok_or_else(unknown)?;
+        if self.opened_books == MAX_OPENED_BOOKS {
+            tracing::warn!(symbol, "too many trading pairs, not opening another book");
+            return Err(unknown());
+        }
+        tracing::info!(symbol, "opening a book for a new trading pair");
+        let book = self.books[catch_all].for_pair(symbol);
+        self.books.push(book);
+        self.opened_books += 1;
+        let index = self.books.len() - 1;
+        self.book_index.insert(symbol.to_string(), index);
+        self.touch(index)
+    }
+
+    /// The book a symbol-scoped command is aimed at. Without a symbol that
+    /// is the only book, not counting a catch-all book that has opened one
+    /// for a pair.
+    fn book(&mut self, symbol: Option<&str>) -> Result<&mut OrderBook, EngineError> {
+        let index = match (symbol, self.books.len(), self.catch_all) {
+            (Some(symbol), _, _) => *self
+                .book_index
+                .get(symbol)
+                .ok_or_else(|| EngineError::UnknownSymbol(symbol.to_string()))?,
+            (None, 1, _) => 0,
+            (None, 2, Some(catch_all)) => 1 - catch_all,
+            (None, _, _) => return Err(EngineError::SymbolRequired),
+        };
+        self.touch(index)
+    }
+
+    fn book_with_order(&mut self, id: u64) -> Result<&mut OrderBook, EngineError> {
+        let index = self
+            .index_of_order(id)
+            .filter(|index| !self.out_of_service.contains(index))
+            .ok_or(EngineError::Book(BookError::OrderNotFound(id)))?;
+        self.touch(index)
+    }
+
+    /// The book order `id` rests in, found through the pair the tracker has
+    /// it under, since every order rests in the book named after its pair.
+    fn index_of_order(&self, id: u64) -> Option<usize> {
+        let index = *self.book_index.get(self.orders.symbol(id)?)?;
+        self.books[index].has_order(id).then_some(index)
+    }
+
+    /// The books in service, with their indices.
+    fn in_service(&self) -> impl Iterator<Item = (usize, &OrderBook)> {
+        self.books
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.out_of_service.contains(index))
+    }
+
+    fn add_order(
+        &mut self,
+        order: Order,
+        idempotency_key: Option<&str>,
+    ) -> Result<Execution, EngineError> {
+        match self.submissions.find(&order, idempotency_key) {
+            Some(Ok(execution)) => {
+                tracing::info!(
+                    original_order_id = execution.order.id,
+                    "order already accepted, returning its original result"
+                );
+                return Ok(execution);
+            }
+            Some(Err(err)) => {
+                tracing::info!(reason = %err, "order rejected");
+                let result = Err(err);
+                self.metrics.record_order(&result);
+                return result;
+            }
+            None => {}
+        }
+        if let Err(err) = self.count_message(order.account.as_deref(), true) {
+            tracing::info!(reason = %err, "order rejected");
+            let report = self.orders.rejected(&order, err.to_string(), now());
+            self.report(report);
+            let result = Err(err);
+            self.metrics.record_order(&result);
+            return result;
+        }
+        // Only orders that can be retried by key need their request kept.
+        let request =
+            (order.client_order_id.is_some() || idempotency_key.is_some()).then(|| order.clone());
+        let mut order = order;
+        let mut fills = Vec::new();
+        let submitted = self
+            .book_for_pair(&order.trading_pair)
+            .and_then(|book| Ok(book.submit(&mut order, now(), &mut fills)?));
+        let result = match submitted {
+            Ok(()) => {
+                let filled = fills.iter().map(|fill| fill.amount).sum();
+                let report = self.orders.accepted(&order, filled, order.timestamp);
+                self.report(report);
+                self.report_fills();
+                self.expire_if_gone(order.id);
+                Ok(Execution { order, fills })
+            }
+            Err(err) => {
+                let report = self.orders.rejected(&order, err.to_string(), now());
+                self.report(report);
+                Err(err)
+            }
+        };
+        self.metrics.record_order(&result);
+        if let (Some(request), Ok(execution)) = (request, &result) {
+            self.submissions
+                .remember(request, idempotency_key, execution);
+        }
+        result
+    }
+
+    fn cancel_order(&mut self, id: u64, owner: Option<&str>) -> Result<Order, EngineError> {
+        self.check_owner(id, owner)?;
+        let account = self.orders.account(id).map(str::to_string);
+        self.count_message(account.as_deref(), false)?;
+        let book = self.book_with_order(id)?;
+        let order = book.cancel_order(id)?;
+        let report = self.orders.cancelled(id, now());
+        self.report(report);
+        Ok(order)
+    }
+
+    fn amend_order(
+        &mut self,
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+        owner: Option<&str>,
+    ) -> Result<Execution, EngineError> {
+        self.check_owner(id, owner)?;
+        let account = self.orders.account(id).map(str::to_string);
+        self.count_message(account.as_deref(), true)?;
+        let book = self.book_with_order(id)?;
+        let execution = book.amend_order(id, amount, price, now())?;
+        let filled = execution.fills.iter().map(|fill| fill.amount).sum();
+        let report = self.orders.amended(&execution.order, filled, now());
+        self.report(report);
+        self.report_fills();
+        self.expire_if_gone(id);
+        Ok(execution)
+    }
+
+    /// Hides order `id` from callers confined to another account, as if it
+    /// did not exist.
+    fn check_owner(&self, id: u64, owner: Option<&str>) -> Result<(), EngineError> {
+        match owner {
+            Some(owner) if self.orders.account(id) != Some(owner) => {
+                Err(EngineError::Book(BookError::OrderNotFound(id)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn cancel_all(&mut self, filter: &CancelFilter) -> Vec<Order> {
+        let mut cancelled = Vec::new();
+        for index in 0..self.books.len() {
+            if let Ok(book) = self.touch(index) {
+                cancelled.extend(book.cancel_all(filter));
+            }
+        }
+        let now = now();
+        for order in &cancelled {
+            let report = self.orders.cancelled(order.id, now);
+            self.report(report);
+        }
+        cancelled
+    }
+
+    /// Fires every dead man's switch whose deadline has passed.
+    fn expire_cancel_deadlines(&mut self, now_ms: u64) {
+        let expired: Vec<String> = self
+            .cancel_deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now_ms)
+            .map(|(account, _)| account.clone())
+            .collect();
+        for account in expired {
+            self.cancel_deadlines.remove(&account);
+            let cancelled = self.cancel_all(&CancelFilter {
+                account: Some(account.clone()),
+                ..CancelFilter::default()
+            });
+            tracing::warn!(
+                account = %account,
+                cancelled = cancelled.len(),
+                "dead man's switch fired"
+            );
+        }
+    }
+
+    fn apply_operation(
+        &mut self,
+        operation: Operation,
+        owner: Option<&str>,
+    ) -> Result<Outcome, EngineError> {
+        match operation {
+            Operation::New { order } => self.add_order(order, None).map(Outcome::Execution),
+            Operation::Cancel { id } => self.cancel_order(id, owner).map(Outcome::Cancelled),
+            Operation::Amend { id, amount, price } => self
+                .amend_order(id, amount, price, owner)
+                .map(Outcome::Execution),
+        }
+    }
+
+    fn collect(&self, orders: impl Fn(&OrderBook) -> Vec<Order>) -> Vec<Order> {
+        self.in_service()
+            .flat_map(|(_, book)| orders(book))
+            .collect()
+    }
+
+    fn apply(&mut self, command: Command) {
+        // Replies are dropped silently when the requester has gone away.
+        match command {
+            Command::AddOrder {
+                order,
+                idempotency_key,
+                reply,
+            } => {
+                let _ = reply.send(self.add_order(order, idempotency_key.as_deref()));
+            }
+            Command::CancelOrder { id, account, reply } => {
+                let _ = reply.send(self.cancel_order(id, account.as_deref()));
+            }
+            Command::AmendOrder {
+                id,
+                amount,
+                price,
+                account,
+                reply,
+            } => {
+                let _ = reply.send(self.amend_order(id, amount, price, account.as_deref()));
+            }
+            Command::Batch {
+                operations,
+                account,
+                reply,
+            } => {
+                tracing::info!(operations = operations.len(), "applying batch");
+                let results = operations
+                    .into_iter()
+                    .map(|operation| self.apply_operation(operation, account.as_deref()))
+                    .collect();
+                let _ = reply.send(results);
+            }
+            Command::MassCancel { filter, reply } => {
+                let _ = reply.send(self.cancel_all(&filter));
+            }
+            Command::CancelAfter {
+                account,
+                timeout,
+                reply,
+            } => {
+                let deadline = timeout.map(|timeout| now_millis() + timeout.as_millis() as u64);
+                match deadline {
+                    Some(deadline) => self.cancel_deadlines.insert(account, deadline),
+                    None => self.cancel_deadlines.remove(&account),
+                };
+                let _ = reply.send(deadline);
+            }
+            Command::GetOrders(reply) => {
+                let _ = reply.send(self.collect(OrderBook::get_orders));
+            }
+            Command::GetOrder { id, reply } => {
+                let order = self
+                    .orders
+                    .get(id)
+                    .ok_or(EngineError::Book(BookError::OrderNotFound(id)));
+                let _ = reply.send(order);
+            }
+            Command::QueryOrders { query, reply } => {
+                let _ = reply.send(self.orders.query(&query));
+            }
+            Command::QueryFills { query, reply } => {
+                let _ = reply.send(self.orders.fills(&query));
+            }
+            Command::QueryTrades { query, reply } => {
+                let _ = reply.send(self.orders.trades(&query));
+            }
+            Command::GetBookPage { query, reply } => {
+                // Reads never open a book for a pair nobody has traded.
+                let page = self.book(query.symbol.as_deref()).map(|book| {
+                    book.resting_page(
+                        query.side,
+                        query.symbol.as_deref(),
+                        query.after,
+                        query.limit,
+                    )
+                });
+                let _ = reply.send(page);
+            }
+            Command::GetBids(reply) => {
+                let _ = reply.send(self.collect(OrderBook::get_all_bids));
+            }
+            Command::GetAsks(reply) => {
+                let _ = reply.send(self.collect(OrderBook::get_all_asks));
+            }
+            Command::GetState { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| book.state()));
+            }
+            Command::GetIndicative { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| book.indicative()));
+            }
+            Command::GetDepth(reply) => {
+                // A catch-all book holds no orders of its own.
+                let depth = self
+                    .in_service()
+                    .map(|(_, book)| book)
+                    .filter(|book| !book.is_catch_all())
+                    .map(|book| (book.symbol().to_string(), book.depth()))
+                    .collect();
+                let _ = reply.send(depth);
+            }
+            Command::GetLatencies(reply) => {
+                let _ = reply.send(self.latencies.report());
+            }
+            Command::ResetLatencies(reply) => {
+                self.latencies.reset();
+                let _ = reply.send(());
+            }
+            Command::Halt { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| {
+                    book.halt(HaltReason::Manual);
+                    book.state()
+                }));
+            }
+            Command::Resume { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| {
+                    book.resume();
+                    book.state()
+                }));
+            }
+            Command::OpenCall { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| {
+                    book.open_call(None);
+                    book.state()
+                }));
+            }
+            Command::Uncross { symbol, reply } => {
+                let _ = reply.send(self.book(symbol.as_deref()).map(|book| book.uncross()));
+            }
+            Command::Tick => {
+                let now = now();
+                // One book at a time, so that a panic takes out only the
+                // book that was ticking.
+                for index in 0..self.books.len() {
+                    if self.touch_only(index) {
+                        self.books[index].tick(now);
+                        self.report_fills();
+                        self.books[index].publish_depth();
+                    }
+                }
+                self.touching.clear();
+                self.expire_cancel_deadlines(now_millis());
+            }
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, OrderType};
+
+    fn add_order(engine: &mut Engine, pair: &str) -> Result<Execution, EngineError> {
+        let order = Order::new(
+            0,
+            OrderType::Limit,
+            pair.to_string(),
+            1.0,
+            Some(Price::new(100.0)),
+            0,
+            BidOrAsk::Bid,
+        );
+        let (reply, mut rx) = oneshot::channel();
+        engine.handle(Command::AddOrder {
+            order,
+            idempotency_key: None,
+            reply,
+        });
+        rx.try_recv().unwrap()
+    }
+
+    #[test]
+    fn test_panicked_book_goes_out_of_service() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let mut engine = Engine::new(OrderBook::new(tx));
+        add_order(&mut engine, "BTC-USD").unwrap();
+        add_order(&mut engine, "ETH-USD").unwrap();
+
+        // As if the last command had panicked halfway through the BTC book.
+        let btc = engine
+            .books
+            .iter()
+            .position(|book| book.symbol() == "BTC-USD");
+        engine.touching = vec![btc.unwrap()];
+        engine.take_out_of_service();
+
+        assert!(matches!(
+            add_order(&mut engine, "BTC-USD"),
+            Err(EngineError::OutOfService(symbol)) if symbol == "BTC-USD"
+        ));
+        add_order(&mut engine, "ETH-USD").unwrap();
+        let (reply, mut rx) = oneshot::channel();
+        engine.handle(Command::GetOrders(reply));
+        let orders = rx.try_recv().unwrap();
+        assert_eq!(orders.len(), 2);
+        assert!(orders.iter().all(|order| order.trading_pair == "ETH-USD"));
+    }
+
+    #[test]
+    fn test_only_orders_open_books_and_only_so_many() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let mut engine = Engine::new(OrderBook::new(tx));
+        let (reply, mut rx) = oneshot::channel();
+        engine.handle(Command::GetBookPage {
+            query: BookQuery {
+                symbol: Some("DOGE-USD".to_string()),
+                side: BidOrAsk::Bid,
+                after: None,
+                limit: 10,
+            },
+            reply,
+        });
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Err(EngineError::UnknownSymbol(symbol)) if symbol == "DOGE-USD"
+        ));
+        assert_eq!(engine.books.len(), 1);
+
+        for pair in 0..MAX_OPENED_BOOKS {
+            add_order(&mut engine, &format!("PAIR{}-USD", pair)).unwrap();
+        }
+        assert!(matches!(
+            add_order(&mut engine, "DOGE-USD"),
+            Err(EngineError::UnknownSymbol(symbol)) if symbol == "DOGE-USD"
+        ));
+        add_order(&mut engine, "PAIR0-USD").unwrap();
+        assert_eq!(engine.books.len(), MAX_OPENED_BOOKS + 1);
+    }
+}
@@ -0,0 +1,616 @@
+use crate::models::{
+    BidOrAsk, ExecutionReport, Fill, FillRecord, Order, OrderRecord, OrderStatus, ReportKind,
+    TradeRecord,
+};
+use crate::persistence::{Record, Writer};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Amounts within this of the order quantity count as fully filled.
+const FILL_EPSILON: f64 = 1e-9;
+/// How many final orders, fills and trades are kept for queries, each.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+/// Results per page when a query does not say.
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// Selects orders for a status query. Without a status only working orders
+/// match; with a final one, only orders still in the history.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OrderQuery {
+    pub status: Option<OrderStatus>,
+    pub symbol: Option<String>,
+    pub side: Option<BidOrAsk>,
+    pub account: Option<String>,
+    /// Only orders with a higher id, for cursor paging.
+    #[serde(skip)]
+    pub after: Option<u64>,
+    /// Only orders whose latest change the history database has yet to
+    /// store.
+    #[serde(skip)]
+    pub unwritten: bool,
+    /// Matches to skip, in order id order.
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for OrderQuery {
+    fn default() -> Self {
+        Self {
+            status: None,
+            symbol: None,
+            side: None,
+            account: None,
+            after: None,
+            unwritten: false,
+            offset: 0,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+/// Selects fills, oldest first.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FillQuery {
+    pub account: Option<String>,
+    pub symbol: Option<String>,
+    pub side: Option<BidOrAsk>,
+    pub order_id: Option<u64>,
+    /// Only fills after the one with this trade id and order id, for
+    /// cursor paging.
+    #[serde(skip)]
+    pub after: Option<(u64, u64)>,
+    /// Only fills the history database has yet to store.
+    #[serde(skip)]
+    pub unwritten: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for FillQuery {
+    fn default() -> Self {
+        Self {
+            account: None,
+            symbol: None,
+            side: None,
+            order_id: None,
+            after: None,
+            unwritten: false,
+            offset: 0,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+/// Selects trades from the tape, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeQuery {
+    pub symbol: Option<String>,
+    /// The aggressor's side. Auction trades have none and never match.
+    pub side: Option<BidOrAsk>,
+    /// Only trades with a higher trade id.
+    pub after: Option<u64>,
+    pub limit: usize,
+}
+
+impl Default for TradeQuery {
+    fn default() -> Self {
+        Self {
+            symbol: None,
+            side: None,
+            after: None,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tracked {
+    /// The order as accepted, with `amount` holding its total quantity.
+    order: Order,
+    filled: f64,
+    /// Sum of price times amount over the fills, for the average price.
+    notional: f64,
+    status: OrderStatus,
+    /// When the order last changed, in Unix seconds.
+    updated: u64,
+    /// Why the order was rejected.
+    reason: Option<String>,
+    /// The number the writer gave the order's latest record.
+    written: u64,
+}
+
+impl Tracked {
+    fn new(order: Order, status: OrderStatus, timestamp: u64) -> Self {
+        Self {
+            order,
+            filled: 0.0,
+            notional: 0.0,
+            status,
+            updated: timestamp,
+            reason: None,
+            written: 0,
+        }
+    }
+
+    fn remaining(&self) -> f64 {
+        if self.status.is_final() {
+            0.0
+        } else {
+            self.order.amount - self.filled
+        }
+    }
+
+    fn average_price(&self) -> Option<f64> {
+        (self.filled > 0.0).then(|| self.notional / self.filled)
+    }
+
+    fn record(&self) -> OrderRecord {
+        OrderRecord {
+            order: self.order.clone(),
+            status: self.status,
+            filled: self.filled,
+            remaining: self.remaining(),
+            average_price: self.average_price(),
+            updated: self.updated,
+            reason: self.reason.clone(),
+        }
+    }
+}
+
+/// Follows each accepted order until it is final and describes every change
+/// as an `ExecutionReport`. Final orders, fills and the public trade tape
+/// are kept, the oldest dropped first, until there are more than
+/// `history_limit` of each. With a `Writer`, every change to an order and
+/// every fill is also sent on to the history database.
+#[derive(Debug)]
+pub struct OrderTracker {
+    orders: HashMap<u64, Tracked>,
+    finished: BTreeMap<u64, Tracked>,
+    /// The ids in `finished`, in the order the orders became final.
+    finish_order: VecDeque<u64>,
+    /// Fills with the number the writer gave each.
+    fills: VecDeque<(u64, FillRecord)>,
+    trades: VecDeque<TradeRecord>,
+    history_limit: usize,
+    writer: Option<Writer>,
+}
+
+impl Default for OrderTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl OrderTracker {
+    pub fn new(history_limit: usize) -> Self {
+        Self {
+            orders: HashMap::new(),
+            finished: BTreeMap::new(),
+            finish_order: VecDeque::new(),
+            fills: VecDeque::new(),
+            trades: VecDeque::new(),
+            history_limit,
+            writer: None,
+        }
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+    }
+
+    pub fn set_writer(&mut self, writer: Writer) {
+        self.writer = Some(writer);
+    }
+
+    /// Starts tracking `order`, of which `filled` has already traded on
+    /// arrival; those fills are reported separately through `fill`.
+    pub fn accepted(
+        &mut self,
+        order: &Order,
+        filled: f64,
+        timestamp: u64,
+    ) -> Option<ExecutionReport> {
+        let mut order = order.clone();
+        order.amount += filled;
+        let mut tracked = Tracked::new(order, OrderStatus::New, timestamp);
+        let report = report(&tracked, ReportKind::New, timestamp);
+        write(&mut self.writer, &mut tracked, true);
+        self.orders.insert(tracked.order.id, tracked);
+        report
+    }
+
+    /// Describes an order the engine refused and files it straight into
+    /// the history.
+    pub fn rejected(
+        &mut self,
+        order: &Order,
+        reason: String,
+        timestamp: u64,
+    ) -> Option<ExecutionReport> {
+        let mut tracked = Tracked::new(order.clone(), OrderStatus::Rejected, timestamp);
+        tracked.reason = Some(reason);
+        let mut report = report(&tracked, ReportKind::Rejected, timestamp);
+        if let Some(report) = report.as_mut() {
+            report.reason = tracked.reason.clone();
+        }
+        write(&mut self.writer, &mut tracked, true);
+        self.archive(tracked);
+        report
+    }
+
+    pub fn fill(&mut self, id: u64, fill: Fill, timestamp: u64) -> Option<ExecutionReport> {
+        let tracked = self.orders.get_mut(&id)?;
+        let record = FillRecord {
+            order_id: id,
+            client_order_id: tracked.order.client_order_id.clone(),
+            account: tracked.order.account.clone(),
+            symbol: tracked.order.trading_pair.clone(),
+            side: tracked.order.bid_or_ask,
+            fill: fill.clone(),
+            timestamp,
+        };
+        let written = match &mut self.writer {
+            Some(writer) => writer.send(Record::Fill(record.clone())),
+            None => 0,
+        };
+        self.fills.push_back((written, record));
+        if self.fills.len() > self.history_limit {
+            self.fills.pop_front();
+        }
+        tracked.updated = timestamp;
+        tracked.filled += fill.amount;
+        tracked.notional += fill.price.to_f64() * fill.amount;
+        tracked.status = if tracked.filled >= tracked.order.amount - FILL_EPSILON {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        let mut report = report(tracked, ReportKind::Fill, timestamp);
+        if let Some(report) = report.as_mut() {
+            report.fill = Some(fill);
+        }
+        write(&mut self.writer, tracked, false);
+        self.forget_if_final(id);
+        report
+    }
+
+    /// Records a new working quantity and price for an order. Fills the
+    /// amend itself caused are reported separately.
+    pub fn amended(
+        &mut self,
+        order: &Order,
+        filled: f64,
+        timestamp: u64,
+    ) -> Option<ExecutionReport> {
+        let tracked = self.orders.get_mut(&order.id)?;
+        tracked.order.amount = tracked.filled + order.amount + filled;
+        tracked.order.price = order.price;
+        tracked.updated = timestamp;
+        let report = report(tracked, ReportKind::Amended, timestamp);
+        write(&mut self.writer, tracked, false);
+        report
+    }
+
+    pub fn cancelled(&mut self, id: u64, timestamp: u64) -> Option<ExecutionReport> {
+        self.finish(id, OrderStatus::Cancelled, ReportKind::Cancelled, timestamp)
+    }
+
+    pub fn expired(&mut self, id: u64, timestamp: u64) -> Option<ExecutionReport> {
+        self.finish(id, OrderStatus::Expired, ReportKind::Expired, timestamp)
+    }
+
+    /// The account of a working order, if it has one.
+    pub fn account(&self, id: u64) -> Option<&str> {
+        self.orders.get(&id)?.order.account.as_deref()
+    }
+
+    /// Whether `id` has been accepted and is not yet final.
+    pub fn is_working(&self, id: u64) -> bool {
+        self.orders.contains_key(&id)
+    }
+
+    /// The trading pair an order was entered for, while it is still known.
+    pub fn symbol(&self, id: u64) -> Option<&str> {
+        self.orders
+            .get(&id)
+            .or_else(|| self.finished.get(&id))
+            .map(|tracked| tracked.order.trading_pair.as_str())
+    }
+
+    /// Adds a trade to the tape. Trade ids must be increasing.
+    pub fn trade(&mut self, trade: TradeRecord) {
+        self.trades.push_back(trade);
+        if self.trades.len() > self.history_limit {
+            self.trades.pop_front();
+        }
+    }
+
+    /// A working order, or a final one still in the history.
+    pub fn get(&self, id: u64) -> Option<OrderRecord> {
+        self.orders
+            .get(&id)
+            .or_else(|| self.finished.get(&id))
+            .map(Tracked::record)
+    }
+
+    /// The orders `query` selects, by ascending id.
+    pub fn query(&self, query: &OrderQuery) -> Vec<OrderRecord> {
+        let selected = |tracked: &&Tracked| {
+            query.status.is_none_or(|status| tracked.status == status)
+                && query
+                    .symbol
+                    .as_ref()
+                    .is_none_or(|symbol| tracked.order.trading_pair == *symbol)
+                && query
+                    .side
+                    .is_none_or(|side| tracked.order.bid_or_ask == side)
+                && query
+                    .account
+                    .as_ref()
+                    .is_none_or(|account| tracked.order.account.as_ref() == Some(account))
+                && query.after.is_none_or(|after| tracked.order.id > after)
+                && (!query.unwritten || self.is_unwritten(tracked.written))
+        };
+        let mut found: Vec<&Tracked> = match query.status {
+            Some(status) if status.is_final() => self.finished.values().filter(selected).collect(),
+            _ => self.orders.values().filter(selected).collect(),
+        };
+        found.sort_unstable_by_key(|tracked| tracked.order.id);
+        found
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .map(Tracked::record)
+            .collect()
+    }
+
+    /// The fills `query` selects, oldest first.
+    pub fn fills(&self, query: &FillQuery) -> Vec<FillRecord> {
+        let start = match query.after {
+            Some((trade_id, order_id)) => self
+                .fills
+                .iter()
+                .position(|(_, fill)| fill.fill.trade_id == trade_id && fill.order_id == order_id)
+                .map(|position| position + 1)
+                .unwrap_or_else(|| {
+                    self.fills
+                        .partition_point(|(_, fill)| fill.fill.trade_id <= trade_id)
+                }),
+            None => 0,
+        };
+        self.fills
+            .range(start..)
+            .filter(|(written, fill)| {
+                query
+                    .account
+                    .as_ref()
+                    .is_none_or(|account| fill.account.as_ref() == Some(account))
+                    && query
+                        .symbol
+                        .as_ref()
+                        .is_none_or(|symbol| fill.symbol == *symbol)
+                    && query.side.is_none_or(|side| fill.side == side)
+                    && query.order_id.is_none_or(|id| fill.order_id == id)
+                    && (!query.unwritten || self.is_unwritten(*written))
+            })
+            .skip(query.offset)
+            .take(query.limit)
+            .map(|(_, fill)| fill.clone())
+            .collect()
+    }
+
+    /// The trades `query` selects, oldest first.
+    pub fn trades(&self, query: &TradeQuery) -> Vec<TradeRecord> {
+        let start = query.after.map_or(0, |after| {
+            self.trades.partition_point(|trade| trade.trade_id <= after)
+        });
+        self.trades
+            .range(start..)
+            .filter(|trade| {
+                query
+                    .symbol
+                    .as_ref()
+                    .is_none_or(|symbol| trade.symbol == *symbol)
+                    && query.side.is_none_or(|side| trade.aggressor == Some(side))
+            })
+            .take(query.limit)
+            .cloned()
+            .collect()
+    }
+
+    fn finish(
+        &mut self,
+        id: u64,
+        status: OrderStatus,
+        kind: ReportKind,
+        timestamp: u64,
+    ) -> Option<ExecutionReport> {
+        let mut tracked = self.orders.remove(&id)?;
+        tracked.status = status;
+        tracked.updated = timestamp;
+        let report = report(&tracked, kind, timestamp);
+        write(&mut self.writer, &mut tracked, false);
+        self.archive(tracked);
+        report
+    }
+
+    fn forget_if_final(&mut self, id: u64) {
+        if self
+            .orders
+            .get(&id)
+            .is_some_and(|tracked| tracked.status.is_final())
+        {
+            if let Some(tracked) = self.orders.remove(&id) {
+                self.archive(tracked);
+            }
+        }
+    }
+
+    /// Whether the writer has yet to store the record it numbered
+    /// `written`. Without a writer nothing is waiting.
+    fn is_unwritten(&self, written: u64) -> bool {
+        self.writer
+            .as_ref()
+            .is_some_and(|writer| !writer.is_done(written))
+    }
+
+    fn archive(&mut self, tracked: Tracked) {
+        self.finish_order.push_back(tracked.order.id);
+        self.finished.insert(tracked.order.id, tracked);
+        while self.finished.len() > self.history_limit {
+            let Some(id) = self.finish_order.pop_front() else {
+                break;
+            };
+            self.finished.remove(&id);
+        }
+    }
+}
+
+/// Sends the order's state to the writer, if there is one, as a `new` order
+/// or as a change to one sent before.
+fn write(writer: &mut Option<Writer>, tracked: &mut Tracked, new: bool) {
+    if let Some(writer) = writer {
+        let record = tracked.record();
+        tracked.written = writer.send(match new {
+            true => Record::NewOrder(record),
+            false => Record::Order(record),
+        });
+    }
+}
+
+/// Only orders with an account get reports: nobody else could receive them.
+fn report(tracked: &Tracked, kind: ReportKind, timestamp: u64) -> Option<ExecutionReport> {
+    let order = &tracked.order;
+    Some(ExecutionReport {
+        kind,
+        order_id: order.id,
+        client_order_id: order.client_order_id.clone(),
+        account: order.account.clone()?,
+        symbol: order.trading_pair.clone(),
+        side: order.bid_or_ask,
+        order_type: order.order_type,
+        price: order.price,
+        status: tracked.status,
+        amount: order.amount,
+        filled: tracked.filled,
+        remaining: tracked.remaining(),
+        average_price: tracked.average_price(),
+        fill: None,
+        reason: None,
+        timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, Liquidity, OrderType, Price};
+
+    fn fill(price: f64, amount: f64) -> Fill {
+        Fill {
+            trade_id: 1,
+            price: Price::new(price),
+            amount,
+            liquidity: Liquidity::Maker,
+            fee: 0.0,
+            fee_asset: None,
+        }
+    }
+
+    #[test]
+    fn test_tracks_fills_to_completion() {
+        let mut tracker = OrderTracker::default();
+        let mut order = Order::new(
+            1,
+            OrderType::Limit,
+            "BTC-USD".to_string(),
+            3.0,
+            Some(Price::new(100.0)),
+            0,
+            BidOrAsk::Bid,
+        );
+        assert!(tracker.accepted(&order, 0.0, 0).is_none());
+        assert!(tracker.is_working(1));
+
+        order.id = 2;
+        order.account = Some("desk-7".to_string());
+        let new = tracker.accepted(&order, 1.0, 0).unwrap();
+        assert_eq!(
+            (new.status, new.amount, new.remaining),
+            (OrderStatus::New, 4.0, 4.0)
+        );
+
+        let partial = tracker.fill(2, fill(100.0, 1.0), 1).unwrap();
+        assert_eq!(partial.status, OrderStatus::PartiallyFilled);
+        let filled = tracker.fill(2, fill(103.0, 3.0), 2).unwrap();
+        assert_eq!(filled.status, OrderStatus::Filled);
+        assert_eq!((filled.filled, filled.remaining), (4.0, 0.0));
+        assert_eq!(filled.average_price, Some(102.25));
+        assert!(!tracker.is_working(2));
+        assert!(tracker.cancelled(2, 3).is_none());
+    }
+
+    #[test]
+    fn test_history_keeps_the_latest_final_orders_and_fills() {
+        let mut tracker = OrderTracker::new(2);
+        for id in 1..=3 {
+            let mut order = Order::new(
+                id,
+                OrderType::Limit,
+                "BTC-USD".to_string(),
+                1.0,
+                Some(Price::new(100.0)),
+                0,
+                BidOrAsk::Ask,
+            );
+            order.account = Some("desk-7".to_string());
+            tracker.accepted(&order, 0.0, 0);
+            tracker.fill(id, fill(100.0, 1.0), id);
+        }
+        assert!(tracker.get(1).is_none());
+        assert_eq!(tracker.get(3).unwrap().status, OrderStatus::Filled);
+
+        let filled = tracker.query(&OrderQuery {
+            status: Some(OrderStatus::Filled),
+            ..OrderQuery::default()
+        });
+        let ids: Vec<u64> = filled.iter().map(|record| record.order.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(tracker.query(&OrderQuery::default()).is_empty());
+
+        let fills = tracker.fills(&FillQuery {
+            account: Some("desk-7".to_string()),
+            offset: 1,
+            ..FillQuery::default()
+        });
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].order_id, 3);
+    }
+
+    #[test]
+    fn test_history_drops_the_orders_that_finished_first() {
+        let mut tracker = OrderTracker::new(2);
+        let mut order = Order::new(
+            1,
+            OrderType::Limit,
+            "BTC-USD".to_string(),
+            1.0,
+            Some(Price::new(100.0)),
+            0,
+            BidOrAsk::Bid,
+        );
+        tracker.accepted(&order, 0.0, 0);
+        for id in 2..=3 {
+            order.id = id;
+            tracker.accepted(&order, 0.0, id);
+            tracker.cancelled(id, id);
+        }
+        // The oldest order finishes last, so it is the newest in the history.
+        tracker.fill(1, fill(100.0, 1.0), 4);
+        assert!(tracker.get(2).is_none());
+        assert_eq!(tracker.get(1).unwrap().status, OrderStatus::Filled);
+        assert_eq!(tracker.get(3).unwrap().status, OrderStatus::Cancelled);
+    }
+}
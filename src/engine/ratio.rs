@@ -0,0 +1,128 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// What happens to an account whose order-to-trade ratio is too high.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatioAction {
+    /// Log and count the breach, but keep accepting orders.
+    Flag,
+    /// Refuse the account's new orders and amends for the rest of the
+    /// window. Cancels are always accepted.
+    Throttle,
+}
+
+/// How many order messages an account may send per trade.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrderToTradeLimit {
+    /// Most new orders, amends and cancels per trade.
+    pub max_ratio: f64,
+    /// Accounts that sent fewer messages in the window are never checked.
+    pub min_orders: u64,
+    /// Counts start over every `window_secs` seconds.
+    pub window_secs: u64,
+    pub action: RatioAction,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    messages: u64,
+    trades: u64,
+    /// Whether the breach was already reported in this window.
+    flagged: bool,
+}
+
+/// Counts order messages and trades per account over fixed windows.
+#[derive(Debug)]
+pub struct OrderToTrade {
+    limit: OrderToTradeLimit,
+    window_start: u64,
+    accounts: HashMap<String, Counts>,
+}
+
+/// The outcome of checking an account's ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Within,
+    /// Over the limit and not yet reported in this window.
+    Breached(RatioAction),
+    /// Over the limit and already reported.
+    StillBreached(RatioAction),
+}
+
+impl OrderToTrade {
+    pub fn new(limit: OrderToTradeLimit) -> Self {
+        Self {
+            limit,
+            window_start: 0,
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// Counts an order message from `account` and checks its ratio.
+    /// `timestamp` is in seconds.
+    pub fn message(&mut self, account: &str, timestamp: u64) -> Verdict {
+        self.roll(timestamp);
+        let counts = self.accounts.entry(account.to_string()).or_default();
+        counts.messages += 1;
+        let ratio = counts.messages as f64 / counts.trades.max(1) as f64;
+        if counts.messages < self.limit.min_orders || ratio <= self.limit.max_ratio {
+            Verdict::Within
+        } else if counts.flagged {
+            Verdict::StillBreached(self.limit.action)
+        } else {
+            counts.flagged = true;
+            Verdict::Breached(self.limit.action)
+        }
+    }
+
+    pub fn traded(&mut self, account: &str, timestamp: u64) {
+        self.roll(timestamp);
+        self.accounts.entry(account.to_string()).or_default().trades += 1;
+    }
+
+    fn roll(&mut self, timestamp: u64) {
+        if timestamp >= self.window_start + self.limit.window_secs.max(1) {
+            self.window_start = timestamp;
+            self.accounts.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_once_per_window_and_trades_restore_the_ratio() {
+        let mut monitor = OrderToTrade::new(OrderToTradeLimit {
+            max_ratio: 2.0,
+            min_orders: 3,
+            window_secs: 60,
+            action: RatioAction::Throttle,
+        });
+        assert_eq!(monitor.message("desk-7", 100), Verdict::Within);
+        assert_eq!(monitor.message("desk-7", 100), Verdict::Within);
+        assert_eq!(
+            monitor.message("desk-7", 100),
+            Verdict::Breached(RatioAction::Throttle)
+        );
+        assert_eq!(
+            monitor.message("desk-7", 110),
+            Verdict::StillBreached(RatioAction::Throttle)
+        );
+        assert_eq!(monitor.message("desk-8", 110), Verdict::Within);
+
+        for _ in 0..3 {
+            monitor.traded("desk-7", 120);
+        }
+        assert_eq!(monitor.message("desk-7", 120), Verdict::Within);
+
+        // A new window starts from nothing.
+        for _ in 0..5 {
+            monitor.message("desk-8", 130);
+        }
+        assert_eq!(monitor.message("desk-8", 160), Verdict::Within);
+    }
+}
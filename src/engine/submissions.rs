@@ -0,0 +1,191 @@
+use crate::engine::EngineError;
+use crate::models::{Execution, Order};
+use std::collections::{HashMap, VecDeque};
+
+/// How many accepted orders are remembered for retries. Once full, the
+/// oldest are forgotten first.
+pub const DEFAULT_RETRY_WINDOW: usize = 100_000;
+
+/// Something a client can retry an order submission by, scoped to the
+/// account that sent it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RetryKey {
+    ClientOrderId {
+        account: Option<String>,
+        id: String,
+    },
+    Idempotency {
+        account: Option<String>,
+        key: String,
+    },
+}
+
+#[derive(Debug)]
+struct Accepted {
+    keys: Vec<RetryKey>,
+    request: Order,
+    execution: Execution,
+}
+
+/// Accepted orders by client order id and `Idempotency-Key`, so that a
+/// retried submission returns the original result instead of trading again.
+/// Rejected orders are not remembered: they created nothing, so a retry is
+/// simply evaluated afresh.
+#[derive(Debug)]
+pub struct Submissions {
+    capacity: usize,
+    keys: HashMap<RetryKey, u64>,
+    accepted: HashMap<u64, Accepted>,
+    /// Order ids in the order they were accepted, for eviction.
+    arrival: VecDeque<u64>,
+}
+
+impl Default for Submissions {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_RETRY_WINDOW)
+    }
+}
+
+impl Submissions {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            keys: HashMap::new(),
+            accepted: HashMap::new(),
+            arrival: VecDeque::new(),
+        }
+    }
+
+    /// The result of an earlier submission sharing one of `order`'s keys:
+    /// its execution if it was the same order, or an error if the key has
+    /// been reused for a different one. `None` for a new order.
+    pub fn find(
+        &self,
+        order: &Order,
+        idempotency_key: Option<&str>,
+    ) -> Option<Result<Execution, EngineError>> {
+        let accepted = retry_keys(order, idempotency_key)
+            .iter()
+            .find_map(|key| self.keys.get(key))
+            .and_then(|id| self.accepted.get(id))?;
+        Some(if same_request(&accepted.request, order) {
+            Ok(accepted.execution.clone())
+        } else {
+            Err(EngineError::DuplicateOrder(accepted.execution.order.id))
+        })
+    }
+
+    /// Remembers an accepted order under every key it carries.
+    pub fn remember(
+        &mut self,
+        request: Order,
+        idempotency_key: Option<&str>,
+        execution: &Execution,
+    ) {
+        let keys = retry_keys(&request, idempotency_key);
+        if keys.is_empty() || self.capacity == 0 {
+            return;
+        }
+        while self.arrival.len() >= self.capacity {
+            let Some(oldest) = self.arrival.pop_front() else {
+                break;
+            };
+            if let Some(forgotten) = self.accepted.remove(&oldest) {
+                for key in &forgotten.keys {
+                    self.keys.remove(key);
+                }
+            }
+        }
+        for key in &keys {
+            self.keys.insert(key.clone(), request.id);
+        }
+        self.arrival.push_back(request.id);
+        self.accepted.insert(
+            request.id,
+            Accepted {
+                keys,
+                request,
+                execution: execution.clone(),
+            },
+        );
+    }
+}
+
+fn retry_keys(order: &Order, idempotency_key: Option<&str>) -> Vec<RetryKey> {
+    let mut keys = Vec::new();
+    if let Some(id) = &order.client_order_id {
+        keys.push(RetryKey::ClientOrderId {
+            account: order.account.clone(),
+            id: id.clone(),
+        });
+    }
+    if let Some(key) = idempotency_key {
+        keys.push(RetryKey::Idempotency {
+            account: order.account.clone(),
+            key: key.to_string(),
+        });
+    }
+    keys
+}
+
+/// Whether two submissions ask for the same order. The engine-assigned id
+/// and the timestamp are not part of the request.
+fn same_request(a: &Order, b: &Order) -> bool {
+    a.order_type == b.order_type
+        && a.trading_pair == b.trading_pair
+        && a.amount == b.amount
+        && a.price == b.price
+        && a.bid_or_ask == b.bid_or_ask
+        && a.client_order_id == b.client_order_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, OrderType, Price};
+
+    fn order(id: u64, client_order_id: &str) -> Order {
+        let mut order = Order::new(
+            id,
+            OrderType::Limit,
+            "BTC-USD".to_string(),
+            1.0,
+            Some(Price::new(100.0)),
+            0,
+            BidOrAsk::Bid,
+        );
+        order.client_order_id = Some(client_order_id.to_string());
+        order.account = Some("desk-7".to_string());
+        order
+    }
+
+    fn execution(order: &Order) -> Execution {
+        Execution {
+            order: order.clone(),
+            fills: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_retry_returns_original_and_reuse_conflicts() {
+        let mut submissions = Submissions::with_capacity(1);
+        let first = order(1, "a");
+        submissions.remember(first.clone(), Some("key-1"), &execution(&first));
+
+        let replayed = submissions.find(&order(2, "a"), None).unwrap().unwrap();
+        assert_eq!(replayed.order.id, 1);
+        let replayed = submissions.find(&order(2, "b"), Some("key-1")).unwrap();
+        assert_eq!(replayed.unwrap_err(), EngineError::DuplicateOrder(1));
+
+        let mut other_account = order(2, "a");
+        other_account.account = Some("desk-8".to_string());
+        assert!(submissions.find(&other_account, None).is_none());
+
+        // The window holds one order, so remembering another forgets both
+        // keys of the first.
+        let second = order(2, "b");
+        submissions.remember(second.clone(), None, &execution(&second));
+        assert!(submissions.find(&order(3, "a"), Some("key-1")).is_none());
+        assert!(submissions.find(&order(3, "b"), None).is_some());
+    }
+}
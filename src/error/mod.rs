@@ -0,0 +1,242 @@
+use crate::auth::AuthError;
+use crate::engine::EngineError;
+use crate::metrics::rejection_reason;
+use crate::order_book::BookError;
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Everything the API can answer with instead of a result. Each variant
+/// carries a stable, machine-readable `code`, such as `off_tick` or
+/// `order_not_found`, and a message for people; responses carry both as
+/// `{"code": ..., "message": ...}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The request is malformed, or the order breaks the instrument's rules.
+    Validation {
+        code: &'static str,
+        message: String,
+    },
+    NotFound {
+        code: &'static str,
+        message: String,
+    },
+    /// The request conflicts with one already accepted.
+    Rejected {
+        code: &'static str,
+        message: String,
+    },
+    /// Trading in the book is halted.
+    Halted {
+        message: String,
+    },
+    /// The caller could not be authenticated, or, with code `forbidden`,
+    /// lacks the permission.
+    Unauthorized {
+        code: &'static str,
+        message: String,
+    },
+    RateLimited {
+        code: &'static str,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The server failed, or with code `unavailable`, is not able to serve
+    /// the request right now.
+    Internal {
+        code: &'static str,
+        message: String,
+    },
+}
+
+/// The JSON body of an error response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+impl Error {
+    pub fn validation(code: &'static str, message: impl fmt::Display) -> Self {
+        Error::Validation {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Validation { code, .. }
+            | Error::NotFound { code, .. }
+            | Error::Rejected { code, .. }
+            | Error::Unauthorized { code, .. }
+            | Error::RateLimited { code, .. }
+            | Error::Internal { code, .. } => code,
+            Error::Halted { .. } => "halted",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Validation { message, .. }
+            | Error::NotFound { message, .. }
+            | Error::Rejected { message, .. }
+            | Error::Halted { message }
+            | Error::Unauthorized { message, .. }
+            | Error::RateLimited { message, .. }
+            | Error::Internal { message, .. } => message,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code().to_string(),
+            message: self.message().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Validation { .. } => StatusCode::BAD_REQUEST,
+            Error::NotFound { .. } => StatusCode::NOT_FOUND,
+            Error::Rejected { .. } => StatusCode::CONFLICT,
+            Error::Halted { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Unauthorized {
+                code: "forbidden", ..
+            } => StatusCode::FORBIDDEN,
+            Error::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::Internal {
+                code: "unavailable" | "out_of_service",
+                ..
+            } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code()).json(self.body());
+        if let Error::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } = self
+        {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        res
+    }
+}
+
+impl From<EngineError> for Error {
+    fn from(err: EngineError) -> Self {
+        let code = rejection_reason(&err);
+        let message = err.to_string();
+        match err {
+            EngineError::Book(BookError::Rejected(_) | BookError::InvalidOrder(_))
+            | EngineError::UnknownSymbol(_)
+            | EngineError::SymbolRequired => Error::Validation { code, message },
+            EngineError::Book(BookError::Halted) => Error::Halted { message },
+            EngineError::Book(BookError::OrderNotFound(_)) => Error::NotFound { code, message },
+            EngineError::DuplicateOrder(_) => Error::Rejected { code, message },
+            EngineError::Throttled(_) => Error::RateLimited {
+                code,
+                message,
+                retry_after: None,
+            },
+            EngineError::Unavailable | EngineError::Internal | EngineError::OutOfService(_) => {
+                Error::Internal { code, message }
+            }
+        }
+    }
+}
+
+/// The history database failed or could not be reached.
+impl From<tokio_postgres::Error> for Error {
+    fn from(err: tokio_postgres::Error) -> Self {
+        tracing::error!(error = %err, "history query failed");
+        Error::Internal {
+            code: "unavailable",
+            message: "order history is unavailable right now".to_string(),
+        }
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(err: AuthError) -> Self {
+        let code = match err {
+            AuthError::MissingSignature => "missing_signature",
+            AuthError::UnknownKey => "unknown_key",
+            AuthError::BadSignature => "bad_signature",
+            AuthError::Expired => "expired_timestamp",
+            AuthError::ReplayedNonce => "replayed_nonce",
+            AuthError::Forbidden(_) => "forbidden",
+        };
+        Error::Unauthorized {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::InstrumentError;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn test_engine_errors_map_to_status_and_code() {
+        let cases = [
+            (
+                EngineError::Book(BookError::Rejected(InstrumentError::OffTick {
+                    price: 1.005,
+                    tick_size: 0.01,
+                })),
+                400,
+                "off_tick",
+            ),
+            (
+                EngineError::Book(BookError::OrderNotFound(7)),
+                404,
+                "order_not_found",
+            ),
+            (EngineError::DuplicateOrder(7), 409, "duplicate_order"),
+            (EngineError::Book(BookError::Halted), 503, "halted"),
+            (EngineError::Throttled("desk-7".into()), 429, "throttled"),
+            (EngineError::Internal, 500, "internal_error"),
+            (
+                EngineError::OutOfService("BTC-USD".into()),
+                503,
+                "out_of_service",
+            ),
+        ];
+        for (err, status, code) in cases {
+            let err = Error::from(err);
+            assert_eq!(err.status_code().as_u16(), status);
+            assert_eq!(err.code(), code);
+        }
+
+        let res =
+            Error::from(AuthError::Forbidden(crate::auth::Permission::Admin)).error_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = res.into_body().try_into_bytes().unwrap();
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "forbidden");
+    }
+}
@@ -0,0 +1,344 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Separates fields on the wire.
+pub const SOH: u8 = 0x01;
+pub const BEGIN_STRING: &str = "FIX.4.4";
+/// Longer messages are treated as a broken stream rather than buffered.
+pub const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+const PREFIX: &[u8] = b"8=FIX.4.4\x01";
+/// `10=nnn` and its SOH.
+const TRAILER_LENGTH: usize = 7;
+
+pub mod tags {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ACCOUNT: u32 = 1;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const BUSINESS_REJECT_REASON: u32 = 380;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const PASSWORD: u32 = 554;
+    pub const LAST_LIQUIDITY_IND: u32 = 851;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+    pub const BUSINESS_MESSAGE_REJECT: &str = "j";
+
+    /// Session-level messages, which are gap filled rather than resent.
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(
+            msg_type,
+            HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON
+        )
+    }
+}
+
+/// Standard header fields other than `BeginString` and `BodyLength`, which
+/// are written when a message is encoded.
+const HEADER_TAGS: [u32; 7] = [
+    tags::MSG_TYPE,
+    tags::SENDER_COMP_ID,
+    tags::TARGET_COMP_ID,
+    tags::MSG_SEQ_NUM,
+    tags::POSS_DUP_FLAG,
+    tags::SENDING_TIME,
+    tags::ORIG_SENDING_TIME,
+];
+
+/// A FIX message as its fields in order, from `MsgType` on. `BeginString`,
+/// `BodyLength` and `CheckSum` are only on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    fields: Vec<(u32, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The stream cannot be split into messages any more.
+    Framing(String),
+    /// The message was framed correctly but is unreadable; it should be
+    /// ignored, dropping the `consumed` bytes it takes up.
+    Garbled { consumed: usize, reason: String },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Framing(reason) => write!(f, "broken FIX stream: {}", reason),
+            DecodeError::Garbled { reason, .. } => write!(f, "garbled FIX message: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            fields: vec![(tags::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl fmt::Display) -> Self {
+        self.push(tag, value);
+        self
+    }
+
+    pub fn push(&mut self, tag: u32, value: impl fmt::Display) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    pub fn msg_type(&self) -> &str {
+        &self.fields[0].1
+    }
+
+    /// The first value of `tag`.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of `tag` parsed, `None` if it is absent and `Err` if it
+    /// does not parse.
+    pub fn parse<T: FromStr>(&self, tag: u32) -> Result<Option<T>, T::Err> {
+        self.get(tag).map(str::parse).transpose()
+    }
+
+    /// A `Y`/`N` field; absent counts as `N`.
+    pub fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.parse(tags::MSG_SEQ_NUM).ok().flatten()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.fields
+            .iter()
+            .map(|(tag, value)| (*tag, value.as_str()))
+    }
+
+    /// The fields after the standard header.
+    pub fn body(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.fields().filter(|(tag, _)| !HEADER_TAGS.contains(tag))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+        let mut out = Vec::with_capacity(body.len() + 32);
+        out.extend_from_slice(PREFIX);
+        out.extend_from_slice(format!("9={}", body.len()).as_bytes());
+        out.push(SOH);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(format!("10={:03}", checksum(&out)).as_bytes());
+        out.push(SOH);
+        out
+    }
+
+    /// Reads the first message in `buf`, with the number of bytes it took,
+    /// or `None` if `buf` does not hold a whole message yet.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, DecodeError> {
+        let prefix = &PREFIX[..buf.len().min(PREFIX.len())];
+        if !buf.starts_with(prefix) {
+            return Err(DecodeError::Framing(format!(
+                "messages must start with 8={}",
+                BEGIN_STRING
+            )));
+        }
+        let rest = &buf[prefix.len()..];
+        let Some(end) = rest.iter().position(|&byte| byte == SOH) else {
+            if rest.len() > 10 {
+                return Err(DecodeError::Framing("BodyLength is too long".to_string()));
+            }
+            return Ok(None);
+        };
+        let body_length = std::str::from_utf8(&rest[..end])
+            .ok()
+            .and_then(|field| field.strip_prefix("9="))
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|&length| length <= MAX_BODY_LENGTH)
+            .ok_or_else(|| DecodeError::Framing("missing or invalid BodyLength".to_string()))?;
+        let body_start = PREFIX.len() + end + 1;
+        let body_end = body_start + body_length;
+        let consumed = body_end + TRAILER_LENGTH;
+        if buf.len() < consumed {
+            return Ok(None);
+        }
+        let trailer = &buf[body_end..consumed];
+        if !trailer.starts_with(b"10=") || trailer[TRAILER_LENGTH - 1] != SOH {
+            return Err(DecodeError::Framing(
+                "BodyLength does not end at CheckSum".to_string(),
+            ));
+        }
+        let garbled = |reason: String| DecodeError::Garbled { consumed, reason };
+        let expected = std::str::from_utf8(&trailer[3..TRAILER_LENGTH - 1])
+            .ok()
+            .and_then(|sum| sum.parse::<u32>().ok());
+        let actual = checksum(&buf[..body_end]);
+        if expected != Some(actual) {
+            return Err(garbled(format!("CheckSum should be {:03}", actual)));
+        }
+        let body = std::str::from_utf8(&buf[body_start..body_end])
+            .map_err(|_| garbled("the body is not UTF-8".to_string()))?;
+        let mut fields = Vec::new();
+        for field in body.split(SOH as char).filter(|field| !field.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .and_then(|(tag, value)| Some((tag.parse::<u32>().ok()?, value)))
+                .filter(|(tag, value)| *tag > 0 && !value.is_empty())
+                .ok_or_else(|| garbled(format!("invalid field {:?}", field)))?;
+            fields.push((tag, value.to_string()));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tags::MSG_TYPE) {
+            return Err(garbled("MsgType must be the third field".to_string()));
+        }
+        Ok(Some((Message { fields }, consumed)))
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|&byte| byte as u32).sum::<u32>() % 256
+}
+
+/// A UTC timestamp in FIX's `YYYYMMDD-HH:MM:SS.sss` form.
+pub fn utc_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (year, month, day) = civil_date(secs / 86_400);
+    let time = secs % 86_400;
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis % 1000
+    )
+}
+
+/// The Gregorian date `days` after 1970-01-01, from Howard Hinnant's
+/// `civil_from_days`.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_and_rejects_bad_checksums() {
+        let message = Message::new(msg_type::HEARTBEAT)
+            .with(tags::SENDER_COMP_ID, "CLIENT")
+            .with(tags::TARGET_COMP_ID, "ORDERBOOK")
+            .with(tags::MSG_SEQ_NUM, 2)
+            .with(tags::TEST_REQ_ID, "t-1");
+        let encoded = message.encode();
+        assert_eq!(
+            String::from_utf8(encoded.clone())
+                .unwrap()
+                .replace('\x01', "|"),
+            "8=FIX.4.4|9=41|35=0|49=CLIENT|56=ORDERBOOK|34=2|112=t-1|10=008|"
+        );
+
+        // Partial input waits for more; two messages are read one by one.
+        assert_eq!(Message::decode(&encoded[..20]), Ok(None));
+        let mut stream = encoded.clone();
+        stream.extend_from_slice(&encoded);
+        let (decoded, consumed) = Message::decode(&stream).unwrap().unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(decoded.seq_num(), Some(2));
+        assert_eq!(decoded.body().collect::<Vec<_>>(), [(112, "t-1")]);
+
+        let mut corrupted = encoded.clone();
+        corrupted[30] = b'X';
+        assert!(matches!(
+            Message::decode(&corrupted),
+            Err(DecodeError::Garbled { consumed, .. }) if consumed == encoded.len()
+        ));
+        assert!(matches!(
+            Message::decode(b"8=FIX.4.2\x019=5\x01"),
+            Err(DecodeError::Framing(_))
+        ));
+    }
+
+    #[test]
+    fn test_formats_utc_timestamps() {
+        assert_eq!(utc_timestamp(0), "19700101-00:00:00.000");
+        assert_eq!(utc_timestamp(951_782_400_123), "20000229-00:00:00.123");
+        assert_eq!(utc_timestamp(1_792_368_245_007), "20261019-00:04:05.007");
+    }
+}
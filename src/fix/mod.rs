@@ -0,0 +1,964 @@
+//! A FIX 4.4 order entry gateway over plain TCP. Sessions log on with
+//! their account token as `Password`, then place, cancel and replace orders
+//! with NewOrderSingle, OrderCancelRequest and OrderCancelReplaceRequest.
+//! Execution reports come from the engine's reports for the account, so
+//! fills on resting orders are reported as they happen.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use actix::{Actor, ActorContext, Addr, Context, Handler};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::auth::Accounts;
+use crate::engine::{EngineError, EngineHandle, OrderQuery};
+use crate::market_data::{self, Deliver, Disconnect, Hub, SessionId, Subscribe};
+use crate::models::{ExecutionReport, OrderRecord, ReportKind};
+
+mod message;
+mod orders;
+mod session;
+
+pub use message::{msg_type, tags, utc_timestamp, DecodeError, Message, BEGIN_STRING};
+use orders::{FieldError, Target};
+use session::{SessionState, MAX_QUEUED_REPORTS};
+
+/// How long a new connection has to send its Logon.
+pub const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+/// The `HeartBtInt` values, in seconds, a Logon may ask for.
+pub const HEARTBEAT_RANGE: std::ops::RangeInclusive<u64> = 1..=300;
+/// How long a logged off session is kept, unless configured otherwise.
+pub const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(60 * 60);
+/// How many sessions the gateway keeps, logged on or not. Logons that would
+/// start another are refused.
+pub const MAX_SESSIONS: usize = 1_000;
+/// How often a session checks whether a heartbeat or test request is due.
+const TIMER_RESOLUTION: Duration = Duration::from_millis(200);
+/// How often the gateway looks for expired sessions.
+const EXPIRY_CHECK: Duration = Duration::from_secs(60);
+/// `SessionRejectReason` for a message from the wrong CompID.
+const COMP_ID_PROBLEM: u32 = 9;
+/// `BusinessRejectReason` for an unsupported message type.
+const UNSUPPORTED_MESSAGE_TYPE: u32 = 3;
+
+/// Accepts FIX sessions and maps their orders onto the engine. Sessions are
+/// named by the client's SenderCompID, belong to the account they first
+/// logged on for and keep their sequence numbers across connections.
+/// Reports for the account that arrive while a session is logged off are
+/// sent once it logs on again, unless it stays away long enough to expire.
+#[derive(Clone)]
+pub struct Gateway {
+    engine: EngineHandle,
+    hub: Addr<Hub>,
+    accounts: Arc<Accounts>,
+    comp_id: String,
+    session_expiry: Duration,
+    sessions: Arc<Mutex<HashMap<String, Slot>>>,
+}
+
+/// A session as the gateway keeps it between connections.
+struct Slot {
+    /// `None` while the session is logged on.
+    state: Option<SessionState>,
+    /// The hub subscription collecting the account's reports.
+    subscription: SessionId,
+    /// When the session last logged off.
+    logged_off: Instant,
+}
+
+impl Gateway {
+    /// A gateway that answers as `comp_id`, the TargetCompID clients send.
+    pub fn new(engine: EngineHandle, hub: Addr<Hub>, comp_id: &str) -> Self {
+        Self {
+            engine,
+            hub,
+            accounts: Arc::new(Accounts::default()),
+            comp_id: comp_id.to_string(),
+            session_expiry: DEFAULT_SESSION_EXPIRY,
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Forgets sessions that have been logged off for `expiry` rather than
+    /// `DEFAULT_SESSION_EXPIRY`. A client logging on after that starts a
+    /// new session.
+    pub fn with_session_expiry(mut self, expiry: Duration) -> Self {
+        self.session_expiry = expiry;
+        self
+    }
+
+    /// Checks each Logon's `Password` against the account tokens. Without
+    /// any tokens, a session trades for the account its SenderCompID names.
+    pub fn with_accounts(mut self, accounts: Arc<Accounts>) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    /// Accepts connections until the listener fails.
+    pub async fn serve(self, listener: TcpListener) {
+        let mut expiry_check = tokio::time::interval(EXPIRY_CHECK);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let gateway = self.clone();
+                        actix::spawn(async move { gateway.connect(stream, peer).await });
+                    }
+                    Err(err) => {
+                        tracing::error!(error = %err, "FIX acceptor failed");
+                        return;
+                    }
+                },
+                _ = expiry_check.tick() => self.expire(&mut self.sessions.lock().unwrap()),
+            }
+        }
+    }
+
+    async fn connect(self, mut stream: TcpStream, peer: SocketAddr) {
+        let _ = stream.set_nodelay(true);
+        let mut buffer = Vec::new();
+        let first = tokio::time::timeout(LOGON_TIMEOUT, read_message(&mut stream, &mut buffer));
+        let logon = match first.await {
+            Ok(Ok(Some(message))) if message.msg_type() == msg_type::LOGON => message,
+            Ok(Ok(Some(message))) => {
+                tracing::warn!(%peer, msg_type = message.msg_type(), "FIX connection did not start with a Logon");
+                return;
+            }
+            Ok(Ok(None)) => return,
+            Ok(Err(err)) => {
+                tracing::warn!(%peer, error = %err, "FIX connection failed before logon");
+                return;
+            }
+            Err(_) => {
+                tracing::warn!(%peer, "FIX connection sent no Logon in time");
+                return;
+            }
+        };
+        let Some(mut session) = self.logon(stream, &logon, peer).await else {
+            return;
+        };
+        session.buffer = buffer;
+        let span =
+            tracing::info_span!("fix", comp_id = %session.comp_id, account = %session.account);
+        async {
+            let result = session.run().await;
+            if let Err(err) = result {
+                tracing::warn!(error = %err, "FIX session dropped");
+            }
+            session.close();
+        }
+        .instrument(span)
+        .await;
+    }
+
+    /// Checks a Logon and answers it, returning the session if it is
+    /// accepted. Refused logons are answered with a Logout.
+    async fn logon(
+        &self,
+        mut stream: TcpStream,
+        logon: &Message,
+        peer: SocketAddr,
+    ) -> Option<Session> {
+        let comp_id = logon
+            .get(tags::SENDER_COMP_ID)
+            .unwrap_or_default()
+            .to_string();
+        let refuse = |text: &str| {
+            tracing::warn!(%peer, comp_id, reason = text, "FIX logon refused");
+            let logout = Message::new(msg_type::LOGOUT)
+                .with(tags::SENDER_COMP_ID, &self.comp_id)
+                .with(tags::TARGET_COMP_ID, &comp_id)
+                .with(tags::MSG_SEQ_NUM, 1)
+                .with(tags::SENDING_TIME, utc_timestamp(now_millis()))
+                .with(tags::TEXT, text);
+            logout.encode()
+        };
+        let heartbeat = logon.parse::<u64>(tags::HEART_BT_INT).ok().flatten();
+        let account = match logon.get(tags::PASSWORD) {
+            _ if self.accounts.is_empty() => Some(comp_id.clone()),
+            Some(token) => self.accounts.authenticate(token).map(str::to_string),
+            None => None,
+        };
+        let problem = if logon.get(tags::TARGET_COMP_ID) != Some(self.comp_id.as_str()) {
+            Some(format!("TargetCompID must be {}", self.comp_id))
+        } else if comp_id.is_empty() {
+            Some("SenderCompID is required".to_string())
+        } else if logon
+            .get(tags::ENCRYPT_METHOD)
+            .is_some_and(|method| method != "0")
+        {
+            Some("EncryptMethod must be 0".to_string())
+        } else if !heartbeat.is_some_and(|heartbeat| HEARTBEAT_RANGE.contains(&heartbeat)) {
+            Some(format!(
+                "HeartBtInt must be between {} and {}",
+                HEARTBEAT_RANGE.start(),
+                HEARTBEAT_RANGE.end()
+            ))
+        } else if account.is_none() {
+            Some("invalid Password".to_string())
+        } else if logon.seq_num().is_none() {
+            Some("MsgSeqNum is required".to_string())
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            let _ = stream.write_all(&refuse(&problem)).await;
+            return None;
+        }
+        let account = account.unwrap_or_default();
+        let mut state = match self.take_state(&comp_id, &account) {
+            Ok(state) => state,
+            Err(problem) => {
+                let _ = stream.write_all(&refuse(problem)).await;
+                return None;
+            }
+        };
+        let reset = logon.flag(tags::RESET_SEQ_NUM_FLAG);
+        if reset {
+            state.reset();
+        }
+
+        let mut session = Session {
+            gateway: self.clone(),
+            stream,
+            buffer: Vec::new(),
+            comp_id,
+            account,
+            state,
+            heartbeat: Duration::from_secs(heartbeat.unwrap_or_default()),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request: None,
+            resend_until: None,
+        };
+        let seq = logon.seq_num().unwrap_or_default();
+        if seq < session.state.next_in {
+            let text = too_low(session.state.next_in, seq);
+            let _ = session.logout(Some(&text)).await;
+            session.release();
+            return None;
+        }
+        let mut reply = Message::new(msg_type::LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, session.heartbeat.as_secs());
+        if reset {
+            reply.push(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        if session.send(reply).await.is_err() {
+            session.close();
+            return None;
+        }
+        tracing::info!(%peer, comp_id = %session.comp_id, account = %session.account, "FIX session logged on");
+        if seq > session.state.next_in {
+            if session.request_resend(seq).await.is_err() {
+                session.close();
+                return None;
+            }
+        } else {
+            session.state.next_in += 1;
+        }
+        Some(session)
+    }
+
+    /// Takes a session's state for a connection logged on for `account`.
+    /// Refuses if another connection holds it or it belongs to another
+    /// account, or if it would be one session too many. A new session
+    /// starts collecting the account's reports, so they are routed before
+    /// its first order can be placed.
+    fn take_state(&self, comp_id: &str, account: &str) -> Result<SessionState, &'static str> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.expire(&mut sessions);
+        let full = sessions.len() >= MAX_SESSIONS;
+        match sessions.get_mut(comp_id) {
+            Some(Slot {
+                state: Some(state), ..
+            }) if state.account != account => Err("the session belongs to another account"),
+            Some(slot) => slot.state.take().ok_or("the session is already logged on"),
+            None if full => Err("too many sessions"),
+            None => {
+                let (reports, received) = mpsc::channel(MAX_QUEUED_REPORTS);
+                let subscription = market_data::next_session_id();
+                self.hub.do_send(Subscribe {
+                    session: subscription,
+                    recipient: Forward(reports).start().recipient(),
+                    account: Some(account.to_string()),
+                    topics: vec!["orders:*".parse().unwrap()],
+                });
+                let slot = Slot {
+                    state: None,
+                    subscription,
+                    logged_off: Instant::now(),
+                };
+                sessions.insert(comp_id.to_string(), slot);
+                Ok(SessionState::new(account, received))
+            }
+        }
+    }
+
+    /// Forgets the sessions logged off for longer than the expiry and ends
+    /// their subscriptions, which stops their `Forward` actors.
+    fn expire(&self, sessions: &mut HashMap<String, Slot>) {
+        sessions.retain(|comp_id, slot| {
+            let expired = slot.state.is_some() && slot.logged_off.elapsed() >= self.session_expiry;
+            if expired {
+                tracing::info!(comp_id, "FIX session expired");
+                self.hub.do_send(Disconnect {
+                    session: slot.subscription,
+                });
+            }
+            !expired
+        });
+    }
+}
+
+/// Hands the reports the hub delivers to the session's connection. Stops
+/// once the hub drops the subscription or the session is gone.
+struct Forward(mpsc::Sender<Arc<str>>);
+
+impl Actor for Forward {
+    type Context = Context<Self>;
+}
+
+impl Handler<Deliver> for Forward {
+    type Result = ();
+
+    fn handle(&mut self, Deliver(text): Deliver, ctx: &mut Self::Context) {
+        match self.0.try_send(text) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("FIX session report queue full, dropping a report");
+            }
+            Err(TrySendError::Closed(_)) => ctx.stop(),
+        }
+    }
+}
+
+/// A report as the hub delivers it.
+#[derive(Deserialize)]
+struct Delivered {
+    data: ExecutionReport,
+}
+
+/// Whether a session goes on after a message.
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Continue,
+    Close,
+}
+
+/// A logged on connection.
+struct Session {
+    gateway: Gateway,
+    stream: TcpStream,
+    /// Bytes read but not yet decoded.
+    buffer: Vec<u8>,
+    /// The client's SenderCompID.
+    comp_id: String,
+    account: String,
+    state: SessionState,
+    heartbeat: Duration,
+    last_sent: Instant,
+    last_received: Instant,
+    /// The TestReqID of an unanswered TestRequest, and when it was sent.
+    test_request: Option<(String, Instant)>,
+    /// While a ResendRequest of ours is outstanding, the highest sequence
+    /// number it has to catch up to.
+    resend_until: Option<u64>,
+}
+
+impl Session {
+    async fn run(&mut self) -> std::io::Result<()> {
+        let mut timer = tokio::time::interval(TIMER_RESOLUTION);
+        let mut chunk = [0; 4096];
+        loop {
+            while let Some(message) = self.buffered()? {
+                if self.handle(message).await? == Flow::Close {
+                    return Ok(());
+                }
+            }
+            tokio::select! {
+                read = self.stream.read(&mut chunk) => {
+                    let read = read?;
+                    if read == 0 {
+                        tracing::info!("FIX connection closed by the client");
+                        return Ok(());
+                    }
+                    self.buffer.extend_from_slice(&chunk[..read]);
+                    self.last_received = Instant::now();
+                    self.test_request = None;
+                }
+                Some(text) = self.state.reports.recv() => self.report(&text).await?,
+                _ = timer.tick() => {
+                    if self.tick().await? == Flow::Close {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next whole message in the buffer. Garbled messages are skipped,
+    /// as FIX asks; a stream that cannot be framed any more is an error.
+    fn buffered(&mut self) -> std::io::Result<Option<Message>> {
+        loop {
+            match Message::decode(&self.buffer) {
+                Ok(Some((message, consumed))) => {
+                    self.buffer.drain(..consumed);
+                    return Ok(Some(message));
+                }
+                Ok(None) => return Ok(None),
+                Err(DecodeError::Garbled { consumed, reason }) => {
+                    tracing::warn!(reason, "garbled FIX message ignored");
+                    self.buffer.drain(..consumed);
+                }
+                Err(err) => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, message: Message) -> std::io::Result<Flow> {
+        tracing::debug!(msg_type = message.msg_type(), seq = ?message.seq_num(), "FIX message received");
+        let Some(seq) = message.seq_num() else {
+            return self.logout(Some("MsgSeqNum is required")).await;
+        };
+        if message.get(tags::SENDER_COMP_ID) != Some(self.comp_id.as_str())
+            || message.get(tags::TARGET_COMP_ID) != Some(self.gateway.comp_id.as_str())
+        {
+            let text = "SenderCompID and TargetCompID must match the logon";
+            self.reject(&message, tags::SENDER_COMP_ID, COMP_ID_PROBLEM, text)
+                .await?;
+            return self.logout(Some(text)).await;
+        }
+        let msg_type = message.msg_type().to_string();
+        let gap_fill = message.flag(tags::GAP_FILL_FLAG);
+        if msg_type == msg_type::SEQUENCE_RESET && !gap_fill {
+            // A reset applies whatever its own sequence number.
+            return self.sequence_reset(&message).await;
+        }
+        if seq > self.state.next_in {
+            match msg_type.as_str() {
+                msg_type::RESEND_REQUEST => self.resend(&message).await?,
+                msg_type::LOGOUT => return self.logout(None).await,
+                _ => {}
+            }
+            if self.resend_until.is_none() {
+                self.request_resend(seq).await?;
+            }
+            return Ok(Flow::Continue);
+        }
+        if seq < self.state.next_in {
+            if message.flag(tags::POSS_DUP_FLAG) {
+                return Ok(Flow::Continue);
+            }
+            let text = too_low(self.state.next_in, seq);
+            return self.logout(Some(&text)).await;
+        }
+        self.state.next_in += 1;
+        if self
+            .resend_until
+            .is_some_and(|until| self.state.next_in > until)
+        {
+            self.resend_until = None;
+        }
+        match msg_type.as_str() {
+            msg_type::HEARTBEAT | msg_type::REJECT => {
+                if msg_type == msg_type::REJECT {
+                    tracing::warn!(
+                        text = message.get(tags::TEXT),
+                        "FIX message rejected by the client"
+                    );
+                }
+            }
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = Message::new(msg_type::HEARTBEAT);
+                if let Some(id) = message.get(tags::TEST_REQ_ID) {
+                    heartbeat.push(tags::TEST_REQ_ID, id);
+                }
+                self.send(heartbeat).await?;
+            }
+            msg_type::RESEND_REQUEST => self.resend(&message).await?,
+            msg_type::SEQUENCE_RESET => return self.sequence_reset(&message).await,
+            msg_type::LOGOUT => return self.logout(None).await,
+            msg_type::LOGON => {
+                let text = "the session is already logged on";
+                self.reject(&message, tags::MSG_TYPE, orders::VALUE_IS_INCORRECT, text)
+                    .await?;
+            }
+            msg_type::NEW_ORDER_SINGLE => self.new_order(&message).await?,
+            msg_type::ORDER_CANCEL_REQUEST => self.cancel(&message).await?,
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.replace(&message).await?,
+            other => {
+                let reject = Message::new(msg_type::BUSINESS_MESSAGE_REJECT)
+                    .with(tags::REF_SEQ_NUM, seq)
+                    .with(tags::REF_MSG_TYPE, other)
+                    .with(tags::BUSINESS_REJECT_REASON, UNSUPPORTED_MESSAGE_TYPE)
+                    .with(tags::TEXT, format!("MsgType {} is not supported", other));
+                self.send(reject).await?;
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn new_order(&mut self, message: &Message) -> std::io::Result<()> {
+        let timestamp = now_millis() / 1000;
+        let mut order = match orders::new_order(message, &self.account, timestamp) {
+            Ok(order) => order,
+            Err(err) => return self.field_error(message, err).await,
+        };
+        let engine = self.gateway.engine.clone();
+        order.id = engine.next_order_id();
+        let cl_ord_id = order.client_order_id.clone().unwrap_or_default();
+        let span = tracing::info_span!(
+            "order",
+            order_id = order.id,
+            client_id = %self.account,
+            client_order_id = cl_ord_id,
+            symbol = %order.trading_pair,
+        );
+        match engine.add_order(order.clone(), None).instrument(span).await {
+            // The New and any fill reports follow from the hub.
+            Ok(execution) => self.state.orders.insert(execution.order.id, &cl_ord_id),
+            Err(err) => {
+                let exec_id = self.state.next_exec_id();
+                let time = utc_timestamp(now_millis());
+                self.send(orders::order_rejected(&order, &err, exec_id, &time))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn cancel(&mut self, message: &Message) -> std::io::Result<()> {
+        let target = match orders::target(message) {
+            Ok(target) => target,
+            Err(err) => return self.field_error(message, err).await,
+        };
+        let Some(record) = self.working_order(&target, false).await? else {
+            return Ok(());
+        };
+        let order_id = record.order.id;
+        self.state
+            .orders
+            .request(order_id, &target.cl_ord_id, &target.orig_cl_ord_id);
+        let span = tracing::info_span!("order", order_id, client_id = %self.account);
+        let result = self
+            .gateway
+            .engine
+            .cancel_order(order_id, Some(self.account.clone()))
+            .instrument(span)
+            .await;
+        if let Err(err) = result {
+            self.refused(&target, &record, false, &err).await?;
+        }
+        Ok(())
+    }
+
+    async fn replace(&mut self, message: &Message) -> std::io::Result<()> {
+        let replace = match orders::replace(message) {
+            Ok(replace) => replace,
+            Err(err) => return self.field_error(message, err).await,
+        };
+        let target = &replace.target;
+        let Some(record) = self.working_order(target, true).await? else {
+            return Ok(());
+        };
+        let order = &record.order;
+        let problem = if replace.side != order.bid_or_ask
+            || replace.symbol != order.trading_pair
+            || replace.order_type != order.order_type
+        {
+            Some("Side, Symbol and OrdType cannot be changed")
+        } else if replace.quantity <= record.filled {
+            Some("OrderQty must be above the quantity already filled")
+        } else {
+            None
+        };
+        if let Some(text) = problem {
+            let reject = orders::cancel_rejected(
+                target,
+                Some(order.id),
+                Some(record.status),
+                true,
+                orders::OTHER,
+                text,
+            );
+            return self.send(reject).await;
+        }
+        let order_id = order.id;
+        self.state
+            .orders
+            .request(order_id, &target.cl_ord_id, &target.orig_cl_ord_id);
+        // The engine amends the quantity still working.
+        let amount = replace.quantity - record.filled;
+        let span = tracing::info_span!("order", order_id, client_id = %self.account);
+        let result = self
+            .gateway
+            .engine
+            .amend_order(order_id, amount, replace.price, Some(self.account.clone()))
+            .instrument(span)
+            .await;
+        if let Err(err) = result {
+            self.refused(target, &record, true, &err).await?;
+        }
+        Ok(())
+    }
+
+    /// The working order of the session's account that a cancel or replace
+    /// refers to. Otherwise answers with an OrderCancelReject and returns
+    /// `None`.
+    async fn working_order(
+        &mut self,
+        target: &Target,
+        replace: bool,
+    ) -> std::io::Result<Option<OrderRecord>> {
+        let engine = &self.gateway.engine;
+        let mut order_id = target
+            .order_id
+            .or_else(|| self.state.orders.order_id(&target.orig_cl_ord_id));
+        if order_id.is_none() {
+            // Placed over another connection, or before a restart of ours.
+            let query = OrderQuery {
+                account: Some(self.account.clone()),
+                limit: usize::MAX,
+                ..OrderQuery::default()
+            };
+            order_id = engine
+                .query_orders(query)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .rev()
+                .find(|record| {
+                    record.order.client_order_id.as_deref() == Some(&target.orig_cl_ord_id)
+                })
+                .map(|record| record.order.id);
+        }
+        let record = match order_id {
+            Some(id) => engine.get_order(id).await.ok(),
+            None => None,
+        }
+        .filter(|record| record.order.account.as_deref() == Some(self.account.as_str()));
+        let (reason, text) = match &record {
+            None => (orders::UNKNOWN_ORDER, "unknown order"),
+            Some(record) if record.status.is_final() => {
+                (orders::TOO_LATE_TO_CANCEL, "the order is no longer working")
+            }
+            Some(_) => return Ok(record),
+        };
+        let reject = orders::cancel_rejected(
+            target,
+            record.as_ref().map(|record| record.order.id),
+            record.as_ref().map(|record| record.status),
+            replace,
+            reason,
+            text,
+        );
+        self.send(reject).await?;
+        Ok(None)
+    }
+
+    /// Answers a cancel or replace the engine refused.
+    async fn refused(
+        &mut self,
+        target: &Target,
+        record: &OrderRecord,
+        replace: bool,
+        err: &EngineError,
+    ) -> std::io::Result<()> {
+        self.state.orders.refused(record.order.id);
+        let reject = orders::cancel_rejected(
+            target,
+            Some(record.order.id),
+            Some(record.status),
+            replace,
+            orders::cxl_rej_reason(err),
+            &orders::describe(err),
+        );
+        self.send(reject).await
+    }
+
+    /// Turns one of the account's engine reports into an ExecutionReport.
+    async fn report(&mut self, text: &str) -> std::io::Result<()> {
+        let report = match serde_json::from_str::<Delivered>(text) {
+            Ok(delivered) => delivered.data,
+            Err(err) => {
+                tracing::warn!(error = %err, "unreadable report for a FIX session");
+                return Ok(());
+            }
+        };
+        // Rejections are answered to the request that caused them, and
+        // only to that connection.
+        if report.kind == ReportKind::Rejected {
+            return Ok(());
+        }
+        let answered = match report.kind {
+            ReportKind::Cancelled | ReportKind::Amended => self
+                .state
+                .orders
+                .answered(report.order_id, report.kind == ReportKind::Amended),
+            _ => None,
+        };
+        let cl_ord_id = match &answered {
+            Some((cl_ord_id, _)) => cl_ord_id.clone(),
+            None => self
+                .state
+                .orders
+                .current(report.order_id)
+                .map(str::to_string)
+                .or_else(|| report.client_order_id.clone())
+                .unwrap_or_else(|| report.order_id.to_string()),
+        };
+        if report.status.is_final() {
+            self.state.orders.remove(report.order_id);
+        }
+        let exec_id = self.state.next_exec_id();
+        let message = orders::execution_report(
+            &report,
+            &cl_ord_id,
+            answered.as_ref().map(|(_, orig)| orig.as_str()),
+            exec_id,
+            &utc_timestamp(now_millis()),
+        );
+        self.send(message).await
+    }
+
+    /// Sends heartbeats and test requests as they fall due, and gives up on
+    /// a client that answers neither.
+    async fn tick(&mut self) -> std::io::Result<Flow> {
+        if let Some((_, sent)) = &self.test_request {
+            if sent.elapsed() >= self.heartbeat {
+                tracing::warn!("FIX test request unanswered, closing");
+                return self.logout(Some("TestRequest unanswered")).await;
+            }
+        } else if self.last_received.elapsed() >= self.heartbeat + self.heartbeat / 5 {
+            let id = now_millis().to_string();
+            self.send(Message::new(msg_type::TEST_REQUEST).with(tags::TEST_REQ_ID, &id))
+                .await?;
+            self.test_request = Some((id, Instant::now()));
+        }
+        if self.last_sent.elapsed() >= self.heartbeat {
+            self.send(Message::new(msg_type::HEARTBEAT)).await?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn sequence_reset(&mut self, message: &Message) -> std::io::Result<Flow> {
+        let new_seq_no = message.parse::<u64>(tags::NEW_SEQ_NO).ok().flatten();
+        match new_seq_no {
+            Some(new_seq_no) if new_seq_no >= self.state.next_in => {
+                self.state.next_in = new_seq_no;
+                if self
+                    .resend_until
+                    .is_some_and(|until| self.state.next_in > until)
+                {
+                    self.resend_until = None;
+                }
+            }
+            _ => {
+                let text = format!("NewSeqNo must be at least {}", self.state.next_in);
+                self.reject(message, tags::NEW_SEQ_NO, orders::VALUE_IS_INCORRECT, &text)
+                    .await?;
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Asks the client to resend from the next expected message on, having
+    /// received `seq`.
+    async fn request_resend(&mut self, seq: u64) -> std::io::Result<()> {
+        tracing::info!(
+            expected = self.state.next_in,
+            received = seq,
+            "FIX sequence gap, requesting resend"
+        );
+        self.resend_until = Some(seq);
+        let request = Message::new(msg_type::RESEND_REQUEST)
+            .with(tags::BEGIN_SEQ_NO, self.state.next_in)
+            .with(tags::END_SEQ_NO, 0);
+        self.send(request).await
+    }
+
+    /// Answers a ResendRequest: application messages are sent again as
+    /// possible duplicates, and session messages are skipped with a
+    /// SequenceReset-GapFill.
+    async fn resend(&mut self, request: &Message) -> std::io::Result<()> {
+        let begin = request
+            .parse::<u64>(tags::BEGIN_SEQ_NO)
+            .ok()
+            .flatten()
+            .unwrap_or(1)
+            .max(1);
+        let end = match request.parse::<u64>(tags::END_SEQ_NO).ok().flatten() {
+            Some(end) if end != 0 && end < self.state.next_out => end + 1,
+            _ => self.state.next_out,
+        };
+        let stored: Vec<(u64, Option<Message>)> = self
+            .state
+            .resend(begin, end)
+            .map(|(seq, message)| (seq, message.cloned()))
+            .collect();
+        let mut gap = None;
+        for (seq, message) in stored {
+            let Some(message) = message else {
+                gap.get_or_insert(seq);
+                continue;
+            };
+            if let Some(start) = gap.take() {
+                self.gap_fill(start, seq).await?;
+            }
+            let original = message
+                .get(tags::SENDING_TIME)
+                .unwrap_or_default()
+                .to_string();
+            let mut resent = self.header(message.msg_type(), seq, Some(&original));
+            for (tag, value) in message.body() {
+                resent.push(tag, value);
+            }
+            self.write(&resent).await?;
+        }
+        if let Some(start) = gap {
+            self.gap_fill(start, end).await?;
+        }
+        Ok(())
+    }
+
+    async fn gap_fill(&mut self, seq: u64, new_seq_no: u64) -> std::io::Result<()> {
+        let now = utc_timestamp(now_millis());
+        let mut message = self.header(msg_type::SEQUENCE_RESET, seq, Some(&now));
+        message.push(tags::GAP_FILL_FLAG, "Y");
+        message.push(tags::NEW_SEQ_NO, new_seq_no);
+        self.write(&message).await
+    }
+
+    async fn field_error(&mut self, message: &Message, err: FieldError) -> std::io::Result<()> {
+        self.reject(message, err.tag, err.reason, &err.text).await
+    }
+
+    /// Sends a session-level Reject of `message`.
+    async fn reject(
+        &mut self,
+        message: &Message,
+        tag: u32,
+        reason: u32,
+        text: &str,
+    ) -> std::io::Result<()> {
+        let reject = Message::new(msg_type::REJECT)
+            .with(tags::REF_SEQ_NUM, message.seq_num().unwrap_or_default())
+            .with(tags::REF_TAG_ID, tag)
+            .with(tags::REF_MSG_TYPE, message.msg_type())
+            .with(tags::SESSION_REJECT_REASON, reason)
+            .with(tags::TEXT, text);
+        self.send(reject).await
+    }
+
+    /// Sends a Logout, with `text` when the gateway ends the session, and
+    /// closes the session.
+    async fn logout(&mut self, text: Option<&str>) -> std::io::Result<Flow> {
+        let mut logout = Message::new(msg_type::LOGOUT);
+        if let Some(text) = text {
+            tracing::warn!(reason = text, "FIX session logged out");
+            logout.push(tags::TEXT, text);
+        } else {
+            tracing::info!("FIX session logged out");
+        }
+        self.send(logout).await?;
+        Ok(Flow::Close)
+    }
+
+    /// Sends `body` as the next message of the session.
+    async fn send(&mut self, body: Message) -> std::io::Result<()> {
+        let mut message = self.header(body.msg_type(), self.state.next_out, None);
+        for (tag, value) in body.body() {
+            message.push(tag, value);
+        }
+        self.state.sent(&message);
+        self.write(&message).await
+    }
+
+    /// The standard header, for a possible duplicate if the original
+    /// sending time is given.
+    fn header(&self, msg_type: &str, seq: u64, original: Option<&str>) -> Message {
+        let mut header = Message::new(msg_type)
+            .with(tags::SENDER_COMP_ID, &self.gateway.comp_id)
+            .with(tags::TARGET_COMP_ID, &self.comp_id)
+            .with(tags::MSG_SEQ_NUM, seq);
+        if original.is_some() {
+            header.push(tags::POSS_DUP_FLAG, "Y");
+        }
+        header.push(tags::SENDING_TIME, utc_timestamp(now_millis()));
+        if let Some(original) = original {
+            header.push(tags::ORIG_SENDING_TIME, original);
+        }
+        header
+    }
+
+    async fn write(&mut self, message: &Message) -> std::io::Result<()> {
+        tracing::debug!(msg_type = message.msg_type(), seq = ?message.seq_num(), "FIX message sent");
+        self.last_sent = Instant::now();
+        self.stream.write_all(&message.encode()).await
+    }
+
+    /// Ends the connection, keeping the session's state, and the reports
+    /// that keep arriving for it, for its next logon.
+    fn close(self) {
+        self.release();
+    }
+
+    fn release(self) {
+        let mut sessions = self.gateway.sessions.lock().unwrap();
+        if let Some(slot) = sessions.get_mut(&self.comp_id) {
+            slot.state = Some(self.state);
+            slot.logged_off = Instant::now();
+        }
+    }
+}
+
+/// Reads until `buffer` holds a whole message, or the connection closes.
+async fn read_message(
+    stream: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+) -> std::io::Result<Option<Message>> {
+    let mut chunk = [0; 4096];
+    loop {
+        match Message::decode(buffer) {
+            Ok(Some((message, consumed))) => {
+                buffer.drain(..consumed);
+                return Ok(Some(message));
+            }
+            Ok(None) => {}
+            Err(DecodeError::Garbled { consumed, .. }) => {
+                buffer.drain(..consumed);
+                continue;
+            }
+            Err(err) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn too_low(expected: u64, received: u64) -> String {
+    format!(
+        "MsgSeqNum too low, expecting {} but received {}",
+        expected, received
+    )
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
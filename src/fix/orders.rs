@@ -0,0 +1,361 @@
+use super::message::{msg_type, tags, Message};
+use crate::engine::EngineError;
+use crate::instrument::InstrumentError;
+use crate::metrics::rejection_reason;
+use crate::models::{
+    BidOrAsk, ExecutionReport, Liquidity, Order, OrderStatus, OrderType, Price, ReportKind,
+};
+use crate::order_book::BookError;
+use std::str::FromStr;
+
+/// `SessionRejectReason` values.
+pub const REQUIRED_TAG_MISSING: u32 = 1;
+pub const VALUE_IS_INCORRECT: u32 = 5;
+pub const INCORRECT_DATA_FORMAT: u32 = 6;
+
+/// `CxlRejReason` values.
+pub const TOO_LATE_TO_CANCEL: u32 = 0;
+pub const UNKNOWN_ORDER: u32 = 1;
+pub const OTHER: u32 = 99;
+
+/// A field that is missing or wrong, answered with a session-level Reject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub tag: u32,
+    /// The `SessionRejectReason`.
+    pub reason: u32,
+    pub text: String,
+}
+
+impl FieldError {
+    fn missing(tag: u32) -> Self {
+        Self {
+            tag,
+            reason: REQUIRED_TAG_MISSING,
+            text: format!("tag {} is required", tag),
+        }
+    }
+
+    fn incorrect(tag: u32, text: &str) -> Self {
+        Self {
+            tag,
+            reason: VALUE_IS_INCORRECT,
+            text: text.to_string(),
+        }
+    }
+}
+
+/// The order a cancel or replace is aimed at, and the request's own id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub cl_ord_id: String,
+    pub orig_cl_ord_id: String,
+    /// The engine's id, if the client sent `OrderID`.
+    pub order_id: Option<u64>,
+}
+
+/// What an OrderCancelReplaceRequest asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replace {
+    pub target: Target,
+    pub side: BidOrAsk,
+    pub symbol: String,
+    pub order_type: OrderType,
+    /// The new total quantity, including what has already filled.
+    pub quantity: f64,
+    pub price: Option<Price>,
+}
+
+/// Reads a NewOrderSingle into an order for `account`.
+pub fn new_order(message: &Message, account: &str, timestamp: u64) -> Result<Order, FieldError> {
+    let cl_ord_id = required(message, tags::CL_ORD_ID)?;
+    if let Some(sent) = message.get(tags::ACCOUNT) {
+        if sent != account {
+            return Err(FieldError::incorrect(
+                tags::ACCOUNT,
+                "Account must be the session's account",
+            ));
+        }
+    }
+    match message.get(tags::TIME_IN_FORCE) {
+        None | Some("0") | Some("1") => {}
+        Some(_) => {
+            return Err(FieldError::incorrect(
+                tags::TIME_IN_FORCE,
+                "only Day and GoodTillCancel orders are supported",
+            ))
+        }
+    }
+    let order_type = order_type(message)?;
+    let mut order = Order::new(
+        0,
+        order_type,
+        required(message, tags::SYMBOL)?.to_string(),
+        quantity(message)?,
+        price(message, order_type)?,
+        timestamp,
+        side(message)?,
+    );
+    order.client_order_id = Some(cl_ord_id.to_string());
+    order.account = Some(account.to_string());
+    Ok(order)
+}
+
+/// Reads the order an OrderCancelRequest or OrderCancelReplaceRequest
+/// refers to.
+pub fn target(message: &Message) -> Result<Target, FieldError> {
+    Ok(Target {
+        cl_ord_id: required(message, tags::CL_ORD_ID)?.to_string(),
+        orig_cl_ord_id: required(message, tags::ORIG_CL_ORD_ID)?.to_string(),
+        order_id: number(message, tags::ORDER_ID)?,
+    })
+}
+
+pub fn replace(message: &Message) -> Result<Replace, FieldError> {
+    let order_type = order_type(message)?;
+    Ok(Replace {
+        target: target(message)?,
+        side: side(message)?,
+        symbol: required(message, tags::SYMBOL)?.to_string(),
+        order_type,
+        quantity: quantity(message)?,
+        price: price(message, order_type)?,
+    })
+}
+
+/// An ExecutionReport for an engine report, sent as `cl_ord_id` and, for
+/// the answer to a cancel or replace, `orig_cl_ord_id`.
+pub fn execution_report(
+    report: &ExecutionReport,
+    cl_ord_id: &str,
+    orig_cl_ord_id: Option<&str>,
+    exec_id: String,
+    transact_time: &str,
+) -> Message {
+    let mut message = Message::new(msg_type::EXECUTION_REPORT)
+        .with(tags::ORDER_ID, report.order_id)
+        .with(tags::CL_ORD_ID, cl_ord_id);
+    if let Some(orig) = orig_cl_ord_id {
+        message.push(tags::ORIG_CL_ORD_ID, orig);
+    }
+    message.push(tags::EXEC_ID, exec_id);
+    message.push(tags::EXEC_TYPE, exec_type(report.kind));
+    message.push(tags::ORD_STATUS, ord_status(report.status));
+    message.push(tags::ACCOUNT, &report.account);
+    message.push(tags::SYMBOL, &report.symbol);
+    message.push(tags::SIDE, side_code(report.side));
+    message.push(tags::ORD_TYPE, ord_type_code(report.order_type));
+    if let Some(price) = report.price {
+        message.push(tags::PRICE, price.to_f64());
+    }
+    message.push(tags::ORDER_QTY, report.amount);
+    if let Some(fill) = &report.fill {
+        message.push(tags::LAST_PX, fill.price.to_f64());
+        message.push(tags::LAST_QTY, fill.amount);
+        message.push(tags::LAST_LIQUIDITY_IND, liquidity_code(fill.liquidity));
+    }
+    let leaves = if report.status.is_final() {
+        0.0
+    } else {
+        report.remaining
+    };
+    message.push(tags::LEAVES_QTY, leaves);
+    message.push(tags::CUM_QTY, report.filled);
+    message.push(tags::AVG_PX, report.average_price.unwrap_or(0.0));
+    message.push(tags::TRANSACT_TIME, transact_time);
+    if let Some(reason) = &report.reason {
+        message.push(tags::TEXT, reason);
+    }
+    message
+}
+
+/// An ExecutionReport rejecting a NewOrderSingle the engine refused.
+pub fn order_rejected(
+    order: &Order,
+    err: &EngineError,
+    exec_id: String,
+    transact_time: &str,
+) -> Message {
+    let mut message = Message::new(msg_type::EXECUTION_REPORT)
+        .with(tags::ORDER_ID, "NONE")
+        .with(
+            tags::CL_ORD_ID,
+            order.client_order_id.as_deref().unwrap_or("NONE"),
+        )
+        .with(tags::EXEC_ID, exec_id)
+        .with(tags::EXEC_TYPE, exec_type(ReportKind::Rejected))
+        .with(tags::ORD_STATUS, ord_status(OrderStatus::Rejected))
+        .with(tags::ORD_REJ_REASON, ord_rej_reason(err))
+        .with(tags::SYMBOL, &order.trading_pair)
+        .with(tags::SIDE, side_code(order.bid_or_ask))
+        .with(tags::ORD_TYPE, ord_type_code(order.order_type));
+    if let Some(account) = &order.account {
+        message.push(tags::ACCOUNT, account);
+    }
+    if let Some(price) = order.price {
+        message.push(tags::PRICE, price.to_f64());
+    }
+    message.push(tags::ORDER_QTY, order.amount);
+    message.push(tags::LEAVES_QTY, 0);
+    message.push(tags::CUM_QTY, 0);
+    message.push(tags::AVG_PX, 0);
+    message.push(tags::TRANSACT_TIME, transact_time);
+    message.push(tags::TEXT, describe(err));
+    message
+}
+
+/// An OrderCancelReject for a cancel, or with `replace` a replace, that
+/// could not be applied.
+pub fn cancel_rejected(
+    target: &Target,
+    order_id: Option<u64>,
+    status: Option<OrderStatus>,
+    replace: bool,
+    reason: u32,
+    text: &str,
+) -> Message {
+    let order_id = order_id.map_or("NONE".to_string(), |id| id.to_string());
+    Message::new(msg_type::ORDER_CANCEL_REJECT)
+        .with(tags::ORDER_ID, order_id)
+        .with(tags::CL_ORD_ID, &target.cl_ord_id)
+        .with(tags::ORIG_CL_ORD_ID, &target.orig_cl_ord_id)
+        .with(
+            tags::ORD_STATUS,
+            ord_status(status.unwrap_or(OrderStatus::Rejected)),
+        )
+        .with(tags::CXL_REJ_RESPONSE_TO, if replace { "2" } else { "1" })
+        .with(tags::CXL_REJ_REASON, reason)
+        .with(tags::TEXT, text)
+}
+
+/// The `CxlRejReason` for a cancel or replace the engine refused.
+pub fn cxl_rej_reason(err: &EngineError) -> u32 {
+    match err {
+        EngineError::Book(BookError::OrderNotFound(_)) => UNKNOWN_ORDER,
+        _ => OTHER,
+    }
+}
+
+/// The stable code the REST API would answer with, then the message.
+pub fn describe(err: &EngineError) -> String {
+    format!("{}: {}", rejection_reason(err), err)
+}
+
+fn ord_rej_reason(err: &EngineError) -> u32 {
+    match err {
+        EngineError::UnknownSymbol(_)
+        | EngineError::Book(BookError::Rejected(InstrumentError::WrongSymbol { .. })) => 1,
+        EngineError::Book(BookError::Halted) => 2,
+        EngineError::Throttled(_) => 3,
+        EngineError::DuplicateOrder(_) => 6,
+        EngineError::Book(BookError::Rejected(
+            InstrumentError::InvalidAmount
+            | InstrumentError::OffLot { .. }
+            | InstrumentError::BelowMinNotional { .. },
+        )) => 13,
+        _ => 99,
+    }
+}
+
+fn exec_type(kind: ReportKind) -> &'static str {
+    match kind {
+        ReportKind::New => "0",
+        ReportKind::Fill => "F",
+        ReportKind::Amended => "5",
+        ReportKind::Cancelled => "4",
+        ReportKind::Expired => "C",
+        ReportKind::Rejected => "8",
+    }
+}
+
+fn ord_status(status: OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::New => "0",
+        OrderStatus::PartiallyFilled => "1",
+        OrderStatus::Filled => "2",
+        OrderStatus::Cancelled => "4",
+        OrderStatus::Expired => "C",
+        OrderStatus::Rejected => "8",
+    }
+}
+
+fn side_code(side: BidOrAsk) -> &'static str {
+    match side {
+        BidOrAsk::Bid => "1",
+        BidOrAsk::Ask => "2",
+    }
+}
+
+fn ord_type_code(order_type: OrderType) -> &'static str {
+    match order_type {
+        OrderType::Market => "1",
+        OrderType::Limit => "2",
+    }
+}
+
+fn liquidity_code(liquidity: Liquidity) -> &'static str {
+    match liquidity {
+        Liquidity::Maker => "1",
+        Liquidity::Taker => "2",
+        Liquidity::Auction => "4",
+    }
+}
+
+fn required(message: &Message, tag: u32) -> Result<&str, FieldError> {
+    message.get(tag).ok_or_else(|| FieldError::missing(tag))
+}
+
+fn number<T: FromStr>(message: &Message, tag: u32) -> Result<Option<T>, FieldError> {
+    message.parse(tag).map_err(|_| FieldError {
+        tag,
+        reason: INCORRECT_DATA_FORMAT,
+        text: format!("tag {} must be a number", tag),
+    })
+}
+
+fn positive(message: &Message, tag: u32) -> Result<Option<f64>, FieldError> {
+    match number::<f64>(message, tag)? {
+        Some(value) if !(value.is_finite() && value > 0.0) => Err(FieldError::incorrect(
+            tag,
+            &format!("tag {} must be positive", tag),
+        )),
+        value => Ok(value),
+    }
+}
+
+fn side(message: &Message) -> Result<BidOrAsk, FieldError> {
+    match required(message, tags::SIDE)? {
+        "1" => Ok(BidOrAsk::Bid),
+        "2" => Ok(BidOrAsk::Ask),
+        _ => Err(FieldError::incorrect(
+            tags::SIDE,
+            "only Buy (1) and Sell (2) are supported",
+        )),
+    }
+}
+
+fn order_type(message: &Message) -> Result<OrderType, FieldError> {
+    match required(message, tags::ORD_TYPE)? {
+        "1" => Ok(OrderType::Market),
+        "2" => Ok(OrderType::Limit),
+        _ => Err(FieldError::incorrect(
+            tags::ORD_TYPE,
+            "only Market (1) and Limit (2) orders are supported",
+        )),
+    }
+}
+
+fn quantity(message: &Message) -> Result<f64, FieldError> {
+    positive(message, tags::ORDER_QTY)?.ok_or_else(|| FieldError::missing(tags::ORDER_QTY))
+}
+
+/// Limit orders need a price; market orders ignore one.
+fn price(message: &Message, order_type: OrderType) -> Result<Option<Price>, FieldError> {
+    let price = positive(message, tags::PRICE)?;
+    match order_type {
+        OrderType::Limit => Ok(Some(Price::new(
+            price.ok_or_else(|| FieldError::missing(tags::PRICE))?,
+        ))),
+        OrderType::Market => Ok(None),
+    }
+}
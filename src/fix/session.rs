@@ -0,0 +1,139 @@
+use super::message::{msg_type, Message};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Application messages kept per session for resending. Older ones are gap
+/// filled when a client asks for them again, as session messages always
+/// are.
+pub const MAX_STORED_MESSAGES: usize = 100_000;
+/// Reports queued per session while it is logged off or falling behind.
+/// Further reports are dropped.
+pub const MAX_QUEUED_REPORTS: usize = 10_000;
+
+/// What a FIX session keeps between connections: the account it trades
+/// for, its sequence numbers, the messages it sent, for resending, the
+/// ClOrdIDs of its working orders and the account's reports, which queue
+/// up while it is logged off. Kept in memory only, so a restart starts
+/// every session afresh.
+#[derive(Debug)]
+pub struct SessionState {
+    /// Fixed by the first logon; later ones must authenticate as the same
+    /// account.
+    pub account: String,
+    /// The `MsgSeqNum` expected from the client next.
+    pub next_in: u64,
+    /// The `MsgSeqNum` of the next message to the client.
+    pub next_out: u64,
+    sent: BTreeMap<u64, Message>,
+    exec_ids: u64,
+    pub orders: ClOrdIds,
+    /// The account's reports as the hub delivers them.
+    pub reports: mpsc::Receiver<Arc<str>>,
+}
+
+impl SessionState {
+    pub fn new(account: &str, reports: mpsc::Receiver<Arc<str>>) -> Self {
+        Self {
+            account: account.to_string(),
+            next_in: 1,
+            next_out: 1,
+            sent: BTreeMap::new(),
+            exec_ids: 0,
+            orders: ClOrdIds::default(),
+            reports,
+        }
+    }
+
+    /// Starts both sequences again from 1, for a logon with
+    /// `ResetSeqNumFlag`. Working orders keep their ClOrdIDs.
+    pub fn reset(&mut self) {
+        self.next_in = 1;
+        self.next_out = 1;
+        self.sent.clear();
+    }
+
+    /// Records `message`, sent as `next_out`, keeping application messages
+    /// for resending.
+    pub fn sent(&mut self, message: &Message) {
+        let seq = self.next_out;
+        self.next_out += 1;
+        if msg_type::is_admin(message.msg_type()) {
+            return;
+        }
+        self.sent.insert(seq, message.clone());
+        while self.sent.len() > MAX_STORED_MESSAGES {
+            self.sent.pop_first();
+        }
+    }
+
+    /// The messages numbered `begin..end`, `None` for those to gap fill.
+    pub fn resend(&self, begin: u64, end: u64) -> impl Iterator<Item = (u64, Option<&Message>)> {
+        (begin..end).map(|seq| (seq, self.sent.get(&seq)))
+    }
+
+    pub fn next_exec_id(&mut self) -> String {
+        self.exec_ids += 1;
+        self.exec_ids.to_string()
+    }
+}
+
+/// Maps the client's ClOrdIDs onto engine order ids. A replace gives an
+/// order a new ClOrdID, while the engine keeps the one it was placed with.
+#[derive(Debug, Default)]
+pub struct ClOrdIds {
+    current: HashMap<u64, String>,
+    orders: HashMap<String, u64>,
+    /// Cancels and replaces sent to the engine but not yet reported, as
+    /// their ClOrdID and OrigClOrdID.
+    pending: HashMap<u64, (String, String)>,
+}
+
+impl ClOrdIds {
+    /// Names `order_id` by `cl_ord_id`, which replaces any earlier name.
+    pub fn insert(&mut self, order_id: u64, cl_ord_id: &str) {
+        if let Some(old) = self.current.insert(order_id, cl_ord_id.to_string()) {
+            self.orders.remove(&old);
+        }
+        self.orders.insert(cl_ord_id.to_string(), order_id);
+    }
+
+    pub fn order_id(&self, cl_ord_id: &str) -> Option<u64> {
+        self.orders.get(cl_ord_id).copied()
+    }
+
+    pub fn current(&self, order_id: u64) -> Option<&str> {
+        self.current.get(&order_id).map(String::as_str)
+    }
+
+    pub fn request(&mut self, order_id: u64, cl_ord_id: &str, orig_cl_ord_id: &str) {
+        self.pending.insert(
+            order_id,
+            (cl_ord_id.to_string(), orig_cl_ord_id.to_string()),
+        );
+    }
+
+    /// The cancel or replace of `order_id` was refused.
+    pub fn refused(&mut self, order_id: u64) {
+        self.pending.remove(&order_id);
+    }
+
+    /// Takes the ClOrdID and OrigClOrdID of the cancel or replace that
+    /// `order_id`'s latest report answers. A replaced order goes by its new
+    /// ClOrdID from then on.
+    pub fn answered(&mut self, order_id: u64, replaced: bool) -> Option<(String, String)> {
+        let (cl_ord_id, orig) = self.pending.remove(&order_id)?;
+        if replaced {
+            self.insert(order_id, &cl_ord_id);
+        }
+        Some((cl_ord_id, orig))
+    }
+
+    /// Forgets a final order.
+    pub fn remove(&mut self, order_id: u64) {
+        self.pending.remove(&order_id);
+        if let Some(cl_ord_id) = self.current.remove(&order_id) {
+            self.orders.remove(&cl_ord_id);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::models::{BidOrAsk, Order, OrderType, Price};
+use crate::order_book::MatchingAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What the book does with a price or quantity that does not sit on the
+/// instrument's tick or lot grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum RoundingPolicy {
+    #[default]
+    Reject,
+    Round,
+}
+
+/// What the book does with new orders while trading is halted. Cancels are
+/// always accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum HaltPolicy {
+    #[default]
+    Reject,
+    Queue,
+}
+
+/// Circuit breaker settings. A fill that would print more than
+/// `max_deviation_pct` away from the reference price halts the book; the
+/// reference is the last trade price as of the start of the current
+/// `window_secs` window.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PriceBand {
+    pub max_deviation_pct: f64,
+    pub window_secs: u64,
+    /// Halts triggered by the band lift on their own after this many
+    /// seconds. `None` leaves the book halted until an admin resumes it.
+    pub halt_secs: Option<u64>,
+    #[serde(default)]
+    pub halt_policy: HaltPolicy,
+    /// Reopen through a call auction of this many seconds instead of going
+    /// straight back to continuous trading.
+    #[serde(default)]
+    pub reopen_call_secs: Option<u64>,
+}
+
+/// Trading fees in basis points of a fill's notional, charged in the quote
+/// asset. A negative maker fee is a rebate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Fees {
+    #[serde(default)]
+    pub maker_bps: f64,
+    #[serde(default)]
+    pub taker_bps: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub min_notional: f64,
+    pub price_precision: u32,
+    #[serde(default)]
+    pub rounding: RoundingPolicy,
+    #[serde(default)]
+    pub price_band: Option<PriceBand>,
+    #[serde(default)]
+    pub matching: MatchingAlgorithm,
+    #[serde(default)]
+    pub fees: Fees,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstrumentError {
+    WrongSymbol { expected: String, got: String },
+    InvalidPrice,
+    InvalidAmount,
+    OffTick { price: f64, tick_size: f64 },
+    OffLot { amount: f64, lot_size: f64 },
+    BelowMinNotional { notional: f64, min_notional: f64 },
+}
+
+impl fmt::Display for InstrumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrumentError::WrongSymbol { expected, got } => {
+                write!(f, "order for {} sent to the {} book", got, expected)
+            }
+            InstrumentError::InvalidPrice => write!(f, "price must be positive"),
+            InstrumentError::InvalidAmount => write!(f, "amount must be positive"),
+            InstrumentError::OffTick { price, tick_size } => {
+                write!(
+                    f,
+                    "price {} is not a multiple of tick size {}",
+                    price, tick_size
+                )
+            }
+            InstrumentError::OffLot { amount, lot_size } => {
+                write!(
+                    f,
+                    "amount {} is not a multiple of lot size {}",
+                    amount, lot_size
+                )
+            }
+            InstrumentError::BelowMinNotional {
+                notional,
+                min_notional,
+            } => write!(f, "notional {} is below minimum {}", notional, min_notional),
+        }
+    }
+}
+
+impl std::error::Error for InstrumentError {}
+
+/// Tolerance used when checking that an `f64` amount sits on the lot grid.
+const LOT_EPSILON: f64 = 1e-9;
+
+impl Instrument {
+    pub fn new(
+        symbol: &str,
+        base_asset: &str,
+        quote_asset: &str,
+        tick_size: f64,
+        lot_size: f64,
+        min_notional: f64,
+        price_precision: u32,
+    ) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size,
+            lot_size,
+            min_notional,
+            price_precision,
+            rounding: RoundingPolicy::Reject,
+            price_band: None,
+            matching: MatchingAlgorithm::Fifo,
+            fees: Fees::default(),
+        }
+    }
+
+    pub fn with_rounding(mut self, rounding: RoundingPolicy) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn with_price_band(mut self, price_band: PriceBand) -> Self {
+        self.price_band = Some(price_band);
+        self
+    }
+
+    pub fn with_fees(mut self, fees: Fees) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_matching(mut self, matching: MatchingAlgorithm) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    pub fn scalar(&self) -> u64 {
+        10u64.pow(self.price_precision)
+    }
+
+    pub fn tick_units(&self) -> u64 {
+        Price::with_precision(self.tick_size, self.price_precision)
+            .units()
+            .max(1)
+    }
+
+    /// Parses `price` at this instrument's precision.
+    pub fn price(&self, price: f64) -> Price {
+        Price::with_precision(price, self.price_precision)
+    }
+
+    /// Checks `order` against the instrument spec, rescaling its price to the
+    /// instrument's precision and, under `RoundingPolicy::Round`, snapping the
+    /// price and amount onto the tick and lot grid. Bids round down and asks
+    /// round up so that rounding never gives the order a worse price.
+    pub fn normalize(&self, order: &mut Order) -> Result<(), InstrumentError> {
+        if order.trading_pair != self.symbol {
+            return Err(InstrumentError::WrongSymbol {
+                expected: self.symbol.clone(),
+                got: order.trading_pair.clone(),
+            });
+        }
+        if order.amount <= 0.0 || !order.amount.is_finite() {
+            return Err(InstrumentError::InvalidAmount);
+        }
+
+        if let Some(price) = order.price {
+            let price = price.rescale(self.scalar());
+            let tick = self.tick_units();
+            let units = price.units();
+            if units == 0 && order.order_type == OrderType::Limit {
+                return Err(InstrumentError::InvalidPrice);
+            }
+            let remainder = units % tick;
+            let units = if remainder == 0 {
+                units
+            } else if self.rounding == RoundingPolicy::Round {
+                match order.bid_or_ask {
+                    BidOrAsk::Bid => units - remainder,
+                    BidOrAsk::Ask => units - remainder + tick,
+                }
+            } else {
+                return Err(InstrumentError::OffTick {
+                    price: price.to_f64(),
+                    tick_size: self.tick_size,
+                });
+            };
+            if units == 0 && order.order_type == OrderType::Limit {
+                return Err(InstrumentError::InvalidPrice);
+            }
+            order.price = Some(Price::from_units(units, self.scalar()));
+        }
+
+        let lots = order.amount / self.lot_size;
+        if (lots - lots.round()).abs() > LOT_EPSILON {
+            if self.rounding == RoundingPolicy::Round {
+                order.amount = lots.floor() * self.lot_size;
+            } else {
+                return Err(InstrumentError::OffLot {
+                    amount: order.amount,
+                    lot_size: self.lot_size,
+                });
+            }
+        } else {
+            order.amount = lots.round() * self.lot_size;
+        }
+        if order.amount <= 0.0 {
+            return Err(InstrumentError::OffLot {
+                amount: order.amount,
+                lot_size: self.lot_size,
+            });
+        }
+
+        // Market orders carry no limit price, so the notional check only
+        // applies when we know what the order is willing to pay.
+        if let Some(price) = order.price {
+            let notional = price.to_f64() * order.amount;
+            if notional + LOT_EPSILON < self.min_notional {
+                return Err(InstrumentError::BelowMinNotional {
+                    notional,
+                    min_notional: self.min_notional,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn btc_usd() -> Instrument {
+        Instrument::new("BTC-USD", "BTC", "USD", 0.5, 0.001, 10.0, 2)
+    }
+
+    fn order(bid_or_ask: BidOrAsk, amount: f64, price: f64) -> Order {
+        Order {
+            id: 1,
+            order_type: OrderType::Limit,
+            trading_pair: "BTC-USD".to_string(),
+            amount,
+            price: Some(Price::new(price)),
+            timestamp: 0,
+            bid_or_ask,
+            client_order_id: None,
+            account: None,
+        }
+    }
+
+    #[test]
+    fn test_accepts_on_grid_order() {
+        let mut o = order(BidOrAsk::Bid, 0.25, 10000.5);
+        btc_usd().normalize(&mut o).unwrap();
+        let price = o.price.unwrap();
+        assert_eq!(price.scalar(), 100);
+        assert_eq!(price.units(), 1_000_050);
+    }
+
+    #[test]
+    fn test_rejects_off_tick_and_off_lot() {
+        let mut o = order(BidOrAsk::Bid, 0.25, 10000.3);
+        assert_matches::assert_matches!(
+            btc_usd().normalize(&mut o),
+            Err(InstrumentError::OffTick { .. })
+        );
+
+        let mut o = order(BidOrAsk::Bid, 0.0005, 10000.0);
+        assert_matches::assert_matches!(
+            btc_usd().normalize(&mut o),
+            Err(InstrumentError::OffLot { .. })
+        );
+    }
+
+    #[test]
+    fn test_rounds_toward_passive_side() {
+        let instrument = btc_usd().with_rounding(RoundingPolicy::Round);
+
+        let mut bid = order(BidOrAsk::Bid, 0.0015, 10000.3);
+        instrument.normalize(&mut bid).unwrap();
+        assert_eq!(bid.price.unwrap().to_f64(), 10000.0);
+        assert!((bid.amount - 0.001).abs() < 1e-12);
+
+        let mut ask = order(BidOrAsk::Ask, 0.001, 10000.3);
+        instrument.normalize(&mut ask).unwrap();
+        assert_eq!(ask.price.unwrap().to_f64(), 10000.5);
+    }
+
+    #[test]
+    fn test_min_notional_and_symbol() {
+        let mut o = order(BidOrAsk::Ask, 0.001, 100.0);
+        assert_matches::assert_matches!(
+            btc_usd().normalize(&mut o),
+            Err(InstrumentError::BelowMinNotional { .. })
+        );
+
+        let mut o = order(BidOrAsk::Ask, 1.0, 100.0);
+        o.trading_pair = "ETH-USD".to_string();
+        assert_matches::assert_matches!(
+            btc_usd().normalize(&mut o),
+            Err(InstrumentError::WrongSymbol { .. })
+        );
+    }
+}
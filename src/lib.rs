@@ -1,4 +1,15 @@
 pub mod api;
+pub mod auth;
+pub mod config;
+pub mod engine;
+pub mod error;
+pub mod fix;
+pub mod instrument;
+pub mod market_data;
+pub mod metrics;
 pub mod models;
 pub mod order_book;
+pub mod persistence;
+pub mod rate_limit;
+pub mod telemetry;
 pub mod websocket;
@@ -1,32 +1,155 @@
 use actix_cors::Cors;
-use actix_web::{http, web, App, HttpServer};
-use models::MatchedOrder;
+use actix_web::{http, middleware, web, App, HttpServer};
+use auth::{Accounts, ApiKeys};
+use config::Config;
+use engine::Engine;
+use market_data::Hub;
+use metrics::Metrics;
+use models::MarketEvent;
 use order_book::OrderBook;
-use orderbook::{api, models, order_book};
+use orderbook::{
+    api, auth, config, engine, fix, market_data, metrics, models, order_book, persistence,
+    rate_limit, telemetry,
+};
+use persistence::Store;
+use rate_limit::RateLimits;
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let (tx, rx) = mpsc::channel::<MatchedOrder>();
-    let order_book = Arc::new(Mutex::new(OrderBook::new(tx)));
-    let rx = Arc::new(Mutex::new(rx));
-    HttpServer::new(move || {
-        let order_book = Arc::clone(&order_book);
-        let rx_clone = Arc::clone(&rx);
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:3000") // Add your frontend url here
-            .allowed_methods(vec!["GET", "POST", "OPTIONS"])
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    telemetry::init(config.logging.format);
+    let store = match &config.persistence.dsn {
+        Some(dsn) => match Store::connect(dsn).await {
+            Ok(store) => Some(web::Data::new(store)),
+            Err(err) => {
+                eprintln!("cannot use persistence.dsn: {}", err);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let metrics = Metrics::new();
+    let books = if config.instruments.is_empty() {
+        vec![OrderBook::new(tx.clone())]
+    } else {
+        config
+            .instruments
+            .iter()
+            .map(|instrument| OrderBook::with_instrument(tx.clone(), instrument.clone()))
+            .collect()
+    };
+    let accounts = web::Data::new(Accounts::new(&config.auth.accounts));
+    let api_keys = web::Data::new(ApiKeys::new(
+        &config.auth.api_keys,
+        config.auth.recv_window_ms,
+    ));
+    if api_keys.is_empty() {
+        tracing::warn!("no auth.api_keys are configured, so REST requests are not authenticated");
+    }
+    let rate_limits = web::Data::new(RateLimits::new(
+        config.rate_limits.per_key,
+        config.rate_limits.per_ip,
+    ));
+    let mut engine = Engine::with_books(books)
+        .with_metrics(metrics.clone())
+        .with_notifier(tx)
+        .with_history_limit(config.persistence.history_limit);
+    if let Some(limit) = config.order_to_trade {
+        engine = engine.with_order_to_trade(limit);
+    }
+    if let Some(store) = &store {
+        engine = engine.with_persistence(store.clone().into_inner().writer(&metrics));
+    }
+    let engine = engine.spawn(config.server.queue_capacity);
+    let hub = Hub::start(rx);
+
+    // Drives timed halts even when no orders are arriving.
+    let ticker = engine.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if ticker.tick().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    if let Some(fix) = &config.fix {
+        let listener = tokio::net::TcpListener::bind(fix.bind.as_str()).await?;
+        tracing::info!(address = %fix.bind, comp_id = %fix.comp_id, "starting FIX gateway");
+        let gateway = fix::Gateway::new(engine.clone(), hub.clone(), &fix.comp_id)
+            .with_accounts(accounts.clone().into_inner());
+        actix_web::rt::spawn(gateway.serve(listener));
+    }
+
+    tracing::info!(
+        address = %config.server.bind,
+        instruments = config.instruments.len(),
+        "starting server"
+    );
+    let server_config = config.server.clone();
+    let features = config.features;
+    let mut server = HttpServer::new(move || {
+        let hub = hub.clone();
+        let mut cors = Cors::default()
+            .allowed_methods(vec!["GET", "POST", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
             .allowed_header(http::header::CONTENT_TYPE)
+            .allowed_headers([
+                "X-Api-Key",
+                "X-Api-Timestamp",
+                "X-Api-Nonce",
+                "X-Api-Signature",
+                "Idempotency-Key",
+                "X-Client-Id",
+            ])
             .max_age(3600);
+        for origin in &server_config.cors_origins {
+            cors = match origin.as_str() {
+                "*" => cors.allow_any_origin(),
+                origin => cors.allowed_origin(origin),
+            };
+        }
 
         App::new()
+            // Inside CORS, so preflights are answered before requests are
+            // limited or their signatures checked. Addresses are limited
+            // before signatures are checked and keys after.
+            .wrap(middleware::from_fn(rate_limit::limit_by_key))
+            .wrap(middleware::from_fn(api::authenticate))
+            .wrap(middleware::from_fn(rate_limit::limit_by_ip))
             .wrap(cors)
-            .app_data(web::Data::new(order_book)) // Share the OrderBook state with the app
-            .configure(|cfg| api::config(cfg, rx_clone)) // Configure your API routes
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+            .wrap(middleware::Condition::new(
+                features.metrics,
+                middleware::from_fn(metrics::track_requests),
+            ))
+            .wrap(TracingLogger::default())
+            .app_data(web::Data::new(engine.clone())) // Share the engine handle with the app
+            .app_data(web::Data::new(metrics.clone()))
+            .app_data(accounts.clone())
+            .app_data(api_keys.clone())
+            .app_data(rate_limits.clone())
+            .configure(|cfg| {
+                if let Some(store) = &store {
+                    cfg.app_data(store.clone());
+                }
+            })
+            .configure(|cfg| api::configure(cfg, hub, features)) // Configure your API routes
+    });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+    server.bind(config.server.bind.as_str())?.run().await
 }
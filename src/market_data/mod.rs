@@ -0,0 +1,491 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use actix::{Actor, Addr, Context, Handler, Message, MessageResult, Recipient};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::{DepthLevel, MarketEvent};
+
+mod stats;
+mod topic;
+
+pub use stats::{Candle, MarketStats, Ticker};
+pub use topic::{Channel, Interval, Topic, TopicError, DEFAULT_DEPTH_LEVELS};
+
+/// Identifies a subscriber to the hub.
+pub type SessionId = u64;
+
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_session_id() -> SessionId {
+    NEXT_SESSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What a subscriber receives: one message of a channel, already
+/// serialized, shared by every subscriber of that channel.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct Deliver(pub Arc<str>);
+
+/// Every message names the concrete channel it belongs to, even when the
+/// subscription was a wildcard, and numbers it. `seq` counts up by one per
+/// message on the channel, so a gap means something was missed.
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T> {
+    pub channel: &'a str,
+    pub seq: u64,
+    pub data: T,
+}
+
+/// Private topics are filed under `account`, so they only ever match that
+/// account's reports. Sessions must have authenticated `account` first.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Subscribe {
+    pub session: SessionId,
+    pub recipient: Recipient<Deliver>,
+    pub account: Option<String>,
+    pub topics: Vec<Topic>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Unsubscribe {
+    pub session: SessionId,
+    pub topics: Vec<Topic>,
+}
+
+/// Drops every subscription of a session that has gone away.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub session: SessionId,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Publish(MarketEvent);
+
+/// Asks for a snapshot of `symbol`'s book, numbered by its `depth` channel
+/// of `levels` levels.
+#[derive(Message)]
+#[rtype(result = "DepthSnapshot")]
+pub struct GetSnapshot {
+    pub symbol: String,
+    pub levels: usize,
+}
+
+/// The levels of a book as the hub last published them, up to
+/// `PUBLISHED_DEPTH` a side, taken in one piece with the sequence number
+/// one of its `depth` channels had reached. Updates on `channel` numbered
+/// above `seq` apply on top of it; the others are already in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DepthSnapshot {
+    pub channel: String,
+    pub seq: u64,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    pub timestamp: u64,
+}
+
+/// A topic as the hub files it: private topics carry the account they
+/// belong to, public ones none.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Route {
+    topic: Topic,
+    account: Option<String>,
+}
+
+impl Route {
+    fn new(topic: Topic, account: Option<&str>) -> Self {
+        let account = account.filter(|_| topic.is_private()).map(str::to_string);
+        Route { topic, account }
+    }
+}
+
+struct Subscriber {
+    recipient: Recipient<Deliver>,
+    account: Option<String>,
+    routes: HashSet<Route>,
+}
+
+/// Fans market events out to subscribed sessions. Events are routed by
+/// topic, and each message is serialized once per channel, and only if
+/// someone is subscribed to it.
+#[derive(Default)]
+pub struct Hub {
+    sessions: HashMap<SessionId, Subscriber>,
+    /// Sessions by the topic they subscribed to, wildcards included.
+    subscribers: HashMap<Route, HashSet<SessionId>>,
+    /// The last sequence number sent on each concrete channel. Private
+    /// channels are numbered per account, so the numbers reveal nothing
+    /// about other accounts' activity.
+    sequences: HashMap<Route, u64>,
+    markets: HashMap<String, MarketStats>,
+    /// The levels each book last published, with their timestamp, for
+    /// snapshots.
+    depths: HashMap<String, (Vec<DepthLevel>, Vec<DepthLevel>, u64)>,
+}
+
+impl Hub {
+    /// Starts the hub on the current arbiter and feeds it the events the
+    /// books publish on `rx`.
+    pub fn start(rx: Receiver<MarketEvent>) -> Addr<Hub> {
+        let hub = Hub::default().start();
+        let events = hub.clone();
+        std::thread::Builder::new()
+            .name("market-data".to_string())
+            .spawn(move || {
+                while let Ok(event) = rx.recv() {
+                    events.do_send(Publish(event));
+                }
+            })
+            .expect("failed to spawn the market data thread");
+        hub
+    }
+
+    fn route(&mut self, event: MarketEvent) {
+        match event {
+            MarketEvent::Trade {
+                symbol,
+                timestamp,
+                trade,
+            } => {
+                let candles = self
+                    .markets
+                    .entry(symbol.clone())
+                    .or_default()
+                    .trade(&trade, timestamp);
+                self.send(Channel::Trades, &symbol, None, || MarketEvent::Trade {
+                    symbol: symbol.clone(),
+                    timestamp,
+                    trade,
+                });
+                for (interval, candle) in Interval::ALL.into_iter().zip(candles) {
+                    self.send(Channel::Candles { interval }, &symbol, None, || candle);
+                }
+                self.send_ticker(&symbol);
+            }
+            MarketEvent::Depth {
+                symbol,
+                bids,
+                asks,
+                timestamp,
+            } => {
+                for levels in self.depth_subscriptions(&symbol) {
+                    self.send(Channel::Depth { levels }, &symbol, None, || {
+                        MarketEvent::Depth {
+                            symbol: symbol.clone(),
+                            bids: bids[..levels.min(bids.len())].to_vec(),
+                            asks: asks[..levels.min(asks.len())].to_vec(),
+                            timestamp,
+                        }
+                    });
+                }
+                let moved = self
+                    .markets
+                    .entry(symbol.clone())
+                    .or_default()
+                    .quote(&bids, &asks, timestamp);
+                if moved {
+                    self.send_ticker(&symbol);
+                }
+                self.depths.insert(symbol, (bids, asks, timestamp));
+            }
+            MarketEvent::Halted { ref symbol, .. } | MarketEvent::Resumed { ref symbol, .. } => {
+                let symbol = symbol.clone();
+                self.send(Channel::Status, &symbol, None, || event);
+            }
+            MarketEvent::Indicative { ref symbol, .. } => {
+                let symbol = symbol.clone();
+                self.send(Channel::Auction, &symbol, None, || event);
+            }
+            MarketEvent::Execution(report) => {
+                let account = Some(report.account.as_str());
+                if report.fill.is_some() {
+                    self.send(Channel::Fills, &report.symbol, account, || &report);
+                }
+                self.send(Channel::Orders, &report.symbol, account, || &report);
+            }
+        }
+    }
+
+    fn send_ticker(&mut self, symbol: &str) {
+        if let Some((recipients, route)) = self.recipients(Channel::Ticker, symbol, None) {
+            let ticker = self.markets[symbol].ticker(symbol);
+            deliver(&recipients, envelope(&mut self.sequences, route, ticker));
+        }
+    }
+
+    /// Serializes `data` and delivers it to the subscribers of `channel`
+    /// for `symbol`, if there are any. Private channels need the `account`
+    /// the data belongs to.
+    fn send<T: Serialize>(
+        &mut self,
+        channel: Channel,
+        symbol: &str,
+        account: Option<&str>,
+        data: impl FnOnce() -> T,
+    ) {
+        if let Some((recipients, route)) = self.recipients(channel, symbol, account) {
+            let text = envelope(&mut self.sequences, route, data());
+            deliver(&recipients, text);
+        }
+    }
+
+    /// Who is subscribed to `channel` for `symbol`, directly or through a
+    /// wildcard, and the concrete route. `None` if nobody is.
+    fn recipients(
+        &self,
+        channel: Channel,
+        symbol: &str,
+        account: Option<&str>,
+    ) -> Option<(Vec<Recipient<Deliver>>, Route)> {
+        let route = Route::new(Topic::new(channel, symbol), account);
+        let wildcard = Route::new(route.topic.wildcard(), account);
+        let sessions: HashSet<&SessionId> = [&route, &wildcard]
+            .into_iter()
+            .filter_map(|route| self.subscribers.get(route))
+            .flatten()
+            .collect();
+        if sessions.is_empty() {
+            return None;
+        }
+        let recipients = sessions
+            .into_iter()
+            .filter_map(|session| self.sessions.get(session))
+            .map(|subscriber| subscriber.recipient.clone())
+            .collect();
+        Some((recipients, route))
+    }
+
+    /// The depths subscribed to for `symbol`, deepest first.
+    fn depth_subscriptions(&self, symbol: &str) -> Vec<usize> {
+        let mut levels: Vec<usize> = self
+            .subscribers
+            .keys()
+            .map(|route| &route.topic)
+            .filter(|topic| topic.symbol.as_deref().is_none_or(|s| s == symbol))
+            .filter_map(|topic| match topic.channel {
+                Channel::Depth { levels } => Some(levels),
+                _ => None,
+            })
+            .collect();
+        levels.sort_unstable_by(|a, b| b.cmp(a));
+        levels.dedup();
+        levels
+    }
+
+    fn unsubscribe(&mut self, session: SessionId, route: &Route) {
+        if let Some(sessions) = self.subscribers.get_mut(route) {
+            sessions.remove(&session);
+            if sessions.is_empty() {
+                self.subscribers.remove(route);
+            }
+        }
+    }
+}
+
+fn envelope<T: Serialize>(sequences: &mut HashMap<Route, u64>, route: Route, data: T) -> Arc<str> {
+    let channel = route.topic.to_string();
+    let seq = sequences.entry(route).or_default();
+    *seq += 1;
+    let envelope = Envelope {
+        channel: &channel,
+        seq: *seq,
+        data,
+    };
+    serde_json::to_string(&envelope)
+        .expect("market data serializes")
+        .into()
+}
+
+fn deliver(recipients: &[Recipient<Deliver>], text: Arc<str>) {
+    for recipient in recipients {
+        recipient.do_send(Deliver(text.clone()));
+    }
+}
+
+impl Actor for Hub {
+    type Context = Context<Self>;
+}
+
+impl Handler<Publish> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, Publish(event): Publish, _ctx: &mut Context<Self>) {
+        self.route(event);
+    }
+}
+
+impl Handler<GetSnapshot> for Hub {
+    type Result = MessageResult<GetSnapshot>;
+
+    fn handle(&mut self, msg: GetSnapshot, _ctx: &mut Context<Self>) -> Self::Result {
+        let route = Route::new(
+            Topic::new(Channel::Depth { levels: msg.levels }, &msg.symbol),
+            None,
+        );
+        let (bids, asks, timestamp) = self.depths.get(&msg.symbol).cloned().unwrap_or_default();
+        MessageResult(DepthSnapshot {
+            channel: route.topic.to_string(),
+            seq: self.sequences.get(&route).copied().unwrap_or_default(),
+            bids,
+            asks,
+            timestamp,
+        })
+    }
+}
+
+impl Handler<Subscribe> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _ctx: &mut Context<Self>) {
+        let subscriber = self
+            .sessions
+            .entry(msg.session)
+            .or_insert_with(|| Subscriber {
+                recipient: msg.recipient,
+                account: None,
+                routes: HashSet::new(),
+            });
+        subscriber.account = msg.account;
+        for topic in msg.topics {
+            if topic.is_private() && subscriber.account.is_none() {
+                tracing::warn!(topic = %topic, "private topic without an account ignored");
+                continue;
+            }
+            let route = Route::new(topic, subscriber.account.as_deref());
+            self.subscribers
+                .entry(route.clone())
+                .or_default()
+                .insert(msg.session);
+            subscriber.routes.insert(route);
+        }
+    }
+}
+
+impl Handler<Unsubscribe> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, _ctx: &mut Context<Self>) {
+        let Some(subscriber) = self.sessions.get_mut(&msg.session) else {
+            return;
+        };
+        let routes: Vec<Route> = msg
+            .topics
+            .into_iter()
+            .map(|topic| Route::new(topic, subscriber.account.as_deref()))
+            .filter(|route| subscriber.routes.remove(route))
+            .collect();
+        for route in &routes {
+            self.unsubscribe(msg.session, route);
+        }
+    }
+}
+
+impl Handler<Disconnect> for Hub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Context<Self>) {
+        if let Some(subscriber) = self.sessions.remove(&msg.session) {
+            for route in &subscriber.routes {
+                self.unsubscribe(msg.session, route);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, MatchedOrder, OrderType, Price};
+    use std::sync::Mutex;
+
+    /// Collects what the hub delivers.
+    #[derive(Default, Clone)]
+    struct Inbox(Arc<Mutex<Vec<serde_json::Value>>>);
+
+    impl Actor for Inbox {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Deliver> for Inbox {
+        type Result = ();
+
+        fn handle(&mut self, Deliver(text): Deliver, _ctx: &mut Context<Self>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(serde_json::from_str(&text).unwrap());
+        }
+    }
+
+    fn trade(symbol: &str, price: f64) -> MarketEvent {
+        MarketEvent::Trade {
+            symbol: symbol.to_string(),
+            timestamp: 0,
+            trade: MatchedOrder {
+                id: 2,
+                matched_with_id: 1,
+                order_type: OrderType::Limit,
+                price: Price::new(price),
+                amount: 1.0,
+                bid_or_ask: BidOrAsk::Bid,
+            },
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_routes_by_topic_with_sequences() {
+        let hub = Hub::default().start();
+        let inbox = Inbox::default();
+        let received = inbox.0.clone();
+        let recipient = inbox.start().recipient();
+        let topics = ["trades:BTC-USD", "ticker:*"]
+            .into_iter()
+            .map(|topic| topic.parse().unwrap())
+            .collect();
+        hub.send(Subscribe {
+            session: 1,
+            recipient,
+            account: None,
+            topics,
+        })
+        .await
+        .unwrap();
+
+        for event in [trade("BTC-USD", 100.0), trade("ETH-USD", 10.0)] {
+            hub.send(Publish(event)).await.unwrap();
+        }
+        // Let the inbox drain its mailbox.
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let received = received.lock().unwrap();
+        let channels: Vec<(&str, u64)> = received
+            .iter()
+            .map(|message| {
+                (
+                    message["channel"].as_str().unwrap(),
+                    message["seq"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            [
+                ("trades:BTC-USD", 1),
+                ("ticker:BTC-USD", 1),
+                ("ticker:ETH-USD", 1)
+            ]
+        );
+        assert_eq!(received[0]["data"]["symbol"], "BTC-USD");
+        assert_eq!(
+            received[2]["data"]["last"],
+            serde_json::json!(Price::new(10.0))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use super::topic::Interval;
+use crate::models::{DepthLevel, MatchedOrder, Price};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Open, high, low and close of the trades in one interval.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Unix time, in seconds, the interval starts at.
+    pub start: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: f64,
+    pub trades: u64,
+}
+
+impl Candle {
+    fn new(start: u64, trade: &MatchedOrder) -> Self {
+        Candle {
+            start,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.amount,
+            trades: 1,
+        }
+    }
+
+    fn add(&mut self, trade: &MatchedOrder) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.amount;
+        self.trades += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ticker {
+    pub symbol: String,
+    pub last: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+    /// Statistics over the trades of the last 24 hours.
+    pub open_24h: Option<Price>,
+    pub high_24h: Option<Price>,
+    pub low_24h: Option<Price>,
+    pub volume_24h: f64,
+    pub timestamp: u64,
+}
+
+/// What the hub keeps per symbol to build tickers and candles.
+#[derive(Debug, Default)]
+pub struct MarketStats {
+    last: Option<Price>,
+    best_bid: Option<Price>,
+    best_ask: Option<Price>,
+    /// The open candle of each interval, in `Interval::ALL` order.
+    candles: [Option<Candle>; 6],
+    /// One minute candles covering the last 24 hours, for the ticker.
+    minutes: VecDeque<Candle>,
+    timestamp: u64,
+}
+
+impl MarketStats {
+    /// Folds a trade into the candles and returns the open candle of every
+    /// interval.
+    pub fn trade(&mut self, trade: &MatchedOrder, timestamp: u64) -> [Candle; 6] {
+        self.last = Some(trade.price);
+        self.timestamp = timestamp;
+        let candles = Interval::ALL.map(|interval| {
+            let start = timestamp - timestamp % interval.secs();
+            let candle = &mut self.candles[interval as usize];
+            match candle {
+                Some(open) if open.start == start => open.add(trade),
+                _ => *candle = Some(Candle::new(start, trade)),
+            }
+            candle.expect("candle was just opened")
+        });
+
+        let minute = candles[Interval::OneMinute as usize];
+        match self.minutes.back_mut() {
+            Some(last) if last.start == minute.start => *last = minute,
+            _ => self.minutes.push_back(minute),
+        }
+        while let Some(first) = self.minutes.front() {
+            if first.start + DAY > minute.start {
+                break;
+            }
+            self.minutes.pop_front();
+        }
+        candles
+    }
+
+    /// Records the top of book. Returns whether it moved.
+    pub fn quote(&mut self, bids: &[DepthLevel], asks: &[DepthLevel], timestamp: u64) -> bool {
+        let best_bid = bids.first().map(|level| level.price);
+        let best_ask = asks.first().map(|level| level.price);
+        if (best_bid, best_ask) == (self.best_bid, self.best_ask) {
+            return false;
+        }
+        self.best_bid = best_bid;
+        self.best_ask = best_ask;
+        self.timestamp = timestamp;
+        true
+    }
+
+    pub fn ticker(&self, symbol: &str) -> Ticker {
+        Ticker {
+            symbol: symbol.to_string(),
+            last: self.last,
+            best_bid: self.best_bid,
+            best_ask: self.best_ask,
+            open_24h: self.minutes.front().map(|candle| candle.open),
+            high_24h: self.minutes.iter().map(|candle| candle.high).max(),
+            low_24h: self.minutes.iter().map(|candle| candle.low).min(),
+            volume_24h: self.minutes.iter().map(|candle| candle.volume).sum(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, OrderType};
+
+    fn trade(price: f64, amount: f64) -> MatchedOrder {
+        MatchedOrder {
+            id: 2,
+            matched_with_id: 1,
+            order_type: OrderType::Limit,
+            price: Price::new(price),
+            amount,
+            bid_or_ask: BidOrAsk::Bid,
+        }
+    }
+
+    #[test]
+    fn test_candles_roll_and_ticker_covers_a_day() {
+        let mut stats = MarketStats::default();
+        stats.trade(&trade(100.0, 1.0), 0);
+        stats.trade(&trade(105.0, 2.0), 30);
+        let candles = stats.trade(&trade(95.0, 1.0), 61);
+
+        let minute = candles[Interval::OneMinute as usize];
+        assert_eq!((minute.start, minute.trades), (60, 1));
+        let hour = candles[Interval::OneHour as usize];
+        assert_eq!(hour.open, Price::new(100.0));
+        assert_eq!(hour.high, Price::new(105.0));
+        assert_eq!(hour.close, Price::new(95.0));
+        assert_eq!(hour.volume, 4.0);
+
+        // A day later only the new trade counts towards the ticker.
+        stats.trade(&trade(90.0, 5.0), 61 + DAY);
+        let ticker = stats.ticker("BTC-USD");
+        assert_eq!(ticker.open_24h, Some(Price::new(90.0)));
+        assert_eq!(ticker.volume_24h, 5.0);
+        assert_eq!(ticker.last, Some(Price::new(90.0)));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::order_book::PUBLISHED_DEPTH;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// How many levels a `depth` subscription gets when it names none.
+pub const DEFAULT_DEPTH_LEVELS: usize = 10;
+
+/// A market data channel for one symbol, or for every symbol when `symbol`
+/// is `None`. Written as `channel:symbol[:parameter]`, for example
+/// `trades:BTC-USD`, `depth:ETH-USD:10`, `ticker:*` or `candles:BTC-USD:1m`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Topic {
+    pub channel: Channel,
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Trades,
+    /// The best `levels` of each side after every change.
+    Depth {
+        levels: usize,
+    },
+    /// Last price, best bid and ask, and 24 hour statistics.
+    Ticker,
+    Candles {
+        interval: Interval,
+    },
+    /// Halts and resumes.
+    Status,
+    /// Indicative prices during call auctions.
+    Auction,
+    /// Execution reports for the session's own orders. Private.
+    Orders,
+    /// The session's own fills, with fees. Private.
+    Fills,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interval {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    OneHour,
+    FourHours,
+    OneDay,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 6] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::FifteenMinutes,
+        Interval::OneHour,
+        Interval::FourHours,
+        Interval::OneDay,
+    ];
+
+    pub fn secs(self) -> u64 {
+        match self {
+            Interval::OneMinute => 60,
+            Interval::FiveMinutes => 5 * 60,
+            Interval::FifteenMinutes => 15 * 60,
+            Interval::OneHour => 60 * 60,
+            Interval::FourHours => 4 * 60 * 60,
+            Interval::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Interval::OneMinute => "1m",
+            Interval::FiveMinutes => "5m",
+            Interval::FifteenMinutes => "15m",
+            Interval::OneHour => "1h",
+            Interval::FourHours => "4h",
+            Interval::OneDay => "1d",
+        }
+    }
+}
+
+impl Topic {
+    pub fn new(channel: Channel, symbol: &str) -> Self {
+        Topic {
+            channel,
+            symbol: Some(symbol.to_string()),
+        }
+    }
+
+    /// Whether the channel carries one account's data and needs an
+    /// authenticated session.
+    pub fn is_private(&self) -> bool {
+        matches!(self.channel, Channel::Orders | Channel::Fills)
+    }
+
+    /// The same channel for every symbol.
+    pub fn wildcard(&self) -> Self {
+        Topic {
+            channel: self.channel,
+            symbol: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicError(String);
+
+impl fmt::Display for TopicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid topic: {}", self.0)
+    }
+}
+
+impl std::error::Error for TopicError {}
+
+impl FromStr for Topic {
+    type Err = TopicError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = |reason: &str| TopicError(format!("{:?}: {}", text, reason));
+        let mut parts = text.split(':');
+        let channel = parts.next().unwrap_or_default();
+        let symbol = match parts.next() {
+            None | Some("") => return Err(error("expected channel:symbol")),
+            Some("*") => None,
+            Some(symbol) => Some(symbol.to_string()),
+        };
+        let parameter = parts.next();
+        if parts.next().is_some() {
+            return Err(error("too many parts"));
+        }
+
+        let channel = match (channel, parameter) {
+            ("trades", None) => Channel::Trades,
+            ("ticker", None) => Channel::Ticker,
+            ("status", None) => Channel::Status,
+            ("auction", None) => Channel::Auction,
+            ("orders", None) => Channel::Orders,
+            ("fills", None) => Channel::Fills,
+            ("depth", levels) => {
+                let levels = match levels {
+                    None => DEFAULT_DEPTH_LEVELS,
+                    Some(levels) => levels
+                        .parse()
+                        .ok()
+                        .filter(|levels| (1..=PUBLISHED_DEPTH).contains(levels))
+                        .ok_or_else(|| {
+                            error(&format!("depth must be 1 to {} levels", PUBLISHED_DEPTH))
+                        })?,
+                };
+                Channel::Depth { levels }
+            }
+            ("candles", Some(interval)) => Channel::Candles {
+                interval: Interval::ALL
+                    .into_iter()
+                    .find(|candidate| candidate.as_str() == interval)
+                    .ok_or_else(|| error("interval must be 1m, 5m, 15m, 1h, 4h or 1d"))?,
+            },
+            ("candles", None) => return Err(error("candles need an interval")),
+            ("trades" | "ticker" | "status" | "auction" | "orders" | "fills", Some(_)) => {
+                return Err(error("channel takes no parameter"))
+            }
+            _ => return Err(error("unknown channel")),
+        };
+        Ok(Topic { channel, symbol })
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = self.symbol.as_deref().unwrap_or("*");
+        match self.channel {
+            Channel::Trades => write!(f, "trades:{}", symbol),
+            Channel::Depth { levels } => write!(f, "depth:{}:{}", symbol, levels),
+            Channel::Ticker => write!(f, "ticker:{}", symbol),
+            Channel::Candles { interval } => write!(f, "candles:{}:{}", symbol, interval.as_str()),
+            Channel::Status => write!(f, "status:{}", symbol),
+            Channel::Auction => write!(f, "auction:{}", symbol),
+            Channel::Orders => write!(f, "orders:{}", symbol),
+            Channel::Fills => write!(f, "fills:{}", symbol),
+        }
+    }
+}
+
+impl Serialize for Topic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Topic {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_grammar() {
+        let topic: Topic = "depth:ETH-USD".parse().unwrap();
+        assert_eq!(topic, Topic::new(Channel::Depth { levels: 10 }, "ETH-USD"));
+        assert_eq!(topic.to_string(), "depth:ETH-USD:10");
+
+        let topic: Topic = "candles:BTC-USD:4h".parse().unwrap();
+        assert_eq!(
+            topic.channel,
+            Channel::Candles {
+                interval: Interval::FourHours
+            }
+        );
+        assert_eq!("ticker:*".parse::<Topic>().unwrap().symbol, None);
+        assert!("fills:*".parse::<Topic>().unwrap().is_private());
+
+        for invalid in [
+            "trades",
+            "trades:",
+            "trades:BTC-USD:1",
+            "depth:BTC-USD:0",
+            "depth:BTC-USD:51",
+            "candles:BTC-USD",
+            "candles:BTC-USD:2m",
+            "quotes:BTC-USD",
+        ] {
+            assert!(invalid.parse::<Topic>().is_err(), "{} parsed", invalid);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::engine::EngineError;
+use crate::instrument::InstrumentError;
+use crate::models::Execution;
+use crate::order_book::{BookDepth, BookError};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use prometheus::{
+    CounterVec, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+
+/// Every metric the server exports, registered in its own registry. Clones
+/// share the same underlying metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub orders_accepted: IntCounter,
+    pub orders_rejected: IntCounterVec,
+    pub fills: IntCounterVec,
+    pub traded_volume: CounterVec,
+    pub book_depth: GaugeVec,
+    pub resting_orders: IntGaugeVec,
+    pub websocket_connections: IntGauge,
+    pub engine_queue_depth: IntGauge,
+    pub request_duration: HistogramVec,
+    pub order_to_trade_breaches: IntCounterVec,
+    pub history_writes_dropped: IntCounter,
+    pub history_writes_refused: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("orderbook".to_string()), None)
+            .expect("valid registry prefix");
+        let metrics = Self {
+            orders_accepted: IntCounter::new(
+                "orders_accepted_total",
+                "Orders accepted by the matching engine.",
+            )
+            .unwrap(),
+            orders_rejected: IntCounterVec::new(
+                Opts::new(
+                    "orders_rejected_total",
+                    "Orders rejected by the matching engine, by reason.",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            fills: IntCounterVec::new(
+                Opts::new("fills_total", "Fills executed, by trading pair."),
+                &["symbol"],
+            )
+            .unwrap(),
+            traded_volume: CounterVec::new(
+                Opts::new(
+                    "traded_volume_total",
+                    "Base amount traded, by trading pair.",
+                ),
+                &["symbol"],
+            )
+            .unwrap(),
+            book_depth: GaugeVec::new(
+                Opts::new("book_depth", "Resting amount on each side of each book."),
+                &["symbol", "side"],
+            )
+            .unwrap(),
+            resting_orders: IntGaugeVec::new(
+                Opts::new("resting_orders", "Orders resting in each book."),
+                &["symbol"],
+            )
+            .unwrap(),
+            websocket_connections: IntGauge::new(
+                "websocket_connections",
+                "Open WebSocket connections.",
+            )
+            .unwrap(),
+            engine_queue_depth: IntGauge::new(
+                "engine_queue_depth",
+                "Commands waiting for the matching engine.",
+            )
+            .unwrap(),
+            request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "HTTP request latency, by route.",
+                ),
+                &["method", "path", "status"],
+            )
+            .unwrap(),
+            order_to_trade_breaches: IntCounterVec::new(
+                Opts::new(
+                    "order_to_trade_breaches_total",
+                    "Accounts found over the order-to-trade ratio limit, by action taken.",
+                ),
+                &["action"],
+            )
+            .unwrap(),
+            history_writes_dropped: IntCounter::new(
+                "history_writes_dropped_total",
+                "Order and fill records dropped because the history database queue was full.",
+            )
+            .unwrap(),
+            history_writes_refused: IntCounter::new(
+                "history_writes_refused_total",
+                "Order and fill records the history database refused to store.",
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register();
+        metrics
+    }
+
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 12] = [
+            Box::new(self.orders_accepted.clone()),
+            Box::new(self.orders_rejected.clone()),
+            Box::new(self.fills.clone()),
+            Box::new(self.traded_volume.clone()),
+            Box::new(self.book_depth.clone()),
+            Box::new(self.resting_orders.clone()),
+            Box::new(self.websocket_connections.clone()),
+            Box::new(self.engine_queue_depth.clone()),
+            Box::new(self.request_duration.clone()),
+            Box::new(self.order_to_trade_breaches.clone()),
+            Box::new(self.history_writes_dropped.clone()),
+            Box::new(self.history_writes_refused.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("metric registered once");
+        }
+    }
+
+    /// Counts the outcome of a new order.
+    pub fn record_order(&self, result: &Result<Execution, EngineError>) {
+        match result {
+            Ok(_) => self.orders_accepted.inc(),
+            Err(err) => self
+                .orders_rejected
+                .with_label_values(&[rejection_reason(err)])
+                .inc(),
+        }
+    }
+
+    pub fn record_trades(&self, symbol: &str, fills: u64, volume: f64) {
+        if fills > 0 {
+            self.fills.with_label_values(&[symbol]).inc_by(fills);
+            self.traded_volume
+                .with_label_values(&[symbol])
+                .inc_by(volume);
+        }
+    }
+
+    pub fn set_depth(&self, symbol: &str, depth: &BookDepth) {
+        self.book_depth
+            .with_label_values(&[symbol, "bid"])
+            .set(depth.bids);
+        self.book_depth
+            .with_label_values(&[symbol, "ask"])
+            .set(depth.asks);
+        self.resting_orders
+            .with_label_values(&[symbol])
+            .set(depth.resting_orders as i64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding cannot fail");
+        String::from_utf8(buffer).expect("text encoding is UTF-8")
+    }
+}
+
+/// A low-cardinality label for why an order was turned away.
+pub fn rejection_reason(err: &EngineError) -> &'static str {
+    match err {
+        EngineError::Book(BookError::Rejected(err)) => match err {
+            InstrumentError::WrongSymbol { .. } => "wrong_symbol",
+            InstrumentError::InvalidPrice => "invalid_price",
+            InstrumentError::InvalidAmount => "invalid_amount",
+            InstrumentError::OffTick { .. } => "off_tick",
+            InstrumentError::OffLot { .. } => "off_lot",
+            InstrumentError::BelowMinNotional { .. } => "below_min_notional",
+        },
+        EngineError::Book(BookError::Halted) => "halted",
+        EngineError::Book(BookError::OrderNotFound(_)) => "order_not_found",
+        EngineError::UnknownSymbol(_) => "unknown_symbol",
+        EngineError::SymbolRequired => "symbol_required",
+        EngineError::DuplicateOrder(_) => "duplicate_order",
+        EngineError::Throttled(_) => "throttled",
+        EngineError::Book(BookError::InvalidOrder(_)) => "invalid_order",
+        EngineError::Unavailable => "unavailable",
+        EngineError::Internal => "internal_error",
+        EngineError::OutOfService(_) => "out_of_service",
+    }
+}
+
+/// Middleware recording each request's latency against its route pattern,
+/// so ids in paths do not blow up the label set. Does nothing unless a
+/// `Metrics` is registered as app data.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let path = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let res = next.call(req).await?;
+    if let Some(metrics) = metrics {
+        metrics
+            .request_duration
+            .with_label_values(&[&method, &path, res.status().as_str()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_labelled_counters() {
+        let metrics = Metrics::new();
+        metrics.record_order(&Err(EngineError::Book(BookError::Halted)));
+        metrics.record_trades("BTC-USD", 2, 1.5);
+
+        let text = metrics.render();
+        assert!(text.contains("orderbook_orders_rejected_total{reason=\"halted\"} 1"));
+        assert!(text.contains("orderbook_fills_total{symbol=\"BTC-USD\"} 2"));
+        assert!(text.contains("orderbook_traded_volume_total{symbol=\"BTC-USD\"} 1.5"));
+        assert!(text.contains("# TYPE orderbook_engine_queue_depth gauge"));
+    }
+}
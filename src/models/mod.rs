@@ -1,36 +1,79 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
 pub enum BidOrAsk {
     Bid,
     Ask,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize, ToSchema)]
 pub enum OrderType {
     Market,
     Limit,
 }
 
-#[derive(Debug, Hash, Clone, Copy, Deserialize, Serialize)]
+/// Number of decimal places `Price::new` keeps when no instrument precision
+/// is known.
+pub const DEFAULT_PRICE_PRECISION: u32 = 5;
+
+/// A price of `integral + fractional / scalar`. Prices read from clients
+/// must have a `scalar` of at least 1 and a `fractional` below it.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(try_from = "PriceFields")]
 pub struct Price {
     integral: u64,
     fractional: u64,
     scalar: u64,
 }
 
+#[derive(Deserialize)]
+struct PriceFields {
+    integral: u64,
+    fractional: u64,
+    scalar: u64,
+}
+
+impl TryFrom<PriceFields> for Price {
+    type Error = String;
+
+    fn try_from(fields: PriceFields) -> Result<Self, Self::Error> {
+        if fields.scalar == 0 {
+            return Err("price scalar must be at least 1".to_string());
+        }
+        if fields.fractional >= fields.scalar {
+            return Err("price fractional must be below its scalar".to_string());
+        }
+        Ok(Price {
+            integral: fields.integral,
+            fractional: fields.fractional,
+            scalar: fields.scalar,
+        })
+    }
+}
+
 impl Price {
     pub fn new(price: f64) -> Price {
-        let scalar = 100000;
-        let integral = price as u64;
-        let fractional = ((price % 1.0) * scalar as f64) as u64;
+        Price::with_precision(price, DEFAULT_PRICE_PRECISION)
+    }
+
+    pub fn with_precision(price: f64, precision: u32) -> Price {
+        let scalar = 10u64.pow(precision);
+        let units = (price * scalar as f64).round() as u64;
+        Price::from_units(units, scalar)
+    }
+
+    /// Builds a price from a count of `1 / scalar` units.
+    pub fn from_units(units: u64, scalar: u64) -> Price {
         Price {
             scalar,
-            integral,
-            fractional,
+            integral: units / scalar,
+            fractional: units % scalar,
         }
     }
+
     pub fn integral(&self) -> u64 {
         self.integral
     }
@@ -42,10 +85,40 @@ impl Price {
     pub fn scalar(&self) -> u64 {
         self.scalar
     }
+
+    /// The price expressed as a whole number of `1 / scalar` units.
+    pub fn units(&self) -> u64 {
+        self.integral
+            .saturating_mul(self.scalar)
+            .saturating_add(self.fractional)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.integral as f64 + self.fractional as f64 / self.scalar as f64
+    }
+
+    /// Re-expresses the price with a different scalar, rounding to the
+    /// nearest representable unit when precision is lost.
+    pub fn rescale(&self, scalar: u64) -> Price {
+        if scalar == self.scalar {
+            return *self;
+        }
+        let fractional = (self.fractional as u128 * scalar as u128 + self.scalar as u128 / 2)
+            / self.scalar as u128;
+        Price::from_units(
+            self.integral
+                .saturating_mul(scalar)
+                .saturating_add(fractional as u64),
+            scalar,
+        )
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct Order {
+    /// Assigned by the engine when the order is submitted; whatever a client
+    /// sends here is ignored.
+    #[serde(default)]
     pub id: u64,
     pub order_type: OrderType,
     pub trading_pair: String,
@@ -53,6 +126,13 @@ pub struct Order {
     pub price: Option<Price>,
     pub timestamp: u64,
     pub bid_or_ask: BidOrAsk,
+    /// The client's own reference for the order, unique within its account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+    /// Who submitted the order, filled in by the server from the caller's
+    /// identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
 }
 
 impl Order {
@@ -62,7 +142,7 @@ impl Order {
         trading_pair: String,
         amount: f64,
         price: Option<Price>,
-        timestamp: u64,
+        _timestamp: u64,
         bid_or_ask: BidOrAsk,
     ) -> Self {
         Self {
@@ -76,11 +156,13 @@ impl Order {
                 .unwrap()
                 .as_secs(),
             bid_or_ask,
+            client_order_id: None,
+            account: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
 pub struct MatchedOrder {
     pub id: u64,
     pub matched_with_id: u64,
@@ -89,3 +171,239 @@ pub struct MatchedOrder {
     pub amount: f64,
     pub bid_or_ask: BidOrAsk,
 }
+
+/// The outcome of submitting an order: the order as it stands afterwards,
+/// with `amount` reduced by whatever traded, and the fills it produced.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Execution {
+    pub order: Order,
+    pub fills: Vec<MatchedOrder>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HaltReason {
+    PriceBand { reference: Price, attempted: Price },
+    Manual,
+}
+
+/// Where an order stands. `Filled`, `Cancelled`, `Expired` and `Rejected`
+/// are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    /// The unfilled rest was dropped by the book rather than the owner: a
+    /// market order that ran out of liquidity or an order that tripped the
+    /// price band.
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn is_final(self) -> bool {
+        !matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+}
+
+/// What an execution report is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    New,
+    Fill,
+    Amended,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+    /// Filled in a call auction uncross, where neither side took liquidity.
+    /// Charged the maker fee.
+    Auction,
+}
+
+/// One fill from the point of view of one side of the trade.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Fill {
+    pub trade_id: u64,
+    pub price: Price,
+    pub amount: f64,
+    pub liquidity: Liquidity,
+    pub fee: f64,
+    /// The asset `fee` is charged in, if the instrument is known.
+    pub fee_asset: Option<String>,
+}
+
+/// A change to one order, sent privately to the account that owns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    pub kind: ReportKind,
+    pub order_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+    pub account: String,
+    pub symbol: String,
+    pub side: BidOrAsk,
+    pub order_type: OrderType,
+    pub price: Option<Price>,
+    pub status: OrderStatus,
+    /// The order's total quantity, including what has filled.
+    pub amount: f64,
+    pub filled: f64,
+    pub remaining: f64,
+    pub average_price: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fill: Option<Fill>,
+    /// Why the order was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub timestamp: u64,
+}
+
+/// An order as it stands, for status queries. `amount` is the order's total
+/// quantity, including what has filled.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrderRecord {
+    #[serde(flatten)]
+    pub order: Order,
+    pub status: OrderStatus,
+    pub filled: f64,
+    pub remaining: f64,
+    pub average_price: Option<f64>,
+    /// When the order last changed, in Unix seconds.
+    pub updated: u64,
+    /// Why the order was rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// A fill together with the order it belongs to, for fill history queries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FillRecord {
+    pub order_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub symbol: String,
+    pub side: BidOrAsk,
+    #[serde(flatten)]
+    pub fill: Fill,
+    pub timestamp: u64,
+}
+
+/// One trade on the public tape.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TradeRecord {
+    pub trade_id: u64,
+    pub symbol: String,
+    pub price: Price,
+    pub amount: f64,
+    /// The side of the order that took liquidity; `None` for trades from a
+    /// call auction uncross.
+    pub aggressor: Option<BidOrAsk>,
+    pub timestamp: u64,
+}
+
+/// Resting interest at one price.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub amount: f64,
+    pub orders: usize,
+}
+
+/// Everything the engine and its books publish to market data subscribers.
+/// `Execution` reports are private to their account and never go out on
+/// public channels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MarketEvent {
+    Trade {
+        symbol: String,
+        timestamp: u64,
+        #[serde(flatten)]
+        trade: MatchedOrder,
+    },
+    /// The best levels of one side of a book after it changed, best first.
+    Depth {
+        symbol: String,
+        bids: Vec<DepthLevel>,
+        asks: Vec<DepthLevel>,
+        timestamp: u64,
+    },
+    Halted {
+        symbol: String,
+        reason: HaltReason,
+        timestamp: u64,
+        resume_at: Option<u64>,
+    },
+    Resumed {
+        symbol: String,
+        timestamp: u64,
+    },
+    /// Published during a call period each time an order is added to the
+    /// call book.
+    Indicative {
+        symbol: String,
+        price: Option<Price>,
+        volume: f64,
+        imbalance: f64,
+        timestamp: u64,
+    },
+    Execution(ExecutionReport),
+}
+
+/// A small integer standing in for a trading pair inside the book, so that
+/// resting orders carry no heap-allocated symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct SymbolId(pub u32);
+
+/// Interns trading pair names into `SymbolId`s. Looking up a symbol that is
+/// already interned does not allocate.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    names: Vec<String>,
+    ids: HashMap<String, SymbolId>,
+}
+
+impl Symbols {
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = SymbolId(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<SymbolId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = SymbolId> {
+        (0..self.names.len() as u32).map(SymbolId)
+    }
+
+    pub fn name(&self, id: SymbolId) -> &str {
+        &self.names[id.0 as usize]
+    }
+}
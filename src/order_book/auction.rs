@@ -0,0 +1,129 @@
+use super::level::Level;
+use crate::models::Price;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+/// The result of an uncross calculation: the single price at which the call
+/// book would execute, how much would trade there, and the signed surplus
+/// left over (positive when buyers are left unfilled).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Uncross {
+    pub price: Price,
+    pub volume: f64,
+    pub imbalance: f64,
+}
+
+/// Finds the equilibrium price of a call book.
+///
+/// Candidates are every price with resting interest. The winner maximizes
+/// executable volume, then minimizes the absolute imbalance. Remaining ties
+/// go to the highest price when every tied candidate has a buy surplus, the
+/// lowest when every one has a sell surplus, then to the price closest to
+/// `reference`, and finally to the lowest price.
+pub fn equilibrium(
+    bids: &BTreeMap<Price, Level>,
+    asks: &BTreeMap<Price, Level>,
+    reference: Option<Price>,
+) -> Option<Uncross> {
+    let candidates: BTreeSet<Price> = bids.keys().chain(asks.keys()).copied().collect();
+
+    let mut best: Vec<Uncross> = Vec::new();
+    for price in candidates {
+        let demand: f64 = bids.range(price..).map(|(_, level)| level.total()).sum();
+        let supply: f64 = asks.range(..=price).map(|(_, level)| level.total()).sum();
+        let volume = demand.min(supply);
+        if volume <= 0.0 {
+            continue;
+        }
+        let candidate = Uncross {
+            price,
+            volume,
+            imbalance: demand - supply,
+        };
+        match best.first() {
+            Some(current)
+                if volume < current.volume
+                    || (volume == current.volume
+                        && candidate.imbalance.abs() > current.imbalance.abs()) => {}
+            Some(current)
+                if volume == current.volume
+                    && candidate.imbalance.abs() == current.imbalance.abs() =>
+            {
+                best.push(candidate)
+            }
+            _ => best = vec![candidate],
+        }
+    }
+
+    if best.len() <= 1 {
+        return best.pop();
+    }
+    if best.iter().all(|c| c.imbalance > 0.0) {
+        return best.last().copied();
+    }
+    if best.iter().all(|c| c.imbalance < 0.0) {
+        return best.first().copied();
+    }
+    if let Some(reference) = reference {
+        let distance = |c: &Uncross| c.price.units().abs_diff(reference.units());
+        return best.iter().min_by_key(|c| distance(c)).copied();
+    }
+    best.first().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::level::{BookOrder, OrderSlab};
+    use super::*;
+    use crate::models::{BidOrAsk, OrderType, SymbolId};
+
+    fn book(levels: &[(f64, f64)], bid_or_ask: BidOrAsk) -> BTreeMap<Price, Level> {
+        let mut slab = OrderSlab::default();
+        let mut book = BTreeMap::new();
+        for (id, &(price, amount)) in levels.iter().enumerate() {
+            let order = BookOrder {
+                id: id as u64,
+                symbol: SymbolId(0),
+                order_type: OrderType::Limit,
+                bid_or_ask,
+                amount,
+                price: Price::new(price),
+                timestamp: 0,
+            };
+            slab.push_back(book.entry(Price::new(price)).or_default(), order);
+        }
+        book
+    }
+
+    #[test]
+    fn test_maximizes_volume() {
+        let bids = book(&[(102.0, 5.0), (101.0, 5.0), (99.0, 10.0)], BidOrAsk::Bid);
+        let asks = book(&[(98.0, 3.0), (100.0, 6.0), (103.0, 10.0)], BidOrAsk::Ask);
+
+        // 100 and 101 both execute 9 with one lot of buy surplus, so the
+        // surplus pushes the price to the top of the range.
+        let uncross = equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(uncross.price, Price::new(101.0));
+        assert_eq!(uncross.volume, 9.0);
+        assert_eq!(uncross.imbalance, 1.0);
+    }
+
+    #[test]
+    fn test_no_cross_has_no_equilibrium() {
+        let bids = book(&[(99.0, 5.0)], BidOrAsk::Bid);
+        let asks = book(&[(100.0, 5.0)], BidOrAsk::Ask);
+        assert!(equilibrium(&bids, &asks, None).is_none());
+    }
+
+    #[test]
+    fn test_balanced_tie_goes_to_reference() {
+        let bids = book(&[(102.0, 5.0)], BidOrAsk::Bid);
+        let asks = book(&[(100.0, 5.0)], BidOrAsk::Ask);
+
+        let uncross = equilibrium(&bids, &asks, Some(Price::new(101.9))).unwrap();
+        assert_eq!(uncross.price, Price::new(102.0));
+        let uncross = equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(uncross.price, Price::new(100.0));
+    }
+}
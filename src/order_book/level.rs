@@ -0,0 +1,287 @@
+use crate::models::{BidOrAsk, Order, OrderType, Price, SymbolId};
+
+/// The book's compact copy of a resting order. Unlike `Order` it owns no
+/// heap data, so storing, matching and removing it never allocates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookOrder {
+    pub id: u64,
+    pub symbol: SymbolId,
+    pub order_type: OrderType,
+    pub bid_or_ask: BidOrAsk,
+    pub amount: f64,
+    pub price: Price,
+    pub timestamp: u64,
+}
+
+impl BookOrder {
+    /// Expands the order back into its API form, without the client order
+    /// id and account, which the book keeps separately.
+    pub fn to_order(self, trading_pair: &str) -> Order {
+        Order {
+            id: self.id,
+            order_type: self.order_type,
+            trading_pair: trading_pair.to_string(),
+            amount: self.amount,
+            price: Some(self.price),
+            timestamp: self.timestamp,
+            bid_or_ask: self.bid_or_ask,
+            client_order_id: None,
+            account: None,
+        }
+    }
+}
+
+/// Storage for every resting order in a book. Each price level is a doubly
+/// linked list threaded through the slab, so an order can be unlinked from
+/// the middle of its queue in O(1) given its key.
+#[derive(Debug, Default)]
+pub struct OrderSlab {
+    slots: Vec<Slot>,
+    free: Option<usize>,
+    /// Bumped by every change to a resting order.
+    revision: u64,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Occupied(Node),
+    Vacant { next_free: Option<usize> },
+}
+
+#[derive(Debug)]
+struct Node {
+    order: BookOrder,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// The FIFO queue at one price: its ends in the slab, how many orders it
+/// holds and their combined resting amount.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Level {
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize,
+    total: f64,
+}
+
+impl Level {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    pub fn head(&self) -> Option<usize> {
+        self.head
+    }
+}
+
+impl OrderSlab {
+    /// Appends `order` to the back of `level` and returns its key.
+    pub fn push_back(&mut self, level: &mut Level, order: BookOrder) -> usize {
+        self.revision += 1;
+        level.total += order.amount;
+        level.len += 1;
+        let node = Node {
+            order,
+            prev: level.tail,
+            next: None,
+        };
+        let key = match self.free {
+            Some(key) => {
+                if let Slot::Vacant { next_free } = self.slots[key] {
+                    self.free = next_free;
+                }
+                self.slots[key] = Slot::Occupied(node);
+                key
+            }
+            None => {
+                self.slots.push(Slot::Occupied(node));
+                self.slots.len() - 1
+            }
+        };
+        match level.tail {
+            Some(tail) => self.node_mut(tail).next = Some(key),
+            None => level.head = Some(key),
+        }
+        level.tail = Some(key);
+        key
+    }
+
+    /// Unlinks the order at `key` from `level` and frees its slot.
+    pub fn remove(&mut self, level: &mut Level, key: usize) -> BookOrder {
+        self.revision += 1;
+        let slot = std::mem::replace(
+            &mut self.slots[key],
+            Slot::Vacant {
+                next_free: self.free,
+            },
+        );
+        let Slot::Occupied(node) = slot else {
+            panic!("order slab key {} is vacant", key);
+        };
+        self.free = Some(key);
+
+        match node.prev {
+            Some(prev) => self.node_mut(prev).next = node.next,
+            None => level.head = node.next,
+        }
+        match node.next {
+            Some(next) => self.node_mut(next).prev = node.prev,
+            None => level.tail = node.prev,
+        }
+        level.len -= 1;
+        level.total -= node.order.amount;
+        if level.is_empty() {
+            level.total = 0.0;
+        }
+        node.order
+    }
+
+    /// Takes `amount` off the order at `key`, keeping its queue position.
+    pub fn reduce(&mut self, level: &mut Level, key: usize, amount: f64) -> &BookOrder {
+        self.revision += 1;
+        level.total -= amount;
+        let order = &mut self.node_mut(key).order;
+        order.amount -= amount;
+        order
+    }
+
+    /// Changes whenever an order is added, reduced or removed, so callers
+    /// can tell whether the book has moved since they last looked.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn get(&self, key: usize) -> &BookOrder {
+        &self.node(key).order
+    }
+
+    pub fn next(&self, key: usize) -> Option<usize> {
+        self.node(key).next
+    }
+
+    pub fn view<'a>(&'a self, level: &'a Level) -> LevelView<'a> {
+        LevelView { slab: self, level }
+    }
+
+    fn node(&self, key: usize) -> &Node {
+        match &self.slots[key] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant { .. } => panic!("order slab key {} is vacant", key),
+        }
+    }
+
+    fn node_mut(&mut self, key: usize) -> &mut Node {
+        match &mut self.slots[key] {
+            Slot::Occupied(node) => node,
+            Slot::Vacant { .. } => panic!("order slab key {} is vacant", key),
+        }
+    }
+}
+
+/// Read-only access to the orders queued at one price, front first.
+#[derive(Clone, Copy)]
+pub struct LevelView<'a> {
+    slab: &'a OrderSlab,
+    level: &'a Level,
+}
+
+impl<'a> LevelView<'a> {
+    pub fn len(&self) -> usize {
+        self.level.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.level.is_empty()
+    }
+
+    pub fn total(&self) -> f64 {
+        self.level.total()
+    }
+
+    pub fn front(&self) -> Option<&'a BookOrder> {
+        self.level.head.map(|key| self.slab.get(key))
+    }
+
+    pub fn iter(&self) -> LevelIter<'a> {
+        LevelIter {
+            slab: self.slab,
+            cursor: self.level.head,
+        }
+    }
+}
+
+pub struct LevelIter<'a> {
+    slab: &'a OrderSlab,
+    cursor: Option<usize>,
+}
+
+impl<'a> Iterator for LevelIter<'a> {
+    type Item = &'a BookOrder;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.cursor?;
+        let node = self.slab.node(key);
+        self.cursor = node.next;
+        Some(&node.order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u64, amount: f64) -> BookOrder {
+        BookOrder {
+            id,
+            symbol: SymbolId(0),
+            order_type: OrderType::Limit,
+            bid_or_ask: BidOrAsk::Bid,
+            amount,
+            price: Price::new(100.0),
+            timestamp: 0,
+        }
+    }
+
+    fn ids(slab: &OrderSlab, level: &Level) -> Vec<u64> {
+        slab.view(level).iter().map(|order| order.id).collect()
+    }
+
+    #[test]
+    fn test_remove_from_middle_keeps_order() {
+        let mut slab = OrderSlab::default();
+        let mut level = Level::default();
+        slab.push_back(&mut level, order(1, 1.0));
+        let middle = slab.push_back(&mut level, order(2, 2.0));
+        slab.push_back(&mut level, order(3, 3.0));
+
+        assert_eq!(slab.remove(&mut level, middle).id, 2);
+        assert_eq!(ids(&slab, &level), vec![1, 3]);
+        assert_eq!(level.total(), 4.0);
+
+        // The freed slot is reused for the next order.
+        assert_eq!(slab.push_back(&mut level, order(4, 1.0)), middle);
+        assert_eq!(ids(&slab, &level), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_remove_ends() {
+        let mut slab = OrderSlab::default();
+        let mut level = Level::default();
+        let first = slab.push_back(&mut level, order(1, 1.0));
+        let last = slab.push_back(&mut level, order(2, 1.0));
+
+        slab.remove(&mut level, first);
+        slab.remove(&mut level, last);
+        assert!(level.is_empty());
+        assert!(slab.view(&level).front().is_none());
+    }
+}
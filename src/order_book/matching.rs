@@ -0,0 +1,257 @@
+use super::level::LevelView;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Guards lot arithmetic against `f64` results such as `2.9999999`.
+const LOT_EPSILON: f64 = 1e-9;
+
+/// Decides how an incoming quantity is shared between the resting orders of
+/// a single price level.
+pub trait MatchingPolicy: fmt::Debug + Send {
+    /// Writes one allocation per order in `level`, in queue order, into
+    /// `allocations`. The allocations never exceed an order's resting amount
+    /// and add up to at most `quantity`. When `lot_size` is set every
+    /// allocation is a whole number of lots.
+    fn allocate(
+        &self,
+        level: LevelView<'_>,
+        quantity: f64,
+        lot_size: Option<f64>,
+        allocations: &mut Vec<f64>,
+    );
+}
+
+/// Per-instrument choice of matching policy, as it appears in configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum MatchingAlgorithm {
+    #[default]
+    Fifo,
+    ProRata {
+        min_allocation: f64,
+    },
+    Hybrid {
+        top_order_pct: f64,
+        min_allocation: f64,
+    },
+}
+
+impl MatchingAlgorithm {
+    pub fn policy(&self) -> Box<dyn MatchingPolicy> {
+        match *self {
+            MatchingAlgorithm::Fifo => Box::new(Fifo),
+            MatchingAlgorithm::ProRata { min_allocation } => Box::new(ProRata { min_allocation }),
+            MatchingAlgorithm::Hybrid {
+                top_order_pct,
+                min_allocation,
+            } => Box::new(Hybrid {
+                top_order_pct,
+                min_allocation,
+            }),
+        }
+    }
+}
+
+/// Strict price-time priority.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl MatchingPolicy for Fifo {
+    fn allocate(
+        &self,
+        level: LevelView<'_>,
+        quantity: f64,
+        _lot_size: Option<f64>,
+        allocations: &mut Vec<f64>,
+    ) {
+        allocations.clear();
+        allocations.resize(level.len(), 0.0);
+        fill_in_time_priority(level, quantity, None, allocations);
+    }
+}
+
+/// Shares the quantity in proportion to resting size. Shares are rounded
+/// down to whole lots and shares below `min_allocation` are dropped; what
+/// is left over goes out in time priority.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRata {
+    pub min_allocation: f64,
+}
+
+impl MatchingPolicy for ProRata {
+    fn allocate(
+        &self,
+        level: LevelView<'_>,
+        quantity: f64,
+        lot_size: Option<f64>,
+        allocations: &mut Vec<f64>,
+    ) {
+        allocations.clear();
+        allocations.resize(level.len(), 0.0);
+        let quantity = pro_rata(
+            level,
+            0,
+            quantity,
+            lot_size,
+            self.min_allocation,
+            allocations,
+        );
+        fill_in_time_priority(level, quantity, lot_size, allocations);
+    }
+}
+
+/// Fills the order at the front of the queue with up to `top_order_pct`
+/// percent of the quantity first, then shares the rest pro-rata across the
+/// remaining orders.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hybrid {
+    pub top_order_pct: f64,
+    pub min_allocation: f64,
+}
+
+impl MatchingPolicy for Hybrid {
+    fn allocate(
+        &self,
+        level: LevelView<'_>,
+        quantity: f64,
+        lot_size: Option<f64>,
+        allocations: &mut Vec<f64>,
+    ) {
+        allocations.clear();
+        allocations.resize(level.len(), 0.0);
+        let Some(top) = level.front() else {
+            return;
+        };
+        let top_share = round_down(quantity * self.top_order_pct / 100.0, lot_size);
+        allocations[0] = top_share.min(top.amount);
+        let quantity = quantity - allocations[0];
+        let quantity = pro_rata(
+            level,
+            1,
+            quantity,
+            lot_size,
+            self.min_allocation,
+            allocations,
+        );
+        fill_in_time_priority(level, quantity, lot_size, allocations);
+    }
+}
+
+/// Allocates `quantity` pro-rata across `level[start..]` and returns what is
+/// left unallocated.
+fn pro_rata(
+    level: LevelView<'_>,
+    start: usize,
+    quantity: f64,
+    lot_size: Option<f64>,
+    min_allocation: f64,
+    allocations: &mut [f64],
+) -> f64 {
+    let total: f64 = level.iter().skip(start).map(|order| order.amount).sum();
+    if total <= 0.0 || quantity <= 0.0 {
+        return quantity;
+    }
+    let fill = quantity.min(total);
+    let mut allocated = 0.0;
+    for (index, order) in level.iter().enumerate().skip(start) {
+        let share = round_down(fill * order.amount / total, lot_size).min(order.amount);
+        if share > 0.0 && share + LOT_EPSILON >= min_allocation {
+            allocations[index] += share;
+            allocated += share;
+        }
+    }
+    quantity - allocated
+}
+
+/// Hands `quantity` out in queue order to whatever each order still has
+/// unallocated. This is the whole of FIFO and the deterministic residual
+/// step of the pro-rata policies.
+fn fill_in_time_priority(
+    level: LevelView<'_>,
+    mut quantity: f64,
+    lot_size: Option<f64>,
+    allocations: &mut [f64],
+) {
+    for (index, order) in level.iter().enumerate() {
+        if quantity <= 0.0 {
+            break;
+        }
+        let extra = round_down((order.amount - allocations[index]).min(quantity), lot_size);
+        allocations[index] += extra;
+        quantity -= extra;
+    }
+}
+
+fn round_down(amount: f64, lot_size: Option<f64>) -> f64 {
+    match lot_size {
+        Some(lot) if lot > 0.0 => ((amount / lot) + LOT_EPSILON).floor() * lot,
+        _ => amount,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::level::{BookOrder, Level, OrderSlab};
+    use super::*;
+    use crate::models::{BidOrAsk, OrderType, Price, SymbolId};
+
+    fn allocate(policy: &dyn MatchingPolicy, amounts: &[f64], quantity: f64) -> Vec<f64> {
+        let mut slab = OrderSlab::default();
+        let mut level = Level::default();
+        for (id, &amount) in amounts.iter().enumerate() {
+            let order = BookOrder {
+                id: id as u64,
+                symbol: SymbolId(0),
+                order_type: OrderType::Limit,
+                bid_or_ask: BidOrAsk::Ask,
+                amount,
+                price: Price::new(100.0),
+                timestamp: 0,
+            };
+            slab.push_back(&mut level, order);
+        }
+        let mut allocations = Vec::new();
+        policy.allocate(slab.view(&level), quantity, Some(1.0), &mut allocations);
+        allocations
+    }
+
+    #[test]
+    fn test_fifo_fills_front_first() {
+        assert_eq!(allocate(&Fifo, &[3.0, 5.0, 2.0], 6.0), vec![3.0, 3.0, 0.0]);
+    }
+
+    #[test]
+    fn test_pro_rata_rounds_residual_in_time_priority() {
+        // 7 lots over 10/10/10: 2 each by proportion, the spare lot goes to
+        // the oldest order.
+        let policy = ProRata {
+            min_allocation: 0.0,
+        };
+        assert_eq!(
+            allocate(&policy, &[10.0, 10.0, 10.0], 7.0),
+            vec![3.0, 2.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn test_pro_rata_min_allocation() {
+        // The small order's one-lot share is below the minimum, so it only
+        // gets filled if anything is left after everyone else.
+        let policy = ProRata {
+            min_allocation: 2.0,
+        };
+        assert_eq!(allocate(&policy, &[20.0, 2.0], 10.0), vec![10.0, 0.0]);
+    }
+
+    #[test]
+    fn test_hybrid_top_order_then_pro_rata() {
+        let policy = Hybrid {
+            top_order_pct: 50.0,
+            min_allocation: 0.0,
+        };
+        assert_eq!(
+            allocate(&policy, &[10.0, 6.0, 2.0], 8.0),
+            vec![4.0, 3.0, 1.0]
+        );
+    }
+}
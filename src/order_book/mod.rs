@@ -1,16 +1,192 @@
-use crate::models::{BidOrAsk, MatchedOrder, Order, OrderType, Price};
+use crate::instrument::{HaltPolicy, Instrument, InstrumentError};
+use crate::models::{
+    BidOrAsk, DepthLevel, Execution, HaltReason, MarketEvent, MatchedOrder, Order, OrderType,
+    Price, SymbolId, Symbols,
+};
 use serde::Deserialize;
 use serde::Serialize;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::mpsc::Sender;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize)]
+mod auction;
+mod level;
+mod matching;
+
+pub use auction::Uncross;
+pub use level::{LevelIter, LevelView};
+pub use matching::{Fifo, Hybrid, MatchingAlgorithm, MatchingPolicy, ProRata};
+
+use level::{BookOrder, Level, OrderSlab};
+
+/// Resting amounts at or below this are treated as fully filled, so that
+/// `f64` residue from pro-rata arithmetic never lingers in the book.
+const AMOUNT_EPSILON: f64 = 1e-9;
+
+/// How many levels of each side a depth update carries.
+pub const PUBLISHED_DEPTH: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookError {
+    Rejected(InstrumentError),
+    /// The order cannot be handled by any book, such as a limit order
+    /// without a price.
+    InvalidOrder(&'static str),
+    Halted,
+    OrderNotFound(u64),
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::Rejected(err) => write!(f, "order rejected: {}", err),
+            BookError::InvalidOrder(reason) => write!(f, "invalid order: {}", reason),
+            BookError::Halted => write!(f, "trading is halted"),
+            BookError::OrderNotFound(id) => write!(f, "order {} not found", id),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+impl From<InstrumentError> for BookError {
+    fn from(err: InstrumentError) -> Self {
+        BookError::Rejected(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TradingStatus {
+    #[default]
+    Trading,
+    Halted {
+        since: u64,
+        reason: HaltReason,
+        resume_at: Option<u64>,
+    },
+}
+
+/// A snapshot of a book's trading status and phase.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BookState {
+    pub status: TradingStatus,
+    pub phase: TradingPhase,
+}
+
+/// Which matching regime the book is in. During `PreOpen` orders are
+/// collected without matching and an indicative uncross price is published;
+/// `OrderBook::uncross` executes the call and moves the book to `Continuous`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum TradingPhase {
+    PreOpen {
+        uncross_at: Option<u64>,
+    },
+    #[default]
+    Continuous,
+}
+
+/// Resting interest on each side of a book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BookDepth {
+    pub bids: f64,
+    pub asks: f64,
+    pub resting_orders: usize,
+}
+
+/// Selects orders for a mass cancel. Every criterion left out matches all
+/// orders.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CancelFilter {
+    pub symbol: Option<String>,
+    pub side: Option<BidOrAsk>,
+    pub account: Option<String>,
+}
+
+impl CancelFilter {
+    pub fn matches(&self, order: &Order) -> bool {
+        self.symbol
+            .as_ref()
+            .is_none_or(|symbol| *symbol == order.trading_pair)
+            && self.side.is_none_or(|side| side == order.bid_or_ask)
+            && self
+                .account
+                .as_ref()
+                .is_none_or(|account| order.account.as_ref() == Some(account))
+    }
+}
+
+#[derive(Debug)]
 pub struct OrderBook {
-    pub bids: BTreeMap<Price, VecDeque<Order>>,
-    pub asks: BTreeMap<Price, VecDeque<Order>>,
-    #[serde(skip_serializing, skip_deserializing)]
-    notifier: Option<Sender<MatchedOrder>>,
+    bids: BTreeMap<Price, Level>,
+    asks: BTreeMap<Price, Level>,
+    orders: OrderSlab,
+    /// Where each resting order lives, so cancels, amends and lookups by id
+    /// never have to scan the book.
+    index: HashMap<u64, OrderLocation>,
+    /// Client order ids and accounts of resting orders that have them. Kept
+    /// out of `BookOrder` so that anonymous orders stay allocation-free.
+    tags: HashMap<u64, OrderTag>,
+    notifier: Option<Sender<MarketEvent>>,
+    instrument: Option<Instrument>,
+    /// The one pair a book without an instrument trades, if it is not the
+    /// catch-all book.
+    pair: Option<String>,
+    status: TradingStatus,
+    phase: TradingPhase,
+    /// Orders received while halted under `HaltPolicy::Queue`.
+    queued: VecDeque<Order>,
+    /// Price band reference and the time its window started.
+    reference: Option<(Price, u64)>,
+    last_trade_price: Option<Price>,
+    /// Fills and base amount traded since the book was created.
+    fill_count: u64,
+    traded_volume: f64,
+    clock: u64,
+    policy: Box<dyn MatchingPolicy>,
+    symbols: Symbols,
+    /// The slab revision the last depth update was published at, and the
+    /// levels published for each symbol.
+    published_revision: u64,
+    published_depth: Vec<(Vec<DepthLevel>, Vec<DepthLevel>)>,
+    /// Trades not yet collected by `drain_journal`, if journaling is on.
+    journal: Option<Vec<JournalEntry>>,
+    /// Scratch space for the matching policy, reused between levels and
+    /// orders so matching does not allocate.
+    allocations: Vec<f64>,
+}
+
+/// A trade as recorded for the engine, which turns it into execution
+/// reports for both sides.
+#[derive(Debug, Clone, Copy)]
+pub struct JournalEntry {
+    /// `id` is the taker and `matched_with_id` the maker, except in an
+    /// auction, where `id` is the buyer.
+    pub trade: MatchedOrder,
+    pub auction: bool,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone)]
+struct OrderTag {
+    client_order_id: Option<String>,
+    account: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OrderLocation {
+    bid_or_ask: BidOrAsk,
+    price: Price,
+    key: usize,
+}
+
+fn default_policy() -> Box<dyn MatchingPolicy> {
+    Box::new(Fifo)
 }
 
 impl Default for OrderBook {
@@ -18,316 +194,971 @@ impl Default for OrderBook {
         Self {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            orders: OrderSlab::default(),
+            index: HashMap::new(),
+            tags: HashMap::new(),
             notifier: None,
+            instrument: None,
+            pair: None,
+            status: TradingStatus::default(),
+            phase: TradingPhase::default(),
+            queued: VecDeque::new(),
+            reference: None,
+            last_trade_price: None,
+            fill_count: 0,
+            traded_volume: 0.0,
+            clock: 0,
+            policy: default_policy(),
+            symbols: Symbols::default(),
+            published_revision: 0,
+            published_depth: Vec::new(),
+            journal: None,
+            allocations: Vec::new(),
         }
     }
 }
 
 impl OrderBook {
-    pub fn new(notifier: Sender<MatchedOrder>) -> Self {
+    pub fn new(notifier: Sender<MarketEvent>) -> Self {
         Self {
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
             notifier: Some(notifier),
+            ..Self::default()
         }
     }
 
-    pub fn add_order(&mut self, mut order: Order, timestamp: u64) -> &Self {
-        order.timestamp = timestamp;
+    /// Creates a book that enforces `instrument`'s tick size, lot size and
+    /// minimum notional on every incoming order.
+    pub fn with_instrument(notifier: Sender<MarketEvent>, instrument: Instrument) -> Self {
+        Self {
+            policy: instrument.matching.policy(),
+            instrument: Some(instrument),
+            ..Self::new(notifier)
+        }
+    }
 
-        let is_market_order = order.order_type == OrderType::Market;
-        let bid_or_ask = order.bid_or_ask.clone();
+    /// An empty book for `symbol` alone, without instrument checks and with
+    /// first in, first out matching. The engine opens one per trading pair
+    /// that reaches a catch-all book, so pairs never match each other.
+    pub fn for_pair(&self, symbol: &str) -> Self {
+        Self {
+            notifier: self.notifier.clone(),
+            pair: Some(symbol.to_string()),
+            journal: self.journal.as_ref().map(|_| Vec::new()),
+            ..Self::default()
+        }
+    }
 
-        let price = order.price.clone().unwrap();
+    /// Keeps every trade until `drain_journal` collects it, however it came
+    /// about: a new order, an amend, a replay after a halt or an uncross.
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Some(Vec::new());
+        self
+    }
 
-        let matched_orders = if is_market_order {
-            self.match_market_order(order.clone())
-        } else {
-            self.match_limit_order(order.clone())
-        };
+    pub fn drain_journal(&mut self) -> impl Iterator<Item = JournalEntry> + '_ {
+        self.journal
+            .iter_mut()
+            .flat_map(|journal| journal.drain(..))
+    }
+
+    /// Replaces the policy used to share fills within a price level.
+    pub fn with_policy(mut self, policy: Box<dyn MatchingPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        if !matched_orders.is_empty() {
-            let total_matched: f64 = matched_orders.iter().map(|o| o.amount).sum();
-            order.amount -= total_matched;
+    pub fn instrument(&self) -> Option<&Instrument> {
+        self.instrument.as_ref()
+    }
+
+    pub fn symbol(&self) -> &str {
+        match (&self.instrument, &self.pair) {
+            (Some(instrument), _) => &instrument.symbol,
+            (None, Some(pair)) => pair,
+            (None, None) => "",
         }
+    }
 
-        if order.amount > 0.0 {
-            let book = match bid_or_ask {
-                BidOrAsk::Bid => &mut self.bids,
-                BidOrAsk::Ask => &mut self.asks,
-            };
-            let entry = book.entry(price).or_insert_with(VecDeque::new);
-            entry.push_back(order);
+    /// Whether the book takes any pair that has no book of its own.
+    pub fn is_catch_all(&self) -> bool {
+        self.instrument.is_none() && self.pair.is_none()
+    }
+
+    pub fn status(&self) -> TradingStatus {
+        self.status
+    }
+
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    pub fn state(&self) -> BookState {
+        BookState {
+            status: self.status,
+            phase: self.phase,
         }
+    }
 
-        self
+    pub fn is_halted(&self) -> bool {
+        matches!(self.status, TradingStatus::Halted { .. })
     }
 
-    pub fn get_all_bids(&self) -> Vec<Order> {
-        let mut orders = Vec::new();
-        for (_, order) in self.bids.iter() {
-            for o in order.iter() {
-                orders.push(o.clone());
+    pub fn add_order(&mut self, mut order: Order, timestamp: u64) -> Result<Execution, BookError> {
+        let mut fills = Vec::new();
+        self.submit(&mut order, timestamp, &mut fills)?;
+        Ok(Execution { order, fills })
+    }
+
+    /// The allocation-free core of `add_order`. Fills are appended to
+    /// `fills`, which callers can clear and reuse between orders, and
+    /// `order.amount` is left holding whatever did not fill. Once the book
+    /// has warmed up, matching, resting and cancelling do not allocate.
+    pub fn submit(
+        &mut self,
+        order: &mut Order,
+        timestamp: u64,
+        fills: &mut Vec<MatchedOrder>,
+    ) -> Result<(), BookError> {
+        order.timestamp = timestamp;
+        self.tick(timestamp);
+        if let Err(err) = self.validate(order) {
+            tracing::info!(reason = %err, "order rejected");
+            return Err(err);
+        }
+        if let Some(instrument) = self.instrument.as_ref() {
+            if let Err(err) = instrument.normalize(order) {
+                tracing::info!(reason = %err, "order rejected");
+                return Err(err.into());
             }
         }
-        orders
+
+        if self.is_halted() {
+            return match self.halt_policy() {
+                HaltPolicy::Queue => {
+                    tracing::info!("order queued while trading is halted");
+                    self.queued.push_back(order.clone());
+                    Ok(())
+                }
+                HaltPolicy::Reject => {
+                    tracing::info!("order rejected while trading is halted");
+                    Err(BookError::Halted)
+                }
+            };
+        }
+
+        tracing::debug!(
+            amount = order.amount,
+            price = order.price.map(|price| price.to_f64()),
+            "order validated"
+        );
+        self.accept(order, fills);
+        Ok(())
     }
 
-    pub fn get_all_asks(&self) -> Vec<Order> {
-        let mut orders = Vec::new();
-        for (_, order) in self.asks.iter() {
-            for o in order.iter() {
-                orders.push(o.clone());
+    /// Checks what every book needs of an order, instrument or not.
+    fn validate(&self, order: &Order) -> Result<(), BookError> {
+        if !(order.amount.is_finite() && order.amount > 0.0) {
+            return Err(BookError::InvalidOrder("amount must be positive"));
+        }
+        if order.price.is_none() {
+            if order.order_type == OrderType::Limit {
+                return Err(BookError::InvalidOrder("limit orders need a price"));
+            }
+            if matches!(self.phase, TradingPhase::PreOpen { .. }) {
+                return Err(BookError::InvalidOrder(
+                    "orders collected for an auction need a price",
+                ));
             }
         }
-        orders
+        Ok(())
     }
 
-    pub fn get_orders(&self) -> Vec<Order> {
-        let mut orders = Vec::new();
-        for (_, order) in self.bids.iter() {
-            for o in order.iter() {
-                orders.push(o.clone());
-            }
+    /// Removes a resting or queued order. Cancels are accepted while halted.
+    pub fn cancel_order(&mut self, id: u64) -> Result<Order, BookError> {
+        if let Some(location) = self.index.get(&id).copied() {
+            let order = self.to_order(self.orders.get(location.key));
+            self.unlink(location);
+            tracing::info!(order_id = id, "order cancelled");
+            return Ok(order);
         }
-        for (_, order) in self.asks.iter() {
-            for o in order.iter() {
-                orders.push(o.clone());
-            }
+        let queued = self.queued.iter().position(|order| order.id == id);
+        queued
+            .and_then(|pos| self.queued.remove(pos))
+            .ok_or(BookError::OrderNotFound(id))
+    }
+
+    /// Cancels every resting or queued order `filter` selects, returning
+    /// them in the order they were cancelled.
+    pub fn cancel_all(&mut self, filter: &CancelFilter) -> Vec<Order> {
+        let ids: Vec<u64> = self
+            .get_orders()
+            .iter()
+            .chain(&self.queued)
+            .filter(|order| filter.matches(order))
+            .map(|order| order.id)
+            .collect();
+        let cancelled: Vec<Order> = ids
+            .into_iter()
+            .filter_map(|id| self.cancel_order(id).ok())
+            .collect();
+        if !cancelled.is_empty() {
+            tracing::info!(count = cancelled.len(), "orders mass cancelled");
         }
-        orders
+        cancelled
     }
 
-    pub fn get_order_by_id(&self, id: u64) -> Option<&Order> {
-        for (_, orders) in self.bids.iter() {
-            for order in orders.iter() {
-                if order.id == id {
-                    return Some(order);
-                }
+    /// Changes a resting order's amount and, optionally, its price. Reducing
+    /// the amount at the same price keeps the order's place in the queue and
+    /// is allowed while halted; any other change re-submits the order at the
+    /// back of the queue, where it may match.
+    pub fn amend_order(
+        &mut self,
+        id: u64,
+        amount: f64,
+        price: Option<Price>,
+        timestamp: u64,
+    ) -> Result<Execution, BookError> {
+        let location = self
+            .index
+            .get(&id)
+            .copied()
+            .ok_or(BookError::OrderNotFound(id))?;
+        if !(amount.is_finite() && amount > 0.0) {
+            return Err(BookError::InvalidOrder("amount must be positive"));
+        }
+        let current = *self.orders.get(location.key);
+        let mut amended = self.to_order(&current);
+        amended.amount = amount;
+        if price.is_some() {
+            amended.price = price;
+        }
+        if let Some(instrument) = self.instrument.as_ref() {
+            instrument.normalize(&mut amended)?;
+        }
+
+        if amended.price == Some(current.price) && amended.amount <= current.amount {
+            let reduce_by = current.amount - amended.amount;
+            let book = match location.bid_or_ask {
+                BidOrAsk::Bid => &mut self.bids,
+                BidOrAsk::Ask => &mut self.asks,
+            };
+            let level = book.get_mut(&location.price).expect("indexed level");
+            self.orders.reduce(level, location.key, reduce_by);
+            tracing::info!(order_id = id, amount, "order reduced in place");
+            return Ok(Execution {
+                order: amended,
+                fills: Vec::new(),
+            });
+        }
+
+        if self.is_halted() {
+            return Err(BookError::Halted);
+        }
+        self.unlink(location);
+        tracing::info!(order_id = id, amount, "order amended and resubmitted");
+        self.tick(timestamp);
+        amended.timestamp = timestamp;
+        let mut fills = Vec::new();
+        self.accept(&mut amended, &mut fills);
+        Ok(Execution {
+            order: amended,
+            fills,
+        })
+    }
+
+    /// Advances the book's clock, rolling the price band window over and
+    /// lifting a timed halt once it has expired.
+    pub fn tick(&mut self, now: u64) {
+        self.clock = self.clock.max(now);
+
+        if let (Some((_, since)), Some(window)) = (self.reference, self.band_window()) {
+            if self.clock.saturating_sub(since) > window {
+                self.reference = self.last_trade_price.map(|price| (price, self.clock));
             }
         }
-        for (_, orders) in self.asks.iter() {
-            for order in orders.iter() {
-                if order.id == id {
-                    return Some(order);
-                }
+
+        if let TradingStatus::Halted {
+            resume_at: Some(resume_at),
+            ..
+        } = self.status
+        {
+            if self.clock >= resume_at {
+                self.resume();
+            }
+        }
+
+        if let TradingPhase::PreOpen {
+            uncross_at: Some(uncross_at),
+        } = self.phase
+        {
+            if self.clock >= uncross_at && !self.is_halted() {
+                self.uncross();
             }
         }
-        None
     }
 
-    pub fn get_market_orders_to_match(&self) -> Vec<Order> {
-        let mut orders = Vec::new();
-        for (_, order) in self.bids.iter() {
-            for o in order.iter() {
-                if o.order_type == OrderType::Market {
-                    orders.push(o.clone());
+    /// Starts a call period. Orders rest without matching until `uncross`
+    /// runs, either when called directly or once `uncross_at` has passed.
+    pub fn open_call(&mut self, uncross_at: Option<u64>) {
+        self.phase = TradingPhase::PreOpen { uncross_at };
+        self.publish_indicative();
+    }
+
+    /// The price and volume the call book would execute at if it were
+    /// uncrossed now.
+    pub fn indicative(&self) -> Option<Uncross> {
+        auction::equilibrium(&self.bids, &self.asks, self.last_trade_price)
+    }
+
+    /// Executes every crossable order at the single equilibrium price and
+    /// switches the book to continuous trading.
+    pub fn uncross(&mut self) -> Vec<MatchedOrder> {
+        let mut matched_orders = Vec::new();
+        self.phase = TradingPhase::Continuous;
+        let Some(uncross) = self.indicative() else {
+            return matched_orders;
+        };
+
+        let mut remaining = uncross.volume;
+        let mut symbol = SymbolId::default();
+        while remaining > AMOUNT_EPSILON {
+            let (Some(mut bids), Some(mut asks)) =
+                (self.bids.last_entry(), self.asks.first_entry())
+            else {
+                break;
+            };
+            if *bids.key() < uncross.price || *asks.key() > uncross.price {
+                break;
+            }
+
+            let bid_key = bids.get().head().expect("non-empty level");
+            let ask_key = asks.get().head().expect("non-empty level");
+            let amount = self
+                .orders
+                .get(bid_key)
+                .amount
+                .min(self.orders.get(ask_key).amount)
+                .min(remaining);
+            remaining -= amount;
+
+            let bid = self.orders.reduce(bids.get_mut(), bid_key, amount);
+            symbol = bid.symbol;
+            let (bid_id, bid_type, bid_done) =
+                (bid.id, bid.order_type, bid.amount <= AMOUNT_EPSILON);
+            let ask = self.orders.reduce(asks.get_mut(), ask_key, amount);
+            let (ask_id, ask_done) = (ask.id, ask.amount <= AMOUNT_EPSILON);
+            matched_orders.push(MatchedOrder {
+                id: bid_id,
+                matched_with_id: ask_id,
+                order_type: bid_type,
+                price: uncross.price,
+                amount,
+                bid_or_ask: BidOrAsk::Bid,
+            });
+
+            if bid_done {
+                self.orders.remove(bids.get_mut(), bid_key);
+                self.index.remove(&bid_id);
+                self.tags.remove(&bid_id);
+                if bids.get().is_empty() {
+                    bids.remove();
                 }
             }
-        }
-        for (_, order) in self.asks.iter() {
-            for o in order.iter() {
-                if o.order_type == OrderType::Market {
-                    orders.push(o.clone());
+            if ask_done {
+                self.orders.remove(asks.get_mut(), ask_key);
+                self.index.remove(&ask_id);
+                self.tags.remove(&ask_id);
+                if asks.get().is_empty() {
+                    asks.remove();
                 }
             }
         }
-        orders
+
+        // The auction print is the new reference for the price band.
+        self.reference = Some((uncross.price, self.clock));
+        self.record_trades(symbol, &matched_orders, true);
+        matched_orders
     }
 
-    pub fn get_limit_orders_to_match(&self) -> Vec<Order> {
-        let mut orders = Vec::new();
-        for (_, order) in self.bids.iter() {
-            for o in order.iter() {
-                if o.order_type == OrderType::Limit {
-                    orders.push(o.clone());
-                }
-            }
+    pub fn halt(&mut self, reason: HaltReason) {
+        let resume_at = match reason {
+            HaltReason::PriceBand { .. } => self
+                .instrument
+                .as_ref()
+                .and_then(|instrument| instrument.price_band.as_ref())
+                .and_then(|band| band.halt_secs)
+                .map(|secs| self.clock + secs),
+            HaltReason::Manual => None,
+        };
+        tracing::warn!(symbol = self.symbol(), ?reason, "trading halted");
+        self.status = TradingStatus::Halted {
+            since: self.clock,
+            reason,
+            resume_at,
+        };
+        self.publish(MarketEvent::Halted {
+            symbol: self.symbol().to_string(),
+            reason,
+            timestamp: self.clock,
+            resume_at,
+        });
+    }
+
+    /// Lifts a halt and replays any orders queued while it was in force.
+    /// If the instrument's band asks for a reopening auction the book goes
+    /// into a call period first. Replay stops early if one of the queued
+    /// orders trips the band again.
+    pub fn resume(&mut self) {
+        if !self.is_halted() {
+            return;
         }
-        for (_, order) in self.asks.iter() {
-            for o in order.iter() {
-                if o.order_type == OrderType::Limit {
-                    orders.push(o.clone());
+        self.status = TradingStatus::Trading;
+        self.reference = None;
+        tracing::info!(symbol = self.symbol(), "trading resumed");
+        self.publish(MarketEvent::Resumed {
+            symbol: self.symbol().to_string(),
+            timestamp: self.clock,
+        });
+
+        let reopen_call_secs = self
+            .instrument
+            .as_ref()
+            .and_then(|instrument| instrument.price_band.as_ref())
+            .and_then(|band| band.reopen_call_secs);
+        if let Some(secs) = reopen_call_secs {
+            self.open_call(Some(self.clock + secs));
+        }
+
+        while !self.is_halted() {
+            match self.queued.pop_front() {
+                Some(mut order) => {
+                    self.accept(&mut order, &mut Vec::new());
                 }
+                None => break,
             }
         }
-        orders
     }
 
-    pub fn get_best_bid(&self) -> Option<&Price> {
-        self.bids.keys().next_back()
+    fn accept(&mut self, order: &mut Order, fills: &mut Vec<MatchedOrder>) {
+        match self.phase {
+            TradingPhase::PreOpen { .. } => match order.price {
+                Some(price) => {
+                    tracing::info!("order collected for the call auction");
+                    self.rest(order, price);
+                    self.publish_indicative();
+                }
+                // Only possible for a market order queued during a halt.
+                None => tracing::info!("order without a price dropped from the call auction"),
+            },
+            TradingPhase::Continuous => self.place(order, fills),
+        }
     }
 
-    pub fn get_best_ask(&self) -> Option<&Price> {
-        self.asks.keys().next()
+    fn publish_indicative(&self) {
+        let uncross = self.indicative();
+        self.publish(MarketEvent::Indicative {
+            symbol: self.symbol().to_string(),
+            price: uncross.map(|uncross| uncross.price),
+            volume: uncross.map_or(0.0, |uncross| uncross.volume),
+            imbalance: uncross.map_or(0.0, |uncross| uncross.imbalance),
+            timestamp: self.clock,
+        });
     }
 
-    pub fn match_market_order(&mut self, market_order: Order) -> Vec<MatchedOrder> {
-        let mut matched_orders = Vec::new();
-        let mut removal_candidates = Vec::new();
-        let mut remaining_amount = market_order.amount;
-        let _order_id = market_order.id;
+    fn rest(&mut self, order: &Order, price: Price) {
+        let resting = BookOrder {
+            id: order.id,
+            symbol: self.symbols.intern(&order.trading_pair),
+            order_type: order.order_type,
+            bid_or_ask: order.bid_or_ask,
+            amount: order.amount,
+            price,
+            timestamp: order.timestamp,
+        };
+        let book = match order.bid_or_ask {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
+        };
+        let level = book.entry(price).or_default();
+        let key = self.orders.push_back(level, resting);
+        tracing::debug!(order_id = order.id, price = price.to_f64(), "order resting");
+        if order.client_order_id.is_some() || order.account.is_some() {
+            self.tags.insert(
+                order.id,
+                OrderTag {
+                    client_order_id: order.client_order_id.clone(),
+                    account: order.account.clone(),
+                },
+            );
+        }
+        self.index.insert(
+            order.id,
+            OrderLocation {
+                bid_or_ask: order.bid_or_ask,
+                price,
+                key,
+            },
+        );
+    }
 
-        let book = match market_order.bid_or_ask {
-            BidOrAsk::Bid => &mut self.asks,
-            BidOrAsk::Ask => &mut self.bids,
+    /// Takes a resting order out of the book.
+    fn unlink(&mut self, location: OrderLocation) -> BookOrder {
+        let book = match location.bid_or_ask {
+            BidOrAsk::Bid => &mut self.bids,
+            BidOrAsk::Ask => &mut self.asks,
         };
+        let level = book.get_mut(&location.price).expect("indexed level");
+        let order = self.orders.remove(level, location.key);
+        if level.is_empty() {
+            book.remove(&location.price);
+        }
+        self.index.remove(&order.id);
+        self.tags.remove(&order.id);
+        order
+    }
 
-        if let Some((first_price, _)) = book.iter().next() {
-            if market_order.bid_or_ask == BidOrAsk::Bid
-                && *first_price >= market_order.price.unwrap_or(Price::new(f64::MAX))
-            {
-                return matched_orders;
-            }
-            if market_order.bid_or_ask == BidOrAsk::Ask
-                && *first_price < market_order.price.unwrap_or(Price::new(f64::MIN))
-            {
-                return matched_orders;
+    fn resting<'a>(
+        &'a self,
+        book: &'a BTreeMap<Price, Level>,
+    ) -> impl Iterator<Item = &'a BookOrder> {
+        book.values()
+            .flat_map(move |level| self.orders.view(level).iter())
+    }
+
+    fn to_order(&self, order: &BookOrder) -> Order {
+        let mut expanded = order.to_order(self.symbols.name(order.symbol));
+        if let Some(tag) = self.tags.get(&order.id) {
+            expanded.client_order_id = tag.client_order_id.clone();
+            expanded.account = tag.account.clone();
+        }
+        expanded
+    }
+
+    fn place(&mut self, order: &mut Order, fills: &mut Vec<MatchedOrder>) {
+        let start = fills.len();
+        let limit = match order.order_type {
+            OrderType::Market => None,
+            OrderType::Limit => order.price,
+        };
+        self.match_order(order, limit, fills);
+        order.amount -= fills[start..].iter().map(|o| o.amount).sum::<f64>();
+        tracing::info!(
+            fills = fills.len() - start,
+            remaining = order.amount,
+            "order matched"
+        );
+
+        // An order that tripped the circuit breaker would cross the book at a
+        // price outside the band if it rested, so its remainder is dropped.
+        // A market order without a price has nowhere to rest either.
+        if order.amount > 0.0 && !self.is_halted() {
+            match order.price {
+                Some(price) => self.rest(order, price),
+                None => tracing::info!(
+                    remaining = order.amount,
+                    "market order remainder dropped for lack of liquidity"
+                ),
             }
         }
-        let mut book_iter = book.iter_mut();
-
-        while remaining_amount > 0.0 {
-            if let Some((price, orders)) = book_iter.next() {
-                while let Some(mut order) = orders.pop_front() {
-                    let id = order.id;
-                    let filled_amount = if order.amount <= remaining_amount {
-                        remaining_amount -= order.amount;
-                        order.amount
-                    } else {
-                        let filled_amount = remaining_amount;
-                        remaining_amount = 0.0;
-                        order.amount -= filled_amount;
-                        orders.push_front(order);
-                        filled_amount
-                    };
-
-                    matched_orders.push(MatchedOrder {
-                        id: market_order.id,
-                        matched_with_id: id,
-                        order_type: market_order.order_type.clone(),
-                        price: price.clone(),
-                        amount: filled_amount,
-                        bid_or_ask: market_order.bid_or_ask.clone(),
-                    });
+    }
 
-                    if remaining_amount <= 0.0 {
-                        break;
-                    }
-                }
+    fn halt_policy(&self) -> HaltPolicy {
+        self.instrument
+            .as_ref()
+            .and_then(|instrument| instrument.price_band.as_ref())
+            .map(|band| band.halt_policy)
+            .unwrap_or_default()
+    }
 
-                if orders.is_empty() {
-                    removal_candidates.push(*price);
-                }
-            } else {
-                break;
+    fn band_window(&self) -> Option<u64> {
+        self.instrument
+            .as_ref()?
+            .price_band
+            .as_ref()
+            .map(|band| band.window_secs)
+    }
+
+    fn band_check(&self) -> BandCheck {
+        let max_deviation_pct = self
+            .instrument
+            .as_ref()
+            .and_then(|instrument| instrument.price_band.as_ref())
+            .map(|band| band.max_deviation_pct);
+        BandCheck {
+            max_deviation_pct,
+            limits: max_deviation_pct
+                .zip(self.reference)
+                .map(|(pct, (reference, _))| band_around(reference, pct)),
+        }
+    }
+
+    fn record_trades(&mut self, symbol: SymbolId, matched_orders: &[MatchedOrder], auction: bool) {
+        for matched_order in matched_orders {
+            if self.reference.is_none() {
+                self.reference = Some((matched_order.price, self.clock));
+            }
+            self.last_trade_price = Some(matched_order.price);
+            self.fill_count += 1;
+            self.traded_volume += matched_order.amount;
+            tracing::info!(
+                maker_id = matched_order.matched_with_id,
+                price = matched_order.price.to_f64(),
+                amount = matched_order.amount,
+                "trade published"
+            );
+            if let Some(journal) = self.journal.as_mut() {
+                journal.push(JournalEntry {
+                    trade: *matched_order,
+                    auction,
+                    timestamp: self.clock,
+                });
+            }
+            if self.notifier.is_some() {
+                self.publish(MarketEvent::Trade {
+                    symbol: self.symbols.name(symbol).to_string(),
+                    timestamp: self.clock,
+                    trade: *matched_order,
+                });
             }
         }
+    }
 
-        for price in removal_candidates {
-            book.remove(&price);
+    /// Publishes the best `PUBLISHED_DEPTH` levels of each side for every
+    /// symbol whose levels changed since the last call. The engine calls
+    /// this once per command, so a batch produces a single update.
+    pub fn publish_depth(&mut self) {
+        let revision = self.orders.revision();
+        if self.notifier.is_none() || revision == self.published_revision {
+            return;
         }
-        for matched_order in &matched_orders {
-            if let Some(sender) = self.notifier.as_ref() {
-                sender.send(matched_order.clone()).unwrap();
+        self.published_revision = revision;
+        self.published_depth
+            .resize(self.symbols.len(), (Vec::new(), Vec::new()));
+        for symbol in self.symbols.ids() {
+            let depth = (
+                self.top_levels(self.bids.iter().rev(), symbol),
+                self.top_levels(self.asks.iter(), symbol),
+            );
+            let published = &mut self.published_depth[symbol.0 as usize];
+            if *published == depth {
+                continue;
             }
+            *published = depth.clone();
+            self.publish(MarketEvent::Depth {
+                symbol: self.symbols.name(symbol).to_string(),
+                bids: depth.0,
+                asks: depth.1,
+                timestamp: self.clock,
+            });
         }
+    }
 
-        matched_orders
+    /// The first `PUBLISHED_DEPTH` levels of `levels` holding orders for
+    /// `symbol`.
+    fn top_levels<'a>(
+        &self,
+        levels: impl Iterator<Item = (&'a Price, &'a Level)>,
+        symbol: SymbolId,
+    ) -> Vec<DepthLevel> {
+        levels
+            .filter_map(|(price, level)| {
+                let (amount, orders) = self
+                    .orders
+                    .view(level)
+                    .iter()
+                    .filter(|order| order.symbol == symbol)
+                    .fold((0.0, 0), |(amount, orders), order| {
+                        (amount + order.amount, orders + 1)
+                    });
+                (orders > 0).then_some(DepthLevel {
+                    price: *price,
+                    amount,
+                    orders,
+                })
+            })
+            .take(PUBLISHED_DEPTH)
+            .collect()
     }
 
-    pub fn match_limit_order(&mut self, limit_order: Order) -> Vec<MatchedOrder> {
-        let mut matched_orders = Vec::new();
-        let order_type = limit_order.order_type;
-        let bid_or_ask = limit_order.bid_or_ask;
-        let order_price = limit_order.price; // Clone the price if necessary
-        let order_id = limit_order.id;
+    fn trip(&mut self, attempted: Option<Price>) {
+        if let (Some(attempted), Some((reference, _))) = (attempted, self.reference) {
+            self.halt(HaltReason::PriceBand {
+                reference,
+                attempted,
+            });
+        }
+    }
 
-        let book = match bid_or_ask {
-            BidOrAsk::Bid => &mut self.asks,
-            BidOrAsk::Ask => &mut self.bids,
+    fn publish(&self, event: MarketEvent) {
+        if let Some(sender) = self.notifier.as_ref() {
+            // A market data consumer going away must not stop matching.
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn get_all_bids(&self) -> Vec<Order> {
+        self.resting(&self.bids).map(|o| self.to_order(o)).collect()
+    }
+
+    pub fn get_all_asks(&self) -> Vec<Order> {
+        self.resting(&self.asks).map(|o| self.to_order(o)).collect()
+    }
+
+    pub fn get_orders(&self) -> Vec<Order> {
+        self.resting(&self.bids)
+            .chain(self.resting(&self.asks))
+            .map(|o| self.to_order(o))
+            .collect()
+    }
+
+    /// Up to `limit` resting orders on one side, best price first and in
+    /// time priority within a level, for `symbol` if given. `after` is the
+    /// price and id of the last order of the previous page; paging resumes
+    /// behind it, or behind orders with lower ids at its price if it has
+    /// left the book. Orders that lose priority while a client pages may be
+    /// skipped or repeated.
+    pub fn resting_page(
+        &self,
+        side: BidOrAsk,
+        symbol: Option<&str>,
+        after: Option<(Price, u64)>,
+        limit: usize,
+    ) -> Vec<Order> {
+        let symbol = match symbol {
+            Some(name) => match self.symbols.get(name) {
+                Some(id) => Some(id),
+                None => return Vec::new(),
+            },
+            None => None,
+        };
+        let levels: Box<dyn Iterator<Item = (&Price, &Level)>> = match (side, after) {
+            (BidOrAsk::Bid, None) => Box::new(self.bids.iter().rev()),
+            (BidOrAsk::Bid, Some((price, _))) => Box::new(self.bids.range(..=price).rev()),
+            (BidOrAsk::Ask, None) => Box::new(self.asks.iter()),
+            (BidOrAsk::Ask, Some((price, _))) => Box::new(self.asks.range(price..)),
         };
+        levels
+            .flat_map(|(price, level)| {
+                let ids = || self.orders.view(level).iter().map(|order| order.id);
+                let skip = match after {
+                    Some((at, after_id)) if at == *price => {
+                        match ids().position(|id| id == after_id) {
+                            Some(position) => position + 1,
+                            None => ids().take_while(|id| *id < after_id).count(),
+                        }
+                    }
+                    _ => 0,
+                };
+                self.orders.view(level).iter().skip(skip)
+            })
+            .filter(|order| symbol.is_none_or(|symbol| order.symbol == symbol))
+            .take(limit)
+            .map(|order| self.to_order(order))
+            .collect()
+    }
 
-        let mut remaining_amount = limit_order.amount;
+    pub fn get_order_by_id(&self, id: u64) -> Option<Order> {
+        self.index
+            .get(&id)
+            .map(|location| self.to_order(self.orders.get(location.key)))
+    }
 
-        while remaining_amount > 0.0 {
-            let mut to_remove = Vec::new();
-            let mut matched = false;
+    pub fn get_market_orders_to_match(&self) -> Vec<Order> {
+        self.resting(&self.bids)
+            .chain(self.resting(&self.asks))
+            .filter(|o| o.order_type == OrderType::Market)
+            .map(|o| self.to_order(o))
+            .collect()
+    }
 
-            for (price, orders) in book.iter_mut() {
-                if (limit_order.bid_or_ask == BidOrAsk::Bid && *price > limit_order.price.unwrap())
-                    || (limit_order.bid_or_ask == BidOrAsk::Ask
-                        && *price < limit_order.price.unwrap())
-                {
-                    break;
-                }
+    pub fn get_limit_orders_to_match(&self) -> Vec<Order> {
+        self.resting(&self.bids)
+            .chain(self.resting(&self.asks))
+            .filter(|o| o.order_type == OrderType::Limit)
+            .map(|o| self.to_order(o))
+            .collect()
+    }
 
-                while let Some(mut order) = orders.pop_front() {
-                    let id = order.id;
-                    let filled_amount = if order.amount <= remaining_amount {
-                        remaining_amount -= order.amount;
-                        order.amount
-                    } else {
-                        let filled_amount = remaining_amount;
-                        remaining_amount = 0.0;
-                        order.amount -= filled_amount;
-                        orders.push_front(order);
-                        filled_amount
-                    };
-
-                    matched_orders.push(MatchedOrder {
-                        id: limit_order.id,
-                        matched_with_id: id,
-                        order_type: limit_order.order_type.clone(),
-                        price: price.clone(),
-                        amount: filled_amount,
-
-                        bid_or_ask: limit_order.bid_or_ask.clone(),
-                    });
+    /// Whether `id` is resting in the book or queued behind a halt.
+    pub fn has_order(&self, id: u64) -> bool {
+        self.index.contains_key(&id) || self.queued.iter().any(|order| order.id == id)
+    }
 
-                    matched = true;
+    pub fn depth(&self) -> BookDepth {
+        BookDepth {
+            bids: self.bids.values().map(Level::total).sum(),
+            asks: self.asks.values().map(Level::total).sum(),
+            resting_orders: self.index.len(),
+        }
+    }
 
-                    if remaining_amount <= 0.0 {
-                        break;
-                    }
-                }
+    /// The number of fills and the total amount traded so far.
+    pub fn traded(&self) -> (u64, f64) {
+        (self.fill_count, self.traded_volume)
+    }
 
-                if orders.is_empty() {
-                    to_remove.push(*price);
-                }
+    pub fn get_best_bid(&self) -> Option<&Price> {
+        self.bids.keys().next_back()
+    }
 
-                if matched {
+    pub fn get_best_ask(&self) -> Option<&Price> {
+        self.asks.keys().next()
+    }
+
+    /// Sweeps the opposite side of the book with no price limit.
+    pub fn match_market_order(&mut self, market_order: Order) -> Vec<MatchedOrder> {
+        let mut matched_orders = Vec::new();
+        self.match_order(&market_order, None, &mut matched_orders);
+        matched_orders
+    }
+
+    pub fn match_limit_order(&mut self, limit_order: Order) -> Vec<MatchedOrder> {
+        let mut matched_orders = Vec::new();
+        self.match_order(&limit_order, limit_order.price, &mut matched_orders);
+        matched_orders
+    }
+
+    /// Walks the opposite side from the best price outwards, handing each
+    /// level's share of the order to the matching policy, until the order is
+    /// filled, `limit` is reached or the price band trips. Fills are
+    /// appended to `matched_orders`.
+    fn match_order(
+        &mut self,
+        taker: &Order,
+        limit: Option<Price>,
+        matched_orders: &mut Vec<MatchedOrder>,
+    ) {
+        let start = matched_orders.len();
+        let symbol = self.symbols.intern(&taker.trading_pair);
+        let mut allocations = std::mem::take(&mut self.allocations);
+        let mut band = self.band_check();
+        let mut tripped = None;
+        let lot_size = self
+            .instrument
+            .as_ref()
+            .map(|instrument| instrument.lot_size);
+        let mut remaining_amount = taker.amount;
+
+        let book = match taker.bid_or_ask {
+            BidOrAsk::Bid => &mut self.asks,
+            BidOrAsk::Ask => &mut self.bids,
+        };
+
+        while remaining_amount > AMOUNT_EPSILON {
+            let best = match taker.bid_or_ask {
+                BidOrAsk::Bid => book.first_entry(),
+                BidOrAsk::Ask => book.last_entry(),
+            };
+            let Some(mut level) = best else {
+                break;
+            };
+            let price = *level.key();
+            let crosses = match (taker.bid_or_ask, limit) {
+                (_, None) => true,
+                (BidOrAsk::Bid, Some(limit)) => price <= limit,
+                (BidOrAsk::Ask, Some(limit)) => price >= limit,
+            };
+            if !crosses {
+                break;
+            }
+            if !band.allows(&price) {
+                tripped = Some(price);
+                break;
+            }
+
+            let orders = level.get_mut();
+            self.policy.allocate(
+                self.orders.view(orders),
+                remaining_amount,
+                lot_size,
+                &mut allocations,
+            );
+            let mut filled = 0.0;
+            let mut cursor = orders.head();
+            for &amount in allocations.iter() {
+                let Some(key) = cursor else {
                     break;
+                };
+                cursor = self.orders.next(key);
+                if amount <= 0.0 {
+                    continue;
+                }
+                let maker = self.orders.reduce(orders, key, amount);
+                let (maker_id, maker_done) = (maker.id, maker.amount <= AMOUNT_EPSILON);
+                filled += amount;
+                matched_orders.push(MatchedOrder {
+                    id: taker.id,
+                    matched_with_id: maker_id,
+                    order_type: taker.order_type,
+                    price,
+                    amount,
+                    bid_or_ask: taker.bid_or_ask,
+                });
+                if maker_done {
+                    self.orders.remove(orders, key);
+                    self.index.remove(&maker_id);
+                    self.tags.remove(&maker_id);
                 }
             }
-
-            for price in to_remove {
-                book.remove(&price);
+            if orders.is_empty() {
+                level.remove();
             }
 
-            if !matched {
+            // A policy that cannot allocate anything (say, less than a lot
+            // is left) would otherwise spin here forever.
+            if filled <= 0.0 {
                 break;
             }
+            remaining_amount -= filled;
         }
 
-        for matched_order in &matched_orders {
-            self.notifier
-                .as_ref()
-                .expect("Notifier is not initialized")
-                .send(matched_order.clone())
-                .unwrap();
-        }
+        self.allocations = allocations;
+        self.record_trades(symbol, &matched_orders[start..], false);
+        self.trip(tripped);
+    }
+}
 
-        matched_orders
+/// The price band in force for one match call. If the book has no reference
+/// price yet, the first print of the call establishes it.
+struct BandCheck {
+    max_deviation_pct: Option<f64>,
+    limits: Option<(Price, Price)>,
+}
+
+impl BandCheck {
+    fn allows(&mut self, price: &Price) -> bool {
+        match (self.limits, self.max_deviation_pct) {
+            (Some((low, high)), _) => *price >= low && *price <= high,
+            (None, Some(pct)) => {
+                self.limits = Some(band_around(*price, pct));
+                true
+            }
+            (None, None) => true,
+        }
     }
 }
 
+/// The inclusive range of prices within `pct` percent of `reference`.
+fn band_around(reference: Price, pct: f64) -> (Price, Price) {
+    let units = reference.units() as f64;
+    let deviation = units * pct / 100.0;
+    (
+        Price::from_units(
+            (units - deviation).max(0.0).ceil() as u64,
+            reference.scalar(),
+        ),
+        Price::from_units((units + deviation).floor() as u64, reference.scalar()),
+    )
+}
+
+/// Prices compare by value, so the same price written with different
+/// scalars is equal.
 impl Ord for Price {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.integral().cmp(&other.integral()) {
-            Ordering::Equal => self.fractional().cmp(&other.fractional()),
-            other => other,
-        }
+        let fraction = |price: &Price, scalar: u64| price.fractional() as u128 * scalar as u128;
+        self.integral()
+            .cmp(&other.integral())
+            .then_with(|| fraction(self, other.scalar()).cmp(&fraction(other, self.scalar())))
     }
 }
 
@@ -339,15 +1170,33 @@ impl PartialOrd for Price {
 
 impl PartialEq for Price {
     fn eq(&self, other: &Self) -> bool {
-        self.integral() == other.integral() && self.fractional() == other.fractional()
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for Price {}
 
+impl Hash for Price {
+    /// Hashes the fraction in lowest terms, as equal prices share it.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let divisor = gcd(self.fractional(), self.scalar()).max(1);
+        self.integral().hash(state);
+        (self.fractional() / divisor).hash(state);
+        (self.scalar() / divisor).hash(state);
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instrument::PriceBand;
     use crate::models::{BidOrAsk, Order, OrderType, Price};
 
     fn test_order(
@@ -365,16 +1214,35 @@ mod tests {
             price: Some(Price::new(price)),
             timestamp: 0,
             bid_or_ask,
+            client_order_id: None,
+            account: None,
         }
     }
 
+    #[test]
+    fn test_prices_compare_across_scalars() {
+        use std::collections::HashSet;
+
+        let coarse = Price::with_precision(101.5, 1);
+        let fine = Price::with_precision(101.5, 4);
+        assert_eq!(coarse, fine);
+        assert!(Price::with_precision(101.25, 2) < coarse);
+        assert!(Price::with_precision(101.75, 2) > fine);
+        assert_eq!(
+            Price::with_precision(101.0, 0),
+            Price::with_precision(101.0, 5)
+        );
+        let prices: HashSet<Price> = [coarse, fine, Price::with_precision(101.0, 3)].into();
+        assert_eq!(prices.len(), 2);
+    }
+
     #[test]
     fn test_add_limit_bid_order() {
-        let dummy_tx = std::sync::mpsc::channel::<MatchedOrder>().0;
+        let dummy_tx = std::sync::mpsc::channel::<MarketEvent>().0;
         let mut book = OrderBook::new(dummy_tx);
 
         let order = test_order(1, OrderType::Limit, BidOrAsk::Bid, 1.0, 10000.0);
-        book.add_order(order, 0);
+        book.add_order(order, 0).unwrap();
 
         let bids = book.get_all_bids();
         assert_eq!(bids.len(), 1);
@@ -383,11 +1251,11 @@ mod tests {
 
     #[test]
     fn test_add_limit_ask_order() {
-        let dummy_tx = std::sync::mpsc::channel::<MatchedOrder>().0;
+        let dummy_tx = std::sync::mpsc::channel::<MarketEvent>().0;
         let mut book = OrderBook::new(dummy_tx);
 
         let order = test_order(2, OrderType::Limit, BidOrAsk::Ask, 2.0, 10500.0);
-        book.add_order(order, 0);
+        book.add_order(order, 0).unwrap();
 
         let asks = book.get_all_asks();
         assert_eq!(asks.len(), 1);
@@ -396,11 +1264,11 @@ mod tests {
 
     #[test]
     fn test_match_limit_order_bid_hits_ask() {
-        let (tx, _rx) = std::sync::mpsc::channel::<MatchedOrder>();
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
         let mut book = OrderBook::new(tx);
 
         let ask = test_order(10, OrderType::Limit, BidOrAsk::Ask, 1.0, 9500.0);
-        book.add_order(ask, 0);
+        book.add_order(ask, 0).unwrap();
 
         let bid = test_order(11, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.0);
         let matches = book.match_limit_order(bid);
@@ -411,11 +1279,11 @@ mod tests {
 
     #[test]
     fn test_partial_fill() {
-        let (tx, _rx) = std::sync::mpsc::channel::<MatchedOrder>();
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
         let mut book = OrderBook::new(tx);
 
         let ask = test_order(1, OrderType::Limit, BidOrAsk::Ask, 2.0, 9500.0);
-        book.add_order(ask, 0);
+        book.add_order(ask, 0).unwrap();
 
         let bid = test_order(2, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.0);
         let matched = book.match_limit_order(bid);
@@ -431,18 +1299,18 @@ mod tests {
 
     #[test]
     fn test_best_bid_and_ask() {
-        let dummy_tx = std::sync::mpsc::channel::<MatchedOrder>().0;
+        let dummy_tx = std::sync::mpsc::channel::<MarketEvent>().0;
         let mut book = OrderBook::new(dummy_tx);
 
         let ask1 = test_order(1, OrderType::Limit, BidOrAsk::Ask, 1.0, 9800.0);
         let ask2 = test_order(2, OrderType::Limit, BidOrAsk::Ask, 1.0, 9700.0);
-        book.add_order(ask1, 0);
-        book.add_order(ask2, 0);
+        book.add_order(ask1, 0).unwrap();
+        book.add_order(ask2, 0).unwrap();
 
         let bid1 = test_order(3, OrderType::Limit, BidOrAsk::Bid, 1.0, 9400.0);
         let bid2 = test_order(4, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.0);
-        book.add_order(bid1, 0);
-        book.add_order(bid2, 0);
+        book.add_order(bid1, 0).unwrap();
+        book.add_order(bid2, 0).unwrap();
 
         let best_ask = book.get_best_ask().unwrap();
         let best_bid = book.get_best_bid().unwrap();
@@ -450,4 +1318,396 @@ mod tests {
         assert_eq!(best_ask.integral(), 9700);
         assert_eq!(best_bid.integral(), 9600);
     }
+
+    #[test]
+    fn test_instrument_rejects_off_tick_order() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let instrument = Instrument::new("BTC-USD", "BTC", "USD", 0.5, 0.01, 1.0, 2);
+        let mut book = OrderBook::with_instrument(tx, instrument);
+
+        let off_tick = test_order(1, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.25);
+        assert!(book.add_order(off_tick, 0).is_err());
+        assert!(book.get_all_bids().is_empty());
+
+        let on_tick = test_order(2, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.5);
+        book.add_order(on_tick, 0).unwrap();
+        let best_bid = book.get_best_bid().unwrap();
+        assert_eq!(best_bid.scalar(), 100);
+        assert_eq!(best_bid.fractional(), 50);
+    }
+
+    fn banded_book(halt_policy: HaltPolicy) -> (OrderBook, std::sync::mpsc::Receiver<MarketEvent>) {
+        let (tx, rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let instrument = Instrument::new("BTC-USD", "BTC", "USD", 1.0, 1.0, 0.0, 2)
+            .with_price_band(PriceBand {
+                max_deviation_pct: 5.0,
+                window_secs: 60,
+                halt_secs: Some(30),
+                halt_policy,
+                reopen_call_secs: None,
+            });
+        (OrderBook::with_instrument(tx, instrument), rx)
+    }
+
+    #[test]
+    fn test_price_band_halts_sweep() {
+        let (mut book, rx) = banded_book(HaltPolicy::Reject);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Ask, 1.0, 100.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Ask, 1.0, 104.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(3, OrderType::Limit, BidOrAsk::Ask, 1.0, 110.0),
+            0,
+        )
+        .unwrap();
+
+        book.add_order(
+            test_order(4, OrderType::Limit, BidOrAsk::Bid, 3.0, 110.0),
+            1,
+        )
+        .unwrap();
+
+        // The first two levels print inside the band, the third trips it.
+        assert!(book.is_halted());
+        assert!(book.get_all_bids().is_empty());
+        assert_eq!(book.get_all_asks().len(), 1);
+        let events: Vec<MarketEvent> = rx.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_matches::assert_matches!(
+            events[2],
+            MarketEvent::Halted {
+                resume_at: Some(31),
+                ..
+            }
+        );
+
+        let rejected = test_order(5, OrderType::Limit, BidOrAsk::Bid, 1.0, 90.0);
+        assert_eq!(book.add_order(rejected, 2).unwrap_err(), BookError::Halted);
+        assert_eq!(book.cancel_order(3).unwrap().id, 3);
+
+        book.tick(31);
+        assert!(!book.is_halted());
+        assert_matches::assert_matches!(rx.try_recv(), Ok(MarketEvent::Resumed { .. }));
+    }
+
+    #[test]
+    fn test_orders_queued_during_halt_replay_on_resume() {
+        let (mut book, _rx) = banded_book(HaltPolicy::Queue);
+        book.halt(HaltReason::Manual);
+
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Ask, 1.0, 100.0),
+            0,
+        )
+        .unwrap();
+        assert!(book.get_all_asks().is_empty());
+
+        // Manual halts have no timer.
+        book.tick(1_000);
+        assert!(book.is_halted());
+
+        book.resume();
+        assert_eq!(book.get_all_asks().len(), 1);
+    }
+
+    #[test]
+    fn test_call_auction_uncrosses_at_single_price() {
+        let (tx, rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        book.open_call(None);
+
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Bid, 2.0, 102.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(test_order(2, OrderType::Limit, BidOrAsk::Ask, 1.0, 99.0), 0)
+            .unwrap();
+        book.add_order(
+            test_order(3, OrderType::Limit, BidOrAsk::Ask, 3.0, 101.0),
+            0,
+        )
+        .unwrap();
+
+        // Nothing matches during the call; indicative prices are published.
+        assert_eq!(book.get_orders().len(), 3);
+        assert!(rx
+            .try_iter()
+            .all(|event| matches!(event, MarketEvent::Indicative { .. })));
+        let indicative = book.indicative().unwrap();
+        assert_eq!(indicative.price, Price::new(101.0));
+        assert_eq!(indicative.volume, 2.0);
+
+        let matched = book.uncross();
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert_eq!(matched.len(), 2);
+        assert!(matched.iter().all(|m| m.price == Price::new(101.0)));
+        assert!(book.get_all_bids().is_empty());
+        assert_eq!(book.get_all_asks()[0].amount, 2.0);
+    }
+
+    #[test]
+    fn test_halt_reopens_through_call() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let instrument = Instrument::new("BTC-USD", "BTC", "USD", 1.0, 1.0, 0.0, 2)
+            .with_price_band(PriceBand {
+                max_deviation_pct: 5.0,
+                window_secs: 60,
+                halt_secs: Some(10),
+                halt_policy: HaltPolicy::Queue,
+                reopen_call_secs: Some(5),
+            });
+        let mut book = OrderBook::with_instrument(tx, instrument);
+        book.halt(HaltReason::Manual);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Bid, 1.0, 100.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Ask, 1.0, 100.0),
+            0,
+        )
+        .unwrap();
+
+        book.resume();
+        assert_eq!(
+            book.phase(),
+            TradingPhase::PreOpen {
+                uncross_at: Some(5)
+            }
+        );
+        assert_eq!(book.get_orders().len(), 2);
+
+        book.tick(5);
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert!(book.get_orders().is_empty());
+    }
+
+    #[test]
+    fn test_ask_hits_highest_bid_first() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Bid, 1.0, 9400.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Bid, 1.0, 9600.0),
+            0,
+        )
+        .unwrap();
+
+        let ask = test_order(3, OrderType::Limit, BidOrAsk::Ask, 1.0, 9500.0);
+        let matched = book.match_limit_order(ask);
+
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].matched_with_id, 2);
+        assert_eq!(matched[0].price, Price::new(9600.0));
+    }
+
+    #[test]
+    fn test_pro_rata_instrument_shares_level() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let instrument = Instrument::new("BTC-USD", "BTC", "USD", 1.0, 1.0, 0.0, 2).with_matching(
+            MatchingAlgorithm::ProRata {
+                min_allocation: 0.0,
+            },
+        );
+        let mut book = OrderBook::with_instrument(tx, instrument);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Ask, 3.0, 100.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Ask, 9.0, 100.0),
+            0,
+        )
+        .unwrap();
+
+        let bid = test_order(3, OrderType::Limit, BidOrAsk::Bid, 4.0, 100.0);
+        let matched = book.match_limit_order(bid);
+
+        let fills: Vec<(u64, f64)> = matched
+            .iter()
+            .map(|m| (m.matched_with_id, m.amount))
+            .collect();
+        assert_eq!(fills, vec![(1, 1.0), (2, 3.0)]);
+    }
+
+    #[test]
+    fn test_cancel_from_middle_of_level() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        for id in 1..=3 {
+            book.add_order(
+                test_order(id, OrderType::Limit, BidOrAsk::Ask, 1.0, 100.0),
+                0,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(book.cancel_order(2).unwrap().id, 2);
+        assert!(book.get_order_by_id(2).is_none());
+        assert_eq!(
+            book.cancel_order(2).unwrap_err(),
+            BookError::OrderNotFound(2)
+        );
+
+        let bid = test_order(4, OrderType::Limit, BidOrAsk::Bid, 2.0, 100.0);
+        let matched: Vec<u64> = book
+            .match_limit_order(bid)
+            .iter()
+            .map(|m| m.matched_with_id)
+            .collect();
+        assert_eq!(matched, vec![1, 3]);
+        assert!(book.get_best_ask().is_none());
+    }
+
+    #[test]
+    fn test_cancel_all_by_side_and_account() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        for (id, side, account) in [
+            (1, BidOrAsk::Ask, "desk-7"),
+            (2, BidOrAsk::Ask, "desk-8"),
+            (3, BidOrAsk::Bid, "desk-7"),
+            (4, BidOrAsk::Ask, "desk-7"),
+        ] {
+            let price = if side == BidOrAsk::Ask { 101.0 } else { 99.0 };
+            let mut order = test_order(id, OrderType::Limit, side, 1.0, price);
+            order.account = Some(account.to_string());
+            book.add_order(order, 0).unwrap();
+        }
+
+        let filter = CancelFilter {
+            side: Some(BidOrAsk::Ask),
+            account: Some("desk-7".to_string()),
+            ..CancelFilter::default()
+        };
+        let cancelled: Vec<u64> = book.cancel_all(&filter).iter().map(|o| o.id).collect();
+        assert_eq!(cancelled, vec![1, 4]);
+        assert_eq!(book.depth().resting_orders, 2);
+        assert_eq!(
+            book.get_order_by_id(2).unwrap().account.as_deref(),
+            Some("desk-8")
+        );
+    }
+
+    #[test]
+    fn test_amend_down_keeps_priority() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Ask, 2.0, 100.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Ask, 2.0, 100.0),
+            0,
+        )
+        .unwrap();
+
+        book.amend_order(1, 1.0, None, 1).unwrap();
+        assert_eq!(book.get_order_by_id(1).unwrap().amount, 1.0);
+        assert_eq!(book.get_all_asks()[0].id, 1);
+
+        // Increasing the amount sends the order to the back of the queue.
+        book.amend_order(1, 3.0, None, 2).unwrap();
+        let ids: Vec<u64> = book.get_all_asks().iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_amend_price_can_match() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        book.add_order(
+            test_order(1, OrderType::Limit, BidOrAsk::Ask, 1.0, 101.0),
+            0,
+        )
+        .unwrap();
+        book.add_order(
+            test_order(2, OrderType::Limit, BidOrAsk::Bid, 1.0, 100.0),
+            0,
+        )
+        .unwrap();
+
+        let execution = book
+            .amend_order(2, 1.0, Some(Price::new(101.0)), 1)
+            .unwrap();
+        assert_eq!(execution.fills.len(), 1);
+        assert!(book.get_orders().is_empty());
+    }
+
+    #[test]
+    fn test_depth_and_traded_totals() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        for (id, price) in [(1, 101.0), (2, 102.0)] {
+            book.add_order(
+                test_order(id, OrderType::Limit, BidOrAsk::Ask, 2.0, price),
+                0,
+            )
+            .unwrap();
+        }
+        book.add_order(
+            test_order(3, OrderType::Limit, BidOrAsk::Bid, 3.0, 102.0),
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(book.traded(), (2, 3.0));
+        assert_eq!(
+            book.depth(),
+            BookDepth {
+                bids: 0.0,
+                asks: 1.0,
+                resting_orders: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resting_page_follows_priority() {
+        let (tx, _rx) = std::sync::mpsc::channel::<MarketEvent>();
+        let mut book = OrderBook::new(tx);
+        for (id, price) in [(1, 101.0), (2, 102.0), (3, 102.0), (4, 103.0)] {
+            book.add_order(
+                test_order(id, OrderType::Limit, BidOrAsk::Ask, 1.0, price),
+                0,
+            )
+            .unwrap();
+        }
+        let ids = |orders: Vec<Order>| orders.iter().map(|order| order.id).collect::<Vec<_>>();
+
+        let first = book.resting_page(BidOrAsk::Ask, None, None, 2);
+        assert_eq!(ids(first), vec![1, 2]);
+        let after = Some((Price::new(102.0), 2));
+        assert_eq!(
+            ids(book.resting_page(BidOrAsk::Ask, None, after, 2)),
+            vec![3, 4]
+        );
+
+        // A cursor whose order has left the book resumes after it.
+        book.cancel_order(2).unwrap();
+        assert_eq!(
+            ids(book.resting_page(BidOrAsk::Ask, None, after, 2)),
+            vec![3, 4]
+        );
+        assert!(book
+            .resting_page(BidOrAsk::Ask, Some("ETH-USD"), None, 2)
+            .is_empty());
+        assert!(book.resting_page(BidOrAsk::Bid, None, None, 2).is_empty());
+    }
 }
@@ -0,0 +1,527 @@
+//! Order and fill history in PostgreSQL. The engine hands every change to
+//! an order, and every fill, to a `Writer`, which stores them in the
+//! background so the engine thread never waits on the database. Queries for
+//! final orders and fills are then answered from the database, together
+//! with the records the engine has not had written yet, rather than from
+//! its bounded in-memory history alone. Order and trade ids carry on from
+//! the highest ones stored, so they stay unique across restarts.
+
+use prometheus::IntCounter;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls, Row};
+
+use crate::engine::{FillQuery, OrderQuery};
+use crate::metrics::Metrics;
+use crate::models::{FillRecord, OrderRecord};
+
+/// How many records may wait for the database. Once that many do, new
+/// ones are dropped, and counted, rather than held in memory.
+pub const WRITE_QUEUE_CAPACITY: usize = 65_536;
+/// How long the writer first waits to retry after losing the database.
+/// The wait doubles with every failed attempt, up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Creates the tables on first use. Each row keeps the record as JSON next
+/// to the columns queries filter on.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS orders (
+        id BIGINT PRIMARY KEY,
+        account TEXT,
+        symbol TEXT NOT NULL,
+        side TEXT NOT NULL,
+        status TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS orders_status ON orders (status, id);
+    CREATE TABLE IF NOT EXISTS fills (
+        trade_id BIGINT NOT NULL,
+        order_id BIGINT NOT NULL,
+        account TEXT,
+        symbol TEXT NOT NULL,
+        side TEXT NOT NULL,
+        record TEXT NOT NULL,
+        PRIMARY KEY (trade_id, order_id)
+    );
+    CREATE INDEX IF NOT EXISTS fills_account ON fills (account, trade_id, order_id);
+";
+
+/// Something for the `Writer` to store.
+#[derive(Debug, Clone)]
+pub enum Record {
+    /// An order the engine has just accepted or rejected. Its id must be
+    /// new to the database.
+    NewOrder(OrderRecord),
+    /// The latest state of an order already stored, replacing the earlier
+    /// one.
+    Order(OrderRecord),
+    /// A fill, whose trade id and order id must be new to the database.
+    Fill(FillRecord),
+}
+
+/// Where the engine sends records. Sending never blocks: records queue up,
+/// up to `WRITE_QUEUE_CAPACITY`, while the database is slow or away.
+#[derive(Debug)]
+pub struct Writer {
+    records: mpsc::Sender<(u64, Record)>,
+    /// The number given to the last record sent.
+    sent: u64,
+    /// The number of the last record the store has finished with, whether
+    /// it was stored or refused.
+    done: Arc<AtomicU64>,
+    dropped: IntCounter,
+    /// The highest order id stored when the writer was made. The engine
+    /// hands out ids after it.
+    pub last_order_id: u64,
+    /// The highest trade id stored when the writer was made.
+    pub last_trade_id: u64,
+}
+
+impl Writer {
+    /// Queues `record` and returns the number it was given. With the queue
+    /// full, the record is dropped.
+    pub fn send(&mut self, record: Record) -> u64 {
+        self.sent += 1;
+        match self.records.try_send((self.sent, record)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full((_, record))) => {
+                tracing::error!(?record, "history queue full, record dropped");
+                self.dropped.inc();
+            }
+            // Only once the store is gone, at shutdown.
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+        self.sent
+    }
+
+    /// Whether the store has finished with the record numbered `number`,
+    /// so that reads from the database include it.
+    pub fn is_done(&self, number: u64) -> bool {
+        number <= self.done.load(Ordering::Acquire)
+    }
+}
+
+/// Why a record was not stored.
+#[derive(Debug)]
+enum WriteError {
+    Database(tokio_postgres::Error),
+    /// A change to an order the database does not hold.
+    MissingOrder(u64),
+}
+
+impl WriteError {
+    /// Whether the write failed because the connection went away, which the
+    /// client may only notice after the server has said it is closing it.
+    fn is_disconnect(&self) -> bool {
+        match self {
+            WriteError::Database(err) => {
+                err.is_closed()
+                    || err.code().is_some_and(|state| {
+                        // Connection exceptions and operator interventions.
+                        state.code().starts_with("08") || state.code().starts_with("57P")
+                    })
+            }
+            WriteError::MissingOrder(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Database(err) => err.fmt(f),
+            WriteError::MissingOrder(id) => write!(f, "order {} was never stored", id),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for WriteError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        WriteError::Database(err)
+    }
+}
+
+/// A connection to the database, made again whenever it is lost.
+struct Connection {
+    dsn: String,
+    client: RwLock<Arc<Client>>,
+    /// Held while connecting again, so only one caller does.
+    reconnecting: Mutex<()>,
+}
+
+impl Connection {
+    async fn open(dsn: &str) -> Result<Self, tokio_postgres::Error> {
+        Ok(Self {
+            dsn: dsn.to_string(),
+            client: RwLock::new(Arc::new(open(dsn).await?)),
+            reconnecting: Mutex::new(()),
+        })
+    }
+
+    fn current(&self) -> Arc<Client> {
+        self.client.read().expect("store lock poisoned").clone()
+    }
+
+    /// The client, connected again first if the connection has been lost.
+    async fn client(&self) -> Result<Arc<Client>, tokio_postgres::Error> {
+        let client = self.current();
+        if !client.is_closed() {
+            return Ok(client);
+        }
+        let _reconnecting = self.reconnecting.lock().await;
+        let client = self.current();
+        if !client.is_closed() {
+            return Ok(client);
+        }
+        tracing::warn!("connecting to the history database again");
+        let client = Arc::new(open(&self.dsn).await?);
+        *self.client.write().expect("store lock poisoned") = client.clone();
+        Ok(client)
+    }
+}
+
+/// The history database. Writes and reads go over connections of their
+/// own, so queries never wait behind a write.
+pub struct Store {
+    writes: Connection,
+    reads: Connection,
+    last_order_id: u64,
+    last_trade_id: u64,
+}
+
+impl Store {
+    /// Connects to `dsn`, creates the tables if they are missing and finds
+    /// the highest order and trade ids stored so far.
+    pub async fn connect(dsn: &str) -> Result<Self, tokio_postgres::Error> {
+        let writes = Connection::open(dsn).await?;
+        let client = writes.current();
+        client.batch_execute(SCHEMA).await?;
+        let last = client
+            .query_one(
+                "SELECT (SELECT COALESCE(MAX(id), 0) FROM orders),
+                        (SELECT COALESCE(MAX(trade_id), 0) FROM fills)",
+                &[],
+            )
+            .await?;
+        Ok(Self {
+            writes,
+            reads: Connection::open(dsn).await?,
+            last_order_id: last.get::<_, i64>(0) as u64,
+            last_trade_id: last.get::<_, i64>(1) as u64,
+        })
+    }
+
+    /// Stores the records sent to the returned writer, in order, until
+    /// every sender is gone. Records dropped from a full queue and records
+    /// the database refuses, such as a second order with the same id, are
+    /// counted in `metrics`.
+    pub fn writer(self: Arc<Self>, metrics: &Metrics) -> Writer {
+        let (records, mut received) = mpsc::channel(WRITE_QUEUE_CAPACITY);
+        let done = Arc::new(AtomicU64::new(0));
+        let writer = Writer {
+            records,
+            sent: 0,
+            done: done.clone(),
+            dropped: metrics.history_writes_dropped.clone(),
+            last_order_id: self.last_order_id,
+            last_trade_id: self.last_trade_id,
+        };
+        let refused = metrics.history_writes_refused.clone();
+        actix_web::rt::spawn(async move {
+            while let Some((number, record)) = received.recv().await {
+                self.store(&record, &refused).await;
+                done.store(number, Ordering::Release);
+            }
+        });
+        writer
+    }
+
+    /// Writes `record`, retrying for as long as the database cannot be
+    /// reached.
+    async fn store(&self, record: &Record, refused: &IntCounter) {
+        let mut delay = RETRY_DELAY;
+        loop {
+            match self.write(record).await {
+                Ok(()) => return,
+                Err(err) if err.is_disconnect() || self.writes.current().is_closed() => {
+                    tracing::warn!(error = %err, retry_in = ?delay, "history database unavailable");
+                    actix_web::rt::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(err) => {
+                    tracing::error!(error = %err, ?record, "failed to store history");
+                    refused.inc();
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn write(&self, record: &Record) -> Result<(), WriteError> {
+        let client = self.writes.client().await?;
+        match record {
+            Record::NewOrder(record) => {
+                let order = &record.order;
+                client
+                    .execute(
+                        "INSERT INTO orders (id, account, symbol, side, status, record)
+                         VALUES ($1, $2, $3, $4, $5, $6)",
+                        &[
+                            &(order.id as i64),
+                            &order.account,
+                            &order.trading_pair,
+                            &name(&order.bid_or_ask),
+                            &name(&record.status),
+                            &to_json(record),
+                        ],
+                    )
+                    .await?;
+            }
+            Record::Order(record) => {
+                let updated = client
+                    .execute(
+                        "UPDATE orders SET status = $2, record = $3 WHERE id = $1",
+                        &[
+                            &(record.order.id as i64),
+                            &name(&record.status),
+                            &to_json(record),
+                        ],
+                    )
+                    .await?;
+                if updated == 0 {
+                    return Err(WriteError::MissingOrder(record.order.id));
+                }
+            }
+            Record::Fill(record) => {
+                client
+                    .execute(
+                        "INSERT INTO fills (trade_id, order_id, account, symbol, side, record)
+                         VALUES ($1, $2, $3, $4, $5, $6)",
+                        &[
+                            &(record.fill.trade_id as i64),
+                            &(record.order_id as i64),
+                            &record.account,
+                            &record.symbol,
+                            &name(&record.side),
+                            &to_json(record),
+                        ],
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The latest stored state of order `id`.
+    pub async fn get_order(&self, id: u64) -> Result<Option<OrderRecord>, tokio_postgres::Error> {
+        let row = self
+            .reads
+            .client()
+            .await?
+            .query_opt("SELECT record FROM orders WHERE id = $1", &[&(id as i64)])
+            .await?;
+        Ok(row.as_ref().and_then(from_row))
+    }
+
+    /// The stored orders `query` selects, by ascending id.
+    pub async fn query_orders(
+        &self,
+        query: &OrderQuery,
+    ) -> Result<Vec<OrderRecord>, tokio_postgres::Error> {
+        let (sql, params) = order_sql(query);
+        let rows = self
+            .reads
+            .client()
+            .await?
+            .query(&sql, &borrow(&params))
+            .await?;
+        Ok(rows.iter().filter_map(from_row).collect())
+    }
+
+    /// The stored fills `query` selects, oldest first.
+    pub async fn query_fills(
+        &self,
+        query: &FillQuery,
+    ) -> Result<Vec<FillRecord>, tokio_postgres::Error> {
+        let (sql, params) = fill_sql(query);
+        let rows = self
+            .reads
+            .client()
+            .await?
+            .query(&sql, &borrow(&params))
+            .await?;
+        Ok(rows.iter().filter_map(from_row).collect())
+    }
+}
+
+async fn open(dsn: &str) -> Result<Client, tokio_postgres::Error> {
+    let (client, connection) = tokio_postgres::connect(dsn, NoTls).await?;
+    actix_web::rt::spawn(async move {
+        if let Err(err) = connection.await {
+            tracing::error!(error = %err, "history database connection failed");
+        }
+    });
+    Ok(client)
+}
+
+type Params = Vec<Box<dyn ToSql + Sync + Send>>;
+
+/// Builds a `WHERE` clause from the conditions that apply, numbering their
+/// parameters in order.
+#[derive(Default)]
+struct Filter {
+    conditions: Vec<String>,
+    params: Params,
+}
+
+impl Filter {
+    /// Adds `param` and returns its placeholder.
+    fn param(&mut self, param: impl ToSql + Sync + Send + 'static) -> String {
+        self.params.push(Box::new(param));
+        format!("${}", self.params.len())
+    }
+
+    /// Compares `column` with `param`, if there is one, by `operator`.
+    fn push_if<T: ToSql + Sync + Send + 'static>(
+        &mut self,
+        column: &str,
+        operator: &str,
+        param: Option<T>,
+    ) {
+        if let Some(param) = param {
+            let placeholder = self.param(param);
+            self.conditions
+                .push(format!("{} {} {}", column, operator, placeholder));
+        }
+    }
+
+    /// The statement for `select`, filtered, sorted by `order_by` and paged.
+    fn finish(
+        mut self,
+        select: &str,
+        order_by: &str,
+        offset: usize,
+        limit: usize,
+    ) -> (String, Params) {
+        let mut sql = select.to_string();
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        let offset = self.param(clamp(offset));
+        let limit = self.param(clamp(limit));
+        sql.push_str(&format!(
+            " ORDER BY {} OFFSET {} LIMIT {}",
+            order_by, offset, limit
+        ));
+        (sql, self.params)
+    }
+}
+
+fn order_sql(query: &OrderQuery) -> (String, Params) {
+    let mut filter = Filter::default();
+    filter.push_if("status", "=", query.status.as_ref().map(name));
+    filter.push_if("symbol", "=", query.symbol.clone());
+    filter.push_if("side", "=", query.side.as_ref().map(name));
+    filter.push_if("account", "=", query.account.clone());
+    filter.push_if("id", ">", query.after.map(|after| after as i64));
+    filter.finish("SELECT record FROM orders", "id", query.offset, query.limit)
+}
+
+fn fill_sql(query: &FillQuery) -> (String, Params) {
+    let mut filter = Filter::default();
+    filter.push_if("account", "=", query.account.clone());
+    filter.push_if("symbol", "=", query.symbol.clone());
+    filter.push_if("side", "=", query.side.as_ref().map(name));
+    filter.push_if("order_id", "=", query.order_id.map(|id| id as i64));
+    if let Some((trade_id, order_id)) = query.after {
+        let trade_id = filter.param(trade_id as i64);
+        let order_id = filter.param(order_id as i64);
+        filter.conditions.push(format!(
+            "(trade_id, order_id) > ({}, {})",
+            trade_id, order_id
+        ));
+    }
+    filter.finish(
+        "SELECT record FROM fills",
+        "trade_id, order_id",
+        query.offset,
+        query.limit,
+    )
+}
+
+fn borrow(params: &Params) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+fn clamp(count: usize) -> i64 {
+    i64::try_from(count).unwrap_or(i64::MAX)
+}
+
+/// How a unit-like enum such as a status or side is written in JSON.
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("only called with unit-like enums"),
+    }
+}
+
+fn to_json<T: Serialize>(record: &T) -> String {
+    serde_json::to_string(record).expect("records serialize to JSON")
+}
+
+fn from_row<T: serde::de::DeserializeOwned>(row: &Row) -> Option<T> {
+    let record: &str = row.get(0);
+    match serde_json::from_str(record) {
+        Ok(record) => Some(record),
+        Err(err) => {
+            tracing::error!(error = %err, "unreadable history record skipped");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BidOrAsk, OrderStatus};
+
+    #[test]
+    fn test_queries_number_only_the_filters_given() {
+        let (sql, params) = order_sql(&OrderQuery {
+            status: Some(OrderStatus::Filled),
+            account: Some("desk-7".to_string()),
+            after: Some(12),
+            limit: 50,
+            ..OrderQuery::default()
+        });
+        assert_eq!(
+            sql,
+            "SELECT record FROM orders WHERE status = $1 AND account = $2 AND id > $3 \
+             ORDER BY id OFFSET $4 LIMIT $5"
+        );
+        assert_eq!(params.len(), 5);
+
+        let (sql, params) = fill_sql(&FillQuery {
+            side: Some(BidOrAsk::Bid),
+            after: Some((7, 3)),
+            ..FillQuery::default()
+        });
+        assert_eq!(
+            sql,
+            "SELECT record FROM fills WHERE side = $1 AND (trade_id, order_id) > ($2, $3) \
+             ORDER BY trade_id, order_id OFFSET $4 LIMIT $5"
+        );
+        assert_eq!(params.len(), 5);
+        assert_eq!(name(&OrderStatus::PartiallyFilled), "partially_filled");
+    }
+}
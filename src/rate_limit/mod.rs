@@ -0,0 +1,272 @@
+use crate::auth::Identity;
+use crate::error::Error;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, ResponseError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Once a limiter tracks this many buckets, full ones are dropped; they
+/// would be recreated full anyway.
+const PRUNE_AT: usize = 10_000;
+
+/// A token bucket: up to `burst` requests at once, refilled at
+/// `per_second`. A burst of 0 turns the limit off.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// The state of a bucket after a request, for the `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the bucket is full again.
+    pub reset: Duration,
+    /// Until the next request would be allowed; zero if this one was.
+    pub retry_after: Duration,
+}
+
+impl Decision {
+    /// Sets the `RateLimit-Limit`, `RateLimit-Remaining` and
+    /// `RateLimit-Reset` headers, and `Retry-After` if the request was
+    /// refused. Headers already present are left alone, so a request with
+    /// an API key reports the key's limit rather than its address's.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        let mut set = |name: HeaderName, value: u64| {
+            if !headers.contains_key(&name) {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        };
+        set(
+            HeaderName::from_static("ratelimit-limit"),
+            self.limit.into(),
+        );
+        set(
+            HeaderName::from_static("ratelimit-remaining"),
+            self.remaining.into(),
+        );
+        set(
+            HeaderName::from_static("ratelimit-reset"),
+            whole_seconds(self.reset),
+        );
+        if !self.allowed {
+            set(RETRY_AFTER, whole_seconds(self.retry_after));
+        }
+    }
+}
+
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// One token bucket per key, all with the same limit.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: Limit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Limit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket. `None` when the limit is off.
+    pub fn check(&self, key: &str, now: Instant) -> Option<Decision> {
+        let Limit { burst, per_second } = self.limit;
+        if burst == 0 {
+            return None;
+        }
+        let capacity = f64::from(burst);
+        let refill = |bucket: &mut Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+            bucket.updated = now;
+        };
+
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| {
+                refill(bucket);
+                bucket.tokens < capacity
+            });
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        refill(bucket);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let seconds_until = |tokens: f64| {
+            if per_second > 0.0 {
+                Duration::from_secs_f64((tokens.max(0.0) / per_second).min(u32::MAX.into()))
+            } else {
+                Duration::MAX
+            }
+        };
+        Some(Decision {
+            allowed,
+            limit: burst,
+            remaining: bucket.tokens as u32,
+            reset: seconds_until(capacity - bucket.tokens),
+            retry_after: if allowed {
+                Duration::ZERO
+            } else {
+                seconds_until(1.0 - bucket.tokens)
+            },
+        })
+    }
+}
+
+/// Request limits per API key and per client address, shared by REST and
+/// WebSocket commands.
+#[derive(Debug)]
+pub struct RateLimits {
+    keys: RateLimiter,
+    ips: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(per_key: Limit, per_ip: Limit) -> Self {
+        Self {
+            keys: RateLimiter::new(per_key),
+            ips: RateLimiter::new(per_ip),
+        }
+    }
+
+    pub fn check_key(&self, key: &str) -> Option<Decision> {
+        self.keys.check(key, Instant::now())
+    }
+
+    pub fn check_ip(&self, ip: IpAddr) -> Option<Decision> {
+        self.ips.check(&ip.to_string(), Instant::now())
+    }
+
+    /// Checks a WebSocket command. Sessions have no API key, so they count
+    /// against their account, if they have one, and their address.
+    pub fn check_session(&self, account: Option<&str>, ip: Option<IpAddr>) -> Option<Decision> {
+        let by_account = account.and_then(|account| {
+            self.keys
+                .check(&format!("account:{}", account), Instant::now())
+        });
+        let by_ip = ip.and_then(|ip| self.check_ip(ip));
+        [by_account, by_ip]
+            .into_iter()
+            .flatten()
+            .min_by_key(|decision| (decision.allowed, decision.remaining))
+    }
+}
+
+/// Middleware limiting requests per client address. Does nothing unless
+/// `RateLimits` are registered as app data. Runs before authentication, so
+/// floods of unsigned requests are turned away cheaply.
+pub async fn limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let decision = req
+        .app_data::<web::Data<RateLimits>>()
+        .zip(req.peer_addr())
+        .and_then(|(limits, peer)| limits.check_ip(peer.ip()));
+    respond(req, next, decision, "address").await
+}
+
+/// Middleware limiting requests per API key, for requests the API key
+/// middleware has authenticated. Does nothing unless `RateLimits` are
+/// registered as app data.
+pub async fn limit_by_key(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let key = req
+        .extensions()
+        .get::<Identity>()
+        .map(|identity| identity.key.clone());
+    let decision = req
+        .app_data::<web::Data<RateLimits>>()
+        .zip(key)
+        .and_then(|(limits, key)| limits.check_key(&key));
+    respond(req, next, decision, "API key").await
+}
+
+async fn respond(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+    decision: Option<Decision>,
+    scope: &str,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    match decision {
+        Some(decision) if !decision.allowed => {
+            tracing::info!(scope, path = req.path(), "request rate limited");
+            let mut res = Error::RateLimited {
+                code: "rate_limited",
+                message: format!("rate limit exceeded for this {}", scope),
+                retry_after: Some(decision.retry_after),
+            }
+            .error_response();
+            decision.write_headers(res.headers_mut());
+            Ok(req.into_response(res).map_into_right_body())
+        }
+        decision => {
+            let mut res = next.call(req).await?;
+            if let Some(decision) = decision {
+                decision.write_headers(res.headers_mut());
+            }
+            Ok(res.map_into_left_body())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_a_burst_then_refills() {
+        let limiter = RateLimiter::new(Limit {
+            burst: 2,
+            per_second: 4.0,
+        });
+        let start = Instant::now();
+        assert_eq!(limiter.check("k", start).unwrap().remaining, 1);
+        assert!(limiter.check("k", start).unwrap().allowed);
+        let refused = limiter.check("k", start).unwrap();
+        assert!(!refused.allowed);
+        assert_eq!(refused.retry_after, Duration::from_millis(250));
+        assert_eq!(refused.reset, Duration::from_millis(500));
+
+        // Other keys have buckets of their own.
+        assert!(limiter.check("other", start).unwrap().allowed);
+        let later = start + Duration::from_millis(250);
+        assert!(limiter.check("k", later).unwrap().allowed);
+        assert!(!limiter.check("k", later).unwrap().allowed);
+
+        let off = RateLimiter::new(Limit {
+            burst: 0,
+            per_second: 0.0,
+        });
+        assert_eq!(off.check("k", start), None);
+    }
+}
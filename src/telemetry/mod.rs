@@ -0,0 +1,42 @@
+use serde::Deserialize;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per event, carrying the fields of every enclosing
+    /// span, so an order's history can be pulled out by its `order_id`.
+    #[default]
+    Json,
+    /// Human-readable lines for local development.
+    Text,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "text" | "pretty" => Ok(LogFormat::Text),
+            other => Err(format!("unknown log format {:?}", other)),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber. Verbosity follows `RUST_LOG`
+/// and defaults to `info`.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+        LogFormat::Text => builder.init(),
+    }
+}
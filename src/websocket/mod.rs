@@ -1,44 +1,392 @@
-use std::sync::mpsc::{self, Receiver};
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use actix::{Actor, StreamHandler};
+use actix::{Actor, ActorContext, ActorFutureExt, Addr, Handler, StreamHandler, WrapFuture};
 use actix_web_actors::ws;
 
-use crate::models::MatchedOrder;
+use crate::auth::Accounts;
+use crate::engine::{EngineError, EngineHandle, Outcome};
+use crate::market_data::{
+    self, Deliver, Disconnect, Hub, SessionId, Subscribe, Topic, Unsubscribe,
+};
+use crate::metrics::Metrics;
+use crate::models::Order;
+use crate::order_book::CancelFilter;
+use crate::rate_limit::RateLimits;
 use actix::AsyncContext;
-use std::sync::{Arc, Mutex};
+use tracing::Instrument;
+
+mod protocol;
+
+pub use protocol::{ClientCommand, Request, Response};
+
+/// How often the server pings each session.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// A session that has sent nothing, not even a pong, for this long is
+/// considered dead and dropped.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
 pub struct MyWebSocket {
-    rx: Arc<Mutex<Receiver<MatchedOrder>>>,
+    id: SessionId,
+    hub: Addr<Hub>,
+    metrics: Option<Metrics>,
+    engine: Option<EngineHandle>,
+    /// The account the session trades for, if the client identified itself.
+    account: Option<String>,
+    /// Whether `account` was proven with a token rather than just named.
+    /// Private channels need it.
+    authenticated: bool,
+    /// Tokens a `login` request is checked against.
+    accounts: Option<Arc<Accounts>>,
+    /// Whether placing, cancelling and amending orders needs `authenticated`.
+    authentication_required: bool,
+    /// Whether the account's orders are cancelled once the session ends.
+    cancel_on_disconnect: bool,
+    rate_limits: Option<Arc<RateLimits>>,
+    /// The client's address, which commands are rate limited by.
+    peer: Option<IpAddr>,
+    last_seen: Instant,
 }
 
 impl MyWebSocket {
-    pub fn new(rx: Arc<Mutex<Receiver<MatchedOrder>>>) -> Self {
-        MyWebSocket { rx }
+    /// A session that receives market data from `hub` once it subscribes.
+    pub fn new(hub: Addr<Hub>) -> Self {
+        MyWebSocket {
+            id: market_data::next_session_id(),
+            hub,
+            metrics: None,
+            engine: None,
+            account: None,
+            authenticated: false,
+            accounts: None,
+            authentication_required: false,
+            cancel_on_disconnect: false,
+            rate_limits: None,
+            peer: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    /// Counts this connection in `metrics` while it is open.
+    pub fn with_metrics(mut self, metrics: Option<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Lets the session place, cancel and amend orders.
+    pub fn with_engine(mut self, engine: Option<EngineHandle>) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn with_account(mut self, account: Option<String>) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// Trades for `account`, which the client has already proven it owns.
+    pub fn with_authenticated_account(mut self, account: String) -> Self {
+        self.account = Some(account);
+        self.authenticated = true;
+        self
+    }
+
+    /// Lets the client authenticate with a `login` request.
+    pub fn with_accounts(mut self, accounts: Option<Arc<Accounts>>) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    /// Refuses to place, cancel or amend orders until the session has
+    /// authenticated. For servers where callers must prove who they are.
+    pub fn require_authentication(mut self) -> Self {
+        self.authentication_required = true;
+        self
+    }
+
+    /// Limits the session's commands, other than pings, by its account and
+    /// by `peer`.
+    pub fn with_rate_limits(
+        mut self,
+        rate_limits: Option<Arc<RateLimits>>,
+        peer: Option<IpAddr>,
+    ) -> Self {
+        self.rate_limits = rate_limits;
+        self.peer = peer;
+        self
+    }
+
+    /// Cancels every order of the session's account when the session closes,
+    /// drops or misses its heartbeats. Needs an engine and an account.
+    pub fn cancel_on_disconnect(mut self) -> Self {
+        self.cancel_on_disconnect = true;
+        self
+    }
+
+    fn reply(&self, ctx: &mut ws::WebsocketContext<Self>, response: &Response) {
+        ctx.text(serde_json::to_string(response).unwrap());
+    }
+
+    fn handle_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => return self.reply(ctx, &Response::invalid(text, err)),
+        };
+        let id = request.id;
+        if !matches!(request.command, ClientCommand::Ping) {
+            let decision = self
+                .rate_limits
+                .as_ref()
+                .and_then(|limits| limits.check_session(self.proven_account(), self.peer));
+            if let Some(decision) = decision.filter(|decision| !decision.allowed) {
+                let response = Response::rate_limited(id, decision.retry_after);
+                return self.reply(ctx, &response);
+            }
+        }
+        let order_entry = matches!(
+            request.command,
+            ClientCommand::Place { .. }
+                | ClientCommand::Cancel { .. }
+                | ClientCommand::Amend { .. }
+        );
+        if order_entry && self.authentication_required && !self.authenticated {
+            let response = Response::unauthorized(id, "order entry needs an authenticated session");
+            return self.reply(ctx, &response);
+        }
+        match request.command {
+            ClientCommand::Ping => self.reply(ctx, &Response::Pong { id }),
+            ClientCommand::Login { token } => {
+                let response = self.login(id, &token);
+                self.reply(ctx, &response);
+            }
+            ClientCommand::Subscribe { topics } => {
+                if !self.authenticated && topics.iter().any(Topic::is_private) {
+                    let response = Response::unauthorized(
+                        id,
+                        "private channels need an authenticated session",
+                    );
+                    return self.reply(ctx, &response);
+                }
+                self.hub.do_send(Subscribe {
+                    session: self.id,
+                    recipient: ctx.address().recipient(),
+                    account: self.account.clone().filter(|_| self.authenticated),
+                    topics,
+                });
+                self.reply(ctx, &Response::Ack { id, result: None });
+            }
+            ClientCommand::Unsubscribe { topics } => {
+                self.hub.do_send(Unsubscribe {
+                    session: self.id,
+                    topics,
+                });
+                self.reply(ctx, &Response::Ack { id, result: None });
+            }
+            ClientCommand::Place { mut order } => {
+                order.account = self.account.clone();
+                self.send(id, ctx, move |engine, client_id| {
+                    place(engine, client_id, order)
+                });
+            }
+            ClientCommand::Cancel { order_id } => {
+                // Other accounts' orders are not found, as over REST.
+                let account = self.account.clone();
+                self.send(id, ctx, move |engine, client_id| {
+                    let span = tracing::info_span!("order", order_id, client_id);
+                    async move {
+                        engine
+                            .cancel_order(order_id, account)
+                            .instrument(span)
+                            .await
+                            .map(Outcome::Cancelled)
+                    }
+                });
+            }
+            ClientCommand::Amend {
+                order_id,
+                amount,
+                price,
+            } => {
+                let account = self.account.clone();
+                self.send(id, ctx, move |engine, client_id| {
+                    let span = tracing::info_span!("order", order_id, client_id);
+                    async move {
+                        engine
+                            .amend_order(order_id, amount, price, account)
+                            .instrument(span)
+                            .await
+                            .map(Outcome::Execution)
+                    }
+                });
+            }
+        }
+    }
+
+    /// The session's account, unless it was only named while account
+    /// tokens are configured, in which case anyone could have named it.
+    fn proven_account(&self) -> Option<&str> {
+        let tokens = self
+            .accounts
+            .as_ref()
+            .is_some_and(|accounts| !accounts.is_empty());
+        self.account
+            .as_deref()
+            .filter(|_| self.authenticated || !tokens)
+    }
+
+    fn login(&mut self, id: u64, token: &str) -> Response {
+        let account = self
+            .accounts
+            .as_ref()
+            .and_then(|accounts| accounts.authenticate(token));
+        let Some(account) = account else {
+            tracing::warn!(account = ?self.account, "websocket login failed");
+            return Response::unauthorized(id, "invalid token");
+        };
+        if self
+            .account
+            .as_deref()
+            .is_some_and(|current| current != account)
+        {
+            return Response::unauthorized(id, "the session already trades for another account");
+        }
+        tracing::info!(account, "websocket session authenticated");
+        self.account = Some(account.to_string());
+        self.authenticated = true;
+        Response::Ack { id, result: None }
+    }
+
+    /// Runs an engine request for the session and replies with its result.
+    fn send<F>(
+        &self,
+        id: u64,
+        ctx: &mut ws::WebsocketContext<Self>,
+        request: impl FnOnce(EngineHandle, &str) -> F,
+    ) where
+        F: Future<Output = Result<Outcome, EngineError>> + 'static,
+    {
+        let Some(engine) = self.engine.clone() else {
+            let response = Response::from_result(id, Err(EngineError::Unavailable));
+            return self.reply(ctx, &response);
+        };
+        let future = request(engine, self.account.as_deref().unwrap_or(""));
+        // Waiting keeps the session's commands in the order they were sent,
+        // so a cancel cannot overtake the order it refers to.
+        ctx.wait(future.into_actor(self).map(move |result, actor, ctx| {
+            actor.reply(ctx, &Response::from_result(id, result));
+        }));
+    }
+}
+
+/// Submits an order, logging it under the same span as REST submissions.
+fn place(
+    engine: EngineHandle,
+    client_id: &str,
+    mut order: Order,
+) -> impl Future<Output = Result<Outcome, EngineError>> {
+    order.id = engine.next_order_id();
+    let span = tracing::info_span!(
+        "order",
+        order_id = order.id,
+        client_id,
+        client_order_id = order.client_order_id.as_deref(),
+        symbol = %order.trading_pair,
+    );
+    async move {
+        engine
+            .add_order(order, None)
+            .instrument(span)
+            .await
+            .map(Outcome::Execution)
     }
 }
 
 impl Actor for MyWebSocket {
     type Context = ws::WebsocketContext<Self>;
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(std::time::Duration::from_secs(1), |actor, ctx| {
-            if let Ok(rx_lock) = actor.rx.lock() {
-                while let Ok(matched_order) = rx_lock.try_recv() {
-                    let order_info = serde_json::to_string(&matched_order).unwrap();
-                    ctx.text(order_info);
-                }
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.websocket_connections.inc();
+        }
+        ctx.run_interval(HEARTBEAT_INTERVAL, |actor, ctx| {
+            if actor.last_seen.elapsed() > CLIENT_TIMEOUT {
+                tracing::warn!(account = ?actor.account, "websocket heartbeat missed, closing");
+                ctx.stop();
+                return;
             }
+            ctx.ping(b"");
         });
     }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.websocket_connections.dec();
+        }
+        self.hub.do_send(Disconnect { session: self.id });
+        if !self.cancel_on_disconnect {
+            return;
+        }
+        if let (Some(engine), Some(account)) = (self.engine.clone(), self.account.clone()) {
+            let filter = CancelFilter {
+                account: Some(account.clone()),
+                ..CancelFilter::default()
+            };
+            actix::spawn(async move {
+                match engine.cancel_all(filter).await {
+                    Ok(cancelled) => tracing::info!(
+                        account = %account,
+                        cancelled = cancelled.len(),
+                        "websocket session ended, orders cancelled"
+                    ),
+                    Err(err) => tracing::error!(
+                        account = %account,
+                        error = %err,
+                        "cancel on disconnect failed"
+                    ),
+                }
+            });
+        }
+    }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWebSocket {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        println!("WS: {:?}", msg);
-        if let Ok(rx_lock) = self.rx.lock() {
-            while let Ok(matched_order) = rx_lock.try_recv() {
-                let order_info = serde_json::to_string(&matched_order).unwrap();
-                print!("Sending: {}", order_info);
-                ctx.text(order_info);
+        tracing::debug!(?msg, "websocket message received");
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(err) => {
+                tracing::warn!(error = %err, "websocket protocol error, closing");
+                ctx.stop();
+                return;
+            }
+        };
+        self.last_seen = Instant::now();
+        match msg {
+            ws::Message::Ping(payload) => ctx.pong(&payload),
+            ws::Message::Text(text) => self.handle_request(&text, ctx),
+            ws::Message::Binary(_) => {
+                let response = Response::Reject {
+                    id: None,
+                    code: "invalid_request".to_string(),
+                    reason: "requests must be sent as text frames".to_string(),
+                };
+                self.reply(ctx, &response);
             }
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => {}
         }
     }
 }
+
+impl Handler<Deliver> for MyWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, Deliver(text): Deliver, ctx: &mut Self::Context) {
+        tracing::trace!(message = %text, "websocket market data sent");
+        ctx.text(&*text);
+    }
+}
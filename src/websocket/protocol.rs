@@ -0,0 +1,144 @@
+use crate::engine::{EngineError, Outcome};
+use crate::market_data::Topic;
+use crate::metrics::rejection_reason;
+use crate::models::{Order, Price};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A command sent over the socket. `id` is chosen by the client and echoed
+/// on the reply, so replies can be matched to requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub id: u64,
+    #[serde(flatten)]
+    pub command: ClientCommand,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientCommand {
+    /// Submits a new order for the session's account. As over REST, the
+    /// engine assigns the order id.
+    Place {
+        order: Order,
+    },
+    Cancel {
+        order_id: u64,
+    },
+    Amend {
+        order_id: u64,
+        amount: f64,
+        #[serde(default)]
+        price: Option<Price>,
+    },
+    /// Authenticates the session as the account `token` belongs to.
+    Login {
+        token: String,
+    },
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    Unsubscribe {
+        topics: Vec<Topic>,
+    },
+    Ping,
+}
+
+/// The server's answer to a `Request`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Ack {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Outcome>,
+    },
+    /// `id` is missing only when the request was too malformed to read
+    /// one from. `code` is a stable, machine-readable reason.
+    Reject {
+        id: Option<u64>,
+        code: String,
+        reason: String,
+    },
+    Pong {
+        id: u64,
+    },
+}
+
+impl Response {
+    pub fn from_result(id: u64, result: Result<Outcome, EngineError>) -> Self {
+        match result {
+            Ok(outcome) => Response::Ack {
+                id,
+                result: Some(outcome),
+            },
+            Err(err) => Response::Reject {
+                id: Some(id),
+                code: rejection_reason(&err).to_string(),
+                reason: err.to_string(),
+            },
+        }
+    }
+
+    pub fn unauthorized(id: u64, reason: &str) -> Self {
+        Response::Reject {
+            id: Some(id),
+            code: "unauthorized".to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn rate_limited(id: u64, retry_after: Duration) -> Self {
+        Response::Reject {
+            id: Some(id),
+            code: "rate_limited".to_string(),
+            reason: format!(
+                "rate limit exceeded, retry in {} ms",
+                retry_after.as_millis()
+            ),
+        }
+    }
+
+    /// Rejects a message that is not a valid `Request`, keeping its id if
+    /// one can be found.
+    pub fn invalid(text: &str, err: serde_json::Error) -> Self {
+        let id = serde_json::from_str::<serde_json::Value>(text)
+            .ok()
+            .and_then(|value| value.get("id").and_then(serde_json::Value::as_u64));
+        Response::Reject {
+            id,
+            code: "invalid_request".to_string(),
+            reason: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_wire_format() {
+        let request: Request =
+            serde_json::from_str(r#"{"id": 7, "type": "cancel", "order_id": 3}"#).unwrap();
+        assert_eq!(request.id, 7);
+        assert!(matches!(
+            request.command,
+            ClientCommand::Cancel { order_id: 3 }
+        ));
+
+        let request: Request =
+            serde_json::from_str(r#"{"id": 8, "type": "subscribe", "topics": ["trades:BTC-USD"]}"#)
+                .unwrap();
+        assert!(
+            matches!(request.command, ClientCommand::Subscribe { topics } if topics == ["trades:BTC-USD".parse().unwrap()])
+        );
+
+        let text = r#"{"id": 9, "type": "launch"}"#;
+        let err = serde_json::from_str::<Request>(text).unwrap_err();
+        let reply = serde_json::to_value(Response::invalid(text, err)).unwrap();
+        assert_eq!(reply["type"], "reject");
+        assert_eq!(reply["id"], 9);
+        assert_eq!(reply["code"], "invalid_request");
+    }
+}
@@ -0,0 +1,107 @@
+use orderbook::models::{BidOrAsk, MatchedOrder, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts allocations made by the current thread, so the test harness's own
+/// threads do not disturb the measurement.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+fn order(order_type: OrderType, bid_or_ask: BidOrAsk, price: Option<f64>) -> Order {
+    Order {
+        id: 0,
+        order_type,
+        trading_pair: "BTC-USD".to_string(),
+        amount: 1.0,
+        price: price.map(Price::new),
+        timestamp: 0,
+        bid_or_ask,
+        client_order_id: None,
+        account: None,
+    }
+}
+
+#[test]
+fn test_steady_state_matching_does_not_allocate() {
+    // No notifier: market data fan-out is outside the matching hot path.
+    let mut book = OrderBook::default();
+    let mut fills: Vec<MatchedOrder> = Vec::with_capacity(16);
+    let mut next_id = 0;
+
+    // Depth on both sides, so no cycle below ever empties a side.
+    for level in 0..5 {
+        for _ in 0..10 {
+            next_id += 1;
+            let mut ask = order(OrderType::Limit, BidOrAsk::Ask, Some(101.0 + level as f64));
+            ask.id = next_id;
+            book.submit(&mut ask, 0, &mut fills).unwrap();
+            next_id += 1;
+            let mut bid = order(OrderType::Limit, BidOrAsk::Bid, Some(99.0 - level as f64));
+            bid.id = next_id;
+            book.submit(&mut bid, 0, &mut fills).unwrap();
+        }
+    }
+    assert!(fills.is_empty());
+
+    let mut resting_ask = order(OrderType::Limit, BidOrAsk::Ask, Some(101.0));
+    let mut crossing_bid = order(OrderType::Limit, BidOrAsk::Bid, Some(101.0));
+    let mut new_level_bid = order(OrderType::Limit, BidOrAsk::Bid, Some(100.0));
+    let mut market_ask = order(OrderType::Market, BidOrAsk::Ask, None);
+
+    let mut cycle = |book: &mut OrderBook, fills: &mut Vec<MatchedOrder>| {
+        for order in [
+            &mut resting_ask,
+            &mut crossing_bid,
+            &mut new_level_bid,
+            &mut market_ask,
+        ] {
+            next_id += 1;
+            order.id = next_id;
+            order.amount = 1.0;
+            fills.clear();
+            book.submit(order, 0, fills).unwrap();
+        }
+        // The market sell took out the level the bid at 100 opened.
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, Price::new(100.0));
+    };
+
+    // Let the slab's free list, the id index and the scratch buffers settle.
+    for _ in 0..100 {
+        cycle(&mut book, &mut fills);
+    }
+
+    let before = allocations();
+    for _ in 0..10_000 {
+        cycle(&mut book, &mut fills);
+    }
+    assert_eq!(allocations() - before, 0);
+    assert_eq!(book.get_orders().len(), 100);
+}
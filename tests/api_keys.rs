@@ -0,0 +1,214 @@
+use actix_web::{middleware, test, web, App};
+use orderbook::api;
+use orderbook::auth::{ApiKey, ApiKeys, Permission, SignedRequest};
+use orderbook::engine::Engine;
+use orderbook::market_data::Hub;
+use orderbook::metrics::Metrics;
+use orderbook::models::{BidOrAsk, MarketEvent, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// A request signed with `key`, whose secret is the key name reversed.
+fn signed(method: &str, uri: &str, body: &Value, key: &str, nonce: &str) -> test::TestRequest {
+    let body = if body.is_null() {
+        Vec::new()
+    } else {
+        body.to_string().into_bytes()
+    };
+    let timestamp = now();
+    let secret: String = key.chars().rev().collect();
+    let signature = SignedRequest {
+        key,
+        timestamp,
+        nonce,
+        method,
+        path: uri,
+        body: &body,
+    }
+    .sign(&secret);
+    test::TestRequest::default()
+        .method(method.parse().unwrap())
+        .uri(uri)
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("X-Api-Key", key))
+        .insert_header(("X-Api-Timestamp", timestamp.to_string()))
+        .insert_header(("X-Api-Nonce", nonce))
+        .insert_header(("X-Api-Signature", signature))
+        .set_payload(body)
+}
+
+fn order(side: BidOrAsk) -> Value {
+    json!({
+        "order_type": "Limit",
+        "trading_pair": "BTC-USD",
+        "amount": 1.0,
+        "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+        "timestamp": 0,
+        "bid_or_ask": side
+    })
+}
+
+#[actix_web::test]
+async fn test_requests_need_a_signed_key_with_permission() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+    let key = |name: &str, account: &str, permissions: &[Permission]| {
+        let secret: String = name.chars().rev().collect();
+        ApiKey::new(name, account, &secret, permissions)
+    };
+    let keys = ApiKeys::new(
+        &[
+            key("trader", "desk-7", &[Permission::Read, Permission::Trade]),
+            key("viewer", "desk-8", &[Permission::Read]),
+            key("ops", "ops", &[Permission::Admin]),
+        ],
+        5_000,
+    );
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::from_fn(api::authenticate))
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(web::Data::new(keys))
+            .app_data(web::Data::new(Metrics::new()))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    for uri in ["/healthcheck", "/metrics"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .set_json(order(BidOrAsk::Ask))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    // The key's account wins over any X-Client-Id.
+    let req = signed("POST", "/orders", &order(BidOrAsk::Ask), "trader", "n-1")
+        .insert_header(("X-Client-Id", "desk-9"))
+        .to_request();
+    let placed: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(placed["order"]["account"], "desk-7");
+
+    let replayed = signed("POST", "/orders", &order(BidOrAsk::Ask), "trader", "n-1").to_request();
+    assert_eq!(test::call_service(&app, replayed).await.status(), 401);
+    let tampered = signed("POST", "/orders", &order(BidOrAsk::Ask), "trader", "n-2")
+        .set_json(order(BidOrAsk::Bid))
+        .to_request();
+    assert_eq!(test::call_service(&app, tampered).await.status(), 401);
+
+    let req = signed("POST", "/orders", &order(BidOrAsk::Ask), "viewer", "n-1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    // Reads are confined to the key's own account too.
+    let req = signed(
+        "GET",
+        "/orders?account=desk-7",
+        &Value::Null,
+        "viewer",
+        "n-2",
+    )
+    .to_request();
+    let orders: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert!(orders.is_empty());
+    let uri = format!("/orders/{}", placed["order"]["id"]);
+    let req = signed("GET", &uri, &Value::Null, "viewer", "n-3").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = signed("GET", &uri, &Value::Null, "trader", "n-5").to_request();
+    let order: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(order["status"], "new");
+
+    // Without admin, a mass cancel only reaches the caller's own orders.
+    let mut other = Order::new(
+        engine.next_order_id(),
+        OrderType::Limit,
+        "BTC-USD".to_string(),
+        1.0,
+        Some(Price::new(101.0)),
+        0,
+        BidOrAsk::Ask,
+    );
+    other.account = Some("desk-8".to_string());
+    engine.add_order(other, None).await.unwrap();
+    let req = signed(
+        "DELETE",
+        "/orders?account=desk-8",
+        &Value::Null,
+        "trader",
+        "n-3",
+    )
+    .to_request();
+    let cancelled: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].account.as_deref(), Some("desk-7"));
+
+    let req = signed("POST", "/admin/halt", &Value::Null, "trader", "n-4").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = signed("POST", "/admin/halt", &Value::Null, "ops", "n-1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn test_keys_only_cancel_and_amend_their_own_orders() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+    let key = |name: &str, account: &str| {
+        let secret: String = name.chars().rev().collect();
+        ApiKey::new(
+            name,
+            account,
+            &secret,
+            &[Permission::Read, Permission::Trade],
+        )
+    };
+    let keys = ApiKeys::new(&[key("desk-7", "desk-7"), key("desk-8", "desk-8")], 5_000);
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::from_fn(api::authenticate))
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(web::Data::new(keys))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let req = signed(
+        "POST",
+        "/api/v1/orders",
+        &order(BidOrAsk::Ask),
+        "desk-7",
+        "n-1",
+    )
+    .to_request();
+    let placed: Value = test::call_and_read_body_json(&app, req).await;
+    let uri = format!("/api/v1/orders/{}", placed["order"]["id"]);
+
+    // Another account's orders are not found, however they are addressed.
+    let req = signed("DELETE", &uri, &Value::Null, "desk-8", "n-1").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let amend = json!({"amount": 2.0, "price": null});
+    let req = signed("PATCH", &uri, &amend, "desk-8", "n-2").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let batch = json!([
+        {"op": "cancel", "id": placed["order"]["id"]},
+        {"op": "amend", "id": placed["order"]["id"], "amount": 2.0, "price": null},
+    ]);
+    let req = signed("POST", "/api/v1/orders/batch", &batch, "desk-8", "n-3").to_request();
+    let items: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(items.iter().all(|item| item["status"] == 404));
+    assert_eq!(engine.get_orders().await.unwrap().len(), 1);
+
+    let req = signed("DELETE", &uri, &Value::Null, "desk-7", "n-2").to_request();
+    let cancelled: Order = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cancelled.account.as_deref(), Some("desk-7"));
+}
@@ -0,0 +1,424 @@
+use actix::Addr;
+use orderbook::auth::{AccountToken, Accounts};
+use orderbook::engine::{Engine, EngineHandle};
+use orderbook::fix::{msg_type, tags, utc_timestamp, Gateway, Message};
+use orderbook::market_data::Hub;
+use orderbook::models::{BidOrAsk, MarketEvent, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn app_engine() -> (EngineHandle, Addr<Hub>) {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let engine = Engine::new(OrderBook::new(tx.clone())).with_notifier(tx);
+    (engine.spawn(16), Hub::start(rx))
+}
+
+async fn serve(gateway: Gateway) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    actix_web::rt::spawn(gateway.serve(listener));
+    addr
+}
+
+/// The client side of a FIX session, numbering what it sends.
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    comp_id: String,
+    seq: u64,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr, comp_id: &str) -> Self {
+        Self {
+            stream: TcpStream::connect(addr).await.unwrap(),
+            buffer: Vec::new(),
+            comp_id: comp_id.to_string(),
+            seq: 1,
+        }
+    }
+
+    /// Logs on and returns the gateway's Logon.
+    async fn logon(&mut self, heartbeat: u64, password: Option<&str>) -> Message {
+        let mut logon = Message::new(msg_type::LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, heartbeat);
+        if let Some(password) = password {
+            logon.push(tags::PASSWORD, password);
+        }
+        self.send(logon).await;
+        self.expect(msg_type::LOGON).await
+    }
+
+    async fn send(&mut self, body: Message) {
+        let seq = self.seq;
+        self.seq += 1;
+        self.send_as(seq, body).await;
+    }
+
+    /// Sends `body` numbered `seq`, leaving the client's numbering alone.
+    async fn send_as(&mut self, seq: u64, body: Message) {
+        let mut message = Message::new(body.msg_type())
+            .with(tags::SENDER_COMP_ID, &self.comp_id)
+            .with(tags::TARGET_COMP_ID, "ORDERBOOK")
+            .with(tags::MSG_SEQ_NUM, seq)
+            .with(tags::SENDING_TIME, utc_timestamp(0));
+        for (tag, value) in body.body() {
+            message.push(tag, value);
+        }
+        self.stream.write_all(&message.encode()).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> Message {
+        let read = async {
+            loop {
+                if let Some((message, consumed)) = Message::decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..consumed);
+                    return message;
+                }
+                let mut chunk = [0; 4096];
+                let read = self.stream.read(&mut chunk).await.unwrap();
+                assert!(read > 0, "the gateway closed the connection");
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        };
+        actix_web::rt::time::timeout(Duration::from_secs(5), read)
+            .await
+            .expect("no message arrived")
+    }
+
+    async fn expect(&mut self, msg_type: &str) -> Message {
+        let message = self.recv().await;
+        assert_eq!(message.msg_type(), msg_type, "unexpected {:?}", message);
+        message
+    }
+
+    /// Waits for an ExecutionReport of `exec_type`.
+    async fn report(&mut self, exec_type: &str) -> Message {
+        loop {
+            let message = self.expect(msg_type::EXECUTION_REPORT).await;
+            if message.get(tags::EXEC_TYPE) == Some(exec_type) {
+                return message;
+            }
+        }
+    }
+
+    /// Whether the gateway has closed the connection.
+    async fn closed(&mut self) -> bool {
+        let mut chunk = [0; 64];
+        let read = self.stream.read(&mut chunk);
+        matches!(
+            actix_web::rt::time::timeout(Duration::from_secs(5), read).await,
+            Ok(Ok(0) | Err(_))
+        )
+    }
+}
+
+fn new_order(cl_ord_id: &str, side: &str, quantity: f64, price: Option<f64>) -> Message {
+    let mut order = Message::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tags::CL_ORD_ID, cl_ord_id)
+        .with(tags::SYMBOL, "BTC-USD")
+        .with(tags::SIDE, side)
+        .with(tags::ORDER_QTY, quantity)
+        .with(tags::ORD_TYPE, if price.is_some() { "2" } else { "1" })
+        .with(tags::TRANSACT_TIME, utc_timestamp(0));
+    if let Some(price) = price {
+        order.push(tags::PRICE, price);
+    }
+    order
+}
+
+#[actix_web::test]
+async fn test_orders_are_placed_replaced_and_cancelled() {
+    let (engine, hub) = app_engine();
+    let accounts = Accounts::new(&[
+        AccountToken::new("desk-1", "token-1"),
+        AccountToken::new("desk-2", "token-2"),
+    ]);
+    let addr =
+        serve(Gateway::new(engine, hub, "ORDERBOOK").with_accounts(Arc::new(accounts))).await;
+
+    let mut intruder = Client::connect(addr, "INTRUDER").await;
+    intruder
+        .send(
+            Message::new(msg_type::LOGON)
+                .with(tags::ENCRYPT_METHOD, 0)
+                .with(tags::HEART_BT_INT, 30)
+                .with(tags::PASSWORD, "wrong"),
+        )
+        .await;
+    let logout = intruder.expect(msg_type::LOGOUT).await;
+    assert_eq!(logout.get(tags::TEXT), Some("invalid Password"));
+    assert!(intruder.closed().await);
+
+    let mut buyer = Client::connect(addr, "BUYER").await;
+    let logon = buyer.logon(30, Some("token-1")).await;
+    assert_eq!(logon.get(tags::HEART_BT_INT), Some("30"));
+    buyer.send(new_order("b-1", "1", 2.0, Some(100.0))).await;
+    let placed = buyer.report("0").await;
+    assert_eq!(placed.get(tags::CL_ORD_ID), Some("b-1"));
+    assert_eq!(placed.get(tags::ORD_STATUS), Some("0"));
+    assert_eq!(placed.get(tags::ACCOUNT), Some("desk-1"));
+    assert_eq!(placed.get(tags::LEAVES_QTY), Some("2"));
+    let order_id = placed.get(tags::ORDER_ID).unwrap().to_string();
+
+    let replace = Message::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+        .with(tags::ORIG_CL_ORD_ID, "b-1")
+        .with(tags::CL_ORD_ID, "b-2")
+        .with(tags::SYMBOL, "BTC-USD")
+        .with(tags::SIDE, "1")
+        .with(tags::ORDER_QTY, 3)
+        .with(tags::ORD_TYPE, "2")
+        .with(tags::PRICE, 100)
+        .with(tags::TRANSACT_TIME, utc_timestamp(0));
+    buyer.send(replace).await;
+    let replaced = buyer.report("5").await;
+    assert_eq!(replaced.get(tags::CL_ORD_ID), Some("b-2"));
+    assert_eq!(replaced.get(tags::ORIG_CL_ORD_ID), Some("b-1"));
+    assert_eq!(replaced.get(tags::ORDER_ID), Some(order_id.as_str()));
+    assert_eq!(replaced.get(tags::ORDER_QTY), Some("3"));
+
+    let mut seller = Client::connect(addr, "SELLER").await;
+    seller.logon(30, Some("token-2")).await;
+    seller.send(new_order("s-1", "2", 1.0, None)).await;
+    let sold = seller.report("F").await;
+    assert_eq!(sold.get(tags::CL_ORD_ID), Some("s-1"));
+    assert_eq!(sold.get(tags::ORD_STATUS), Some("2"));
+    assert_eq!(sold.get(tags::LAST_PX), Some("100"));
+    assert_eq!(sold.get(tags::LAST_LIQUIDITY_IND), Some("2"));
+    // The resting order's fill goes by its new ClOrdID.
+    let bought = buyer.report("F").await;
+    assert_eq!(bought.get(tags::CL_ORD_ID), Some("b-2"));
+    assert_eq!(bought.get(tags::ORD_STATUS), Some("1"));
+    assert_eq!(bought.get(tags::LAST_QTY), Some("1"));
+    assert_eq!(bought.get(tags::CUM_QTY), Some("1"));
+    assert_eq!(bought.get(tags::LEAVES_QTY), Some("2"));
+    assert_eq!(bought.get(tags::LAST_LIQUIDITY_IND), Some("1"));
+
+    // Orders of other accounts are unknown.
+    let cancel = |orig: &str, cl_ord_id: &str| {
+        Message::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(tags::ORIG_CL_ORD_ID, orig)
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::SYMBOL, "BTC-USD")
+            .with(tags::SIDE, "1")
+            .with(tags::TRANSACT_TIME, utc_timestamp(0))
+    };
+    seller
+        .send(cancel("b-2", "s-2").with(tags::ORDER_ID, &order_id))
+        .await;
+    let refused = seller.expect(msg_type::ORDER_CANCEL_REJECT).await;
+    assert_eq!(refused.get(tags::CXL_REJ_REASON), Some("1"));
+    assert_eq!(refused.get(tags::CXL_REJ_RESPONSE_TO), Some("1"));
+
+    buyer.send(cancel("b-2", "b-3")).await;
+    let cancelled = buyer.report("4").await;
+    assert_eq!(cancelled.get(tags::CL_ORD_ID), Some("b-3"));
+    assert_eq!(cancelled.get(tags::ORIG_CL_ORD_ID), Some("b-2"));
+    assert_eq!(cancelled.get(tags::ORD_STATUS), Some("4"));
+    assert_eq!(cancelled.get(tags::LEAVES_QTY), Some("0"));
+    buyer.send(cancel("b-3", "b-4")).await;
+    let refused = buyer.expect(msg_type::ORDER_CANCEL_REJECT).await;
+    assert_eq!(refused.get(tags::CXL_REJ_REASON), Some("1"));
+
+    // A limit order without a price fails validation.
+    let unpriced = Message::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tags::CL_ORD_ID, "b-5")
+        .with(tags::SYMBOL, "BTC-USD")
+        .with(tags::SIDE, "1")
+        .with(tags::ORDER_QTY, 1)
+        .with(tags::ORD_TYPE, "2")
+        .with(tags::TRANSACT_TIME, utc_timestamp(0));
+    buyer.send(unpriced).await;
+    let rejected = buyer.expect(msg_type::REJECT).await;
+    assert_eq!(rejected.get(tags::REF_TAG_ID), Some("44"));
+    assert_eq!(rejected.get(tags::SESSION_REJECT_REASON), Some("1"));
+
+    buyer.send(Message::new("AE")).await;
+    let rejected = buyer.expect(msg_type::BUSINESS_MESSAGE_REJECT).await;
+    assert_eq!(rejected.get(tags::REF_MSG_TYPE), Some("AE"));
+    assert_eq!(rejected.get(tags::BUSINESS_REJECT_REASON), Some("3"));
+}
+
+#[actix_web::test]
+async fn test_sequence_gaps_are_recovered() {
+    let (engine, hub) = app_engine();
+    let addr = serve(Gateway::new(engine.clone(), hub, "ORDERBOOK")).await;
+    let mut client = Client::connect(addr, "DESK-9").await;
+    client.logon(30, None).await;
+    client.send(new_order("o-1", "1", 1.0, Some(100.0))).await;
+    let placed = client.report("0").await;
+    assert_eq!(placed.get(tags::ACCOUNT), Some("DESK-9"));
+    assert_eq!(placed.seq_num(), Some(2));
+
+    // Messages 3 and 4 go missing.
+    client
+        .send_as(
+            5,
+            Message::new(msg_type::TEST_REQUEST).with(tags::TEST_REQ_ID, "t-1"),
+        )
+        .await;
+    let request = client.expect(msg_type::RESEND_REQUEST).await;
+    assert_eq!(request.get(tags::BEGIN_SEQ_NO), Some("3"));
+    assert_eq!(request.get(tags::END_SEQ_NO), Some("0"));
+    client
+        .send_as(
+            3,
+            Message::new(msg_type::SEQUENCE_RESET)
+                .with(tags::GAP_FILL_FLAG, "Y")
+                .with(tags::NEW_SEQ_NO, 5),
+        )
+        .await;
+    client.seq = 5;
+    client
+        .send(
+            Message::new(msg_type::TEST_REQUEST)
+                .with(tags::POSS_DUP_FLAG, "Y")
+                .with(tags::TEST_REQ_ID, "t-1"),
+        )
+        .await;
+    let heartbeat = client.expect(msg_type::HEARTBEAT).await;
+    assert_eq!(heartbeat.get(tags::TEST_REQ_ID), Some("t-1"));
+    assert_eq!(heartbeat.seq_num(), Some(4));
+
+    // Session messages are skipped, and the order's report resent.
+    client
+        .send(
+            Message::new(msg_type::RESEND_REQUEST)
+                .with(tags::BEGIN_SEQ_NO, 1)
+                .with(tags::END_SEQ_NO, 0),
+        )
+        .await;
+    let gap_fill = client.expect(msg_type::SEQUENCE_RESET).await;
+    assert_eq!(gap_fill.seq_num(), Some(1));
+    assert!(gap_fill.flag(tags::GAP_FILL_FLAG));
+    assert_eq!(gap_fill.get(tags::NEW_SEQ_NO), Some("2"));
+    let resent = client.expect(msg_type::EXECUTION_REPORT).await;
+    assert_eq!(resent.seq_num(), Some(2));
+    assert!(resent.flag(tags::POSS_DUP_FLAG));
+    assert_eq!(
+        resent.get(tags::ORIG_SENDING_TIME),
+        placed.get(tags::SENDING_TIME)
+    );
+    assert_eq!(resent.get(tags::EXEC_ID), placed.get(tags::EXEC_ID));
+    let gap_fill = client.expect(msg_type::SEQUENCE_RESET).await;
+    assert_eq!(gap_fill.seq_num(), Some(3));
+    assert_eq!(gap_fill.get(tags::NEW_SEQ_NO), Some("5"));
+
+    client.send(Message::new(msg_type::LOGOUT)).await;
+    client.expect(msg_type::LOGOUT).await;
+    assert!(client.closed().await);
+
+    // The session carries on where it left off, and an idle client is
+    // sent heartbeats and then tested.
+    let seq = client.seq;
+    let mut client = Client::connect(addr, "DESK-9").await;
+    client.seq = seq;
+    let logon = client.logon(1, None).await;
+    assert_eq!(logon.seq_num(), Some(6));
+    client.expect(msg_type::HEARTBEAT).await;
+    let test = client.expect(msg_type::TEST_REQUEST).await;
+    let id = test.get(tags::TEST_REQ_ID).unwrap().to_string();
+    client
+        .send(Message::new(msg_type::HEARTBEAT).with(tags::TEST_REQ_ID, id))
+        .await;
+
+    // A message numbered below what was already received ends the session.
+    client.send_as(2, Message::new(msg_type::HEARTBEAT)).await;
+    let logout = loop {
+        let message = client.recv().await;
+        if message.msg_type() != msg_type::HEARTBEAT {
+            break message;
+        }
+    };
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert!(logout
+        .get(tags::TEXT)
+        .unwrap()
+        .starts_with("MsgSeqNum too low"));
+    assert!(client.closed().await);
+}
+
+#[actix_web::test]
+async fn test_sessions_keep_their_account_and_reports() {
+    let (engine, hub) = app_engine();
+    let accounts = Accounts::new(&[
+        AccountToken::new("desk-1", "token-1"),
+        AccountToken::new("desk-2", "token-2"),
+    ]);
+    let addr =
+        serve(Gateway::new(engine.clone(), hub, "ORDERBOOK").with_accounts(Arc::new(accounts)))
+            .await;
+
+    let mut client = Client::connect(addr, "BUYER").await;
+    client.logon(30, Some("token-1")).await;
+    client.send(new_order("b-1", "1", 2.0, Some(100.0))).await;
+    client.report("0").await;
+    client.send(Message::new(msg_type::LOGOUT)).await;
+    client.expect(msg_type::LOGOUT).await;
+    assert!(client.closed().await);
+
+    // Filled while the session is logged off.
+    let mut ask = Order::new(
+        engine.next_order_id(),
+        OrderType::Limit,
+        "BTC-USD".to_string(),
+        1.0,
+        Some(Price::new(100.0)),
+        0,
+        BidOrAsk::Ask,
+    );
+    ask.account = Some("desk-2".to_string());
+    engine.add_order(ask, None).await.unwrap();
+
+    // The session stays with the account it first logged on for.
+    let seq = client.seq;
+    let mut intruder = Client::connect(addr, "BUYER").await;
+    intruder.seq = seq;
+    intruder
+        .send(
+            Message::new(msg_type::LOGON)
+                .with(tags::ENCRYPT_METHOD, 0)
+                .with(tags::HEART_BT_INT, 30)
+                .with(tags::PASSWORD, "token-2"),
+        )
+        .await;
+    let logout = intruder.expect(msg_type::LOGOUT).await;
+    assert_eq!(
+        logout.get(tags::TEXT),
+        Some("the session belongs to another account")
+    );
+    assert!(intruder.closed().await);
+
+    let mut client = Client::connect(addr, "BUYER").await;
+    client.seq = seq;
+    client.logon(30, Some("token-1")).await;
+    let filled = client.report("F").await;
+    assert_eq!(filled.get(tags::CL_ORD_ID), Some("b-1"));
+    assert_eq!(filled.get(tags::ACCOUNT), Some("desk-1"));
+    assert_eq!(filled.get(tags::LAST_QTY), Some("1"));
+    assert_eq!(filled.get(tags::LEAVES_QTY), Some("1"));
+}
+
+#[actix_web::test]
+async fn test_logged_off_sessions_expire() {
+    let (engine, hub) = app_engine();
+    let addr =
+        serve(Gateway::new(engine, hub, "ORDERBOOK").with_session_expiry(Duration::ZERO)).await;
+
+    let mut client = Client::connect(addr, "BUYER").await;
+    client.logon(30, None).await;
+    client.send(Message::new(msg_type::LOGOUT)).await;
+    client.expect(msg_type::LOGOUT).await;
+    assert!(client.closed().await);
+
+    // The session is gone, so its sequence numbers start again rather than
+    // this Logon being too low.
+    let mut client = Client::connect(addr, "BUYER").await;
+    let logon = client.logon(30, None).await;
+    assert_eq!(logon.seq_num(), Some(1));
+}
@@ -0,0 +1,67 @@
+use actix_web::{middleware, test, web, App};
+use orderbook::api;
+use orderbook::engine::Engine;
+use orderbook::market_data::Hub;
+use orderbook::metrics::{self, Metrics};
+use orderbook::models::MarketEvent;
+use orderbook::order_book::OrderBook;
+use serde_json::json;
+use std::sync::mpsc;
+
+#[actix_web::test]
+async fn test_metrics_endpoint_reports_orders_and_depth() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let metrics = Metrics::new();
+    let engine = Engine::new(OrderBook::new(tx))
+        .with_metrics(metrics.clone())
+        .spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .app_data(web::Data::new(engine))
+            .app_data(web::Data::new(metrics))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    for (id, trading_pair, side, amount) in [
+        (1, "BTC-USD", "Ask", 2.0),
+        (2, "BTC-USD", "Bid", 0.5),
+        (3, "ETH-USD", "Ask", 1.0),
+        (4, "ETH-USD", "Bid", 1.0),
+    ] {
+        let order = json!({
+            "id": id,
+            "order_type": "Limit",
+            "trading_pair": trading_pair,
+            "amount": amount,
+            "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+            "timestamp": 0,
+            "bid_or_ask": side
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(&order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let text = String::from_utf8(body.to_vec()).unwrap();
+
+    assert!(text.contains("orderbook_orders_accepted_total 4"));
+    assert!(text.contains("orderbook_fills_total{symbol=\"BTC-USD\"} 1"));
+    assert!(text.contains("orderbook_fills_total{symbol=\"ETH-USD\"} 1"));
+    assert!(text.contains("orderbook_traded_volume_total{symbol=\"BTC-USD\"} 0.5"));
+    assert!(text.contains("orderbook_traded_volume_total{symbol=\"ETH-USD\"} 1"));
+    assert!(text.contains("orderbook_resting_orders{symbol=\"ETH-USD\"} 0"));
+    assert!(text.contains("orderbook_book_depth{side=\"ask\",symbol=\"BTC-USD\"} 1.5"));
+    assert!(text.contains("orderbook_resting_orders{symbol=\"BTC-USD\"} 1"));
+    assert!(text.contains("orderbook_engine_queue_depth 0"));
+    assert!(text.contains(
+        "orderbook_http_request_duration_seconds_count{method=\"POST\",path=\"/orders\",status=\"200\"} 4"
+    ));
+}
@@ -0,0 +1,94 @@
+use actix_web::{test, web, App};
+use orderbook::api;
+use orderbook::engine::Engine;
+use orderbook::market_data::Hub;
+use orderbook::models::MarketEvent;
+use orderbook::order_book::OrderBook;
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[actix_web::test]
+async fn test_order_span_follows_order_onto_engine_thread() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    tracing_subscriber::fmt()
+        .with_env_filter("orderbook=debug")
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(move || writer.clone())
+        .init();
+
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    for (id, side) in [(1, "Ask"), (2, "Bid")] {
+        let order = json!({
+            "id": id,
+            "order_type": "Limit",
+            "trading_pair": "BTC-USD",
+            "amount": 1.0,
+            "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+            "timestamp": 0,
+            "bid_or_ask": side
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .insert_header(("X-Client-Id", "desk-7"))
+            .set_json(&order)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let events: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|event: &Value| event["span"]["order_id"] == 2)
+        .collect();
+    let messages: Vec<&str> = events
+        .iter()
+        .map(|event| event["fields"]["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            "order received",
+            "order validated",
+            "trade published",
+            "order matched",
+            "order executed",
+        ]
+    );
+    for event in &events {
+        assert_eq!(event["span"]["client_id"], "desk-7");
+        assert_eq!(event["span"]["symbol"], "BTC-USD");
+    }
+    // Matching happened on the engine thread, inside the request's span.
+    let matched = &events[3];
+    assert_eq!(matched["fields"]["fills"], 1);
+    assert_eq!(matched["fields"]["remaining"], 0.0);
+}
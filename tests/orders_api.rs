@@ -0,0 +1,557 @@
+use actix_web::{test, web, App};
+use orderbook::api;
+use orderbook::config::Features;
+use orderbook::engine::{CommandKind, CommandLatency, Engine};
+use orderbook::instrument::Instrument;
+use orderbook::market_data::Hub;
+use orderbook::models::{BidOrAsk, Execution, MarketEvent, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use serde_json::{json, Value};
+use std::sync::mpsc;
+
+#[actix_web::test]
+async fn test_orders_go_through_engine() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let ask = json!({
+        "id": 1,
+        "order_type": "Limit",
+        "trading_pair": "BTC-USD",
+        "amount": 2.0,
+        "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+        "timestamp": 0,
+        "bid_or_ask": "Ask"
+    });
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .set_json(&ask)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    // A market order with no price used to panic while holding the book
+    // lock. Now it fills what it can and the rest is dropped.
+    let market_bid = json!({
+        "id": 2,
+        "order_type": "Market",
+        "trading_pair": "BTC-USD",
+        "amount": 3.0,
+        "price": null,
+        "timestamp": 0,
+        "bid_or_ask": "Bid"
+    });
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .set_json(&market_bid)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let req = test::TestRequest::get().uri("/orders").to_request();
+    let orders: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert!(orders.is_empty());
+
+    // Orders the book cannot take are refused with a code to match on.
+    let mut limit_bid = market_bid.clone();
+    limit_bid["order_type"] = json!("Limit");
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .set_json(&limit_bid)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "invalid_order");
+
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .insert_header(("Content-Type", "application/json"))
+        .set_payload("{")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "invalid_json");
+
+    // Malformed prices are refused before they reach the engine.
+    for price in [
+        json!({"integral": 100, "fractional": 0, "scalar": 0}),
+        json!({"integral": 100, "fractional": 10, "scalar": 10}),
+    ] {
+        let mut bid = ask.clone();
+        bid["price"] = price;
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(&bid)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "invalid_json");
+    }
+}
+
+#[actix_web::test]
+async fn test_latency_histograms_per_command() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    let req = test::TestRequest::get().uri("/admin/latency").to_request();
+    let latencies: Vec<CommandLatency> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(latencies.len(), 1);
+    assert_eq!(latencies[0].command, CommandKind::QueryOrders);
+    assert_eq!(latencies[0].count, 3);
+    assert!(latencies[0].min_ns <= latencies[0].p50_ns);
+    assert!(latencies[0].p50_ns <= latencies[0].max_ns);
+
+    let req = test::TestRequest::delete()
+        .uri("/admin/latency")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::get().uri("/admin/latency").to_request();
+    let latencies: Vec<CommandLatency> = test::call_and_read_body_json(&app, req).await;
+    // Only the reset itself has been recorded since.
+    assert_eq!(latencies.len(), 1);
+    assert_eq!(latencies[0].command, CommandKind::ResetLatencies);
+}
+
+#[actix_web::test]
+async fn test_routes_orders_to_instrument_books() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let books = ["BTC-USD", "ETH-USD"]
+        .into_iter()
+        .map(|symbol| {
+            let (base, quote) = symbol.split_once('-').unwrap();
+            OrderBook::with_instrument(
+                tx.clone(),
+                Instrument::new(symbol, base, quote, 0.5, 0.001, 10.0, 2),
+            )
+        })
+        .collect();
+    let engine = Engine::with_books(books).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::configure(cfg, hub.clone(), Features::default())),
+    )
+    .await;
+
+    for (id, trading_pair, status) in [(1, "ETH-USD", 200), (2, "DOGE-USD", 400)] {
+        let order = json!({
+            "id": id,
+            "order_type": "Limit",
+            "trading_pair": trading_pair,
+            "amount": 1.0,
+            "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+            "timestamp": 0,
+            "bid_or_ask": "Ask"
+        });
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(&order)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    let req = test::TestRequest::get().uri("/orders").to_request();
+    let orders: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(orders.len(), 1);
+    assert_eq!(orders[0].trading_pair, "ETH-USD");
+
+    // With several books, per-book endpoints need to be told which one.
+    let req = test::TestRequest::get().uri("/status").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::get()
+        .uri("/status?symbol=BTC-USD")
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_default_book_keeps_pairs_apart() {
+    let (tx, _rx) = mpsc::channel::<MarketEvent>();
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    for (trading_pair, side) in [("BTC-USD", BidOrAsk::Bid), ("ETH-USD", BidOrAsk::Ask)] {
+        let order = Order::new(
+            engine.next_order_id(),
+            OrderType::Limit,
+            trading_pair.to_string(),
+            1.0,
+            Some(Price::new(100.0)),
+            0,
+            side,
+        );
+        let execution = engine.add_order(order, None).await.unwrap();
+        assert!(execution.fills.is_empty());
+    }
+    assert_eq!(engine.get_orders().await.unwrap().len(), 2);
+    let symbols: Vec<String> = engine
+        .depth()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(symbol, _)| symbol)
+        .collect();
+    assert_eq!(symbols, ["BTC-USD", "ETH-USD"]);
+    assert!(engine.state(None).await.is_err());
+    assert!(engine.state(Some("ETH-USD".to_string())).await.is_ok());
+}
+
+#[actix_web::test]
+async fn test_order_ids_are_assigned_and_retries_are_idempotent() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let bid = |client_order_id: &str, amount: f64| {
+        json!({
+            "id": 42,
+            "client_order_id": client_order_id,
+            "order_type": "Limit",
+            "trading_pair": "BTC-USD",
+            "amount": amount,
+            "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+            "timestamp": 0,
+            "bid_or_ask": "Bid"
+        })
+    };
+    let submit = |order: serde_json::Value, client_id: &str| {
+        test::TestRequest::post()
+            .uri("/orders")
+            .insert_header(("X-Client-Id", client_id.to_string()))
+            .set_json(order)
+            .to_request()
+    };
+
+    // The id a client sends is ignored.
+    let first: Execution =
+        test::call_and_read_body_json(&app, submit(bid("a", 1.0), "desk-7")).await;
+    assert_eq!(first.order.id, 1);
+    assert_eq!(first.order.client_order_id.as_deref(), Some("a"));
+    assert_eq!(first.order.account.as_deref(), Some("desk-7"));
+
+    // A retry gets the original execution back and creates nothing.
+    let retried: Execution =
+        test::call_and_read_body_json(&app, submit(bid("a", 1.0), "desk-7")).await;
+    assert_eq!(retried.order.id, first.order.id);
+
+    // Reusing the id for a different order is refused, but other accounts
+    // have ids of their own.
+    let resp = test::call_service(&app, submit(bid("a", 2.0), "desk-7")).await;
+    assert_eq!(resp.status(), 409);
+    let other: Execution =
+        test::call_and_read_body_json(&app, submit(bid("a", 1.0), "desk-8")).await;
+    assert_ne!(other.order.id, first.order.id);
+
+    // An Idempotency-Key works the same way for orders without a client id.
+    let mut keyed = bid("b", 1.0);
+    keyed.as_object_mut().unwrap().remove("client_order_id");
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .insert_header(("Idempotency-Key", "retry-1"))
+            .set_json(&keyed)
+            .to_request();
+        let execution: Execution = test::call_and_read_body_json(&app, req).await;
+        ids.push(execution.order.id);
+    }
+    assert_eq!(ids[0], ids[1]);
+
+    let req = test::TestRequest::get().uri("/orders").to_request();
+    let orders: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(orders.len(), 3);
+    let resting = orders
+        .iter()
+        .find(|order| order.id == first.order.id)
+        .unwrap();
+    assert_eq!(resting.client_order_id.as_deref(), Some("a"));
+    assert_eq!(resting.account.as_deref(), Some("desk-7"));
+}
+
+#[actix_web::test]
+async fn test_batch_operations_and_mass_cancel() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let order = |side: &str, integral: u64| {
+        json!({
+            "order_type": "Limit",
+            "trading_pair": "BTC-USD",
+            "amount": 1.0,
+            "price": {"integral": integral, "fractional": 0, "scalar": 100000},
+            "timestamp": 0,
+            "bid_or_ask": side
+        })
+    };
+    let batch = json!([
+        {"op": "new", "order": order("Ask", 101)},
+        {"op": "new", "order": order("Ask", 102)},
+        {"op": "cancel", "id": 999},
+        {"op": "amend", "id": 1, "amount": 0.5, "price": null},
+    ]);
+    let req = test::TestRequest::post()
+        .uri("/orders/batch")
+        .insert_header(("X-Client-Id", "desk-7"))
+        .set_json(&batch)
+        .to_request();
+    let items: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    let statuses: Vec<u64> = items
+        .iter()
+        .map(|item| item["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, vec![200, 200, 404, 200]);
+    assert_eq!(items[1]["execution"]["order"]["id"], 2);
+    assert_eq!(items[2]["error"]["code"], "order_not_found");
+    assert_eq!(items[2]["error"]["message"], "order 999 not found");
+    assert_eq!(items[3]["execution"]["order"]["amount"], 0.5);
+
+    let req = test::TestRequest::post()
+        .uri("/orders")
+        .insert_header(("X-Client-Id", "desk-8"))
+        .set_json(order("Ask", 103))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::delete()
+        .uri("/orders?symbol=BTC-USD&side=Ask&account=desk-7")
+        .to_request();
+    let cancelled: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    let mut ids: Vec<u64> = cancelled.iter().map(|order| order.id).collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2]);
+
+    let req = test::TestRequest::get().uri("/orders").to_request();
+    let orders: Vec<Order> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(orders.len(), 1);
+    assert_eq!(orders[0].account.as_deref(), Some("desk-8"));
+}
+
+#[actix_web::test]
+async fn test_order_status_and_history() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let place = |account: &str, side: &str, amount: f64| {
+        test::TestRequest::post()
+            .uri("/orders")
+            .insert_header(("X-Client-Id", account.to_string()))
+            .set_json(json!({
+                "order_type": "Limit",
+                "trading_pair": "BTC-USD",
+                "amount": amount,
+                "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+                "timestamp": 0,
+                "bid_or_ask": side
+            }))
+            .to_request()
+    };
+    let ask: Execution = test::call_and_read_body_json(&app, place("desk-7", "Ask", 3.0)).await;
+    let bid: Execution = test::call_and_read_body_json(&app, place("desk-8", "Bid", 1.0)).await;
+
+    let uri = format!("/orders/{}", ask.order.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status["status"], "partially_filled");
+    assert_eq!(
+        (status["amount"].as_f64(), status["filled"].as_f64()),
+        (Some(3.0), Some(1.0))
+    );
+    assert_eq!(status["average_price"], 100.0);
+
+    // Final orders stay queryable once they have left the book.
+    let req = test::TestRequest::delete().uri(&uri).to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        (status["status"].as_str(), status["remaining"].as_f64()),
+        (Some("cancelled"), Some(0.0))
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/orders?status=filled&account=desk-8")
+        .to_request();
+    let filled: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(filled.len(), 1);
+    assert_eq!(filled[0]["id"], bid.order.id);
+    let req = test::TestRequest::get().uri("/orders").to_request();
+    let working: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(working.is_empty());
+
+    let req = test::TestRequest::get()
+        .uri("/fills?account=desk-7")
+        .to_request();
+    let fills: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fills.len(), 1);
+    assert_eq!(fills[0]["order_id"], ask.order.id);
+    assert_eq!(fills[0]["liquidity"], "maker");
+    let req = test::TestRequest::get()
+        .uri("/fills?limit=1&offset=1")
+        .to_request();
+    let fills: Vec<Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(fills[0]["order_id"], ask.order.id);
+
+    let req = test::TestRequest::get().uri("/orders/999").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get().uri("/fills?limit=0").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_v1_lists_are_paged_with_cursors() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let place = |side: &str, amount: f64, price: u64| {
+        test::TestRequest::post()
+            .uri("/api/v1/orders")
+            .insert_header(("X-Client-Id", "desk-9"))
+            .set_json(json!({
+                "order_type": "Limit",
+                "trading_pair": "BTC-USD",
+                "amount": amount,
+                "price": {"integral": price, "fractional": 0, "scalar": 100000},
+                "timestamp": 0,
+                "bid_or_ask": side
+            }))
+            .to_request()
+    };
+    let mut ids = Vec::new();
+    for price in [99, 100, 100, 98] {
+        let execution: Execution =
+            test::call_and_read_body_json(&app, place("Bid", 1.0, price)).await;
+        ids.push(execution.order.id);
+    }
+
+    // Best price first, then time priority, two at a time.
+    let req = test::TestRequest::get()
+        .uri("/api/v1/bids?limit=2")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert!(res.headers().get("Deprecation").is_none());
+    let page: Value = test::read_body_json(res).await;
+    let page_ids = |page: &Value| -> Vec<u64> {
+        page["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_u64().unwrap())
+            .collect()
+    };
+    assert_eq!(page_ids(&page), vec![ids[1], ids[2]]);
+    let uri = format!(
+        "/api/v1/bids?limit=2&cursor={}",
+        page["next_cursor"].as_str().unwrap()
+    );
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page_ids(&page), vec![ids[0], ids[3]]);
+    assert!(page["next_cursor"].is_null());
+
+    // Takes both bids at 100.
+    test::call_service(&app, place("Ask", 2.0, 100)).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/trades?limit=1&side=Ask")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"][0]["aggressor"], "Ask");
+    let first = page["data"][0]["trade_id"].as_u64().unwrap();
+    let uri = format!(
+        "/api/v1/trades?limit=1&side=Ask&cursor={}",
+        page["next_cursor"].as_str().unwrap()
+    );
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"][0]["trade_id"], first + 1);
+    assert!(page["next_cursor"].is_null());
+    let req = test::TestRequest::get()
+        .uri("/api/v1/trades?side=Bid")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"], json!([]));
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/orders?side=Bid&limit=1")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page_ids(&page), vec![ids[0]]);
+    let uri = format!(
+        "/api/v1/orders?side=Bid&limit=1&cursor={}",
+        page["next_cursor"].as_str().unwrap()
+    );
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page_ids(&page), vec![ids[3]]);
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?order_id=0&cursor=7")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 400);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "invalid_query");
+
+    // The unversioned routes still answer, marked as deprecated.
+    let req = test::TestRequest::get().uri("/bids").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.headers().get("Deprecation").unwrap(), "true");
+    let bids: Vec<Order> = test::read_body_json(res).await;
+    assert_eq!(bids.len(), 2);
+}
@@ -0,0 +1,216 @@
+use actix_web::{test, web, App};
+use orderbook::api;
+use orderbook::engine::Engine;
+use orderbook::market_data::Hub;
+use orderbook::metrics::Metrics;
+use orderbook::models::{Execution, MarketEvent};
+use orderbook::order_book::OrderBook;
+use orderbook::persistence::Store;
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// A scratch PostgreSQL database for this test, which drops its tables.
+/// Without one the test is skipped.
+const DATABASE_URL: &str = "ORDERBOOK_TEST_DATABASE_URL";
+
+/// Waits for the writer to store order `id` in `status`, as writes trail
+/// the engine.
+async fn wait_until_stored(client: &tokio_postgres::Client, id: u64, status: &str) {
+    for _ in 0..100 {
+        let row = client
+            .query_opt(
+                "SELECT 1 FROM orders WHERE id = $1 AND status = $2",
+                &[&(id as i64), &status],
+            )
+            .await
+            .unwrap();
+        if row.is_some() {
+            return;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("order {} never reached the database as {}", id, status);
+}
+
+#[actix_web::test]
+async fn test_history_outlives_the_in_memory_limit() {
+    let Ok(dsn) = std::env::var(DATABASE_URL) else {
+        eprintln!("{} is not set, skipping", DATABASE_URL);
+        return;
+    };
+    let (client, connection) = tokio_postgres::connect(&dsn, tokio_postgres::NoTls)
+        .await
+        .unwrap();
+    actix_web::rt::spawn(connection);
+    client
+        .batch_execute("DROP TABLE IF EXISTS orders, fills")
+        .await
+        .unwrap();
+    let store = web::Data::new(Store::connect(&dsn).await.unwrap());
+    let metrics = Metrics::new();
+
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx))
+        .with_history_limit(1)
+        .with_persistence(store.clone().into_inner().writer(&metrics))
+        .spawn(16);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .app_data(store)
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let place = |account: &str, side: &str, amount: f64| {
+        test::TestRequest::post()
+            .uri("/api/v1/orders")
+            .insert_header(("X-Client-Id", account.to_string()))
+            .set_json(json!({
+                "order_type": "Limit",
+                "trading_pair": "BTC-USD",
+                "amount": amount,
+                "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+                "timestamp": 0,
+                "bid_or_ask": side
+            }))
+            .to_request()
+    };
+    let ask: Execution = test::call_and_read_body_json(&app, place("desk-7", "Ask", 3.0)).await;
+    let first: Execution = test::call_and_read_body_json(&app, place("desk-8", "Bid", 1.0)).await;
+    let second: Execution = test::call_and_read_body_json(&app, place("desk-8", "Bid", 1.0)).await;
+    let uri = format!("/api/v1/orders/{}", ask.order.id);
+    let req = test::TestRequest::delete().uri(&uri).to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    wait_until_stored(&client, ask.order.id, "cancelled").await;
+
+    // The engine only remembers one final order and one fill; the rest
+    // come from the database.
+    let uri = format!("/api/v1/orders/{}", first.order.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        (status["status"].as_str(), status["filled"].as_f64()),
+        (Some("filled"), Some(1.0))
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/orders?status=filled&limit=1")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"][0]["id"], first.order.id);
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/v1/orders?status=filled&cursor={}", cursor))
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"][0]["id"], second.order.id);
+    assert!(page["next_cursor"].is_null());
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?account=desk-7")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    let fills = page["data"].as_array().unwrap();
+    assert_eq!(fills.len(), 2);
+    assert!(fills.iter().all(|fill| fill["order_id"] == ask.order.id));
+    assert_eq!(fills[0]["liquidity"], "maker");
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?account=desk-7&limit=1")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/v1/fills?account=desk-7&cursor={}", cursor))
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"].as_array().map(Vec::len), Some(1));
+    assert_eq!(page["data"][0]["trade_id"], fills[1]["trade_id"]);
+    let last_trade_id = fills[1]["trade_id"].as_u64().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/orders/999")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    // After a restart, ids carry on from the stored ones rather than
+    // overwriting them.
+    let store = web::Data::new(Store::connect(&dsn).await.unwrap());
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx))
+        .with_persistence(store.clone().into_inner().writer(&metrics))
+        .spawn(16);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .app_data(store)
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+    let ask: Execution = test::call_and_read_body_json(&app, place("desk-9", "Ask", 1.0)).await;
+    let bid: Execution = test::call_and_read_body_json(&app, place("desk-9", "Bid", 1.0)).await;
+    assert!(ask.order.id > second.order.id);
+    wait_until_stored(&client, bid.order.id, "filled").await;
+
+    let uri = format!("/api/v1/orders/{}", first.order.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status["account"], "desk-8");
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?account=desk-9")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    let fills = page["data"].as_array().unwrap();
+    assert_eq!(fills.len(), 2);
+    assert!(fills
+        .iter()
+        .all(|fill| fill["trade_id"].as_u64().unwrap() > last_trade_id));
+
+    // Writes and reads carry on once the connection has been lost.
+    client
+        .batch_execute(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+             WHERE datname = current_database() AND pid <> pg_backend_pid()",
+        )
+        .await
+        .unwrap();
+    test::call_service(&app, place("desk-10", "Ask", 1.0)).await;
+    let bid: Execution = test::call_and_read_body_json(&app, place("desk-10", "Bid", 1.0)).await;
+    wait_until_stored(&client, bid.order.id, "filled").await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?account=desk-10")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"].as_array().map(Vec::len), Some(2));
+
+    // Records the database has yet to store are read from the engine.
+    client
+        .batch_execute("BEGIN; LOCK TABLE orders IN EXCLUSIVE MODE")
+        .await
+        .unwrap();
+    test::call_service(&app, place("desk-11", "Ask", 1.0)).await;
+    let bid: Execution = test::call_and_read_body_json(&app, place("desk-11", "Bid", 1.0)).await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/fills?account=desk-11")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"].as_array().map(Vec::len), Some(2));
+    let req = test::TestRequest::get()
+        .uri("/api/v1/orders?status=filled&account=desk-11")
+        .to_request();
+    let page: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page["data"].as_array().map(Vec::len), Some(2));
+    let stored = client
+        .query_one("SELECT COUNT(*) FROM fills WHERE account = 'desk-11'", &[])
+        .await
+        .unwrap();
+    assert_eq!(stored.get::<_, i64>(0), 0);
+    client.batch_execute("COMMIT").await.unwrap();
+    wait_until_stored(&client, bid.order.id, "filled").await;
+    assert_eq!(metrics.history_writes_dropped.get(), 0);
+    assert_eq!(metrics.history_writes_refused.get(), 0);
+}
@@ -0,0 +1,116 @@
+use actix_web::{middleware, test, web, App};
+use orderbook::api;
+use orderbook::engine::{Engine, EngineError, OrderToTradeLimit, RatioAction};
+use orderbook::market_data::Hub;
+use orderbook::models::MarketEvent;
+use orderbook::order_book::OrderBook;
+use orderbook::rate_limit::{Limit, RateLimits};
+use serde_json::json;
+use std::sync::mpsc;
+
+fn order(price: u64) -> serde_json::Value {
+    json!({
+        "order_type": "Limit",
+        "trading_pair": "BTC-USD",
+        "amount": 1.0,
+        "price": {"integral": price, "fractional": 0, "scalar": 100000},
+        "timestamp": 0,
+        "bid_or_ask": "Ask"
+    })
+}
+
+#[actix_web::test]
+async fn test_addresses_get_429_once_their_bucket_is_empty() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx)).spawn(16);
+    let limits = RateLimits::new(
+        Limit {
+            burst: 0,
+            per_second: 0.0,
+        },
+        Limit {
+            burst: 2,
+            per_second: 0.5,
+        },
+    );
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::from_fn(orderbook::rate_limit::limit_by_ip))
+            .app_data(web::Data::new(engine))
+            .app_data(web::Data::new(limits))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+    let request = |address: &str| {
+        test::TestRequest::get()
+            .uri("/orders")
+            .peer_addr(address.parse().unwrap())
+            .to_request()
+    };
+
+    for remaining in ["1", "0"] {
+        let res = test::call_service(&app, request("10.0.0.1:5000")).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get("ratelimit-limit").unwrap(), "2");
+        assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), remaining);
+    }
+    let refused = test::call_service(&app, request("10.0.0.1:5001")).await;
+    assert_eq!(refused.status(), 429);
+    assert_eq!(refused.headers().get("retry-after").unwrap(), "2");
+    assert_eq!(refused.headers().get("ratelimit-reset").unwrap(), "4");
+    let body: serde_json::Value = test::read_body_json(refused).await;
+    assert_eq!(body["code"], "rate_limited");
+
+    // Other addresses are unaffected.
+    let res = test::call_service(&app, request("10.0.0.2:5000")).await;
+    assert_eq!(res.status(), 200);
+}
+
+#[actix_web::test]
+async fn test_order_to_trade_ratio_throttles_quote_spam() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let hub = Hub::start(rx);
+    let engine = Engine::new(OrderBook::new(tx))
+        .with_order_to_trade(OrderToTradeLimit {
+            max_ratio: 2.0,
+            min_orders: 3,
+            window_secs: 3600,
+            action: RatioAction::Throttle,
+        })
+        .spawn(16);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+    let place = |account: &str, price: u64| {
+        test::TestRequest::post()
+            .uri("/orders")
+            .insert_header(("X-Client-Id", account.to_string()))
+            .set_json(order(price))
+            .to_request()
+    };
+
+    let first: serde_json::Value = test::call_and_read_body_json(&app, place("desk-7", 101)).await;
+    test::call_service(&app, place("desk-7", 102)).await;
+    let throttled = test::call_service(&app, place("desk-7", 103)).await;
+    assert_eq!(throttled.status(), 429);
+    // Other accounts trade on, and the throttled one can still cancel.
+    assert_eq!(
+        test::call_service(&app, place("desk-8", 104))
+            .await
+            .status(),
+        200
+    );
+    let id = first["order"]["id"].as_u64().unwrap();
+    engine.cancel_order(id, None).await.unwrap();
+    assert_eq!(
+        engine
+            .amend_order(id + 1, 2.0, None, None)
+            .await
+            .unwrap_err(),
+        EngineError::Throttled("desk-7".to_string())
+    );
+}
@@ -0,0 +1,556 @@
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+use actix_web::{test, web, App, HttpServer};
+use futures_util::{SinkExt, StreamExt};
+use orderbook::api;
+use orderbook::auth::{AccountToken, Accounts};
+use orderbook::engine::{Engine, EngineHandle};
+use orderbook::instrument::{Fees, Instrument};
+use orderbook::market_data::Hub;
+use orderbook::models::{BidOrAsk, MarketEvent, Order, OrderType, Price};
+use orderbook::order_book::OrderBook;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+fn app_engine() -> (EngineHandle, Addr<Hub>) {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    (Engine::new(OrderBook::new(tx)).spawn(16), Hub::start(rx))
+}
+
+async fn rest(engine: &EngineHandle, account: &str) -> u64 {
+    submit(engine, account, "BTC-USD", BidOrAsk::Bid).await
+}
+
+async fn submit(engine: &EngineHandle, account: &str, symbol: &str, side: BidOrAsk) -> u64 {
+    let mut order = Order::new(
+        engine.next_order_id(),
+        OrderType::Limit,
+        symbol.to_string(),
+        1.0,
+        Some(Price::new(100.0)),
+        0,
+        side,
+    );
+    order.account = Some(account.to_string());
+    engine.add_order(order, None).await.unwrap().order.id
+}
+
+async fn accounts(engine: &EngineHandle) -> Vec<String> {
+    let mut accounts: Vec<String> = engine
+        .get_orders()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|order| order.account)
+        .collect();
+    accounts.sort();
+    accounts
+}
+
+/// Starts a real server, since WebSocket sessions cannot go through
+/// `test::init_service`.
+fn serve(engine: EngineHandle, hub: Addr<Hub>) -> (SocketAddr, ServerHandle) {
+    serve_with_accounts(engine, hub, Accounts::new(&[]))
+}
+
+fn serve_with_accounts(
+    engine: EngineHandle,
+    hub: Addr<Hub>,
+    accounts: Accounts,
+) -> (SocketAddr, ServerHandle) {
+    let accounts = web::Data::new(accounts);
+    let server = HttpServer::new(move || {
+        let hub = hub.clone();
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(accounts.clone())
+            .configure(move |cfg| api::config(cfg, hub))
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    (addr, handle)
+}
+
+async fn connect(addr: SocketAddr, query: &str, account: &str) -> Socket {
+    connect_with_header(addr, query, "X-Client-Id", account).await
+}
+
+async fn connect_with_header(
+    addr: SocketAddr,
+    query: &str,
+    name: &'static str,
+    value: &str,
+) -> Socket {
+    let mut request = format!("ws://{}/ws/{}", addr, query)
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(name, value.parse().unwrap());
+    tokio_tungstenite::connect_async(request).await.unwrap().0
+}
+
+/// Sends a request and waits for the reply carrying its id.
+async fn call(socket: &mut Socket, request: Value) -> Value {
+    let id = request["id"].clone();
+    socket
+        .send(Message::Text(request.to_string()))
+        .await
+        .unwrap();
+    while let Some(message) = socket.next().await {
+        if let Message::Text(text) = message.unwrap() {
+            let reply: Value = serde_json::from_str(&text).unwrap();
+            if reply["id"] == id {
+                return reply;
+            }
+        }
+    }
+    panic!("socket closed before replying to {}", id);
+}
+
+#[actix_web::test]
+async fn test_cancel_on_disconnect() {
+    let (engine, hub) = app_engine();
+    let (addr, handle) = serve(engine.clone(), hub);
+
+    rest(&engine, "desk-7").await;
+    rest(&engine, "desk-8").await;
+
+    let mut socket = connect(addr, "?cancel_on_disconnect=true", "desk-7").await;
+    // Orders placed while the session is up are covered too.
+    rest(&engine, "desk-7").await;
+    socket.send(Message::Close(None)).await.unwrap();
+    drop(socket);
+
+    let mut remaining = accounts(&engine).await;
+    for _ in 0..50 {
+        if remaining.len() == 1 {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        remaining = accounts(&engine).await;
+    }
+    assert_eq!(remaining, vec!["desk-8"]);
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_cancel_on_disconnect_needs_an_account() {
+    let (engine, hub) = app_engine();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/ws/?cancel_on_disconnect=true")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_cancel_on_disconnect_needs_a_token_once_tokens_exist() {
+    let (engine, hub) = app_engine();
+    let tokens = Accounts::new(&[AccountToken::new("desk-7", "token-7")]);
+    let (addr, handle) = serve_with_accounts(engine.clone(), hub, tokens);
+    rest(&engine, "desk-7").await;
+
+    // Naming the account is not enough to have its orders cancelled.
+    let mut request = format!("ws://{}/ws/?cancel_on_disconnect=true", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("X-Client-Id", "desk-7".parse().unwrap());
+    assert!(tokio_tungstenite::connect_async(request).await.is_err());
+    assert_eq!(accounts(&engine).await, vec!["desk-7"]);
+
+    let mut socket = connect_with_header(
+        addr,
+        "?cancel_on_disconnect=true",
+        "Authorization",
+        "Bearer token-7",
+    )
+    .await;
+    socket.send(Message::Close(None)).await.unwrap();
+    drop(socket);
+    let mut remaining = accounts(&engine).await;
+    for _ in 0..50 {
+        if remaining.is_empty() {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        remaining = accounts(&engine).await;
+    }
+    assert!(remaining.is_empty());
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_dead_mans_switch() {
+    let (engine, hub) = app_engine();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+    rest(&engine, "desk-7").await;
+    rest(&engine, "desk-8").await;
+
+    let arm = |account: &str, timeout_ms: u64| {
+        test::TestRequest::post()
+            .uri("/orders/cancel-after")
+            .insert_header(("X-Client-Id", account.to_string()))
+            .set_json(json!({ "timeout_ms": timeout_ms }))
+            .to_request()
+    };
+    let armed: serde_json::Value = test::call_and_read_body_json(&app, arm("desk-7", 50)).await;
+    assert_eq!(armed["account"], "desk-7");
+    assert!(armed["cancel_at"].as_u64().is_some());
+    // Disarmed switches never fire.
+    test::call_service(&app, arm("desk-8", 50)).await;
+    let disarmed: serde_json::Value = test::call_and_read_body_json(&app, arm("desk-8", 0)).await;
+    assert!(disarmed["cancel_at"].is_null());
+
+    // Nothing happens before the deadline...
+    engine.tick().await.unwrap();
+    assert_eq!(accounts(&engine).await, vec!["desk-7", "desk-8"]);
+
+    // ...and the first tick after it cancels the account's orders.
+    actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    engine.tick().await.unwrap();
+    assert_eq!(accounts(&engine).await, vec!["desk-8"]);
+
+    let req = test::TestRequest::post()
+        .uri("/orders/cancel-after")
+        .set_json(json!({ "timeout_ms": 1000 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_dead_mans_switch_needs_a_token_once_tokens_exist() {
+    let (engine, hub) = app_engine();
+    let tokens = Accounts::new(&[AccountToken::new("desk-7", "token-7")]);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(web::Data::new(tokens))
+            .configure(|cfg| api::config(cfg, hub.clone())),
+    )
+    .await;
+
+    // Naming the account is not enough to arm its switch.
+    let arm = |header: (&'static str, &'static str)| {
+        test::TestRequest::post()
+            .uri("/orders/cancel-after")
+            .insert_header(header)
+            .set_json(json!({ "timeout_ms": 50 }))
+            .to_request()
+    };
+    let refused = test::call_service(&app, arm(("X-Client-Id", "desk-7"))).await;
+    assert_eq!(refused.status(), 400);
+    let forged = test::call_service(&app, arm(("Authorization", "Bearer token-8"))).await;
+    assert_eq!(forged.status(), 401);
+
+    let armed: serde_json::Value =
+        test::call_and_read_body_json(&app, arm(("Authorization", "Bearer token-7"))).await;
+    assert_eq!(armed["account"], "desk-7");
+    assert!(armed["cancel_at"].as_u64().is_some());
+}
+
+#[actix_web::test]
+async fn test_order_entry_over_websocket() {
+    let (engine, hub) = app_engine();
+    let (addr, handle) = serve(engine.clone(), hub);
+    let mut socket = connect(addr, "", "desk-7").await;
+
+    let pong = call(&mut socket, json!({"id": 1, "type": "ping"})).await;
+    assert_eq!(pong["type"], "pong");
+
+    let order = json!({
+        "order_type": "Limit",
+        "trading_pair": "BTC-USD",
+        "amount": 2.0,
+        "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+        "timestamp": 0,
+        "bid_or_ask": "Ask"
+    });
+    let placed = call(
+        &mut socket,
+        json!({"id": 2, "type": "place", "order": order}),
+    )
+    .await;
+    assert_eq!(placed["type"], "ack");
+    let order = &placed["result"]["execution"]["order"];
+    assert_eq!(order["account"], "desk-7");
+    let order_id = order["id"].as_u64().unwrap();
+
+    let amended = call(
+        &mut socket,
+        json!({"id": 3, "type": "amend", "order_id": order_id, "amount": 1.0}),
+    )
+    .await;
+    assert_eq!(amended["result"]["execution"]["order"]["amount"], 1.0);
+
+    let cancelled = call(
+        &mut socket,
+        json!({"id": 4, "type": "cancel", "order_id": order_id}),
+    )
+    .await;
+    assert_eq!(cancelled["result"]["cancelled"]["id"], order_id);
+
+    let rejected = call(
+        &mut socket,
+        json!({"id": 5, "type": "cancel", "order_id": order_id}),
+    )
+    .await;
+    assert_eq!(rejected["type"], "reject");
+    assert_eq!(rejected["code"], "order_not_found");
+
+    let invalid = call(&mut socket, json!({"id": 6, "type": "launch"})).await;
+    assert_eq!(invalid["code"], "invalid_request");
+
+    let subscribed = call(
+        &mut socket,
+        json!({"id": 7, "type": "unsubscribe", "topics": ["trades:BTC-USD"]}),
+    )
+    .await;
+    assert_eq!(subscribed["type"], "ack");
+
+    assert!(engine.get_orders().await.unwrap().is_empty());
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_order_entry_needs_authentication_once_tokens_exist() {
+    let (engine, hub) = app_engine();
+    let tokens = Accounts::new(&[
+        AccountToken::new("desk-7", "token-7"),
+        AccountToken::new("desk-8", "token-8"),
+    ]);
+    let (addr, handle) = serve_with_accounts(engine.clone(), hub, tokens);
+    let order = json!({
+        "order_type": "Limit",
+        "trading_pair": "BTC-USD",
+        "amount": 2.0,
+        "price": {"integral": 100, "fractional": 0, "scalar": 100000},
+        "timestamp": 0,
+        "bid_or_ask": "Ask"
+    });
+
+    let mut anonymous = connect(addr, "", "desk-7").await;
+    let rejected = call(
+        &mut anonymous,
+        json!({"id": 1, "type": "place", "order": order}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "unauthorized");
+    let order_id = rest(&engine, "desk-7").await;
+    let rejected = call(
+        &mut anonymous,
+        json!({"id": 2, "type": "cancel", "order_id": order_id}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "unauthorized");
+
+    // Authenticated sessions only reach their own account's orders.
+    let mut other = connect_with_header(addr, "", "Authorization", "Bearer token-8").await;
+    let rejected = call(
+        &mut other,
+        json!({"id": 1, "type": "cancel", "order_id": order_id}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "order_not_found");
+    let rejected = call(
+        &mut other,
+        json!({"id": 2, "type": "amend", "order_id": order_id, "amount": 1.0}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "order_not_found");
+
+    let mut owner = connect_with_header(addr, "", "Authorization", "Bearer token-7").await;
+    let cancelled = call(
+        &mut owner,
+        json!({"id": 1, "type": "cancel", "order_id": order_id}),
+    )
+    .await;
+    assert_eq!(cancelled["result"]["cancelled"]["id"], order_id);
+    for mut socket in [anonymous, other, owner] {
+        socket.send(Message::Close(None)).await.unwrap();
+    }
+    handle.stop(true).await;
+}
+
+/// Waits for the next market data message, skipping replies.
+async fn next_message(socket: &mut Socket) -> Value {
+    let wait = async {
+        while let Some(message) = socket.next().await {
+            if let Message::Text(text) = message.unwrap() {
+                let message: Value = serde_json::from_str(&text).unwrap();
+                if message.get("channel").is_some() {
+                    return message;
+                }
+            }
+        }
+        panic!("socket closed");
+    };
+    actix_web::rt::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("no market data arrived")
+}
+
+#[actix_web::test]
+async fn test_market_data_channels() {
+    let (engine, hub) = app_engine();
+    let (addr, handle) = serve(engine.clone(), hub.clone());
+    let mut socket = connect(addr, "", "desk-7").await;
+
+    let subscribed = call(
+        &mut socket,
+        json!({"id": 1, "type": "subscribe", "topics": ["trades:BTC-USD", "depth:*:1"]}),
+    )
+    .await;
+    assert_eq!(subscribed["type"], "ack");
+    let rejected = call(
+        &mut socket,
+        json!({"id": 2, "type": "subscribe", "topics": ["candles:BTC-USD:2m"]}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "invalid_request");
+
+    submit(&engine, "desk-8", "ETH-USD", BidOrAsk::Bid).await;
+    let depth = next_message(&mut socket).await;
+    assert_eq!(depth["channel"], "depth:ETH-USD:1");
+    assert_eq!(depth["seq"], 1);
+    assert_eq!(depth["data"]["bids"][0]["orders"], 1);
+
+    // ETH trades are not subscribed to, so the first trade seen is BTC's.
+    submit(&engine, "desk-9", "ETH-USD", BidOrAsk::Ask).await;
+    submit(&engine, "desk-8", "BTC-USD", BidOrAsk::Bid).await;
+    submit(&engine, "desk-9", "BTC-USD", BidOrAsk::Ask).await;
+    let mut channels = Vec::new();
+    let trade = loop {
+        let message = next_message(&mut socket).await;
+        if message["channel"] == "trades:BTC-USD" {
+            break message;
+        }
+        channels.push((message["channel"].clone(), message["seq"].clone()));
+    };
+    assert_eq!(
+        channels,
+        [
+            (json!("depth:ETH-USD:1"), json!(2)),
+            (json!("depth:BTC-USD:1"), json!(1)),
+        ]
+    );
+    assert_eq!(trade["seq"], 1);
+    assert_eq!(trade["data"]["type"], "trade");
+    assert_eq!(trade["data"]["symbol"], "BTC-USD");
+    assert_eq!(trade["data"]["amount"], 1.0);
+
+    // A snapshot carries the sequence number its channel had reached.
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .configure(move |cfg| api::config(cfg, hub)),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/depth?symbol=ETH-USD&levels=1")
+        .to_request();
+    let snapshot: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(snapshot["channel"], "depth:ETH-USD:1");
+    assert_eq!(snapshot["seq"], 2);
+    assert_eq!(snapshot["bids"], json!([]));
+    let req = test::TestRequest::get()
+        .uri("/api/v1/depth?symbol=ETH-USD&levels=51")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    handle.stop(true).await;
+}
+
+#[actix_web::test]
+async fn test_private_channels() {
+    let (tx, rx) = mpsc::channel::<MarketEvent>();
+    let instrument =
+        Instrument::new("BTC-USD", "BTC", "USD", 0.01, 0.001, 0.0, 2).with_fees(Fees {
+            maker_bps: -1.0,
+            taker_bps: 5.0,
+        });
+    let engine = Engine::new(OrderBook::with_instrument(tx.clone(), instrument))
+        .with_notifier(tx)
+        .spawn(16);
+    let accounts = Accounts::new(&[
+        AccountToken::new("desk-7", "token-7"),
+        AccountToken::new("desk-8", "token-8"),
+    ]);
+    let (addr, handle) = serve_with_accounts(engine.clone(), Hub::start(rx), accounts);
+    let subscribe = json!({"id": 1, "type": "subscribe", "topics": ["orders:*", "fills:*"]});
+
+    // Private channels need an authenticated session.
+    let mut anonymous = connect(addr, "", "desk-7").await;
+    let rejected = call(&mut anonymous, subscribe.clone()).await;
+    assert_eq!(rejected["code"], "unauthorized");
+    let rejected = call(
+        &mut anonymous,
+        json!({"id": 2, "type": "login", "token": "token-8"}),
+    )
+    .await;
+    assert_eq!(rejected["code"], "unauthorized");
+
+    let mut maker = connect_with_header(addr, "", "Authorization", "Bearer token-7").await;
+    assert_eq!(call(&mut maker, subscribe.clone()).await["type"], "ack");
+    let mut taker = connect(addr, "", "desk-8").await;
+    let login = call(
+        &mut taker,
+        json!({"id": 2, "type": "login", "token": "token-8"}),
+    )
+    .await;
+    assert_eq!(login["type"], "ack");
+    assert_eq!(call(&mut taker, subscribe).await["type"], "ack");
+
+    let ask = submit(&engine, "desk-7", "BTC-USD", BidOrAsk::Ask).await;
+    let bid = submit(&engine, "desk-8", "BTC-USD", BidOrAsk::Bid).await;
+
+    for (socket, account, order_id, liquidity, fee) in [
+        (&mut maker, "desk-7", ask, "maker", -0.01),
+        (&mut taker, "desk-8", bid, "taker", 0.05),
+    ] {
+        let mut messages = Vec::new();
+        for _ in 0..3 {
+            let message = next_message(socket).await;
+            assert_eq!(message["data"]["account"], account);
+            assert_eq!(message["data"]["order_id"], order_id);
+            messages.push(message);
+        }
+        assert_eq!(messages[0]["channel"], "orders:BTC-USD");
+        assert_eq!(messages[0]["data"]["kind"], "new");
+        assert_eq!(messages[1]["channel"], "fills:BTC-USD");
+        assert_eq!(messages[2]["channel"], "orders:BTC-USD");
+        assert_eq!(messages[2]["seq"], 2);
+        let report = &messages[2]["data"];
+        assert_eq!(report["kind"], "fill");
+        assert_eq!(report["status"], "filled");
+        assert_eq!(report["remaining"], 0.0);
+        assert_eq!(report["fill"]["liquidity"], liquidity);
+        assert!((report["fill"]["fee"].as_f64().unwrap() - fee).abs() < 1e-9);
+        assert_eq!(report["fill"]["fee_asset"], "USD");
+    }
+
+    handle.stop(true).await;
+}